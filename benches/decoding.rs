@@ -0,0 +1,131 @@
+// Decoding benchmarks over payloads similar to what RabbitMQ produces.
+//
+//   cargo bench
+//   cargo bench --features arena
+//
+// See BENCHMARKS.md for the numbers.
+
+#![allow(clippy::needless_return)]
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use erl_etf::*;
+use num::bigint::BigInt;
+
+// deep_list is nested deeper than DEFAULT_MAX_NESTING_DEPTH
+const MAX_DEPTH: usize = 1024;
+
+fn atom(name: &str) -> ErlTerm {
+    ErlTerm::Atom(InternedAtom::new(name).unwrap())
+}
+
+fn binary(s: &str) -> ErlTerm {
+    ErlTerm::Binary(s.as_bytes().to_vec())
+}
+
+fn tuple(elements: Vec<ErlTerm>) -> ErlTerm {
+    ErlTerm::Tuple(Tuple { elements })
+}
+
+fn list(elements: Vec<ErlTerm>) -> ErlTerm {
+    ErlTerm::List(List { elements })
+}
+
+fn integer(i: i32) -> ErlTerm {
+    match u8::try_from(i) {
+        Ok(small) => ErlTerm::SmallInteger(small),
+        Err(_) => ErlTerm::Integer(i),
+    }
+}
+
+// An amqqueue record, as stored in the metadata store
+fn queue_record(i: i32) -> ErlTerm {
+    let pid = ErlTerm::Pid(ErlPid {
+        node: Atom { name: InternedAtom::new("rabbit@hostname").unwrap() },
+        id: 1000 + i as u32,
+        serial: 0,
+        creation: 1698765432,
+    });
+    tuple(vec![
+        atom("amqqueue"),
+        tuple(vec![atom("resource"), binary("/"), atom("queue"), binary(&format!("orders.eu-west.{}", i))]),
+        atom("true"),
+        atom("false"),
+        atom("none"),
+        list(vec![
+            tuple(vec![binary("x-queue-type"), atom("longstr"), binary("quorum")]),
+            tuple(vec![binary("x-max-length"), atom("long"), integer(100_000)]),
+        ]),
+        pid,
+        list(vec![]),
+        list(vec![]),
+        list(vec![]),
+        atom("undefined"),
+        atom("undefined"),
+        list(vec![]),
+        list(vec![]),
+        atom("live"),
+        integer(0),
+        list(vec![]),
+        binary("/"),
+        list(vec![tuple(vec![atom("user"), binary("guest")])]),
+        atom("rabbit_quorum_queue"),
+        list(vec![]),
+    ])
+}
+
+fn payloads() -> Vec<(&'static str, Vec<u8>)> {
+    let queues = list((0..1000).map(queue_record).collect());
+    let big_binary = ErlTerm::Binary(vec![0xab; 1024 * 1024]);
+    let mut deep_list = list(vec![]);
+    for i in 0..500 {
+        deep_list = list(vec![integer(i), deep_list]);
+    }
+    let bigints = list(
+        (0..1000)
+            .map(|i| ErlTerm::BigInteger(BigInt::from(u64::MAX).pow(1 + i % 8) * (i + 1)))
+            .collect(),
+    );
+    let small_tuples = list((0..10_000).map(|i| tuple(vec![integer(i), binary("a")])).collect());
+
+    return vec![
+        ("queue_records", queues),
+        ("big_binary", big_binary),
+        ("deep_list", deep_list),
+        ("bigints", bigints),
+        ("small_tuples", small_tuples),
+    ]
+    .into_iter()
+    .map(|(name, term)| {
+        let mut bytes = Vec::new();
+        Encoder::new(Box::new(&mut bytes)).with_max_depth(MAX_DEPTH).encode(&term).unwrap();
+        (name, bytes)
+    })
+    .collect();
+}
+
+fn decoding(c: &mut Criterion) {
+    for (name, bytes) in payloads() {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("reader", bytes.len()), &bytes, |b, bytes| {
+            b.iter(|| Decoder::new(Box::new(&bytes[..])).with_max_depth(MAX_DEPTH).decode().unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("slice", bytes.len()), &bytes, |b, bytes| {
+            b.iter(|| SliceDecoder::new(black_box(bytes)).with_max_depth(MAX_DEPTH).decode().unwrap())
+        });
+        #[cfg(feature = "arena")]
+        group.bench_with_input(BenchmarkId::new("arena", bytes.len()), &bytes, |b, bytes| {
+            b.iter(|| {
+                let arena = Bump::new();
+                black_box(decode_in_with_max_depth(&arena, black_box(bytes), MAX_DEPTH).unwrap());
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("validate", bytes.len()), &bytes, |b, bytes| {
+            b.iter(|| Decoder::new(Box::new(black_box(&bytes[..]))).with_max_depth(MAX_DEPTH).skip_term().unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, decoding);
+criterion_main!(benches);
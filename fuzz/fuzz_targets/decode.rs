@@ -0,0 +1,13 @@
+// Decoder must never panic, and whatever it decodes must encode
+// and decode back to the same term
+#![no_main]
+
+use erl_etf::{Decoder, ErlTerm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(term) = Decoder::new(Box::new(data)).decode() {
+        let bytes = term.to_bytes().expect("decoded terms can be encoded");
+        assert_eq!(term, ErlTerm::from_bytes(&bytes).expect("encoded terms can be decoded"));
+    }
+});
@@ -0,0 +1,15 @@
+// The slice and arena decoders must agree with Decoder
+#![no_main]
+
+use erl_etf::{decode_in, Bump, Decoder, ErlTerm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let from_reader = Decoder::new(Box::new(data)).decode().ok();
+    let from_slice = ErlTerm::from_bytes(data).ok();
+    assert_eq!(from_reader, from_slice);
+
+    let arena = Bump::new();
+    let from_arena = decode_in(&arena, data).ok().map(|term| term.to_term());
+    assert_eq!(from_reader, from_arena);
+});
@@ -0,0 +1,16 @@
+// Validation, lazy decoding and annotated dumps must never panic
+#![no_main]
+
+use erl_etf::{annotate, validate, LazyTerm};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = validate(data);
+    if let Ok(term) = LazyTerm::new(data) {
+        for element in term.elements() {
+            let _ = element.decode();
+        }
+        let _ = term.list_tail();
+    }
+    let _ = annotate(data).to_string();
+});
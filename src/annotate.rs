@@ -0,0 +1,423 @@
+// Annotated hex dumps of external term format data, for finding out
+// why (and where) a payload is rejected.
+//
+// Every line starts with the offset, followed by the bytes of a tag and
+// its header fields and a description, nested terms are indented:
+//
+// 0000 83  version 131
+// 0001 68 02  SMALL_TUPLE_EXT arity=2
+// 0003   77 02  SMALL_ATOM_UTF8_EXT len=2
+// 0005     6f 6b  |ok|
+// 0007   6d 00000005  BINARY_EXT len=5
+// 000c     68 65 6c 6c  |hell|
+// 0010     [..]  !! unexpected end of input: BINARY_EXT needs 5 bytes, 4 left
+//
+// The walk stops at the first malformed byte, which is shown in square brackets
+// ([..] past the end of input). Compressed terms are not inflated.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::fmt;
+
+use byteorder::{BigEndian, ByteOrder};
+use num::bigint::{BigInt, Sign};
+
+use crate::constants;
+use crate::DEFAULT_MAX_NESTING_DEPTH;
+
+const BYTES_PER_LINE: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AnnotatedLine {
+    pub offset: usize,
+    pub depth: usize,
+    pub hex: String,
+    pub note: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AnnotationFailure {
+    pub offset: usize,
+    pub depth: usize,
+    // None when the input ended prematurely
+    pub byte: Option<u8>,
+    pub reason: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Annotation {
+    pub lines: Vec<AnnotatedLine>,
+    pub failure: Option<AnnotationFailure>,
+}
+
+// Annotates one or more concatenated terms
+pub fn annotate(bytes: &[u8]) -> Annotation {
+    let mut annotator = Annotator { bytes, pos: 0, depth: 0, lines: Vec::new() };
+    let mut failure = None;
+    loop {
+        annotator.depth = 0;
+        if let Err(f) = annotator.versioned_term() {
+            failure = Some(f);
+            break;
+        }
+        if annotator.pos >= bytes.len() {
+            break;
+        }
+    }
+    return Annotation { lines: annotator.lines, failure };
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            let indent = 2 * line.depth;
+            writeln!(f, "{:04x} {:indent$}{}  {}", line.offset, "", line.hex, line.note)?;
+        }
+        if let Some(failure) = &self.failure {
+            let indent = 2 * failure.depth;
+            let byte = match failure.byte {
+                Some(b) => format!("{:02x}", b),
+                None => "..".to_string(),
+            };
+            writeln!(f, "{:04x} {:indent$}[{}]  !! {}", failure.offset, "", byte, failure.reason)?;
+        }
+        Ok(())
+    }
+}
+
+type Step = Result<(), AnnotationFailure>;
+
+struct Annotator<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    depth: usize,
+    lines: Vec<AnnotatedLine>,
+}
+
+impl<'a> Annotator<'a> {
+    fn versioned_term(&mut self) -> Step {
+        let start = self.pos;
+        let version = self.take(1, "version")?[0];
+        if version != constants::TERM_FORMAT_VERSION {
+            return Err(self.fail(start, format!("unsupported version {}", version)));
+        }
+        self.line(start, &[&[version]], format!("version {}", version));
+        return self.term();
+    }
+
+    fn term(&mut self) -> Step {
+        let start = self.pos;
+        let tag = self.take(1, "tag")?[0];
+        let name = match constants::tag_name(tag) {
+            Some(name) => name,
+            None => return Err(self.fail(start, format!("unrecognized tag {}", tag))),
+        };
+
+        match tag {
+            constants::ATOM_EXT | constants::ATOM_UTF8_EXT => {
+                let length = self.take(2, name)?;
+                let n = BigEndian::read_u16(length) as usize;
+                self.line(start, &[&[tag], length], format!("{} len={}", name, n));
+                self.atom_text(tag, n, name)
+            }
+            constants::SMALL_ATOM_UTF8_EXT => {
+                let length = self.take(1, name)?;
+                let n = length[0] as usize;
+                self.line(start, &[&[tag], length], format!("{} len={}", name, n));
+                self.atom_text(tag, n, name)
+            }
+            constants::SMALL_INTEGER_EXT => {
+                let value = self.take(1, name)?;
+                self.line(start, &[&[tag], value], format!("{} {}", name, value[0]));
+                Ok(())
+            }
+            constants::INTEGER_EXT => {
+                let value = self.take(4, name)?;
+                self.line(start, &[&[tag], value], format!("{} {}", name, BigEndian::read_i32(value)));
+                Ok(())
+            }
+            constants::NEW_FLOAT_EXT => {
+                let value = self.take(8, name)?;
+                self.line(start, &[&[tag], value], format!("{} {:?}", name, BigEndian::read_f64(value)));
+                Ok(())
+            }
+            constants::SMALL_BIG_EXT | constants::LARGE_BIG_EXT => {
+                let length = self.take(if tag == constants::SMALL_BIG_EXT { 1 } else { 4 }, name)?;
+                let n = if length.len() == 1 { length[0] as usize } else { BigEndian::read_u32(length) as usize };
+                let sign_offset = self.pos;
+                let sign_byte = self.take(1, name)?;
+                let sign = match sign_byte[0] {
+                    0 => Sign::Plus,
+                    1 => Sign::Minus,
+                    s => return Err(self.fail(sign_offset, format!("sign must be either 0 or 1, given: {}", s))),
+                };
+                self.line(start, &[&[tag], length, sign_byte], format!("{} n={} sign={}", name, n, sign_byte[0]));
+                let digits = self.payload(n, name)?;
+                let value = BigInt::from_bytes_le(sign, digits);
+                self.describe_last_payload(digits.len(), value.to_string());
+                Ok(())
+            }
+            constants::BINARY_EXT => {
+                let length = self.take(4, name)?;
+                let n = BigEndian::read_u32(length) as usize;
+                self.line(start, &[&[tag], length], format!("{} len={}", name, n));
+                self.payload(n, name).map(|_| ())
+            }
+            constants::BIT_BINARY_EXT => {
+                let length = self.take(4, name)?;
+                let n = BigEndian::read_u32(length) as usize;
+                let bits_offset = self.pos;
+                let bits = self.take(1, name)?;
+                let tail_len = bits[0];
+                if tail_len > 8 || (n == 0) != (tail_len == 0) {
+                    return Err(self.fail(bits_offset, format!("invalid bit binary tail length: {}", tail_len)));
+                }
+                self.line(start, &[&[tag], length, bits], format!("{} len={} bits={}", name, n, tail_len));
+                self.payload(n, name).map(|_| ())
+            }
+            constants::NEW_PID_EXT => {
+                self.line(start, &[&[tag]], name.to_string());
+                self.nested(|a| {
+                    a.node()?;
+                    a.fields(&[("id", 4), ("serial", 4), ("creation", 4)], name)
+                })
+            }
+            constants::NEW_PORT_EXT => {
+                self.line(start, &[&[tag]], name.to_string());
+                self.nested(|a| {
+                    a.node()?;
+                    a.fields(&[("id", 4), ("creation", 4)], name)
+                })
+            }
+            constants::V4_PORT_EXT => {
+                self.line(start, &[&[tag]], name.to_string());
+                self.nested(|a| {
+                    a.node()?;
+                    a.fields(&[("id", 8), ("creation", 4)], name)
+                })
+            }
+            constants::SMALL_TUPLE_EXT | constants::LARGE_TUPLE_EXT => {
+                let arity = self.take(if tag == constants::SMALL_TUPLE_EXT { 1 } else { 4 }, name)?;
+                let n = if arity.len() == 1 { arity[0] as usize } else { BigEndian::read_u32(arity) as usize };
+                self.line(start, &[&[tag], arity], format!("{} arity={}", name, n));
+                self.nested(|a| a.terms(n))
+            }
+            constants::NIL_EXT => {
+                self.line(start, &[&[tag]], name.to_string());
+                Ok(())
+            }
+            constants::LIST_EXT => {
+                let length = self.take(4, name)?;
+                let n = BigEndian::read_u32(length) as usize;
+                self.line(start, &[&[tag], length], format!("{} len={}", name, n));
+                // the tail follows the elements
+                self.nested(|a| a.terms(n + 1))
+            }
+            constants::STRING_EXT => {
+                let length = self.take(2, name)?;
+                let n = BigEndian::read_u16(length) as usize;
+                self.line(start, &[&[tag], length], format!("{} len={}", name, n));
+                self.payload(n, name).map(|_| ())
+            }
+            constants::MAP_EXT => {
+                let arity = self.take(4, name)?;
+                let n = BigEndian::read_u32(arity) as usize;
+                self.line(start, &[&[tag], arity], format!("{} arity={}", name, n));
+                // keys and values alternate
+                self.nested(|a| a.terms(2 * n))
+            }
+            constants::NEWER_REFERENCE_EXT => {
+                let length = self.take(2, name)?;
+                let n = BigEndian::read_u16(length) as usize;
+                self.line(start, &[&[tag], length], format!("{} len={}", name, n));
+                self.nested(|a| {
+                    a.node()?;
+                    a.fields(&[("creation", 4)], name)?;
+                    a.fields(&vec![("id", 4); n], name)
+                })
+            }
+            constants::FUN_EXPORT_EXT => {
+                self.line(start, &[&[tag]], name.to_string());
+                self.nested(|a| {
+                    a.term_of(ATOMS, "module")?;
+                    a.term_of(ATOMS, "function name")?;
+                    a.term_of(&[constants::SMALL_INTEGER_EXT], "arity")
+                })
+            }
+            constants::NEW_FUN_EXT => {
+                let size_offset = self.pos;
+                let size = self.take(4, name)?;
+                let declared = BigEndian::read_u32(size) as usize;
+                self.line(start, &[&[tag], size], format!("{} size={}", name, declared));
+                self.nested(|a| {
+                    let fields_offset = a.pos;
+                    let fields = a.take(1 + 16 + 4 + 4, name)?;
+                    let free_variable_count = BigEndian::read_u32(&fields[21..]) as usize;
+                    a.line(
+                        fields_offset,
+                        &[&fields[..1], &fields[1..17], &fields[17..21], &fields[21..]],
+                        format!("arity={} index={} num_free={}", fields[0], BigEndian::read_u32(&fields[17..21]), free_variable_count),
+                    );
+                    a.term_of(ATOMS, "module")?;
+                    let integers = &[constants::SMALL_INTEGER_EXT, constants::INTEGER_EXT];
+                    a.term_of(integers, "old index")?;
+                    a.term_of(integers, "old uniq")?;
+                    a.term_of(&[constants::NEW_PID_EXT], "creator pid")?;
+                    a.terms(free_variable_count)?;
+                    let actual = a.pos - size_offset;
+                    if actual != declared {
+                        return Err(a.fail(size_offset, format!("fun size mismatch: declared {}, actual {}", declared, actual)));
+                    }
+                    Ok(())
+                })
+            }
+            constants::COMPRESSED => {
+                let size = self.take(4, name)?;
+                self.line(start, &[&[tag], size], format!("{} size={}", name, BigEndian::read_u32(size)));
+                Err(self.fail(self.pos, "compressed data is not annotated".to_string()))
+            }
+            _ => Err(self.fail(start, format!("{} is not supported", name))),
+        }
+    }
+
+    fn terms(&mut self, n: usize) -> Step {
+        for _ in 0..n {
+            self.term()?;
+        }
+        return Ok(());
+    }
+
+    fn term_of(&mut self, tags: &[u8], what: &str) -> Step {
+        let start = self.pos;
+        let tag = self.peek(what)?;
+        if !tags.contains(&tag) {
+            let name = constants::tag_name(tag).map_or(tag.to_string(), |n| n.to_string());
+            return Err(self.fail(start, format!("{} cannot be {}", what, name)));
+        }
+        return self.term();
+    }
+
+    fn node(&mut self) -> Step {
+        return self.term_of(ATOMS, "node name");
+    }
+
+    fn atom_text(&mut self, tag: u8, n: usize, name: &str) -> Step {
+        let text_offset = self.pos;
+        let text = self.payload(n, name)?;
+        // the decoders' checks
+        if tag == constants::ATOM_EXT {
+            let (_, _, had_errors) = encoding_rs::WINDOWS_1252.decode(text);
+            if had_errors {
+                return Err(self.fail(text_offset, "invalid Latin1 atom".to_string()));
+            }
+        } else if core::str::from_utf8(text).is_err() {
+            return Err(self.fail(text_offset, "invalid UTF-8 atom".to_string()));
+        }
+        return Ok(());
+    }
+
+    fn fields(&mut self, fields: &[(&str, usize)], name: &str) -> Step {
+        let start = self.pos;
+        let size = fields.iter().map(|(_, n)| n).sum();
+        let bytes = self.take(size, name)?;
+        let mut groups = Vec::new();
+        let mut notes = Vec::new();
+        let mut i = 0;
+        for (field, n) in fields {
+            let value = &bytes[i..i + n];
+            let number = if *n == 8 { BigEndian::read_u64(value) } else { BigEndian::read_u32(value) as u64 };
+            groups.push(value);
+            notes.push(format!("{}={}", field, number));
+            i += n;
+        }
+        self.line(start, &groups, notes.join(" "));
+        return Ok(());
+    }
+
+    // Raw bytes of atoms, binaries, strings and big integers
+    fn payload(&mut self, n: usize, name: &str) -> Result<&'a [u8], AnnotationFailure> {
+        let start = self.pos;
+        let available = self.bytes.len() - start;
+        // annotate whatever is there before reporting a truncated payload
+        let bytes = &self.bytes[start..start + n.min(available)];
+        self.depth += 1;
+        for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let preview: String = chunk.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }).collect();
+            self.lines.push(AnnotatedLine {
+                offset: start + i * BYTES_PER_LINE,
+                depth: self.depth,
+                hex: hex.join(" "),
+                note: format!("|{}|", preview),
+            });
+        }
+        let taken = self.take(n, name);
+        self.depth -= 1;
+        return taken;
+    }
+
+    fn describe_last_payload(&mut self, n: usize, note: String) {
+        if n == 0 {
+            return;
+        }
+        let lines = n.div_ceil(BYTES_PER_LINE);
+        let first = self.lines.len() - lines;
+        self.lines[first].note = note;
+        for line in &mut self.lines[first + 1..] {
+            line.note.clear();
+        }
+    }
+
+    fn nested<F: FnOnce(&mut Self) -> Step>(&mut self, f: F) -> Step {
+        // the top level term is at depth 0
+        if self.depth + 1 >= DEFAULT_MAX_NESTING_DEPTH {
+            return Err(self.fail(self.pos, format!("nested more than {} levels deep", DEFAULT_MAX_NESTING_DEPTH)));
+        }
+        self.depth += 1;
+        f(self)?;
+        self.depth -= 1;
+        return Ok(());
+    }
+
+    fn peek(&self, what: &str) -> Result<u8, AnnotationFailure> {
+        match self.bytes.get(self.pos) {
+            Some(b) => Ok(*b),
+            None => Err(self.truncated(what, 1)),
+        }
+    }
+
+    fn take(&mut self, n: usize, what: &str) -> Result<&'a [u8], AnnotationFailure> {
+        if self.bytes.len() - self.pos < n {
+            return Err(self.truncated(what, n));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        return Ok(bytes);
+    }
+
+    fn truncated(&self, what: &str, n: usize) -> AnnotationFailure {
+        let left = self.bytes.len() - self.pos;
+        let reason = format!("unexpected end of input: {} needs {} bytes, {} left", what, n, left);
+        return self.fail(self.bytes.len(), reason);
+    }
+
+    fn line(&mut self, offset: usize, groups: &[&[u8]], note: String) {
+        let hex: Vec<String> = groups
+            .iter()
+            .map(|g| g.iter().map(|b| format!("{:02x}", b)).collect())
+            .collect();
+        self.lines.push(AnnotatedLine { offset, depth: self.depth, hex: hex.join(" "), note });
+    }
+
+    fn fail(&self, offset: usize, reason: String) -> AnnotationFailure {
+        let byte = self.bytes.get(offset).copied();
+        return AnnotationFailure { offset, depth: self.depth, byte, reason };
+    }
+}
+
+const ATOMS: &[u8] = &[
+    constants::ATOM_EXT,
+    constants::ATOM_UTF8_EXT,
+    constants::SMALL_ATOM_UTF8_EXT,
+];
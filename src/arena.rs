@@ -0,0 +1,353 @@
+// Decoding into a bump arena (requires the arena feature).
+//
+// ArenaTerm mirrors ErlTerm, but tuples, lists, binaries and everything
+// else that ErlTerm keeps in separate heap allocations live in the arena
+// passed to decode_in, so decoding a large term costs a handful of
+// allocations, and dropping it is free: the memory is reclaimed when the
+// arena is reset or dropped. Big integers keep their digits and are
+// converted to BigInt on demand because BigInt owns a heap allocation.
+//
+// ArenaTerm::to_term converts a term (or any of its subterms) into an ErlTerm.
+
+use bumpalo::collections::Vec as BumpVec;
+use bumpalo::Bump;
+use byteorder::{BigEndian, ByteOrder};
+use num::bigint::{BigInt, Sign};
+
+use crate::*;
+use crate::constants;
+use crate::cursor::{latin1_atom, utf8_atom, Cursor};
+use crate::numerical::to_sign;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArenaTerm<'a> {
+    Atom(InternedAtom),
+    SmallInteger(u8),
+    Integer(i32),
+    // sign and little endian digits, as in SMALL_BIG_EXT
+    BigInteger(Sign, &'a [u8]),
+    Float(f64),
+    BitBinary(&'a [u8], u8),
+    Binary(&'a [u8]),
+    Pid(&'a ErlPid),
+    V3Port(&'a ErlV3Port),
+    V4Port(&'a ErlV4Port),
+    Tuple(&'a [ArenaTerm<'a>]),
+    List(&'a [ArenaTerm<'a>]),
+    ImproperList(&'a [ArenaTerm<'a>], &'a ArenaTerm<'a>),
+    Ref(InternedAtom, u32, &'a [u32]),
+    ExternalFun(&'a ExternalFun),
+    InternalFun(&'a ArenaInternalFun<'a>),
+    Map(&'a [(ArenaTerm<'a>, ArenaTerm<'a>)]),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ArenaInternalFun<'a> {
+    pub arity: u8,
+    pub uniq_beam_md5: [u8; 16],
+    pub index: u32,
+    pub module: InternedAtom,
+    pub old_index: i32,
+    pub old_uniq_hash: i32,
+    pub creator_pid: &'a ErlPid,
+    pub free_vars: &'a [ArenaTerm<'a>],
+}
+
+// Decodes a term with the version byte, allocating it in the arena
+pub fn decode_in<'a>(arena: &'a Bump, bytes: &[u8]) -> Result<ArenaTerm<'a>, DecodingError> {
+    return decode_in_with_max_depth(arena, bytes, DEFAULT_MAX_NESTING_DEPTH);
+}
+
+// Like decode_in, but rejects terms nested more than max_depth levels deep
+pub fn decode_in_with_max_depth<'a>(
+    arena: &'a Bump,
+    bytes: &[u8],
+    max_depth: usize,
+) -> Result<ArenaTerm<'a>, DecodingError> {
+    let mut decoder = ArenaDecoder { arena, cursor: Cursor::new(bytes), depth: 0, max_depth };
+    let version = decoder.cursor.read_u8()?;
+    if version != constants::TERM_FORMAT_VERSION {
+        return Err(DecodingError::UnsupportedVersion { version });
+    }
+    return decoder.read_next_term();
+}
+
+impl<'a> ArenaTerm<'a> {
+    pub fn to_term(&self) -> ErlTerm {
+        match *self {
+            ArenaTerm::Atom(name) => ErlTerm::Atom(name),
+            ArenaTerm::SmallInteger(i) => ErlTerm::SmallInteger(i),
+            ArenaTerm::Integer(i) => ErlTerm::Integer(i),
+            ArenaTerm::BigInteger(sign, digits) => ErlTerm::BigInteger(BigInt::from_bytes_le(sign, digits)),
+            ArenaTerm::Float(f) => ErlTerm::Float(OrderedFloat(f)),
+            ArenaTerm::BitBinary(bytes, bits) => ErlTerm::BitBinary(bytes.to_vec(), bits),
+            ArenaTerm::Binary(bytes) => ErlTerm::Binary(bytes.to_vec()),
+            ArenaTerm::Pid(pid) => ErlTerm::Pid(pid.clone()),
+            ArenaTerm::V3Port(port) => ErlTerm::V3Port(port.clone()),
+            ArenaTerm::V4Port(port) => ErlTerm::V4Port(port.clone()),
+            ArenaTerm::Tuple(elements) => ErlTerm::Tuple(Tuple { elements: to_terms(elements) }),
+            ArenaTerm::List(elements) => ErlTerm::List(List { elements: to_terms(elements) }),
+            ArenaTerm::ImproperList(elements, tail) => ErlTerm::ImproperList(ImproperList {
+                elements: to_terms(elements),
+                tail: Box::new(tail.to_term()),
+            }),
+            ArenaTerm::Ref(node, creation, id) => ErlTerm::Ref(Ref {
+                node: Atom { name: node },
+                creation,
+                id: id.to_vec(),
+            }),
+            ArenaTerm::ExternalFun(fun) => ErlTerm::ExternalFun(fun.clone()),
+            ArenaTerm::InternalFun(fun) => ErlTerm::InternalFun(InternalFun {
+                arity: fun.arity,
+                uniq_beam_md5: fun.uniq_beam_md5,
+                index: fun.index,
+                free_variable_count: fun.free_vars.len() as u32,
+                module: Atom { name: fun.module },
+                old_index: fun.old_index,
+                old_uniq_hash: fun.old_uniq_hash,
+                creator_pid: fun.creator_pid.clone(),
+                free_vars: to_terms(fun.free_vars),
+            }),
+            ArenaTerm::Map(entries) => ErlTerm::Map(Map {
+                entries: entries.iter().map(|(k, v)| (k.to_term(), v.to_term())).collect(),
+            }),
+        }
+    }
+}
+
+impl<'a> From<&ArenaTerm<'a>> for ErlTerm {
+    fn from(term: &ArenaTerm<'a>) -> Self {
+        return term.to_term();
+    }
+}
+
+fn to_terms(elements: &[ArenaTerm<'_>]) -> Vec<ErlTerm> {
+    return elements.iter().map(ArenaTerm::to_term).collect();
+}
+
+struct ArenaDecoder<'a, 'b> {
+    arena: &'a Bump,
+    cursor: Cursor<'b>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a, 'b> ArenaDecoder<'a, 'b> {
+    fn read_next_term(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        if self.depth >= self.max_depth {
+            return Err(DecodingError::NestingTooDeep { limit: self.max_depth });
+        }
+        self.depth += 1;
+        let term = self.read_term();
+        self.depth -= 1;
+        return term;
+    }
+
+    fn read_term(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let tag = self.cursor.read_u8()?;
+        match tag {
+            constants::ATOM_EXT => {
+                let n = self.cursor.read_u16()? as usize;
+                let bytes = self.cursor.take(n)?;
+                Ok(ArenaTerm::Atom(latin1_atom(bytes)?))
+            }
+            constants::ATOM_UTF8_EXT => {
+                let n = self.cursor.read_u16()? as usize;
+                let bytes = self.cursor.take(n)?;
+                Ok(ArenaTerm::Atom(utf8_atom(bytes)?))
+            }
+            constants::SMALL_ATOM_UTF8_EXT => {
+                let n = self.cursor.read_u8()? as usize;
+                let bytes = self.cursor.take(n)?;
+                Ok(ArenaTerm::Atom(utf8_atom(bytes)?))
+            }
+            constants::SMALL_INTEGER_EXT => Ok(ArenaTerm::SmallInteger(self.cursor.read_u8()?)),
+            constants::INTEGER_EXT => Ok(ArenaTerm::Integer(self.cursor.read_i32()?)),
+            constants::SMALL_BIG_EXT => {
+                let n = self.cursor.read_u8()? as usize;
+                self.big_integer(n)
+            }
+            constants::LARGE_BIG_EXT => {
+                let n = self.cursor.read_u32()? as usize;
+                self.big_integer(n)
+            }
+            constants::NEW_FLOAT_EXT => Ok(ArenaTerm::Float(self.cursor.read_f64()?)),
+            constants::BINARY_EXT => {
+                let n = self.cursor.read_u32()? as usize;
+                let bytes = self.cursor.take(n)?;
+                Ok(ArenaTerm::Binary(self.arena.alloc_slice_copy(bytes)))
+            }
+            constants::BIT_BINARY_EXT => self.bit_binary(),
+            constants::NEW_PID_EXT => {
+                let pid = self.pid()?;
+                Ok(ArenaTerm::Pid(self.arena.alloc(pid)))
+            }
+            constants::NEW_PORT_EXT => self.v3_port(),
+            constants::V4_PORT_EXT => self.v4_port(),
+            constants::SMALL_TUPLE_EXT => {
+                let n = self.cursor.read_u8()? as usize;
+                Ok(ArenaTerm::Tuple(self.terms(n)?))
+            }
+            constants::LARGE_TUPLE_EXT => {
+                let n = self.cursor.read_u32()? as usize;
+                Ok(ArenaTerm::Tuple(self.terms(n)?))
+            }
+            constants::NIL_EXT => Ok(ArenaTerm::List(&[])),
+            constants::STRING_EXT => self.string(),
+            constants::LIST_EXT => self.list(),
+            constants::MAP_EXT => self.map(),
+            constants::NEWER_REFERENCE_EXT => self.reference(),
+            constants::FUN_EXPORT_EXT => self.external_fun(),
+            constants::NEW_FUN_EXT => self.internal_fun(),
+            _ => Err(DecodingError::UnrecognizedTag { tag }),
+        }
+    }
+
+    // Anything beyond the simplest terms is decoded outside of read_term,
+    // to keep its stack frame (which every level of nesting pays for) small
+
+    fn bit_binary(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let n = self.cursor.read_u32()? as usize;
+        let tail_len = self.cursor.read_u8()?;
+        if tail_len > 8 || (n == 0) != (tail_len == 0) {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid bit binary tail length: {}", tail_len),
+            );
+            return Err(DecodingError::DecodingFailure(e));
+        }
+        let bytes = self.arena.alloc_slice_copy(self.cursor.take(n)?);
+        // keep the trailing bits right-aligned, like Decoder does
+        if let Some(last) = bytes.last_mut() {
+            *last >>= 8 - tail_len;
+        }
+        return Ok(ArenaTerm::BitBinary(bytes, tail_len));
+    }
+
+    fn v3_port(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let node = self.node()?;
+        let id = self.cursor.read_u32()?;
+        let creation = self.cursor.read_u32()?;
+        return Ok(ArenaTerm::V3Port(self.arena.alloc(ErlV3Port { node, id, creation })));
+    }
+
+    fn v4_port(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let node = self.node()?;
+        let id = self.cursor.read_u64()?;
+        let creation = self.cursor.read_u32()?;
+        return Ok(ArenaTerm::V4Port(self.arena.alloc(ErlV4Port { node, id, creation })));
+    }
+
+    fn list(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let n = self.cursor.read_u32()? as usize;
+        let elements = self.terms(n)?;
+        match self.read_next_term()? {
+            ArenaTerm::List([]) => Ok(ArenaTerm::List(elements)),
+            tail => Ok(ArenaTerm::ImproperList(elements, self.arena.alloc(tail))),
+        }
+    }
+
+    // A list of small integers, what term_to_binary produces for strings
+    fn string(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let n = self.cursor.read_u16()? as usize;
+        let bytes = self.cursor.take(n)?;
+        let elements = self.arena.alloc_slice_fill_iter(bytes.iter().map(|b| ArenaTerm::SmallInteger(*b)));
+        return Ok(ArenaTerm::List(elements));
+    }
+
+    fn map(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let n = self.cursor.read_u32()? as usize;
+        // every entry takes at least two bytes
+        let mut entries = BumpVec::with_capacity_in(n.min(self.cursor.remaining() / 2), self.arena);
+        for _ in 0..n {
+            let key = self.read_next_term()?;
+            entries.push((key, self.read_next_term()?));
+        }
+        return Ok(ArenaTerm::Map(entries.into_bump_slice()));
+    }
+
+    fn reference(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let n = self.cursor.read_u16()? as usize;
+        let node = self.node()?.name;
+        let creation = self.cursor.read_u32()?;
+        let bytes = self.cursor.take(4 * n)?;
+        let id = self.arena.alloc_slice_fill_iter(bytes.chunks(4).map(BigEndian::read_u32));
+        return Ok(ArenaTerm::Ref(node, creation, id));
+    }
+
+    fn external_fun(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let module = self.node()?;
+        let function_name = self.node()?;
+        let arity = match self.read_next_term()? {
+            ArenaTerm::SmallInteger(arity) => arity,
+            _ => return Err(DecodingError::CompoundTypeDecodingFailure()),
+        };
+        return Ok(ArenaTerm::ExternalFun(self.arena.alloc(ExternalFun { module, function_name, arity })));
+    }
+
+    fn internal_fun(&mut self) -> Result<ArenaTerm<'a>, DecodingError> {
+        let _size = self.cursor.read_u32()?;
+        let arity = self.cursor.read_u8()?;
+        let mut uniq_beam_md5 = [0; 16];
+        uniq_beam_md5.copy_from_slice(self.cursor.take(16)?);
+        let index = self.cursor.read_u32()?;
+        let free_variable_count = self.cursor.read_u32()? as usize;
+        let module = self.node()?.name;
+        let old_index = self.small_integer()?;
+        let old_uniq_hash = self.small_integer()?;
+        if self.cursor.read_u8()? != constants::NEW_PID_EXT {
+            return Err(DecodingError::CompoundTypeDecodingFailure());
+        }
+        let creator_pid = self.arena.alloc(self.pid()?);
+        let free_vars = self.terms(free_variable_count)?;
+        return Ok(ArenaTerm::InternalFun(self.arena.alloc(ArenaInternalFun {
+            arity,
+            uniq_beam_md5,
+            index,
+            module,
+            old_index,
+            old_uniq_hash,
+            creator_pid,
+            free_vars,
+        })));
+    }
+
+    fn terms(&mut self, n: usize) -> Result<&'a [ArenaTerm<'a>], DecodingError> {
+        // every term takes at least one byte, a bogus length cannot
+        // make us allocate more than the input size
+        let mut elements = BumpVec::with_capacity_in(n.min(self.cursor.remaining()), self.arena);
+        for _ in 0..n {
+            elements.push(self.read_next_term()?);
+        }
+        return Ok(elements.into_bump_slice());
+    }
+
+    fn big_integer(&mut self, n: usize) -> Result<ArenaTerm<'a>, DecodingError> {
+        let sign = to_sign(self.cursor.read_u8()?)?;
+        let digits = self.cursor.take(n)?;
+        return Ok(ArenaTerm::BigInteger(sign, self.arena.alloc_slice_copy(digits)));
+    }
+
+    fn pid(&mut self) -> Result<ErlPid, DecodingError> {
+        let node = self.node()?;
+        let id = self.cursor.read_u32()?;
+        let serial = self.cursor.read_u32()?;
+        let creation = self.cursor.read_u32()?;
+        return Ok(ErlPid { node, id, serial, creation });
+    }
+
+    fn node(&mut self) -> Result<Atom, DecodingError> {
+        match self.read_next_term()? {
+            ArenaTerm::Atom(name) => Ok(Atom { name }),
+            _ => Err(DecodingError::CompoundTypeDecodingFailure()),
+        }
+    }
+
+    fn small_integer(&mut self) -> Result<i32, DecodingError> {
+        match self.read_next_term()? {
+            ArenaTerm::SmallInteger(i) => Ok(i as i32),
+            ArenaTerm::Integer(i) => Ok(i),
+            _ => Err(DecodingError::CompoundTypeDecodingFailure()),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+// A global atom table, similar to the one in the BEAM.
+//
+// Every distinct atom name is stored once and never freed, so interned
+// atoms are plain pointers: cloning is a copy, equality and hashing
+// compare and hash the pointer. As with the BEAM, the number of atoms
+// is limited (ATOM_TABLE_DEFAULT_LIMIT by default) because decoding
+// untrusted input could otherwise grow the table without bounds.
+//
+// Without std the table is a BTreeSet behind a spinning lock.
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use std::collections::HashSet as AtomSet;
+#[cfg(feature = "std")]
+use std::sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeSet as AtomSet;
+#[cfg(not(feature = "std"))]
+use spin::{Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use thiserror::Error;
+
+// The BEAM default, see erl +t
+pub const ATOM_TABLE_DEFAULT_LIMIT: usize = 1_048_576;
+
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+#[error("atom table is full ({limit} atoms)")]
+pub struct AtomTableFull {
+    pub limit: usize,
+}
+
+struct AtomTable {
+    atoms: AtomSet<&'static str>,
+    limit: usize,
+}
+
+fn new_table() -> RwLock<AtomTable> {
+    return RwLock::new(AtomTable { atoms: AtomSet::new(), limit: ATOM_TABLE_DEFAULT_LIMIT });
+}
+
+#[cfg(feature = "std")]
+fn table() -> &'static RwLock<AtomTable> {
+    static TABLE: OnceLock<RwLock<AtomTable>> = OnceLock::new();
+    return TABLE.get_or_init(new_table);
+}
+
+#[cfg(not(feature = "std"))]
+fn table() -> &'static RwLock<AtomTable> {
+    static TABLE: Once<RwLock<AtomTable>> = Once::new();
+    return TABLE.call_once(new_table);
+}
+
+#[cfg(feature = "std")]
+fn read_table() -> RwLockReadGuard<'static, AtomTable> {
+    return table().read().unwrap();
+}
+
+#[cfg(feature = "std")]
+fn write_table() -> RwLockWriteGuard<'static, AtomTable> {
+    return table().write().unwrap();
+}
+
+#[cfg(not(feature = "std"))]
+fn read_table() -> RwLockReadGuard<'static, AtomTable> {
+    return table().read();
+}
+
+#[cfg(not(feature = "std"))]
+fn write_table() -> RwLockWriteGuard<'static, AtomTable> {
+    return table().write();
+}
+
+// Number of atoms interned so far
+pub fn atom_count() -> usize {
+    return read_table().atoms.len();
+}
+
+// Changes the maximum number of atoms, existing atoms are kept
+// even when there are more of them than the new limit
+pub fn set_atom_limit(limit: usize) {
+    write_table().limit = limit;
+}
+
+#[derive(Clone, Copy)]
+pub struct InternedAtom(&'static str);
+
+impl InternedAtom {
+    pub fn new(name: &str) -> Result<Self, AtomTableFull> {
+        if let Some(interned) = read_table().atoms.get(name) {
+            return Ok(InternedAtom(interned));
+        }
+
+        let mut table = write_table();
+        // another thread could have added it in the meantime
+        if let Some(interned) = table.atoms.get(name) {
+            return Ok(InternedAtom(interned));
+        }
+        if table.atoms.len() >= table.limit {
+            return Err(AtomTableFull { limit: table.limit });
+        }
+        let interned: &'static str = Box::leak(name.into());
+        table.atoms.insert(interned);
+        return Ok(InternedAtom(interned));
+    }
+
+    // For the atoms the library itself sends, such as 'DOWN' or bert. There is
+    // a fixed number of them, so they are interned even when the table is full
+    #[cfg(any(feature = "bert", feature = "distribution"))]
+    pub(crate) fn fixed(name: &'static str) -> Self {
+        if let Some(interned) = read_table().atoms.get(name) {
+            return InternedAtom(interned);
+        }
+        let mut table = write_table();
+        if let Some(interned) = table.atoms.get(name) {
+            return InternedAtom(interned);
+        }
+        table.atoms.insert(name);
+        return InternedAtom(name);
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        return self.0;
+    }
+}
+
+impl TryFrom<&str> for InternedAtom {
+    type Error = AtomTableFull;
+
+    fn try_from(name: &str) -> Result<Self, AtomTableFull> {
+        return InternedAtom::new(name);
+    }
+}
+
+impl TryFrom<String> for InternedAtom {
+    type Error = AtomTableFull;
+
+    fn try_from(name: String) -> Result<Self, AtomTableFull> {
+        return InternedAtom::new(&name);
+    }
+}
+
+impl From<InternedAtom> for String {
+    fn from(atom: InternedAtom) -> Self {
+        return atom.0.to_string();
+    }
+}
+
+impl Deref for InternedAtom {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        return self.0;
+    }
+}
+
+impl AsRef<str> for InternedAtom {
+    fn as_ref(&self) -> &str {
+        return self.0;
+    }
+}
+
+// Names are unique in the table, so the pointers can be compared
+impl PartialEq for InternedAtom {
+    fn eq(&self, other: &Self) -> bool {
+        return core::ptr::eq(self.0, other.0);
+    }
+}
+
+impl Eq for InternedAtom {}
+
+impl Hash for InternedAtom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0.as_ptr() as usize).hash(state);
+    }
+}
+
+impl PartialEq<str> for InternedAtom {
+    fn eq(&self, other: &str) -> bool {
+        return self.0 == other;
+    }
+}
+
+impl PartialEq<&str> for InternedAtom {
+    fn eq(&self, other: &&str) -> bool {
+        return self.0 == *other;
+    }
+}
+
+impl PartialEq<String> for InternedAtom {
+    fn eq(&self, other: &String) -> bool {
+        return self.0 == other;
+    }
+}
+
+impl fmt::Debug for InternedAtom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl fmt::Display for InternedAtom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
@@ -0,0 +1,267 @@
+// Reading compiled Erlang modules (requires the beam feature).
+//
+// A .beam file is an IFF container: "FOR1", the size of the rest, "BEAM",
+// and then chunks of a 4 character id, a 32 bit size and the data, each
+// padded to a multiple of 4 bytes. BeamFile splits it into chunks and
+// decodes the ones that matter for tooling:
+//
+// Chunk      | contents
+// -----------+----------------------------------------------------------
+// AtU8, Atom | the atom table, the module name first
+// ExpT, ImpT | exported and imported functions, as indexes into the atoms
+// LitT       | literals, zlib-compressed external terms
+// Attr       | module attributes, an external term ([{vsn, ...}, ...])
+// CInf       | compile info, an external term ([{version, ...}, ...])
+//
+// Nothing is decoded until asked for, and other chunks (the code itself,
+// debug info) are left as bytes. See beam_lib for the Erlang side.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+use thiserror::Error;
+
+use crate::*;
+
+#[derive(Error, Debug)]
+pub enum BeamError {
+    #[error("failed to read the BEAM file")]
+    Io(#[from] io::Error),
+    #[error("not a BEAM file")]
+    NotABeamFile,
+    #[error("malformed chunk")]
+    MalformedChunk { id: String },
+    #[error("chunk is missing")]
+    MissingChunk { id: String },
+    #[error("failed to decode a term in a chunk")]
+    DecodingFailure(#[from] DecodingError),
+}
+
+pub struct BeamFile {
+    chunks: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl BeamFile {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, BeamError> {
+        return BeamFile::parse(&fs::read(path)?);
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, BeamError> {
+        let mut header = bytes;
+        let mut tag = [0; 4];
+        header.read_exact(&mut tag).map_err(|_| BeamError::NotABeamFile)?;
+        let size = header.read_u32::<BigEndian>().map_err(|_| BeamError::NotABeamFile)? as usize;
+        let mut form = [0; 4];
+        header.read_exact(&mut form).map_err(|_| BeamError::NotABeamFile)?;
+        if &tag != b"FOR1" || &form != b"BEAM" {
+            return Err(BeamError::NotABeamFile);
+        }
+        // the size counts "BEAM" and the chunks
+        let mut rest = header.get(..size.saturating_sub(4)).ok_or(BeamError::NotABeamFile)?;
+
+        let mut chunks = Vec::new();
+        while !rest.is_empty() {
+            let mut id = [0; 4];
+            rest.read_exact(&mut id).map_err(|_| BeamError::NotABeamFile)?;
+            let malformed = || BeamError::MalformedChunk { id: chunk_name(&id) };
+            let len = rest.read_u32::<BigEndian>().map_err(|_| malformed())? as usize;
+            let data = rest.get(..len).ok_or_else(malformed)?;
+            chunks.push((id, data.to_vec()));
+            let padded = len.div_ceil(4) * 4;
+            rest = rest.get(padded..).unwrap_or_default();
+        }
+        return Ok(BeamFile { chunks });
+    }
+
+    pub fn chunk_ids(&self) -> Vec<String> {
+        return self.chunks.iter().map(|(id, _)| chunk_name(id)).collect();
+    }
+
+    pub fn chunk(&self, id: &str) -> Option<&[u8]> {
+        return self.chunks.iter().find(|(chunk, _)| chunk == id.as_bytes()).map(|(_, data)| &data[..]);
+    }
+
+    // The atom table, atoms are not interned so that reading many modules
+    // does not fill up the atom table
+    pub fn atoms(&self) -> Result<Vec<String>, BeamError> {
+        if let Some(data) = self.chunk("AtU8") {
+            return read_atoms(data, "AtU8", |bytes| String::from_utf8(bytes.to_vec()).ok());
+        }
+        if let Some(data) = self.chunk("Atom") {
+            return read_atoms(data, "Atom", |bytes| Some(bytes.iter().map(|b| *b as char).collect()));
+        }
+        return Err(missing("AtU8"));
+    }
+
+    pub fn module(&self) -> Result<String, BeamError> {
+        return self.atoms()?.into_iter().next().ok_or_else(|| malformed("AtU8"));
+    }
+
+    // Exported functions as (name, arity)
+    pub fn exports(&self) -> Result<Vec<(String, u32)>, BeamError> {
+        return self.functions("ExpT");
+    }
+
+    // Local functions as (name, arity)
+    pub fn locals(&self) -> Result<Vec<(String, u32)>, BeamError> {
+        return self.functions("LocT");
+    }
+
+    // Called functions of other modules as (module, name, arity)
+    pub fn imports(&self) -> Result<Vec<(String, String, u32)>, BeamError> {
+        let atoms = self.atoms()?;
+        let atom = |index: u32| atom_at(&atoms, index, "ImpT");
+        let mut imports = Vec::new();
+        for [module, function, arity] in self.table::<3>("ImpT")? {
+            imports.push((atom(module)?, atom(function)?, arity));
+        }
+        return Ok(imports);
+    }
+
+    // All module attributes, the -vsn and any custom ones
+    pub fn attributes(&self) -> Result<ErlTerm, BeamError> {
+        return decode(self.chunk("Attr").ok_or_else(|| missing("Attr"))?);
+    }
+
+    // The value of an attribute, the last one for attributes given more than once
+    pub fn attribute(&self, name: &str) -> Result<Option<ErlTerm>, BeamError> {
+        return Ok(property(self.attributes()?, name));
+    }
+
+    // The module version, the MD5 of the module unless set with -vsn
+    pub fn vsn(&self) -> Result<Option<ErlTerm>, BeamError> {
+        return self.attribute("vsn");
+    }
+
+    pub fn compile_info(&self) -> Result<ErlTerm, BeamError> {
+        return decode(self.chunk("CInf").ok_or_else(|| missing("CInf"))?);
+    }
+
+    // The literal table, empty for modules without literals
+    pub fn literals(&self) -> Result<Vec<ErlTerm>, BeamError> {
+        let Some(mut data) = self.chunk("LitT") else { return Ok(Vec::new()) };
+        let size = data.read_u32::<BigEndian>().map_err(|_| malformed("LitT"))?;
+        let table = if size == 0 {
+            // OTP 28 can leave the table uncompressed
+            data.to_vec()
+        } else {
+            let mut table = Vec::new();
+            ZlibDecoder::new(data).take(size as u64).read_to_end(&mut table)?;
+            if table.len() != size as usize {
+                return Err(malformed("LitT"));
+            }
+            table
+        };
+
+        let mut rest = &table[..];
+        let count = rest.read_u32::<BigEndian>().map_err(|_| malformed("LitT"))?;
+        let mut literals = Vec::with_capacity((count as usize).min(rest.len()));
+        for _ in 0..count {
+            let len = rest.read_u32::<BigEndian>().map_err(|_| malformed("LitT"))? as usize;
+            let literal = rest.get(..len).ok_or_else(|| malformed("LitT"))?;
+            literals.push(decode(literal)?);
+            rest = &rest[len..];
+        }
+        return Ok(literals);
+    }
+
+    fn functions(&self, id: &str) -> Result<Vec<(String, u32)>, BeamError> {
+        let atoms = self.atoms()?;
+        let mut functions = Vec::new();
+        for [function, arity, _label] in self.table::<3>(id)? {
+            functions.push((atom_at(&atoms, function, id)?, arity));
+        }
+        return Ok(functions);
+    }
+
+    // A count and that many rows of N 32 bit numbers
+    fn table<const N: usize>(&self, id: &str) -> Result<Vec<[u32; N]>, BeamError> {
+        let mut data = self.chunk(id).ok_or_else(|| missing(id))?;
+        let count = data.read_u32::<BigEndian>().map_err(|_| malformed(id))? as usize;
+        if data.len() / (4 * N) < count {
+            return Err(malformed(id));
+        }
+        let mut rows = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut row = [0; N];
+            for value in row.iter_mut() {
+                *value = data.read_u32::<BigEndian>()?;
+            }
+            rows.push(row);
+        }
+        return Ok(rows);
+    }
+}
+
+fn read_atoms(mut data: &[u8], id: &str, atom: impl Fn(&[u8]) -> Option<String>) -> Result<Vec<String>, BeamError> {
+    let count = data.read_i32::<BigEndian>().map_err(|_| malformed(id))?;
+    // a negative count means compact-encoded lengths (OTP 28)
+    let compact = count < 0;
+    let count = count.unsigned_abs() as usize;
+    let mut atoms = Vec::with_capacity(count.min(data.len()));
+    for _ in 0..count {
+        let len = if compact { compact_length(&mut data) } else { data.read_u8().ok().map(|len| len as usize) };
+        let len = len.ok_or_else(|| malformed(id))?;
+        let name = data.get(..len).ok_or_else(|| malformed(id))?;
+        atoms.push(atom(name).ok_or_else(|| malformed(id))?);
+        data = &data[len..];
+    }
+    return Ok(atoms);
+}
+
+// An unsigned number in the compact term encoding of BEAM code
+fn compact_length(data: &mut &[u8]) -> Option<usize> {
+    let first = data.read_u8().ok()?;
+    if first & 0x08 == 0 {
+        return Some((first >> 4) as usize);
+    }
+    if first & 0x10 == 0 {
+        return Some((((first & 0xe0) as usize) << 3) | data.read_u8().ok()? as usize);
+    }
+    let n = (first >> 5) as usize + 2;
+    // longer encodings do not make sense for atom lengths
+    if n > 8 {
+        return None;
+    }
+    let bytes = data.get(..n)?;
+    *data = &data[n..];
+    return Some(bytes.iter().fold(0, |value, byte| (value << 8) | *byte as usize));
+}
+
+// Atom indexes in the tables start at 1
+fn atom_at(atoms: &[String], index: u32, id: &str) -> Result<String, BeamError> {
+    let atom = (index as usize).checked_sub(1).and_then(|index| atoms.get(index));
+    return atom.cloned().ok_or_else(|| malformed(id));
+}
+
+fn decode(bytes: &[u8]) -> Result<ErlTerm, BeamError> {
+    return Ok(Decoder::new(Box::new(bytes)).decode()?);
+}
+
+// The value of the last {Name, Value} in a proplist
+fn property(list: ErlTerm, name: &str) -> Option<ErlTerm> {
+    let ErlTerm::List(List { elements }) = list else { return None };
+    return elements.into_iter().rev().find_map(|element| match element {
+        ErlTerm::Tuple(Tuple { mut elements }) if elements.len() == 2 && is_atom(&elements[0], name) => elements.pop(),
+        _ => None,
+    });
+}
+
+fn is_atom(term: &ErlTerm, name: &str) -> bool {
+    return matches!(term, ErlTerm::Atom(atom) if atom.as_str() == name);
+}
+
+fn chunk_name(id: &[u8; 4]) -> String {
+    return String::from_utf8_lossy(id).into_owned();
+}
+
+fn malformed(id: &str) -> BeamError {
+    return BeamError::MalformedChunk { id: id.to_string() };
+}
+
+fn missing(id: &str) -> BeamError {
+    return BeamError::MissingChunk { id: id.to_string() };
+}
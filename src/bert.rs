@@ -0,0 +1,473 @@
+// BERT and BERT-RPC (requires the bert feature).
+//
+// BERT is the external term format with conventions for what Erlang has no
+// type for, which BertTerm makes explicit:
+//
+// BertTerm  | term
+// ----------+------------------------------------------------
+// Nil       | {bert, nil}
+// Bool      | {bert, true}, {bert, false}
+// Dict      | {bert, dict, [{Key, Value}, ...]}
+// Time      | {bert, time, Megaseconds, Seconds, Microseconds}
+// Regex     | {bert, regex, Source, Options}
+// Tuple     | any other tuple
+// List      | any other proper list
+// Term      | anything else, as it is
+//
+// BERT-RPC exchanges such terms in {packet, 4} frames: the client sends
+// {call, Module, Function, Args} and gets {reply, Result} or {error, Error}
+// back, or sends {cast, Module, Function, Args} and gets {noreply}.
+// BertClient is a client for it, and BertServer a server that runs calls
+// with a handler function, good for tests and as a local stand-in for a
+// real service. See https://bert-rpc.org
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use num::ToPrimitive;
+use thiserror::Error;
+
+use crate::*;
+
+#[derive(Error, Debug)]
+pub enum BertError {
+    #[error("failed to talk to the BERT-RPC server")]
+    Io(#[from] io::Error),
+    #[error("failed to exchange a BERT-RPC message")]
+    Port(#[from] PortError),
+    #[error("malformed BERT complex type")]
+    InvalidComplexType { term: Box<ErlTerm> },
+    #[error("unexpected BERT-RPC message")]
+    UnexpectedMessage { message: Box<ErlTerm> },
+    #[error("the server closed the connection")]
+    Closed,
+    #[error("BERT-RPC call failed")]
+    Remote(Box<BertRpcError>),
+    #[error("failed to decode a BERT term")]
+    DecodingFailure(#[from] DecodingError),
+    #[error("failed to encode a BERT term")]
+    EncodingFailure(#[from] EncodingError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BertTerm {
+    Nil,
+    Bool(bool),
+    Dict(Vec<(BertTerm, BertTerm)>),
+    Time(SystemTime),
+    Regex { source: Vec<u8>, options: Vec<ErlTerm> },
+    Tuple(Vec<BertTerm>),
+    List(Vec<BertTerm>),
+    Term(ErlTerm),
+}
+
+impl BertTerm {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BertError> {
+        return BertTerm::try_from(ErlTerm::from_bytes(bytes)?);
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, BertError> {
+        return Ok(ErlTerm::from(self).to_bytes()?);
+    }
+}
+
+impl From<&BertTerm> for ErlTerm {
+    fn from(term: &BertTerm) -> Self {
+        match term {
+            BertTerm::Nil => bert(vec![atom("nil")]),
+            BertTerm::Bool(true) => bert(vec![atom("true")]),
+            BertTerm::Bool(false) => bert(vec![atom("false")]),
+            BertTerm::Dict(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| tuple(vec![key.into(), value.into()]))
+                    .collect();
+                bert(vec![atom("dict"), ErlTerm::List(List { elements: entries })])
+            }
+            BertTerm::Time(time) => {
+                // Erlang's now(), with a negative megasecond count before 1970
+                let micros = match time.duration_since(UNIX_EPOCH) {
+                    Ok(since) => since.as_micros() as i128,
+                    Err(e) => -(e.duration().as_micros() as i128),
+                };
+                let seconds = micros.div_euclid(1_000_000);
+                bert(vec![
+                    atom("time"),
+                    integer_term(seconds.div_euclid(1_000_000) as i64),
+                    integer_term(seconds.rem_euclid(1_000_000) as i64),
+                    integer_term(micros.rem_euclid(1_000_000) as i64),
+                ])
+            }
+            BertTerm::Regex { source, options } => bert(vec![
+                atom("regex"),
+                ErlTerm::Binary(source.clone()),
+                ErlTerm::List(List { elements: options.clone() }),
+            ]),
+            BertTerm::Tuple(elements) => tuple(elements.iter().map(ErlTerm::from).collect()),
+            BertTerm::List(elements) => ErlTerm::List(List { elements: elements.iter().map(ErlTerm::from).collect() }),
+            BertTerm::Term(term) => term.clone(),
+        }
+    }
+}
+
+impl From<BertTerm> for ErlTerm {
+    fn from(term: BertTerm) -> Self {
+        match term {
+            BertTerm::Term(term) => term,
+            term => ErlTerm::from(&term),
+        }
+    }
+}
+
+impl TryFrom<ErlTerm> for BertTerm {
+    type Error = BertError;
+
+    fn try_from(term: ErlTerm) -> Result<Self, Self::Error> {
+        match term {
+            ErlTerm::Tuple(Tuple { elements }) if elements.first() == Some(&atom("bert")) => {
+                return complex_type(elements);
+            }
+            ErlTerm::Tuple(Tuple { elements }) => {
+                let elements = elements.into_iter().map(BertTerm::try_from).collect::<Result<_, _>>()?;
+                return Ok(BertTerm::Tuple(elements));
+            }
+            ErlTerm::List(List { elements }) => {
+                let elements = elements.into_iter().map(BertTerm::try_from).collect::<Result<_, _>>()?;
+                return Ok(BertTerm::List(elements));
+            }
+            term => return Ok(BertTerm::Term(term)),
+        }
+    }
+}
+
+fn complex_type(elements: Vec<ErlTerm>) -> Result<BertTerm, BertError> {
+    let invalid = |elements: Vec<ErlTerm>| BertError::InvalidComplexType { term: Box::new(tuple(elements)) };
+    let kind = match elements.get(1) {
+        Some(ErlTerm::Atom(kind)) => kind.as_str(),
+        _ => return Err(invalid(elements)),
+    };
+    match (kind, &elements[2..]) {
+        ("nil", []) => return Ok(BertTerm::Nil),
+        ("true", []) => return Ok(BertTerm::Bool(true)),
+        ("false", []) => return Ok(BertTerm::Bool(false)),
+        ("dict", [ErlTerm::List(entries)]) => {
+            let mut dict = Vec::with_capacity(entries.elements.len());
+            for entry in &entries.elements {
+                match entry {
+                    ErlTerm::Tuple(Tuple { elements: pair }) if pair.len() == 2 => {
+                        dict.push((BertTerm::try_from(pair[0].clone())?, BertTerm::try_from(pair[1].clone())?));
+                    }
+                    _ => return Err(invalid(elements)),
+                }
+            }
+            return Ok(BertTerm::Dict(dict));
+        }
+        ("time", [megaseconds, seconds, microseconds]) => {
+            let time = match (integer_value(megaseconds), integer_value(seconds), integer_value(microseconds)) {
+                (Some(megaseconds), Some(seconds), Some(microseconds)) => {
+                    let micros = (megaseconds as i128 * 1_000_000 + seconds as i128) * 1_000_000 + microseconds as i128;
+                    match u64::try_from(micros.unsigned_abs()).map(Duration::from_micros) {
+                        Ok(since) if micros >= 0 => UNIX_EPOCH.checked_add(since),
+                        Ok(since) => UNIX_EPOCH.checked_sub(since),
+                        Err(_) => None,
+                    }
+                }
+                _ => None,
+            };
+            return time.map(BertTerm::Time).ok_or_else(|| invalid(elements));
+        }
+        ("regex", [ErlTerm::Binary(source), ErlTerm::List(options)]) => {
+            return Ok(BertTerm::Regex { source: source.clone(), options: options.elements.clone() });
+        }
+        _ => return Err(invalid(elements)),
+    }
+}
+
+//
+// BERT-RPC
+//
+
+// The error tuple, {error, {Type, Code, Class, Detail, Backtrace}}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BertRpcError {
+    pub error_type: BertErrorType,
+    pub code: i32,
+    pub class: String,
+    pub detail: String,
+    pub backtrace: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BertErrorType {
+    Protocol,
+    Server,
+    User,
+    Proxy,
+}
+
+impl BertRpcError {
+    pub fn new(error_type: BertErrorType, code: i32, class: &str, detail: &str) -> Self {
+        return BertRpcError {
+            error_type,
+            code,
+            class: class.to_string(),
+            detail: detail.to_string(),
+            backtrace: Vec::new(),
+        };
+    }
+
+    // An error raised by the called function
+    pub fn user(class: &str, detail: &str) -> Self {
+        return BertRpcError::new(BertErrorType::User, 0, class, detail);
+    }
+
+    // Server error codes from the BERT-RPC spec
+    pub fn no_such_module(module: &str) -> Self {
+        return BertRpcError::new(BertErrorType::Server, 1, "ServerError", &format!("no such module: {}", module));
+    }
+
+    pub fn no_such_function(module: &str, function: &str) -> Self {
+        let detail = format!("no such function: {}:{}", module, function);
+        return BertRpcError::new(BertErrorType::Server, 2, "ServerError", &detail);
+    }
+
+    fn to_term(&self) -> ErlTerm {
+        let error_type = match self.error_type {
+            BertErrorType::Protocol => "protocol",
+            BertErrorType::Server => "server",
+            BertErrorType::User => "user",
+            BertErrorType::Proxy => "proxy",
+        };
+        let backtrace = self.backtrace.iter().map(|line| ErlTerm::Binary(line.as_bytes().to_vec())).collect();
+        let error = tuple(vec![
+            atom(error_type),
+            integer_term(self.code as i64),
+            ErlTerm::Binary(self.class.as_bytes().to_vec()),
+            ErlTerm::Binary(self.detail.as_bytes().to_vec()),
+            ErlTerm::List(List { elements: backtrace }),
+        ]);
+        return tuple(vec![atom("error"), error]);
+    }
+
+    fn from_term(error: &ErlTerm) -> Option<Self> {
+        let ErlTerm::Tuple(Tuple { elements }) = error else { return None };
+        let [ErlTerm::Atom(error_type), code, class, detail, ErlTerm::List(backtrace)] = elements.as_slice() else {
+            return None;
+        };
+        let error_type = match error_type.as_str() {
+            "protocol" => BertErrorType::Protocol,
+            "server" => BertErrorType::Server,
+            "user" => BertErrorType::User,
+            "proxy" => BertErrorType::Proxy,
+            _ => return None,
+        };
+        return Some(BertRpcError {
+            error_type,
+            code: integer_value(code)?.to_i32()?,
+            class: text(class)?,
+            detail: text(detail)?,
+            backtrace: backtrace.elements.iter().map(text).collect::<Option<_>>()?,
+        });
+    }
+}
+
+pub struct BertClient {
+    stream: TcpStream,
+    port: Port<TcpStream, TcpStream>,
+}
+
+impl BertClient {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, BertError> {
+        let stream = TcpStream::connect(addr)?;
+        let port = Port::new(stream.try_clone()?, stream.try_clone()?, Packet::Four);
+        return Ok(BertClient { stream, port });
+    }
+
+    // How long to wait for replies, forever when None
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<(), BertError> {
+        self.stream.set_read_timeout(timeout)?;
+        return Ok(());
+    }
+
+    pub fn call(&mut self, module: &str, function: &str, args: Vec<ErlTerm>) -> Result<ErlTerm, BertError> {
+        let reply = self.request("call", module, function, args)?;
+        if let ErlTerm::Tuple(Tuple { elements }) = &reply {
+            if let [tag, result] = elements.as_slice() {
+                if *tag == atom("reply") {
+                    return Ok(result.clone());
+                }
+            }
+        }
+        return Err(unexpected(reply));
+    }
+
+    // Asks the server to run the function without waiting for it to finish
+    pub fn cast(&mut self, module: &str, function: &str, args: Vec<ErlTerm>) -> Result<(), BertError> {
+        let reply = self.request("cast", module, function, args)?;
+        if reply == tuple(vec![atom("noreply")]) {
+            return Ok(());
+        }
+        return Err(unexpected(reply));
+    }
+
+    fn request(&mut self, kind: &'static str, module: &str, function: &str, args: Vec<ErlTerm>) -> Result<ErlTerm, BertError> {
+        let module = InternedAtom::new(module).map_err(DecodingError::from)?;
+        let function = InternedAtom::new(function).map_err(DecodingError::from)?;
+        let request = tuple(vec![atom(kind), ErlTerm::Atom(module), ErlTerm::Atom(function), ErlTerm::List(List { elements: args })]);
+        self.port.send(&request)?;
+        let reply = self.port.receive()?.ok_or(BertError::Closed)?;
+        if let ErlTerm::Tuple(Tuple { elements }) = &reply {
+            if let [tag, error] = elements.as_slice() {
+                if *tag == atom("error") {
+                    let error = BertRpcError::from_term(error).ok_or_else(|| unexpected(reply.clone()))?;
+                    return Err(BertError::Remote(Box::new(error)));
+                }
+            }
+        }
+        return Ok(reply);
+    }
+}
+
+// Runs the calls of its clients with a handler function of the module, the
+// function and the arguments, on a thread per connection
+pub struct BertServer {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+type Handler = dyn Fn(&str, &str, Vec<ErlTerm>) -> Result<ErlTerm, BertRpcError> + Send + Sync;
+
+impl BertServer {
+    // Listens on an ephemeral port of the loopback interface
+    pub fn start(
+        handler: impl Fn(&str, &str, Vec<ErlTerm>) -> Result<ErlTerm, BertRpcError> + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        return BertServer::bind((Ipv4Addr::LOCALHOST, 0), handler);
+    }
+
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        handler: impl Fn(&str, &str, Vec<ErlTerm>) -> Result<ErlTerm, BertRpcError> + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let accepting = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    // a client that goes away only ends its own connection
+                    let _ = serve(stream, &*handler);
+                });
+            }
+        });
+        return Ok(BertServer { addr, stopped });
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    pub fn client(&self) -> Result<BertClient, BertError> {
+        return BertClient::connect(self.addr);
+    }
+}
+
+impl Drop for BertServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wakes up the accepting thread so that it notices
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(stream: TcpStream, handler: &Handler) -> Result<(), BertError> {
+    let mut port = Port::new(stream.try_clone()?, stream, Packet::Four);
+    while let Some(request) = port.receive()? {
+        let call = match &request {
+            ErlTerm::Tuple(Tuple { elements }) => match elements.as_slice() {
+                [ErlTerm::Atom(kind), ErlTerm::Atom(module), ErlTerm::Atom(function), ErlTerm::List(args)] => {
+                    Some((kind.as_str(), module.as_str(), function.as_str(), args.elements.clone()))
+                }
+                // info packets are hints for the next request, which we do not need
+                [ErlTerm::Atom(kind), ..] if kind.as_str() == "info" => continue,
+                _ => None,
+            },
+            _ => None,
+        };
+        match call {
+            Some(("call", module, function, args)) => match handler(module, function, args) {
+                Ok(result) => port.send(&tuple(vec![atom("reply"), result]))?,
+                Err(error) => port.send(&error.to_term())?,
+            },
+            Some(("cast", module, function, args)) => {
+                port.send(&tuple(vec![atom("noreply")]))?;
+                let _ = handler(module, function, args);
+            }
+            _ => {
+                let error = BertRpcError::new(BertErrorType::Protocol, 0, "ProtocolError", "invalid request");
+                port.send(&error.to_term())?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn unexpected(message: ErlTerm) -> BertError {
+    return BertError::UnexpectedMessage { message: Box::new(message) };
+}
+
+fn bert(mut elements: Vec<ErlTerm>) -> ErlTerm {
+    elements.insert(0, atom("bert"));
+    return tuple(elements);
+}
+
+fn tuple(elements: Vec<ErlTerm>) -> ErlTerm {
+    return ErlTerm::Tuple(Tuple { elements });
+}
+
+fn atom(name: &'static str) -> ErlTerm {
+    return ErlTerm::Atom(InternedAtom::fixed(name));
+}
+
+// Strings are binaries in BERT, but other implementations may send charlists
+fn text(term: &ErlTerm) -> Option<String> {
+    match term {
+        ErlTerm::Binary(bytes) => String::from_utf8(bytes.clone()).ok(),
+        ErlTerm::List(List { elements }) => elements
+            .iter()
+            .map(|c| integer_value(c).and_then(|c| char::from_u32(u32::try_from(c).ok()?)))
+            .collect(),
+        _ => None,
+    }
+}
+
+fn integer_value(term: &ErlTerm) -> Option<i64> {
+    match term {
+        ErlTerm::SmallInteger(i) => Some(*i as i64),
+        ErlTerm::Integer(i) => Some(*i as i64),
+        ErlTerm::BigInteger(i) => i.to_i64(),
+        _ => None,
+    }
+}
+
+fn integer_term(i: i64) -> ErlTerm {
+    if let Ok(i) = u8::try_from(i) {
+        return ErlTerm::SmallInteger(i);
+    }
+    if let Ok(i) = i32::try_from(i) {
+        return ErlTerm::Integer(i);
+    }
+    return ErlTerm::BigInteger(i.into());
+}
@@ -0,0 +1,262 @@
+// Command line tool for inspecting files with terms in the external
+// term format (requires the cli feature).
+//
+// Input can contain several concatenated terms, each of them optionally
+// compressed the way term_to_binary(Term, [compressed]) does it.
+
+#![allow(clippy::needless_return)]
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use erl_etf::{annotate, Decoder, ErlTerm, JsonOptions, Path, Selector};
+
+const BIGGEST_BINARIES: usize = 5;
+
+#[derive(Parser)]
+#[command(name = "etf", version, about = "Inspects Erlang external term format data")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints terms in Erlang syntax, one per paragraph, terminated with a dot
+    Decode {
+        /// Input file, standard input if omitted or -
+        file: Option<PathBuf>,
+        /// Print every term on a single line
+        #[arg(long)]
+        compact: bool,
+    },
+    /// Converts terms to JSON, one document per term
+    ToJson {
+        file: Option<PathBuf>,
+        /// Use the lossless mapping that from-json can convert back
+        #[arg(long)]
+        lossless: bool,
+        /// Print every document on a single line
+        #[arg(long)]
+        compact: bool,
+    },
+    /// Converts a stream of JSON documents to concatenated terms
+    FromJson {
+        file: Option<PathBuf>,
+        /// Expect the lossless mapping produced by to-json --lossless
+        #[arg(long)]
+        lossless: bool,
+        /// Output file, standard output if omitted
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Checks that the input consists of well-formed terms
+    Validate { file: Option<PathBuf> },
+    /// Prints term counts per type, nesting depth and the biggest binaries
+    Stats { file: Option<PathBuf> },
+    /// Prints an annotated hex dump, stopping at the first malformed byte
+    Dump { file: Option<PathBuf> },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("etf: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    let mut out = io::stdout().lock();
+    match command {
+        Command::Decode { file, compact } => {
+            for (_, term) in decode_all(&read_input(&file)?)? {
+                if compact {
+                    writeln!(out, "{}.", term)?;
+                } else {
+                    writeln!(out, "{:#}.", term)?;
+                }
+            }
+        }
+        Command::ToJson { file, lossless, compact } => {
+            let options = json_options(lossless);
+            for (_, term) in decode_all(&read_input(&file)?)? {
+                let json = term.to_json(&options);
+                if compact {
+                    writeln!(out, "{}", serde_json::to_string(&json)?)?;
+                } else {
+                    writeln!(out, "{}", serde_json::to_string_pretty(&json)?)?;
+                }
+            }
+        }
+        Command::FromJson { file, lossless, output } => {
+            let options = json_options(lossless);
+            let input = read_input(&file)?;
+            let mut bytes = Vec::new();
+            for json in serde_json::Deserializer::from_slice(&input).into_iter() {
+                let term = ErlTerm::from_json(&json?, &options)?;
+                bytes.extend(term.to_bytes()?);
+            }
+            match output {
+                Some(path) => fs::write(path, bytes)?,
+                None => out.write_all(&bytes)?,
+            }
+        }
+        Command::Validate { file } => {
+            let input = read_input(&file)?;
+            let terms = decode_all(&input)?;
+            for (i, (offset, _)) in terms.iter().enumerate() {
+                let end = terms.get(i + 1).map_or(input.len(), |(next, _)| *next);
+                writeln!(out, "term {} at offset {}: {} bytes", i, offset, end - offset)?;
+            }
+            writeln!(out, "{} terms, {} bytes, ok", terms.len(), input.len())?;
+        }
+        Command::Stats { file } => {
+            let input = read_input(&file)?;
+            let terms = decode_all(&input)?;
+            let mut stats = Stats::default();
+            for (i, (_, term)) in terms.iter().enumerate() {
+                let depth = stats.visit(i, term, &mut Vec::new());
+                stats.max_depth = stats.max_depth.max(depth);
+            }
+            writeln!(out, "terms: {}", terms.len())?;
+            writeln!(out, "bytes: {}", input.len())?;
+            writeln!(out, "max depth: {}", stats.max_depth)?;
+            writeln!(out, "types:")?;
+            for (name, count) in &stats.types {
+                writeln!(out, "  {:<14} {}", name, count)?;
+            }
+            if !stats.binaries.is_empty() {
+                writeln!(out, "biggest binaries:")?;
+            }
+            for (size, term, path) in &stats.binaries {
+                writeln!(out, "  {:>10} bytes  term {} {}", size, term, path)?;
+            }
+        }
+        Command::Dump { file } => {
+            let annotation = annotate(&read_input(&file)?);
+            write!(out, "{}", annotation)?;
+            if let Some(failure) = annotation.failure {
+                return Err(format!("malformed input at offset {}: {}", failure.offset, failure.reason).into());
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn read_input(file: &Option<PathBuf>) -> io::Result<Vec<u8>> {
+    match file {
+        Some(path) if path.as_os_str() != "-" => fs::read(path),
+        _ => {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+    }
+}
+
+fn json_options(lossless: bool) -> JsonOptions {
+    if lossless {
+        return JsonOptions::lossless();
+    }
+    return JsonOptions::default();
+}
+
+// Decodes all concatenated terms, returning them with their offsets
+fn decode_all(input: &[u8]) -> Result<Vec<(usize, ErlTerm)>, Box<dyn Error>> {
+    let mut terms = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let offset = input.len() - rest.len();
+        let term = Decoder::new(Box::new(&mut rest))
+            .decode()
+            .map_err(|e| format!("term {} at offset {}: {}", terms.len(), offset, e))?;
+        terms.push((offset, term));
+    }
+    return Ok(terms);
+}
+
+#[derive(Default)]
+struct Stats {
+    types: BTreeMap<&'static str, usize>,
+    max_depth: usize,
+    // (size, term index, path), biggest first
+    binaries: Vec<(usize, usize, Path)>,
+}
+
+impl Stats {
+    // Returns the depth of the term
+    fn visit(&mut self, index: usize, term: &ErlTerm, path: &mut Vec<Selector>) -> usize {
+        *self.types.entry(type_name(term)).or_insert(0) += 1;
+        match term {
+            ErlTerm::Binary(bytes) | ErlTerm::BitBinary(bytes, _) => {
+                self.record_binary(bytes.len(), index, path);
+                1
+            }
+            ErlTerm::Tuple(t) => 1 + self.visit_elements(index, &t.elements, path),
+            ErlTerm::List(l) => 1 + self.visit_elements(index, &l.elements, path),
+            ErlTerm::ImproperList(l) => {
+                let depth = self.visit_elements(index, &l.elements, path);
+                path.push(Selector::Tail);
+                let tail_depth = self.visit(index, &l.tail, path);
+                path.pop();
+                1 + depth.max(tail_depth)
+            }
+            ErlTerm::Map(m) => {
+                let mut depth = 0;
+                for (key, value) in &m.entries {
+                    depth = depth.max(self.visit(index, key, path));
+                    path.push(Selector::MapKey(key.clone()));
+                    depth = depth.max(self.visit(index, value, path));
+                    path.pop();
+                }
+                1 + depth
+            }
+            _ => 1,
+        }
+    }
+
+    fn visit_elements(&mut self, index: usize, elements: &[ErlTerm], path: &mut Vec<Selector>) -> usize {
+        let mut depth = 0;
+        for (i, element) in elements.iter().enumerate() {
+            path.push(Selector::Index(i));
+            depth = depth.max(self.visit(index, element, path));
+            path.pop();
+        }
+        return depth;
+    }
+
+    fn record_binary(&mut self, size: usize, index: usize, path: &[Selector]) {
+        let position = self.binaries.partition_point(|(s, _, _)| *s >= size);
+        if position < BIGGEST_BINARIES {
+            self.binaries.insert(position, (size, index, Path { selectors: path.to_vec() }));
+            self.binaries.truncate(BIGGEST_BINARIES);
+        }
+    }
+}
+
+fn type_name(term: &ErlTerm) -> &'static str {
+    match term {
+        ErlTerm::Atom(_) => "atom",
+        ErlTerm::SmallInteger(_) | ErlTerm::Integer(_) | ErlTerm::BigInteger(_) => "integer",
+        ErlTerm::Float(_) => "float",
+        ErlTerm::Binary(_) => "binary",
+        ErlTerm::BitBinary(_, _) => "bitstring",
+        ErlTerm::Pid(_) => "pid",
+        ErlTerm::V3Port(_) | ErlTerm::V4Port(_) => "port",
+        ErlTerm::Tuple(_) => "tuple",
+        ErlTerm::List(_) => "list",
+        ErlTerm::ImproperList(_) => "improper_list",
+        ErlTerm::Ref(_) => "reference",
+        ErlTerm::ExternalFun(_) | ErlTerm::InternalFun(_) => "fun",
+        ErlTerm::Map(_) => "map",
+    }
+}
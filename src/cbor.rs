@@ -0,0 +1,314 @@
+// Conversion between terms and CBOR (requires the cbor feature).
+//
+// Types that CBOR has no native representation for use tags
+// from the CBOR_TAG_* range below, so that any term round trips:
+//
+// Term            | CBOR
+// ----------------+------------------------------------------------------------
+// atom            | tag ATOM(text), true and false are booleans
+// integer         | integer, tag 2 or 3 (bignum) outside of the 64-bit range
+// float           | float
+// binary          | byte string
+// bit binary      | tag BIT_BINARY([bytes, trailing bits])
+// tuple           | tag TUPLE([elements])
+// proper list     | array
+// improper list   | tag IMPROPER_LIST([[elements], tail])
+// pid             | tag PID([node, id, serial, creation])
+// port            | tag V3_PORT([node, id, creation]), tag V4_PORT([node, id, creation])
+// reference       | tag REF([node, creation, [id, ...]])
+// external fun    | tag EXTERNAL_FUN([module, function, arity])
+// internal fun    | tag INTERNAL_FUN([module, arity, uniq, index, old_index, old_uniq_hash, pid, [free vars]])
+// map             | tag MAP({key: value, ...})
+//
+// Values produced by other CBOR encoders are converted as follows: text strings
+// become binaries, null becomes the atom null, maps become proplists.
+
+use num::bigint::{BigInt, Sign};
+use num::ToPrimitive;
+use ciborium::value::{Integer, Value};
+use thiserror::Error;
+
+use crate::*;
+
+// "ETF" followed by a type number
+pub const CBOR_TAG_ATOM: u64 = 0x45_54_46_01;
+pub const CBOR_TAG_PID: u64 = 0x45_54_46_02;
+pub const CBOR_TAG_V3_PORT: u64 = 0x45_54_46_03;
+pub const CBOR_TAG_V4_PORT: u64 = 0x45_54_46_04;
+pub const CBOR_TAG_REF: u64 = 0x45_54_46_05;
+pub const CBOR_TAG_BIT_BINARY: u64 = 0x45_54_46_06;
+pub const CBOR_TAG_IMPROPER_LIST: u64 = 0x45_54_46_07;
+pub const CBOR_TAG_EXTERNAL_FUN: u64 = 0x45_54_46_08;
+pub const CBOR_TAG_INTERNAL_FUN: u64 = 0x45_54_46_09;
+pub const CBOR_TAG_TUPLE: u64 = 0x45_54_46_0a;
+pub const CBOR_TAG_MAP: u64 = 0x45_54_46_0b;
+
+// RFC 8949, section 3.4.3
+const BIGNUM_POSITIVE: u64 = 2;
+const BIGNUM_NEGATIVE: u64 = 3;
+
+#[derive(Error, Debug)]
+pub enum CborError {
+    #[error("failed to read CBOR data")]
+    DecodingFailure(#[from] ciborium::de::Error<std::io::Error>),
+    #[error("failed to write CBOR data")]
+    EncodingFailure(#[from] ciborium::ser::Error<std::io::Error>),
+    #[error("CBOR value cannot be converted into a term")]
+    UnsupportedValue { value: Value },
+    #[error("too many atoms")]
+    AtomTableFull(#[from] AtomTableFull),
+}
+
+impl ErlTerm {
+    pub fn to_cbor_value(&self) -> Value {
+        return to_value(self);
+    }
+
+    pub fn from_cbor_value(value: &Value) -> Result<ErlTerm, CborError> {
+        return from_value(value);
+    }
+
+    pub fn to_cbor(&self) -> Result<Vec<u8>, CborError> {
+        let mut out = Vec::new();
+        ciborium::ser::into_writer(&self.to_cbor_value(), &mut out)?;
+        return Ok(out);
+    }
+
+    pub fn from_cbor(bytes: &[u8]) -> Result<ErlTerm, CborError> {
+        let value: Value = ciborium::de::from_reader(bytes)?;
+        return from_value(&value);
+    }
+}
+
+//
+// ErlTerm => CBOR
+//
+
+fn tagged(tag: u64, value: Value) -> Value {
+    return Value::Tag(tag, Box::new(value));
+}
+
+fn text(s: &str) -> Value {
+    return Value::Text(s.to_string());
+}
+
+fn array(elements: &[ErlTerm]) -> Value {
+    return Value::Array(elements.iter().map(to_value).collect());
+}
+
+fn pid_value(pid: &ErlPid) -> Value {
+    return tagged(CBOR_TAG_PID, Value::Array(vec![
+        text(&pid.node.name),
+        pid.id.into(),
+        pid.serial.into(),
+        pid.creation.into(),
+    ]));
+}
+
+fn to_value(term: &ErlTerm) -> Value {
+    match term {
+        ErlTerm::Atom(name) => match name.as_str() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => tagged(CBOR_TAG_ATOM, text(name)),
+        },
+        ErlTerm::SmallInteger(i) => (*i).into(),
+        ErlTerm::Integer(i) => (*i).into(),
+        ErlTerm::BigInteger(i) => match i.to_i128().map(Integer::try_from) {
+            Some(Ok(n)) => Value::Integer(n),
+            _ => {
+                let (sign, magnitude) = i.to_bytes_be();
+                if sign == Sign::Minus {
+                    // a negative bignum encodes -1 - n
+                    let n: BigInt = -i - 1;
+                    tagged(BIGNUM_NEGATIVE, Value::Bytes(n.to_bytes_be().1))
+                } else {
+                    tagged(BIGNUM_POSITIVE, Value::Bytes(magnitude))
+                }
+            }
+        },
+        ErlTerm::Float(f) => Value::Float(f.0),
+        ErlTerm::Binary(bytes) => Value::Bytes(bytes.clone()),
+        ErlTerm::BitBinary(bytes, bits) => {
+            tagged(CBOR_TAG_BIT_BINARY, Value::Array(vec![Value::Bytes(bytes.clone()), (*bits).into()]))
+        }
+        ErlTerm::Pid(pid) => pid_value(pid),
+        ErlTerm::V3Port(port) => tagged(CBOR_TAG_V3_PORT, Value::Array(vec![
+            text(&port.node.name),
+            port.id.into(),
+            port.creation.into(),
+        ])),
+        ErlTerm::V4Port(port) => tagged(CBOR_TAG_V4_PORT, Value::Array(vec![
+            text(&port.node.name),
+            port.id.into(),
+            port.creation.into(),
+        ])),
+        ErlTerm::Tuple(t) => tagged(CBOR_TAG_TUPLE, array(&t.elements)),
+        ErlTerm::List(l) => array(&l.elements),
+        ErlTerm::ImproperList(l) => {
+            tagged(CBOR_TAG_IMPROPER_LIST, Value::Array(vec![array(&l.elements), to_value(&l.tail)]))
+        }
+        ErlTerm::Ref(r) => tagged(CBOR_TAG_REF, Value::Array(vec![
+            text(&r.node.name),
+            r.creation.into(),
+            Value::Array(r.id.iter().map(|&i| i.into()).collect()),
+        ])),
+        ErlTerm::ExternalFun(fun) => tagged(CBOR_TAG_EXTERNAL_FUN, Value::Array(vec![
+            text(&fun.module.name),
+            text(&fun.function_name.name),
+            fun.arity.into(),
+        ])),
+        ErlTerm::InternalFun(fun) => tagged(CBOR_TAG_INTERNAL_FUN, Value::Array(vec![
+            text(&fun.module.name),
+            fun.arity.into(),
+            Value::Bytes(fun.uniq_beam_md5.to_vec()),
+            fun.index.into(),
+            fun.old_index.into(),
+            fun.old_uniq_hash.into(),
+            pid_value(&fun.creator_pid),
+            array(&fun.free_vars),
+        ])),
+        ErlTerm::Map(m) => tagged(CBOR_TAG_MAP, Value::Map(
+            m.entries.iter().map(|(k, v)| (to_value(k), to_value(v))).collect(),
+        )),
+    }
+}
+
+//
+// CBOR => ErlTerm
+//
+
+fn from_value(value: &Value) -> Result<ErlTerm, CborError> {
+    match value {
+        Value::Integer(i) => Ok(integer_term(BigInt::from(i128::from(*i)))),
+        Value::Bytes(bytes) => Ok(ErlTerm::Binary(bytes.clone())),
+        Value::Float(f) => Ok(ErlTerm::Float(OrderedFloat(*f))),
+        Value::Text(s) => Ok(ErlTerm::Binary(s.as_bytes().to_vec())),
+        Value::Bool(b) => Ok(ErlTerm::Atom(InternedAtom::new(&b.to_string())?)),
+        Value::Null => Ok(ErlTerm::Atom(InternedAtom::new("null")?)),
+        Value::Array(values) => Ok(ErlTerm::List(List { elements: values_to_terms(values)? })),
+        Value::Map(pairs) => {
+            let mut elements = Vec::with_capacity(pairs.len());
+            for (k, v) in pairs {
+                elements.push(ErlTerm::Tuple(Tuple { elements: vec![from_value(k)?, from_value(v)?] }));
+            }
+            Ok(ErlTerm::List(List { elements }))
+        }
+        Value::Tag(tag, inner) => tagged_to_term(*tag, inner),
+        _ => Err(unsupported(value)),
+    }
+}
+
+fn unsupported(value: &Value) -> CborError {
+    return CborError::UnsupportedValue { value: value.clone() };
+}
+
+fn values_to_terms(values: &[Value]) -> Result<Vec<ErlTerm>, CborError> {
+    return values.iter().map(from_value).collect();
+}
+
+fn integer_term(i: BigInt) -> ErlTerm {
+    if let Some(n) = i.to_u8() {
+        return ErlTerm::SmallInteger(n);
+    }
+    if let Some(n) = i.to_i32() {
+        return ErlTerm::Integer(n);
+    }
+    return ErlTerm::BigInteger(i);
+}
+
+fn tagged_to_term(tag: u64, value: &Value) -> Result<ErlTerm, CborError> {
+    let fields: &[Value] = match value {
+        Value::Array(values) => values,
+        _ => &[],
+    };
+    let term = match (tag, value, fields) {
+        (BIGNUM_POSITIVE, Value::Bytes(bytes), _) => integer_term(BigInt::from_bytes_be(Sign::Plus, bytes)),
+        (BIGNUM_NEGATIVE, Value::Bytes(bytes), _) => {
+            integer_term(-BigInt::from_bytes_be(Sign::Plus, bytes) - 1)
+        }
+        (CBOR_TAG_ATOM, Value::Text(name), _) => ErlTerm::Atom(InternedAtom::new(name)?),
+        (CBOR_TAG_TUPLE, Value::Array(values), _) => ErlTerm::Tuple(Tuple { elements: values_to_terms(values)? }),
+        (CBOR_TAG_MAP, Value::Map(pairs), _) => {
+            let mut entries = Vec::with_capacity(pairs.len());
+            for (k, v) in pairs {
+                entries.push((from_value(k)?, from_value(v)?));
+            }
+            ErlTerm::Map(Map { entries })
+        }
+        (CBOR_TAG_BIT_BINARY, _, [Value::Bytes(bytes), bits_value]) => {
+            // the trailing bits the decoder accepts
+            let bits: u8 = number(bits_value)?;
+            if bits > 8 || bytes.is_empty() != (bits == 0) {
+                return Err(unsupported(bits_value));
+            }
+            ErlTerm::BitBinary(bytes.clone(), bits)
+        }
+        (CBOR_TAG_IMPROPER_LIST, _, [Value::Array(elements), tail]) => ErlTerm::ImproperList(ImproperList {
+            elements: values_to_terms(elements)?,
+            tail: Box::new(from_value(tail)?),
+        }),
+        (CBOR_TAG_PID, _, [_, _, _, _]) => ErlTerm::Pid(pid_from_fields(fields)?),
+        (CBOR_TAG_V3_PORT, _, [node, id, creation]) => ErlTerm::V3Port(ErlV3Port {
+            node: atom(node)?,
+            id: number(id)?,
+            creation: number(creation)?,
+        }),
+        (CBOR_TAG_V4_PORT, _, [node, id, creation]) => ErlTerm::V4Port(ErlV4Port {
+            node: atom(node)?,
+            id: number(id)?,
+            creation: number(creation)?,
+        }),
+        (CBOR_TAG_REF, _, [node, creation, Value::Array(ids)]) => ErlTerm::Ref(Ref {
+            node: atom(node)?,
+            creation: number(creation)?,
+            id: ids.iter().map(number).collect::<Result<Vec<u32>, CborError>>()?,
+        }),
+        (CBOR_TAG_EXTERNAL_FUN, _, [module, function_name, arity]) => ErlTerm::ExternalFun(ExternalFun {
+            module: atom(module)?,
+            function_name: atom(function_name)?,
+            arity: number(arity)?,
+        }),
+        (
+            CBOR_TAG_INTERNAL_FUN,
+            _,
+            [module, arity, uniq @ Value::Bytes(uniq_bytes), index, old_index, old_uniq_hash, Value::Tag(CBOR_TAG_PID, pid), Value::Array(free_vars)],
+        ) => {
+            let free_vars = values_to_terms(free_vars)?;
+            ErlTerm::InternalFun(InternalFun {
+                arity: number(arity)?,
+                uniq_beam_md5: uniq_bytes.as_slice().try_into().map_err(|_| unsupported(uniq))?,
+                index: number(index)?,
+                free_variable_count: free_vars.len() as u32,
+                module: atom(module)?,
+                old_index: number(old_index)?,
+                old_uniq_hash: number(old_uniq_hash)?,
+                creator_pid: pid_from_fields(pid.as_array().ok_or_else(|| unsupported(pid))?)?,
+                free_vars,
+            })
+        }
+        _ => return Err(unsupported(&Value::Tag(tag, Box::new(value.clone())))),
+    };
+    return Ok(term);
+}
+
+fn pid_from_fields(fields: &[Value]) -> Result<ErlPid, CborError> {
+    match fields {
+        [node, id, serial, creation] => Ok(ErlPid {
+            node: atom(node)?,
+            id: number(id)?,
+            serial: number(serial)?,
+            creation: number(creation)?,
+        }),
+        _ => Err(unsupported(&Value::Array(fields.to_vec()))),
+    }
+}
+
+fn atom(value: &Value) -> Result<Atom, CborError> {
+    let name = value.as_text().ok_or_else(|| unsupported(value))?;
+    return Ok(Atom { name: InternedAtom::new(name)? });
+}
+
+fn number<T: TryFrom<Integer>>(value: &Value) -> Result<T, CborError> {
+    return value.as_integer().and_then(|i| T::try_from(i).ok()).ok_or_else(|| unsupported(value));
+}
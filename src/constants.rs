@@ -3,14 +3,14 @@
 
 // Section 12.1
 pub(crate) const TERM_FORMAT_VERSION: u8 = 131;
+// Section 12.2
+pub(crate) const COMPRESSED: u8 = 80;
 // Section 12.3
-#[allow(dead_code)]
 pub(crate) const DISTRIBUTION_HEADER: u8 = 68;
 // Sections 12.30 through 12.32
 pub(crate) const ATOM_UTF8_EXT: u8 = 118;
 pub(crate) const SMALL_ATOM_UTF8_EXT: u8 = 119;
 pub(crate) const ATOM_EXT: u8 = 100;
-#[allow(dead_code)]
 pub(crate) const SMALL_ATOM_EXT: u8 = 115;
 // Sections 12.27, 12.6
 pub(crate) const NEW_FLOAT_EXT: u8 = 70;
@@ -39,11 +39,47 @@ pub(crate) const SMALL_TUPLE_EXT: u8 = 104;
 pub(crate) const LARGE_TUPLE_EXT: u8 = 105;
 // Section 12.16
 pub(crate) const NIL_EXT: u8 = 106;
+// Section 12.15
+pub(crate) const STRING_EXT: u8 = 107;
 // Section 12.18
 pub(crate) const LIST_EXT: u8 = 108;
 // Section 12.24
 pub(crate) const NEWER_REFERENCE_EXT: u8 = 90;
+// Section 12.29
+pub(crate) const MAP_EXT: u8 = 116;
 // Section 12.26
 pub(crate) const NEW_FUN_EXT: u8 = 112;
 // Section 12.27
 pub(crate) const FUN_EXPORT_EXT: u8 = 113;
+
+pub(crate) fn tag_name(tag: u8) -> Option<&'static str> {
+    let name = match tag {
+        COMPRESSED => "COMPRESSED",
+        DISTRIBUTION_HEADER => "DISTRIBUTION_HEADER",
+        ATOM_UTF8_EXT => "ATOM_UTF8_EXT",
+        SMALL_ATOM_UTF8_EXT => "SMALL_ATOM_UTF8_EXT",
+        ATOM_EXT => "ATOM_EXT",
+        SMALL_ATOM_EXT => "SMALL_ATOM_EXT",
+        NEW_FLOAT_EXT => "NEW_FLOAT_EXT",
+        SMALL_INTEGER_EXT => "SMALL_INTEGER_EXT",
+        INTEGER_EXT => "INTEGER_EXT",
+        SMALL_BIG_EXT => "SMALL_BIG_EXT",
+        LARGE_BIG_EXT => "LARGE_BIG_EXT",
+        BIT_BINARY_EXT => "BIT_BINARY_EXT",
+        BINARY_EXT => "BINARY_EXT",
+        NEW_PID_EXT => "NEW_PID_EXT",
+        NEW_PORT_EXT => "NEW_PORT_EXT",
+        V4_PORT_EXT => "V4_PORT_EXT",
+        SMALL_TUPLE_EXT => "SMALL_TUPLE_EXT",
+        LARGE_TUPLE_EXT => "LARGE_TUPLE_EXT",
+        NIL_EXT => "NIL_EXT",
+        STRING_EXT => "STRING_EXT",
+        LIST_EXT => "LIST_EXT",
+        NEWER_REFERENCE_EXT => "NEWER_REFERENCE_EXT",
+        MAP_EXT => "MAP_EXT",
+        NEW_FUN_EXT => "NEW_FUN_EXT",
+        FUN_EXPORT_EXT => "FUN_EXPORT_EXT",
+        _ => return None,
+    };
+    return Some(name);
+}
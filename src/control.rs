@@ -0,0 +1,391 @@
+// Distribution control messages (requires the distribution feature).
+//
+// Every distribution message starts with a control tuple whose first
+// element is the operation, e.g. {2, '', ToPid} for SEND. Operations that
+// have a _TT counterpart carrying a sequential trace token are one variant
+// here, with the token as an Option: SEND and SEND_TT are both Send.
+//
+// Operations marked with a payload below are followed by a second term:
+// the message itself, the exit reason, or the spawn argument list.
+// See https://www.erlang.org/doc/apps/erts/erl_dist_protocol.html
+
+use std::convert::TryFrom;
+
+use num::ToPrimitive;
+
+use crate::*;
+
+const LINK: u8 = 1;
+const SEND: u8 = 2;
+const EXIT: u8 = 3;
+const UNLINK: u8 = 4;
+const NODE_LINK: u8 = 5;
+const REG_SEND: u8 = 6;
+const GROUP_LEADER: u8 = 7;
+const EXIT2: u8 = 8;
+const SEND_TT: u8 = 12;
+const EXIT_TT: u8 = 13;
+const REG_SEND_TT: u8 = 16;
+const EXIT2_TT: u8 = 18;
+const MONITOR_P: u8 = 19;
+const DEMONITOR_P: u8 = 20;
+const MONITOR_P_EXIT: u8 = 21;
+const SEND_SENDER: u8 = 22;
+const SEND_SENDER_TT: u8 = 23;
+const PAYLOAD_EXIT: u8 = 24;
+const PAYLOAD_EXIT_TT: u8 = 25;
+const PAYLOAD_EXIT2: u8 = 26;
+const PAYLOAD_EXIT2_TT: u8 = 27;
+const PAYLOAD_MONITOR_P_EXIT: u8 = 28;
+const SPAWN_REQUEST: u8 = 29;
+const SPAWN_REQUEST_TT: u8 = 30;
+const SPAWN_REPLY: u8 = 31;
+const SPAWN_REPLY_TT: u8 = 32;
+const ALIAS_SEND: u8 = 33;
+const ALIAS_SEND_TT: u8 = 34;
+const UNLINK_ID: u8 = 35;
+const UNLINK_ID_ACK: u8 = 36;
+
+// Monitors can refer to processes by pid or by registered name
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Process {
+    Pid(ErlPid),
+    Name(Atom),
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum ControlMessage {
+    Link { from: ErlPid, to: ErlPid },
+    // payload: the message
+    Send { to: ErlPid, token: Option<ErlTerm> },
+    Exit { from: ErlPid, to: ErlPid, reason: ErlTerm, token: Option<ErlTerm> },
+    // replaced by UnlinkId in OTP 26
+    Unlink { from: ErlPid, to: ErlPid },
+    NodeLink,
+    // payload: the message
+    RegSend { from: ErlPid, to_name: Atom, token: Option<ErlTerm> },
+    GroupLeader { from: ErlPid, to: ErlPid },
+    Exit2 { from: ErlPid, to: ErlPid, reason: ErlTerm, token: Option<ErlTerm> },
+    MonitorP { from: ErlPid, to: Process, reference: Ref },
+    DemonitorP { from: ErlPid, to: Process, reference: Ref },
+    MonitorPExit { from: Process, to: ErlPid, reference: Ref, reason: ErlTerm },
+    // payload: the message
+    SendSender { from: ErlPid, to: ErlPid, token: Option<ErlTerm> },
+    // payload: the exit reason
+    PayloadExit { from: ErlPid, to: ErlPid, token: Option<ErlTerm> },
+    // payload: the exit reason
+    PayloadExit2 { from: ErlPid, to: ErlPid, token: Option<ErlTerm> },
+    // payload: the exit reason
+    PayloadMonitorPExit { from: Process, to: ErlPid, reference: Ref },
+    // payload: the argument list
+    SpawnRequest {
+        request_id: Ref,
+        from: ErlPid,
+        group_leader: ErlPid,
+        module: Atom,
+        function: Atom,
+        arity: u8,
+        options: Vec<ErlTerm>,
+        token: Option<ErlTerm>,
+    },
+    // result is the new pid, or an error atom
+    SpawnReply { request_id: Ref, to: ErlPid, flags: u8, result: ErlTerm, token: Option<ErlTerm> },
+    // payload: the message
+    AliasSend { from: ErlPid, alias: Ref, token: Option<ErlTerm> },
+    UnlinkId { id: u64, from: ErlPid, to: ErlPid },
+    UnlinkIdAck { id: u64, from: ErlPid, to: ErlPid },
+}
+
+impl ControlMessage {
+    // Whether the control message is followed by a second term
+    pub fn has_payload(&self) -> bool {
+        return matches!(
+            self,
+            ControlMessage::Send { .. }
+                | ControlMessage::RegSend { .. }
+                | ControlMessage::SendSender { .. }
+                | ControlMessage::PayloadExit { .. }
+                | ControlMessage::PayloadExit2 { .. }
+                | ControlMessage::PayloadMonitorPExit { .. }
+                | ControlMessage::SpawnRequest { .. }
+                | ControlMessage::AliasSend { .. }
+        );
+    }
+}
+
+//
+// ControlMessage => ErlTerm
+//
+
+impl From<&ControlMessage> for ErlTerm {
+    fn from(message: &ControlMessage) -> Self {
+        let pid = |pid: &ErlPid| ErlTerm::Pid(pid.clone());
+        let reference = |r: &Ref| ErlTerm::Ref(r.clone());
+        // the Unused elements are always the empty atom
+        let unused = || ErlTerm::Atom(InternedAtom::fixed(""));
+
+        return match message {
+            ControlMessage::Link { from, to } => control(LINK, vec![pid(from), pid(to)]),
+            ControlMessage::Send { to, token } => traced(SEND, SEND_TT, token, vec![unused(), pid(to)]),
+            ControlMessage::Exit { from, to, reason, token } => exit(EXIT, EXIT_TT, token, from, to, reason),
+            ControlMessage::Unlink { from, to } => control(UNLINK, vec![pid(from), pid(to)]),
+            ControlMessage::NodeLink => control(NODE_LINK, vec![]),
+            ControlMessage::RegSend { from, to_name, token } => {
+                traced(REG_SEND, REG_SEND_TT, token, vec![pid(from), unused(), to_name.clone().into()])
+            }
+            ControlMessage::GroupLeader { from, to } => control(GROUP_LEADER, vec![pid(from), pid(to)]),
+            ControlMessage::Exit2 { from, to, reason, token } => exit(EXIT2, EXIT2_TT, token, from, to, reason),
+            ControlMessage::MonitorP { from, to, reference: r } => {
+                control(MONITOR_P, vec![pid(from), process(to), reference(r)])
+            }
+            ControlMessage::DemonitorP { from, to, reference: r } => {
+                control(DEMONITOR_P, vec![pid(from), process(to), reference(r)])
+            }
+            ControlMessage::MonitorPExit { from, to, reference: r, reason } => {
+                control(MONITOR_P_EXIT, vec![process(from), pid(to), reference(r), reason.clone()])
+            }
+            ControlMessage::SendSender { from, to, token } => {
+                traced(SEND_SENDER, SEND_SENDER_TT, token, vec![pid(from), pid(to)])
+            }
+            ControlMessage::PayloadExit { from, to, token } => {
+                traced(PAYLOAD_EXIT, PAYLOAD_EXIT_TT, token, vec![pid(from), pid(to)])
+            }
+            ControlMessage::PayloadExit2 { from, to, token } => {
+                traced(PAYLOAD_EXIT2, PAYLOAD_EXIT2_TT, token, vec![pid(from), pid(to)])
+            }
+            ControlMessage::PayloadMonitorPExit { from, to, reference: r } => {
+                control(PAYLOAD_MONITOR_P_EXIT, vec![process(from), pid(to), reference(r)])
+            }
+            ControlMessage::SpawnRequest { request_id, from, group_leader, module, function, arity, options, token } => {
+                let mfa = ErlTerm::Tuple(Tuple {
+                    elements: vec![module.clone().into(), function.clone().into(), ErlTerm::SmallInteger(*arity)],
+                });
+                let options = ErlTerm::List(List { elements: options.clone() });
+                let elements = vec![reference(request_id), pid(from), pid(group_leader), mfa, options];
+                traced(SPAWN_REQUEST, SPAWN_REQUEST_TT, token, elements)
+            }
+            ControlMessage::SpawnReply { request_id, to, flags, result, token } => {
+                let elements = vec![reference(request_id), pid(to), ErlTerm::SmallInteger(*flags), result.clone()];
+                traced(SPAWN_REPLY, SPAWN_REPLY_TT, token, elements)
+            }
+            ControlMessage::AliasSend { from, alias, token } => {
+                traced(ALIAS_SEND, ALIAS_SEND_TT, token, vec![pid(from), reference(alias)])
+            }
+            ControlMessage::UnlinkId { id, from, to } => control(UNLINK_ID, vec![integer(*id), pid(from), pid(to)]),
+            ControlMessage::UnlinkIdAck { id, from, to } => {
+                control(UNLINK_ID_ACK, vec![integer(*id), pid(from), pid(to)])
+            }
+        };
+    }
+}
+
+impl From<ControlMessage> for ErlTerm {
+    fn from(message: ControlMessage) -> Self {
+        return ErlTerm::from(&message);
+    }
+}
+
+fn control(operation: u8, mut elements: Vec<ErlTerm>) -> ErlTerm {
+    elements.insert(0, ErlTerm::SmallInteger(operation));
+    return ErlTerm::Tuple(Tuple { elements });
+}
+
+// The trace token is the last element of _TT control messages
+fn traced(operation: u8, traced_operation: u8, token: &Option<ErlTerm>, mut elements: Vec<ErlTerm>) -> ErlTerm {
+    match token {
+        Some(token) => {
+            elements.push(token.clone());
+            return control(traced_operation, elements);
+        }
+        None => return control(operation, elements),
+    }
+}
+
+// except in EXIT_TT and EXIT2_TT, where it comes before the reason
+fn exit(
+    operation: u8,
+    traced_operation: u8,
+    token: &Option<ErlTerm>,
+    from: &ErlPid,
+    to: &ErlPid,
+    reason: &ErlTerm,
+) -> ErlTerm {
+    let mut elements = vec![ErlTerm::Pid(from.clone()), ErlTerm::Pid(to.clone())];
+    elements.extend(token.clone());
+    elements.push(reason.clone());
+    return control(if token.is_some() { traced_operation } else { operation }, elements);
+}
+
+fn process(process: &Process) -> ErlTerm {
+    match process {
+        Process::Pid(pid) => ErlTerm::Pid(pid.clone()),
+        Process::Name(name) => name.clone().into(),
+    }
+}
+
+fn integer(i: u64) -> ErlTerm {
+    if let Ok(small) = u8::try_from(i) {
+        return ErlTerm::SmallInteger(small);
+    }
+    if let Ok(i) = i32::try_from(i) {
+        return ErlTerm::Integer(i);
+    }
+    return ErlTerm::BigInteger(i.into());
+}
+
+//
+// ErlTerm => ControlMessage
+//
+
+impl TryFrom<ErlTerm> for ControlMessage {
+    type Error = DistributionError;
+
+    fn try_from(term: ErlTerm) -> Result<Self, Self::Error> {
+        return from_term(&term).ok_or(DistributionError::UnsupportedControlMessage { control: term });
+    }
+}
+
+fn from_term(term: &ErlTerm) -> Option<ControlMessage> {
+    let elements = match term {
+        ErlTerm::Tuple(tuple) => &tuple.elements[..],
+        _ => return None,
+    };
+    let (operation, fields) = elements.split_first()?;
+    let operation = match operation {
+        ErlTerm::SmallInteger(operation) => *operation,
+        _ => return None,
+    };
+    let token = |i: usize| fields.get(i).cloned();
+
+    let message = match (operation, fields) {
+        (LINK, [from, to]) => ControlMessage::Link { from: pid(from)?, to: pid(to)? },
+        (SEND, [_, to]) | (SEND_TT, [_, to, _]) => ControlMessage::Send { to: pid(to)?, token: token(2) },
+        (EXIT, [from, to, reason]) => {
+            ControlMessage::Exit { from: pid(from)?, to: pid(to)?, reason: reason.clone(), token: None }
+        }
+        (EXIT_TT, [from, to, token, reason]) => ControlMessage::Exit {
+            from: pid(from)?,
+            to: pid(to)?,
+            reason: reason.clone(),
+            token: Some(token.clone()),
+        },
+        (UNLINK, [from, to]) => ControlMessage::Unlink { from: pid(from)?, to: pid(to)? },
+        (NODE_LINK, []) => ControlMessage::NodeLink,
+        (REG_SEND, [from, _, to_name]) | (REG_SEND_TT, [from, _, to_name, _]) => {
+            ControlMessage::RegSend { from: pid(from)?, to_name: atom(to_name)?, token: token(3) }
+        }
+        (GROUP_LEADER, [from, to]) => ControlMessage::GroupLeader { from: pid(from)?, to: pid(to)? },
+        (EXIT2, [from, to, reason]) => {
+            ControlMessage::Exit2 { from: pid(from)?, to: pid(to)?, reason: reason.clone(), token: None }
+        }
+        (EXIT2_TT, [from, to, token, reason]) => ControlMessage::Exit2 {
+            from: pid(from)?,
+            to: pid(to)?,
+            reason: reason.clone(),
+            token: Some(token.clone()),
+        },
+        (MONITOR_P, [from, to, r]) => {
+            ControlMessage::MonitorP { from: pid(from)?, to: process_of(to)?, reference: reference(r)? }
+        }
+        (DEMONITOR_P, [from, to, r]) => {
+            ControlMessage::DemonitorP { from: pid(from)?, to: process_of(to)?, reference: reference(r)? }
+        }
+        (MONITOR_P_EXIT, [from, to, r, reason]) => ControlMessage::MonitorPExit {
+            from: process_of(from)?,
+            to: pid(to)?,
+            reference: reference(r)?,
+            reason: reason.clone(),
+        },
+        (SEND_SENDER, [from, to]) | (SEND_SENDER_TT, [from, to, _]) => {
+            ControlMessage::SendSender { from: pid(from)?, to: pid(to)?, token: token(2) }
+        }
+        (PAYLOAD_EXIT, [from, to]) | (PAYLOAD_EXIT_TT, [from, to, _]) => {
+            ControlMessage::PayloadExit { from: pid(from)?, to: pid(to)?, token: token(2) }
+        }
+        (PAYLOAD_EXIT2, [from, to]) | (PAYLOAD_EXIT2_TT, [from, to, _]) => {
+            ControlMessage::PayloadExit2 { from: pid(from)?, to: pid(to)?, token: token(2) }
+        }
+        (PAYLOAD_MONITOR_P_EXIT, [from, to, r]) => {
+            ControlMessage::PayloadMonitorPExit { from: process_of(from)?, to: pid(to)?, reference: reference(r)? }
+        }
+        (SPAWN_REQUEST, [request_id, from, group_leader, mfa, options])
+        | (SPAWN_REQUEST_TT, [request_id, from, group_leader, mfa, options, _]) => {
+            let (module, function, arity) = match mfa {
+                ErlTerm::Tuple(Tuple { elements }) => match &elements[..] {
+                    [module, function, ErlTerm::SmallInteger(arity)] => (atom(module)?, atom(function)?, *arity),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let options = match options {
+                ErlTerm::List(list) => list.elements.clone(),
+                _ => return None,
+            };
+            ControlMessage::SpawnRequest {
+                request_id: reference(request_id)?,
+                from: pid(from)?,
+                group_leader: pid(group_leader)?,
+                module,
+                function,
+                arity,
+                options,
+                token: token(5),
+            }
+        }
+        (SPAWN_REPLY, [request_id, to, ErlTerm::SmallInteger(flags), result])
+        | (SPAWN_REPLY_TT, [request_id, to, ErlTerm::SmallInteger(flags), result, _]) => ControlMessage::SpawnReply {
+            request_id: reference(request_id)?,
+            to: pid(to)?,
+            flags: *flags,
+            result: result.clone(),
+            token: token(4),
+        },
+        (ALIAS_SEND, [from, alias]) | (ALIAS_SEND_TT, [from, alias, _]) => {
+            ControlMessage::AliasSend { from: pid(from)?, alias: reference(alias)?, token: token(2) }
+        }
+        (UNLINK_ID, [id, from, to]) => ControlMessage::UnlinkId { id: unsigned(id)?, from: pid(from)?, to: pid(to)? },
+        (UNLINK_ID_ACK, [id, from, to]) => {
+            ControlMessage::UnlinkIdAck { id: unsigned(id)?, from: pid(from)?, to: pid(to)? }
+        }
+        _ => return None,
+    };
+    return Some(message);
+}
+
+fn pid(term: &ErlTerm) -> Option<ErlPid> {
+    match term {
+        ErlTerm::Pid(pid) => Some(pid.clone()),
+        _ => None,
+    }
+}
+
+fn atom(term: &ErlTerm) -> Option<Atom> {
+    match term {
+        ErlTerm::Atom(name) => Some(Atom { name: *name }),
+        _ => None,
+    }
+}
+
+fn reference(term: &ErlTerm) -> Option<Ref> {
+    match term {
+        ErlTerm::Ref(r) => Some(r.clone()),
+        _ => None,
+    }
+}
+
+fn process_of(term: &ErlTerm) -> Option<Process> {
+    match term {
+        ErlTerm::Pid(pid) => Some(Process::Pid(pid.clone())),
+        ErlTerm::Atom(name) => Some(Process::Name(Atom { name: *name })),
+        _ => None,
+    }
+}
+
+fn unsigned(term: &ErlTerm) -> Option<u64> {
+    match term {
+        ErlTerm::SmallInteger(i) => Some(*i as u64),
+        ErlTerm::Integer(i) => u64::try_from(*i).ok(),
+        ErlTerm::BigInteger(i) => i.to_u64(),
+        _ => None,
+    }
+}
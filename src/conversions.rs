@@ -79,6 +79,16 @@ impl TryInto<ImproperList> for ErlTerm {
         }
     }
 }
+impl TryInto<Map> for ErlTerm {
+    type Error = ();
+
+    fn try_into(self) -> Result<Map, Self::Error> {
+        match self {
+            ErlTerm::Map(val) => Ok(val),
+            _ => Err(()),
+        }
+    }
+}
 impl TryInto<InternalFun> for ErlTerm {
     type Error = ();
 
@@ -100,13 +110,17 @@ impl From<Atom> for ErlTerm {
         ErlTerm::Atom(val.name)
     }
 }
-impl From<String> for ErlTerm {
-    fn from(val: String) -> Self {
-        ErlTerm::Atom(val)
+impl TryFrom<String> for ErlTerm {
+    type Error = AtomTableFull;
+
+    fn try_from(val: String) -> Result<Self, AtomTableFull> {
+        Ok(ErlTerm::Atom(InternedAtom::new(&val)?))
     }
 }
-impl From<&str> for ErlTerm {
-    fn from(val: &str) -> Self {
-        ErlTerm::Atom(val.to_string())
+impl TryFrom<&str> for ErlTerm {
+    type Error = AtomTableFull;
+
+    fn try_from(val: &str) -> Result<Self, AtomTableFull> {
+        Ok(ErlTerm::Atom(InternedAtom::new(val)?))
     }
 }
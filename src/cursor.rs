@@ -0,0 +1,90 @@
+// Bounds-checked reads from an in-memory buffer, used by the decoders
+// that work on slices instead of io::Read
+
+use alloc::string::ToString;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::io;
+use crate::{DecodingError, InternedAtom};
+
+pub(crate) struct Cursor<'b> {
+    bytes: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Cursor<'b> {
+    pub(crate) fn new(bytes: &'b [u8]) -> Self {
+        Cursor { bytes, pos: 0 }
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        return self.pos;
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        return self.bytes.len() - self.pos;
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'b [u8], DecodingError> {
+        if self.remaining() < n {
+            return Err(eof());
+        }
+        let bytes = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        return Ok(bytes);
+    }
+
+    pub(crate) fn read_u8(&mut self) -> Result<u8, DecodingError> {
+        match self.bytes.get(self.pos) {
+            Some(b) => {
+                self.pos += 1;
+                Ok(*b)
+            }
+            None => Err(eof()),
+        }
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16, DecodingError> {
+        return Ok(BigEndian::read_u16(self.take(2)?));
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32, DecodingError> {
+        return Ok(BigEndian::read_u32(self.take(4)?));
+    }
+
+    pub(crate) fn read_i32(&mut self) -> Result<i32, DecodingError> {
+        return Ok(BigEndian::read_i32(self.take(4)?));
+    }
+
+    pub(crate) fn read_u64(&mut self) -> Result<u64, DecodingError> {
+        return Ok(BigEndian::read_u64(self.take(8)?));
+    }
+
+    pub(crate) fn read_f64(&mut self) -> Result<f64, DecodingError> {
+        return Ok(BigEndian::read_f64(self.take(8)?));
+    }
+}
+
+fn eof() -> DecodingError {
+    let e = io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+    return DecodingError::DecodingFailure(e);
+}
+
+// ATOM_UTF8_EXT and SMALL_ATOM_UTF8_EXT
+pub(crate) fn utf8_atom(bytes: &[u8]) -> Result<InternedAtom, DecodingError> {
+    match core::str::from_utf8(bytes) {
+        Ok(name) => Ok(InternedAtom::new(name)?),
+        Err(e) => Err(DecodingError::DecodingFailure(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))),
+    }
+}
+
+// ATOM_EXT, assumes Latin1 (Windows-1252) encoding
+pub(crate) fn latin1_atom(bytes: &[u8]) -> Result<InternedAtom, DecodingError> {
+    let (name, _, had_errors) = encoding_rs::WINDOWS_1252.decode(bytes);
+    if had_errors {
+        let e = io::Error::new(io::ErrorKind::InvalidData, name.to_string());
+        return Err(DecodingError::DecodingFailure(e));
+    }
+    return Ok(InternedAtom::new(&name)?);
+}
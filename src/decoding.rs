@@ -7,19 +7,34 @@ use crate::*;
 use crate::constants;
 use crate::numerical::*;
 
-pub struct Decoder {
-    reader: Box<dyn io::Read>,
+// Lengths come from the input and cannot be trusted, larger vectors
+// grow as their contents are actually read
+const MAX_PREALLOCATED: usize = 4096;
+const MAX_PREALLOCATED_BYTES: usize = 16 * 1024 * 1024;
+
+pub struct Decoder<'a> {
+    reader: Box<dyn io::Read + 'a>,
     buffer: Vec<u8>,
+    depth: usize,
+    max_depth: usize,
 }
 
-impl Decoder {
-    pub fn new(reader: Box<dyn io::Read>) -> Self {
+impl<'a> Decoder<'a> {
+    pub fn new(reader: Box<dyn io::Read + 'a>) -> Self {
         Decoder {
             reader,
             buffer: Vec::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_NESTING_DEPTH,
         }
     }
 
+    // Rejects terms nested more than max_depth levels deep
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        return self;
+    }
+
     pub fn decode(&mut self) -> DecodingResult {
         let version = self.reader.read_u8()?;
         if version != constants::TERM_FORMAT_VERSION {
@@ -27,9 +42,47 @@ impl Decoder {
         }
 
         let tag = self.reader.read_u8()?;
+        #[cfg(feature = "zlib")]
+        if tag == constants::COMPRESSED {
+            return self.decode_compressed();
+        }
+
         // TODO: distribution header
-        // TODO: compressed term
-        return self.decode_tagged_with(tag);
+        return self.read_term_tagged(tag);
+    }
+
+    // term_to_binary(Term, [compressed]): the uncompressed size and a zlib
+    // stream of the term without its version byte. The stream is fed a byte
+    // at a time so that nothing after it is consumed from the reader
+    #[cfg(feature = "zlib")]
+    fn decode_compressed(&mut self) -> DecodingResult {
+        let size = self.read_u32()? as usize;
+        let input = io::BufReader::with_capacity(1, &mut self.reader);
+        // One byte more than expected, so that the stream is read up to
+        // its checksum, or a longer one is caught
+        let mut inflated = Vec::with_capacity(size.min(MAX_PREALLOCATED_BYTES));
+        flate2::bufread::ZlibDecoder::new(input)
+            .take(size as u64 + 1)
+            .read_to_end(&mut inflated)?;
+        if inflated.len() != size {
+            let message = format!("compressed term inflated to {} bytes, expected {}", inflated.len(), size);
+            return Err(DecodingError::DecodingFailure(io::Error::new(io::ErrorKind::InvalidData, message)));
+        }
+
+        let mut decoder = Decoder::new(Box::new(&inflated[..])).with_max_depth(self.max_depth);
+        return decoder.read_next_term();
+    }
+
+    // Walks the next term without materialising it, returns the number
+    // of bytes consumed (including the version byte)
+    pub fn skip_term(&mut self) -> Result<usize, DecodingError> {
+        let version = self.reader.read_u8()?;
+        if version != constants::TERM_FORMAT_VERSION {
+            return Err(DecodingError::UnsupportedVersion { version });
+        }
+
+        let n = self.skip_next_term()?;
+        return Ok(1 + n);
     }
 
     fn decode_tagged_with(&mut self, tag: u8) -> DecodingResult {
@@ -50,7 +103,9 @@ impl Decoder {
             constants::SMALL_TUPLE_EXT => self.decode_small_tuple(),
             constants::LARGE_TUPLE_EXT => self.decode_large_tuple(),
             constants::NIL_EXT => self.decode_nil(),
+            constants::STRING_EXT => self.decode_string(),
             constants::LIST_EXT => self.decode_list(),
+            constants::MAP_EXT => self.decode_map(),
             constants::NEWER_REFERENCE_EXT => self.decode_newer_reference(),
             constants::FUN_EXPORT_EXT => self.decode_external_fun(),
             constants::NEW_FUN_EXT => self.decode_internal_fun(),
@@ -58,9 +113,24 @@ impl Decoder {
         }
     }
 
-    fn read_next_term(&mut self) -> DecodingResult {
+    pub(crate) fn read_next_term(&mut self) -> DecodingResult {
         let term_tag = self.reader.read_u8()?;
-        return self.decode_tagged_with(term_tag);
+        return self.read_term_tagged(term_tag);
+    }
+
+    fn read_term_tagged(&mut self, term_tag: u8) -> DecodingResult {
+        self.enter()?;
+        let term = self.decode_tagged_with(term_tag);
+        self.depth -= 1;
+        return term;
+    }
+
+    fn enter(&mut self) -> Result<(), DecodingError> {
+        if self.depth >= self.max_depth {
+            return Err(DecodingError::NestingTooDeep { limit: self.max_depth });
+        }
+        self.depth += 1;
+        return Ok(());
     }
 
     fn read_u8(&mut self) -> Result<u8, std::io::Error> {
@@ -90,26 +160,24 @@ impl Decoder {
     // Legacy atom encoding format, assumes Latin1 (Windows-1252) encoding
     fn decode_atom_ext(&mut self) -> DecodingResult {
         let length = self.read_u16()? as usize;
-        self.buffer.resize(length, 0);
-        self.reader.read_exact(&mut self.buffer)?;
+        self.fill_buffer(length)?;
 
         let (s, _, had_errors) = WINDOWS_1252.decode(&self.buffer);
         if had_errors {
             let e = io::Error::new(io::ErrorKind::InvalidData, s.to_string());
             return Err(DecodingError::DecodingFailure(e));
         } else {
-            return Ok(ErlTerm::Atom(s.to_string()));
+            return Ok(ErlTerm::Atom(InternedAtom::new(&s)?));
         }
     }
 
     // Modern atom encoding format, assumes UTF-8 encoding
     fn decode_atom_utf8_ext(&mut self) -> DecodingResult {
         let length = self.read_u16()? as usize;
-        self.buffer.resize(length, 0);
-        self.reader.read_exact(&mut self.buffer)?;
+        self.fill_buffer(length)?;
 
         match str::from_utf8(&self.buffer) {
-            Ok(s) => Ok(ErlTerm::Atom(s.to_string())),
+            Ok(s) => Ok(ErlTerm::Atom(InternedAtom::new(s)?)),
             Err(e) => {
                 let io_e = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
                 Err(DecodingError::DecodingFailure(io_e))
@@ -120,11 +188,10 @@ impl Decoder {
     // Modern atom encoding format, assumes UTF-8 encoding
     fn decode_small_atom_utf8_ext(&mut self) -> DecodingResult {
         let length: u8 = self.reader.read_u8()?;
-        self.buffer.resize(length as usize, 0);
-        self.reader.read_exact(&mut self.buffer)?;
+        self.fill_buffer(length as usize)?;
 
         match str::from_utf8(&self.buffer) {
-            Ok(s) => Ok(ErlTerm::Atom(s.to_string())),
+            Ok(s) => Ok(ErlTerm::Atom(InternedAtom::new(s)?)),
             Err(e) => {
                 let io_e = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
                 Err(DecodingError::DecodingFailure(io_e))
@@ -156,8 +223,7 @@ impl Decoder {
         let n = self.read_u8()? as usize;
         let sign = self.reader.read_u8()?;
 
-        self.buffer.resize(n, 0);
-        self.reader.read_exact(&mut self.buffer)?;
+        self.fill_buffer(n)?;
 
         // section 12.18:
         // The digits are stored with the least significant byte stored first.
@@ -169,8 +235,7 @@ impl Decoder {
         let n = self.read_u32()? as usize;
         let sign = self.reader.read_u8()?;
 
-        self.buffer.resize(n, 0);
-        self.reader.read_exact(&mut self.buffer)?;
+        self.fill_buffer(n)?;
 
         // section 12.18:
         // The digits are stored with the least significant byte stored first.
@@ -180,7 +245,7 @@ impl Decoder {
 
     fn decode_float(&mut self) -> DecodingResult {
         match self.read_f64() {
-            Ok(i) => Ok(ErlTerm::Float(OrderedFloat::<f64>(i as f64))),
+            Ok(i) => Ok(ErlTerm::Float(OrderedFloat::<f64>(i))),
             Err(e) => {
                 let io_e = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
                 Err(DecodingError::DecodingFailure(io_e))
@@ -190,18 +255,22 @@ impl Decoder {
 
     fn decode_binary(&mut self) -> DecodingResult {
         let n = self.read_u32()? as usize;
-        let mut input = vec![0; n];
-
-        self.reader.read_exact(&mut input)?;
+        let input = self.read_bytes(n)?;
         Ok(ErlTerm::Binary(input))
     }
 
     fn decode_bit_binary(&mut self) -> DecodingResult {
         let n = self.read_u32()? as usize;
         let tail_len = self.reader.read_u8()?;
+        if tail_len > 8 || (n == 0) != (tail_len == 0) {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid bit binary tail length: {}", tail_len),
+            );
+            return Err(DecodingError::DecodingFailure(e));
+        }
 
-        let mut input = vec![0; n];
-        self.reader.read_exact(&mut input)?;
+        let mut input = self.read_bytes(n)?;
         if !input.is_empty() {
             let shift_by = 8 - tail_len;
             let tail = input[n - 1] >> shift_by;
@@ -265,11 +334,12 @@ impl Decoder {
 
     fn decode_small_tuple(&mut self) -> DecodingResult {
         let n = self.read_u8()? as usize;
-        let mut items = Vec::with_capacity(n);
+        let mut items = Vec::with_capacity(n.min(MAX_PREALLOCATED));
 
         for _i in 0..n {
             match self.read_next_term() {
                 Ok(term) => items.push(term),
+                Err(e @ (DecodingError::NestingTooDeep { .. } | DecodingError::AtomTableFull(_))) => return Err(e),
                 Err(_) => return Err(DecodingError::CompoundTypeDecodingFailure()),
             }
         }
@@ -279,11 +349,12 @@ impl Decoder {
 
     fn decode_large_tuple(&mut self) -> DecodingResult {
         let n = self.read_u32()? as usize;
-        let mut items = Vec::with_capacity(n);
+        let mut items = Vec::with_capacity(n.min(MAX_PREALLOCATED));
 
         for _i in 0..n {
             match self.read_next_term() {
                 Ok(term) => items.push(term),
+                Err(e @ (DecodingError::NestingTooDeep { .. } | DecodingError::AtomTableFull(_))) => return Err(e),
                 Err(_) => return Err(DecodingError::CompoundTypeDecodingFailure())
             }
         }
@@ -293,11 +364,12 @@ impl Decoder {
 
     fn decode_list(&mut self) -> DecodingResult {
         let n = self.read_u32()? as usize;
-        let mut items = Vec::with_capacity(n);
+        let mut items = Vec::with_capacity(n.min(MAX_PREALLOCATED));
 
         for _i in 0..n {
             match self.read_next_term() {
                 Ok(term) => items.push(term),
+                Err(e @ (DecodingError::NestingTooDeep { .. } | DecodingError::AtomTableFull(_))) => return Err(e),
                 Err(_) => return Err(DecodingError::CompoundTypeDecodingFailure())
             }
         }
@@ -320,6 +392,31 @@ impl Decoder {
         }
     }
 
+    // A list of small integers, what term_to_binary produces for strings
+    fn decode_string(&mut self) -> DecodingResult {
+        let n = self.read_u16()? as usize;
+        let bytes = self.read_bytes(n)?;
+        let elements = bytes.into_iter().map(ErlTerm::SmallInteger).collect();
+        Ok(ErlTerm::List(List { elements }))
+    }
+
+    fn decode_map(&mut self) -> DecodingResult {
+        let n = self.read_u32()? as usize;
+        let mut entries = Vec::with_capacity(n.min(MAX_PREALLOCATED));
+
+        for _i in 0..n {
+            let key = self.read_next_term();
+            let value = key.and_then(|key| Ok((key, self.read_next_term()?)));
+            match value {
+                Ok(entry) => entries.push(entry),
+                Err(e @ (DecodingError::NestingTooDeep { .. } | DecodingError::AtomTableFull(_))) => return Err(e),
+                Err(_) => return Err(DecodingError::CompoundTypeDecodingFailure())
+            }
+        }
+
+        Ok(ErlTerm::Map(Map { entries }))
+    }
+
     fn decode_nil(&mut self) -> DecodingResult {
         return Ok(ErlTerm::List(List::nil()));
     }
@@ -331,7 +428,7 @@ impl Decoder {
             Ok(atom) => {
                 let creation = self.read_u32()?;
                 // remaining ref ID bytes
-                let mut tail = Vec::<u32>::with_capacity(arity);
+                let mut tail = Vec::<u32>::with_capacity(arity.min(MAX_PREALLOCATED));
 
                 for _i in 0..arity {
                     let j = self.read_u32()?;
@@ -346,14 +443,17 @@ impl Decoder {
     }
 
     fn decode_external_fun(&mut self) -> DecodingResult {
+        let compound = |_| DecodingError::CompoundTypeDecodingFailure();
         let module_term = self.read_next_term()?;
-        let module_atom = TryInto::<Atom>::try_into(module_term).unwrap();
+        let module_atom = TryInto::<Atom>::try_into(module_term).map_err(compound)?;
 
         let fn_name_term = self.read_next_term()?;
-        let fn_name_atom = TryInto::<Atom>::try_into(fn_name_term).unwrap();
+        let fn_name_atom = TryInto::<Atom>::try_into(fn_name_term).map_err(compound)?;
 
-        let arity_term = self.read_next_term()?;
-        let arity = TryInto::<u8>::try_into(arity_term).unwrap();
+        let arity = match self.read_next_term()? {
+            ErlTerm::SmallInteger(arity) => arity,
+            _ => return Err(DecodingError::CompoundTypeDecodingFailure()),
+        };
 
         Ok(ErlTerm::ExternalFun(
             ExternalFun {
@@ -369,17 +469,18 @@ impl Decoder {
         let arity = self.read_u8()?;
 
         let mut uniq_beam_md5 = [0; 16];
-        let _ = self.reader.read_exact(&mut uniq_beam_md5);
+        self.reader.read_exact(&mut uniq_beam_md5)?;
 
         let idx = self.read_u32()?;
         let free_variable_count = self.read_u32()?;
 
-        let module_atom: Atom = self.read_next_term()?.try_into().unwrap();
+        let compound = |_| DecodingError::CompoundTypeDecodingFailure();
+        let module_atom: Atom = self.read_next_term()?.try_into().map_err(compound)?;
 
-        let old_idx: i32 = self.read_next_term()?.try_into().unwrap();
-        let old_uniq: i32 = self.read_next_term()?.try_into().unwrap();
-        let creator_pid: ErlPid = self.read_next_term()?.try_into().unwrap();
-        let mut free_vars = Vec::with_capacity(free_variable_count as usize);
+        let old_idx: i32 = self.read_next_term()?.try_into().map_err(compound)?;
+        let old_uniq: i32 = self.read_next_term()?.try_into().map_err(compound)?;
+        let creator_pid: ErlPid = self.read_next_term()?.try_into().map_err(compound)?;
+        let mut free_vars = Vec::with_capacity((free_variable_count as usize).min(MAX_PREALLOCATED));
         for _i in 0..free_variable_count {
             free_vars.push(self.read_next_term()?);
         }
@@ -396,4 +497,209 @@ impl Decoder {
             free_vars
         }))
     }
+
+    //
+    // Skipping
+    //
+
+    pub(crate) fn skip_next_term(&mut self) -> Result<usize, DecodingError> {
+        let tag = self.reader.read_u8()?;
+        self.enter()?;
+        let n = self.skip_tagged_with(tag);
+        self.depth -= 1;
+        return Ok(1 + n?);
+    }
+
+    // Skips the next term, failing if its tag is not one of the expected ones
+    fn skip_next_term_of(&mut self, expected: &[u8]) -> Result<usize, DecodingError> {
+        let tag = self.reader.read_u8()?;
+        if !expected.contains(&tag) {
+            return Err(DecodingError::CompoundTypeDecodingFailure());
+        }
+        self.enter()?;
+        let n = self.skip_tagged_with(tag);
+        self.depth -= 1;
+        return Ok(1 + n?);
+    }
+
+    fn skip_atom(&mut self) -> Result<usize, DecodingError> {
+        return self.skip_next_term_of(&[
+            constants::ATOM_EXT,
+            constants::ATOM_UTF8_EXT,
+            constants::SMALL_ATOM_UTF8_EXT,
+        ]);
+    }
+
+    fn skip_tagged_with(&mut self, tag: u8) -> Result<usize, DecodingError> {
+        match tag {
+            constants::ATOM_EXT => {
+                let length = self.read_u16()? as usize;
+                self.fill_buffer(length)?;
+                let (_, _, had_errors) = WINDOWS_1252.decode(&self.buffer);
+                if had_errors {
+                    let e = io::Error::new(io::ErrorKind::InvalidData, "invalid Latin1 atom");
+                    return Err(DecodingError::DecodingFailure(e));
+                }
+                Ok(2 + length)
+            }
+            constants::ATOM_UTF8_EXT => {
+                let length = self.read_u16()? as usize;
+                self.fill_buffer(length)?;
+                self.check_utf8_buffer()?;
+                Ok(2 + length)
+            }
+            constants::SMALL_ATOM_UTF8_EXT => {
+                let length = self.read_u8()? as usize;
+                self.fill_buffer(length)?;
+                self.check_utf8_buffer()?;
+                Ok(1 + length)
+            }
+            constants::SMALL_INTEGER_EXT => self.skip_bytes(1),
+            constants::INTEGER_EXT => self.skip_bytes(4),
+            constants::SMALL_BIG_EXT => {
+                let n = self.read_u8()? as usize;
+                to_sign(self.read_u8()?)?;
+                Ok(2 + self.skip_bytes(n)?)
+            }
+            constants::LARGE_BIG_EXT => {
+                let n = self.read_u32()? as usize;
+                to_sign(self.read_u8()?)?;
+                Ok(5 + self.skip_bytes(n)?)
+            }
+            constants::NEW_FLOAT_EXT => self.skip_bytes(8),
+            constants::BINARY_EXT => {
+                let n = self.read_u32()? as usize;
+                Ok(4 + self.skip_bytes(n)?)
+            }
+            constants::BIT_BINARY_EXT => {
+                let n = self.read_u32()? as usize;
+                let tail_len = self.read_u8()?;
+                if tail_len > 8 || (n == 0 && tail_len != 0) || (n > 0 && tail_len == 0) {
+                    let e = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid bit binary tail length: {}", tail_len),
+                    );
+                    return Err(DecodingError::DecodingFailure(e));
+                }
+                Ok(5 + self.skip_bytes(n)?)
+            }
+            constants::NEW_PID_EXT => {
+                let node = self.skip_atom()?;
+                Ok(node + self.skip_bytes(12)?)
+            }
+            constants::NEW_PORT_EXT => {
+                let node = self.skip_atom()?;
+                Ok(node + self.skip_bytes(8)?)
+            }
+            constants::V4_PORT_EXT => {
+                let node = self.skip_atom()?;
+                Ok(node + self.skip_bytes(12)?)
+            }
+            constants::SMALL_TUPLE_EXT => {
+                let n = self.read_u8()? as usize;
+                Ok(1 + self.skip_terms(n)?)
+            }
+            constants::LARGE_TUPLE_EXT => {
+                let n = self.read_u32()? as usize;
+                Ok(4 + self.skip_terms(n)?)
+            }
+            constants::NIL_EXT => Ok(0),
+            constants::STRING_EXT => {
+                let n = self.read_u16()? as usize;
+                Ok(2 + self.skip_bytes(n)?)
+            }
+            constants::MAP_EXT => {
+                let n = self.read_u32()? as usize;
+                Ok(4 + self.skip_terms(2 * n)?)
+            }
+            constants::LIST_EXT => {
+                let n = self.read_u32()? as usize;
+                let elements = self.skip_terms(n)?;
+                let tail = self.skip_next_term()?;
+                Ok(4 + elements + tail)
+            }
+            constants::NEWER_REFERENCE_EXT => {
+                let arity = self.read_u16()? as usize;
+                let node = self.skip_atom()?;
+                Ok(2 + node + self.skip_bytes(4 + 4 * arity)?)
+            }
+            constants::FUN_EXPORT_EXT => {
+                let module = self.skip_atom()?;
+                let function_name = self.skip_atom()?;
+                let arity = self.skip_next_term_of(&[constants::SMALL_INTEGER_EXT])?;
+                Ok(module + function_name + arity)
+            }
+            constants::NEW_FUN_EXT => {
+                let size = self.read_u32()? as usize;
+                // arity, uniq MD5, index
+                let mut n = 4 + self.skip_bytes(1 + 16 + 4)?;
+                let free_variable_count = self.read_u32()? as usize;
+                n += 4;
+                n += self.skip_atom()?;
+                let integers = [constants::SMALL_INTEGER_EXT, constants::INTEGER_EXT];
+                n += self.skip_next_term_of(&integers)?;
+                n += self.skip_next_term_of(&integers)?;
+                n += self.skip_next_term_of(&[constants::NEW_PID_EXT])?;
+                n += self.skip_terms(free_variable_count)?;
+                if n != size {
+                    let e = io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("fun size mismatch: declared {}, actual {}", size, n),
+                    );
+                    return Err(DecodingError::DecodingFailure(e));
+                }
+                Ok(n)
+            }
+            _ => Err(DecodingError::UnrecognizedTag { tag }),
+        }
+    }
+
+    fn skip_terms(&mut self, count: usize) -> Result<usize, DecodingError> {
+        let mut n = 0;
+        for _i in 0..count {
+            n += self.skip_next_term()?;
+        }
+        return Ok(n);
+    }
+
+    fn skip_bytes(&mut self, n: usize) -> Result<usize, DecodingError> {
+        let copied = io::copy(&mut (&mut self.reader).take(n as u64), &mut io::sink())?;
+        if copied != n as u64 {
+            let e = io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+            return Err(DecodingError::DecodingFailure(e));
+        }
+        return Ok(n);
+    }
+
+    fn fill_buffer(&mut self, n: usize) -> Result<(), io::Error> {
+        self.buffer.clear();
+        return read_exactly(&mut self.reader, n, &mut self.buffer);
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = Vec::new();
+        read_exactly(&mut self.reader, n, &mut bytes)?;
+        return Ok(bytes);
+    }
+
+    fn check_utf8_buffer(&self) -> Result<(), DecodingError> {
+        match str::from_utf8(&self.buffer) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let io_e = io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+                Err(DecodingError::DecodingFailure(io_e))
+            }
+        }
+    }
+}
+
+// Like read_exact, but without allocating a buffer of the declared length
+// up front: a bogus length fails with UnexpectedEof once the input runs out
+fn read_exactly(reader: &mut dyn io::Read, n: usize, buffer: &mut Vec<u8>) -> Result<(), io::Error> {
+    buffer.reserve(n.min(MAX_PREALLOCATED_BYTES));
+    let read = reader.take(n as u64).read_to_end(buffer)?;
+    if read != n {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+    }
+    return Ok(());
 }
@@ -0,0 +1,236 @@
+// Structural differences between two terms.
+//
+// Changes are keyed by a Path (see the query module) that points
+// into the old term, except for added list elements and map keys which
+// point into the new one. Lists are compared using their longest common
+// subsequence, so an insertion does not show up as a change of every
+// element that follows it. Maps are compared key by key.
+
+use core::fmt;
+
+use crate::*;
+
+// List pairs larger than this are compared element by element
+const LCS_MAX_CELLS: usize = 1_000_000;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Change {
+    Added { path: Path, term: ErlTerm },
+    Removed { path: Path, term: ErlTerm },
+    Changed { path: Path, old: ErlTerm, new: ErlTerm },
+    TypeChanged { path: Path, old: ErlTerm, new: ErlTerm },
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Added { path, .. } => path,
+            Change::Removed { path, .. } => path,
+            Change::Changed { path, .. } => path,
+            Change::TypeChanged { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path();
+        if path.selectors.is_empty() {
+            write!(f, "(root): ")?;
+        } else {
+            write!(f, "{}: ", path)?;
+        }
+        match self {
+            Change::Added { term, .. } => write!(f, "added {}", term),
+            Change::Removed { term, .. } => write!(f, "removed {}", term),
+            Change::Changed { old, new, .. } => write!(f, "changed {} to {}", old, new),
+            Change::TypeChanged { old, new, .. } => write!(
+                f,
+                "changed {} {} to {} {}",
+                type_name(old),
+                old,
+                type_name(new),
+                new
+            ),
+        }
+    }
+}
+
+// Lists the differences between two terms, an empty list means they are equal
+pub fn diff(old: &ErlTerm, new: &ErlTerm) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_at(&mut Vec::new(), old, new, &mut changes);
+    return changes;
+}
+
+// One change per line, suitable for test failure messages
+pub fn render_diff(changes: &[Change]) -> String {
+    let lines: Vec<String> = changes.iter().map(|c| c.to_string()).collect();
+    return lines.join("\n");
+}
+
+fn type_name(term: &ErlTerm) -> &'static str {
+    match term {
+        ErlTerm::Atom(_) => "atom",
+        ErlTerm::SmallInteger(_) | ErlTerm::Integer(_) | ErlTerm::BigInteger(_) => "integer",
+        ErlTerm::Float(_) => "float",
+        ErlTerm::Binary(_) => "binary",
+        ErlTerm::BitBinary(_, _) => "bitstring",
+        ErlTerm::Pid(_) => "pid",
+        ErlTerm::V3Port(_) | ErlTerm::V4Port(_) => "port",
+        ErlTerm::Tuple(_) => "tuple",
+        ErlTerm::List(_) | ErlTerm::ImproperList(_) => "list",
+        ErlTerm::Ref(_) => "reference",
+        ErlTerm::ExternalFun(_) | ErlTerm::InternalFun(_) => "fun",
+        ErlTerm::Map(_) => "map",
+    }
+}
+
+fn path_of(selectors: &[Selector]) -> Path {
+    return Path { selectors: selectors.to_vec() };
+}
+
+fn diff_at(path: &mut Vec<Selector>, old: &ErlTerm, new: &ErlTerm, changes: &mut Vec<Change>) {
+    if old == new {
+        return;
+    }
+    if type_name(old) != type_name(new) {
+        changes.push(Change::TypeChanged { path: path_of(path), old: old.clone(), new: new.clone() });
+        return;
+    }
+
+    match (old, new) {
+        (ErlTerm::Tuple(a), ErlTerm::Tuple(b)) if a.elements.len() == b.elements.len() => {
+            for (i, (x, y)) in a.elements.iter().zip(&b.elements).enumerate() {
+                path.push(Selector::Index(i));
+                diff_at(path, x, y, changes);
+                path.pop();
+            }
+        }
+        (ErlTerm::List(_) | ErlTerm::ImproperList(_), ErlTerm::List(_) | ErlTerm::ImproperList(_)) => {
+            let (a, a_tail) = list_parts(old);
+            let (b, b_tail) = list_parts(new);
+            diff_elements(path, a, b, changes);
+
+            path.push(Selector::Tail);
+            diff_at(path, &a_tail, &b_tail, changes);
+            path.pop();
+        }
+        (ErlTerm::Map(a), ErlTerm::Map(b)) => {
+            for (key, x) in &a.entries {
+                path.push(Selector::MapKey(key.clone()));
+                match b.get(key) {
+                    Some(y) => diff_at(path, x, y, changes),
+                    None => changes.push(Change::Removed { path: path_of(path), term: x.clone() }),
+                }
+                path.pop();
+            }
+            for (key, y) in &b.entries {
+                if a.get(key).is_none() {
+                    path.push(Selector::MapKey(key.clone()));
+                    changes.push(Change::Added { path: path_of(path), term: y.clone() });
+                    path.pop();
+                }
+            }
+        }
+        _ => changes.push(Change::Changed { path: path_of(path), old: old.clone(), new: new.clone() }),
+    }
+}
+
+// Elements and the tail of a list, the tail of a proper list is []
+fn list_parts(term: &ErlTerm) -> (&[ErlTerm], ErlTerm) {
+    match term {
+        ErlTerm::List(l) => (&l.elements, ErlTerm::List(List::nil())),
+        ErlTerm::ImproperList(l) => (&l.elements, (*l.tail).clone()),
+        _ => (&[], ErlTerm::List(List::nil())),
+    }
+}
+
+enum Edit {
+    Keep(usize, usize),
+    Remove(usize),
+    Add(usize),
+}
+
+fn diff_elements(path: &mut Vec<Selector>, a: &[ErlTerm], b: &[ErlTerm], changes: &mut Vec<Change>) {
+    let edits = if a.len().saturating_mul(b.len()) > LCS_MAX_CELLS {
+        positional_edits(a.len(), b.len())
+    } else {
+        lcs_edits(a, b)
+    };
+
+    // removals and additions between two kept elements are paired up
+    // and reported as changes of those elements
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for edit in edits.iter().chain(core::iter::once(&Edit::Keep(a.len(), b.len()))) {
+        match edit {
+            Edit::Remove(x) => removed.push(*x),
+            Edit::Add(y) => added.push(*y),
+            Edit::Keep(x, y) => {
+                for (k, x) in removed.iter().enumerate() {
+                    path.push(Selector::Index(*x));
+                    match added.get(k) {
+                        Some(y) => diff_at(path, &a[*x], &b[*y], changes),
+                        None => changes.push(Change::Removed { path: path_of(path), term: a[*x].clone() }),
+                    }
+                    path.pop();
+                }
+                for y in added.iter().skip(removed.len()) {
+                    path.push(Selector::Index(*y));
+                    changes.push(Change::Added { path: path_of(path), term: b[*y].clone() });
+                    path.pop();
+                }
+                removed.clear();
+                added.clear();
+
+                if *x < a.len() {
+                    path.push(Selector::Index(*x));
+                    diff_at(path, &a[*x], &b[*y], changes);
+                    path.pop();
+                }
+            }
+        }
+    }
+}
+
+fn positional_edits(n: usize, m: usize) -> Vec<Edit> {
+    let mut edits: Vec<Edit> = (0..n.min(m)).map(|i| Edit::Keep(i, i)).collect();
+    edits.extend((m..n).map(Edit::Remove));
+    edits.extend((n..m).map(Edit::Add));
+    return edits;
+}
+
+fn lcs_edits(a: &[ErlTerm], b: &[ErlTerm]) -> Vec<Edit> {
+    let (n, m) = (a.len(), b.len());
+    // lengths[i][j] is the LCS length of a[i..] and b[j..]
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if a[i] == b[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            edits.push(Edit::Keep(i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            edits.push(Edit::Remove(i));
+            i += 1;
+        } else {
+            edits.push(Edit::Add(j));
+            j += 1;
+        }
+    }
+    edits.extend((i..n).map(Edit::Remove));
+    edits.extend((j..m).map(Edit::Add));
+    return edits;
+}
@@ -0,0 +1,318 @@
+// Reading disk_log files in the internal format (requires the disk_log feature).
+//
+// Mnesia's .DCD and .DCL files, ets:tab2file dumps and many application logs
+// are disk_logs. A file starts with an 8 byte header, <<1,2,3,4>> and
+// whether the log was closed properly, followed by one chunk per logged term:
+//
+//     <<Size:32, 98,87,76,65, Term:Size/binary>>
+//
+// where Term is the term_to_binary of the logged term. Terms of 65528 bytes
+// or more have the MD5 of the size bytes between the magic and the term, so
+// that a corrupt size is detected. Logs written before OTP R8 use the magic
+// <<12,33,44,55>> instead.
+//
+// A log that was not closed properly (the node was killed, the disk filled
+// up) can end in a truncated chunk, and a damaged disk can leave garbage in
+// between. Like disk_log:chunk/2 on a read-only log, the readers skip what
+// is not a valid chunk, carry on with the next one and report what they
+// skipped in bad_chunks.
+//
+// Wrap logs are a number of such files, Name.1 to Name.N, written to in
+// turn. WrapLogReader reads them from the oldest to the newest, which it
+// finds out from the index file Name.idx.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+use crate::*;
+
+const LOG_MAGIC: [u8; 4] = [1, 2, 3, 4];
+const OPENED: [u8; 4] = [6, 7, 8, 9];
+const CLOSED: [u8; 4] = [99, 88, 77, 11];
+const BIG_MAGIC: [u8; 4] = [98, 87, 76, 65];
+const OLD_MAGIC: [u8; 4] = [12, 33, 44, 55];
+const MIN_MD5_TERM: usize = 65528;
+const HEADER_LEN: usize = 8;
+
+// How much more to read at a time while looking for the next chunk
+const RESYNC_READ: usize = 64 * 1024;
+
+#[derive(Error, Debug)]
+pub enum DiskLogError {
+    #[error("failed to read the log")]
+    Io(#[from] io::Error),
+    #[error("not a disk_log in the internal format")]
+    NotADiskLog,
+    #[error("no files of the wrap log")]
+    NoWrapLogFiles { base: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadChunkReason {
+    // bytes that are not a chunk, skipped until the next one
+    BadBytes,
+    // the log ends in the middle of a chunk
+    Truncated,
+    // the MD5 of a big term's size does not match
+    ChecksumMismatch,
+    // a well-formed chunk with a term that does not decode
+    Undecodable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadChunk {
+    // position in the file
+    pub offset: u64,
+    pub len: u64,
+    pub reason: BadChunkReason,
+}
+
+pub struct DiskLogReader<R: Read> {
+    reader: R,
+    closed: bool,
+    buffer: Vec<u8>,
+    start: usize,
+    // file position of buffer[start]
+    offset: u64,
+    eof: bool,
+    bad_chunks: Vec<BadChunk>,
+}
+
+impl DiskLogReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DiskLogError> {
+        return DiskLogReader::new(BufReader::new(File::open(path)?));
+    }
+}
+
+impl<R: Read> DiskLogReader<R> {
+    // Reads the file header
+    pub fn new(mut reader: R) -> Result<Self, DiskLogError> {
+        let mut header = [0; HEADER_LEN];
+        reader.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => DiskLogError::NotADiskLog,
+            _ => DiskLogError::Io(e),
+        })?;
+        if header[..4] != LOG_MAGIC || (header[4..] != OPENED && header[4..] != CLOSED) {
+            return Err(DiskLogError::NotADiskLog);
+        }
+        return Ok(DiskLogReader {
+            reader,
+            closed: header[4..] == CLOSED,
+            buffer: Vec::new(),
+            start: 0,
+            offset: HEADER_LEN as u64,
+            eof: false,
+            bad_chunks: Vec::new(),
+        });
+    }
+
+    // Whether the log was closed properly, a log that was not may end in a
+    // truncated chunk
+    pub fn was_closed(&self) -> bool {
+        return self.closed;
+    }
+
+    // What was skipped so far
+    pub fn bad_chunks(&self) -> &[BadChunk] {
+        return &self.bad_chunks;
+    }
+
+    fn next_term(&mut self) -> Result<Option<ErlTerm>, DiskLogError> {
+        return Ok(self.next_chunk()?.map(|(term, _)| term));
+    }
+
+    // The next term along with the bytes it was decoded from
+    pub(crate) fn next_chunk(&mut self) -> Result<Option<(ErlTerm, Vec<u8>)>, DiskLogError> {
+        loop {
+            if !self.fill(8)? {
+                let rest = self.available();
+                if rest > 0 {
+                    self.skip(rest, BadChunkReason::Truncated);
+                }
+                return Ok(None);
+            }
+            let chunk = &self.buffer[self.start..];
+            let size = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+            let magic = [chunk[4], chunk[5], chunk[6], chunk[7]];
+            if magic != BIG_MAGIC && magic != OLD_MAGIC {
+                self.resync(BadChunkReason::BadBytes)?;
+                continue;
+            }
+            let checked = magic == BIG_MAGIC && size >= MIN_MD5_TERM;
+            let header_len = if checked { 8 + 16 } else { 8 };
+
+            if checked && self.fill(header_len)? {
+                let chunk = &self.buffer[self.start..];
+                if Md5::digest(&chunk[..4])[..] != chunk[8..24] {
+                    self.resync(BadChunkReason::ChecksumMismatch)?;
+                    continue;
+                }
+            }
+            if !self.fill(header_len + size)? {
+                // a corrupt size can make a chunk look longer than the log
+                self.resync(BadChunkReason::Truncated)?;
+                continue;
+            }
+            let bytes = &self.buffer[self.start + header_len..self.start + header_len + size];
+            match ErlTerm::from_bytes(bytes) {
+                Ok(term) => {
+                    let bytes = bytes.to_vec();
+                    self.consume(header_len + size);
+                    return Ok(Some((term, bytes)));
+                }
+                Err(_) => self.skip(header_len + size, BadChunkReason::Undecodable),
+            }
+        }
+    }
+
+    // Skips to the next chunk magic after the current position, or to the
+    // end of the log when there is none
+    fn resync(&mut self, reason: BadChunkReason) -> Result<(), DiskLogError> {
+        // the next chunk starts a byte further at the earliest, its magic 4 bytes into it
+        let mut from = 5;
+        loop {
+            let rest = self.buffer.get(self.start + from..).unwrap_or_default();
+            if let Some(position) = rest.windows(4).position(|window| window == BIG_MAGIC || window == OLD_MAGIC) {
+                self.skip(from + position - 4, reason);
+                return Ok(());
+            }
+            if self.eof {
+                self.skip(self.available(), reason);
+                return Ok(());
+            }
+            // the last 3 bytes could be the start of a magic
+            from = self.available().saturating_sub(3).max(5);
+            self.fill(self.available() + RESYNC_READ)?;
+        }
+    }
+
+    fn skip(&mut self, len: usize, reason: BadChunkReason) {
+        let bad_chunk = BadChunk { offset: self.offset, len: len as u64, reason };
+        // consecutive garbage is one bad chunk
+        match self.bad_chunks.last_mut() {
+            Some(last) if last.offset + last.len == self.offset && last.reason == reason => last.len += len as u64,
+            _ => self.bad_chunks.push(bad_chunk),
+        }
+        self.consume(len);
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+        self.offset += len as u64;
+    }
+
+    fn available(&self) -> usize {
+        return self.buffer.len() - self.start;
+    }
+
+    // Makes sure that len bytes are available, false when the log ends first
+    fn fill(&mut self, len: usize) -> Result<bool, DiskLogError> {
+        if self.available() >= len {
+            return Ok(true);
+        }
+        if self.start > 0 {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+        }
+        while self.buffer.len() < len && !self.eof {
+            let wanted = (len - self.buffer.len()).max(8 * 1024);
+            let read = (&mut self.reader).take(wanted as u64).read_to_end(&mut self.buffer)?;
+            if read == 0 {
+                self.eof = true;
+            }
+        }
+        return Ok(self.buffer.len() >= len);
+    }
+}
+
+impl<R: Read> Iterator for DiskLogReader<R> {
+    type Item = Result<ErlTerm, DiskLogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        return self.next_term().transpose();
+    }
+}
+
+pub struct WrapLogReader {
+    files: Vec<PathBuf>,
+    current: Option<(PathBuf, DiskLogReader<BufReader<File>>)>,
+    bad_chunks: Vec<(PathBuf, BadChunk)>,
+}
+
+impl WrapLogReader {
+    // Opens the wrap log with the files base.1, base.2 and so on
+    pub fn open(base: impl AsRef<Path>) -> Result<Self, DiskLogError> {
+        let base = base.as_ref();
+        let file = |n: u32| PathBuf::from(format!("{}.{}", base.display(), n));
+        let count = (1..).take_while(|n| file(*n).is_file()).count() as u32;
+        if count == 0 {
+            return Err(DiskLogError::NoWrapLogFiles { base: base.to_path_buf() });
+        }
+        // the oldest file is the one after the one being written to
+        let current = current_file(&PathBuf::from(format!("{}.idx", base.display()))).unwrap_or(count);
+        let current = current.clamp(1, count);
+        let order = (current + 1..=count).chain(1..=current);
+        return Ok(WrapLogReader { files: order.map(file).collect(), current: None, bad_chunks: Vec::new() });
+    }
+
+    // The files, from the oldest to the newest
+    pub fn files(&self) -> &[PathBuf] {
+        return &self.files;
+    }
+
+    // What was skipped so far, and in which file
+    pub fn bad_chunks(&self) -> Vec<(PathBuf, BadChunk)> {
+        let mut bad_chunks = self.bad_chunks.clone();
+        if let Some((path, reader)) = &self.current {
+            bad_chunks.extend(reader.bad_chunks().iter().map(|bad_chunk| (path.clone(), bad_chunk.clone())));
+        }
+        return bad_chunks;
+    }
+
+    fn next_term(&mut self) -> Result<Option<ErlTerm>, DiskLogError> {
+        loop {
+            if let Some((_, reader)) = &mut self.current {
+                if let Some(term) = reader.next_term()? {
+                    return Ok(Some(term));
+                }
+                let (path, reader) = self.current.take().unwrap();
+                self.bad_chunks.extend(reader.bad_chunks.into_iter().map(|bad_chunk| (path.clone(), bad_chunk)));
+            }
+            if self.files.is_empty() {
+                return Ok(None);
+            }
+            let path = self.files.remove(0);
+            // files that were never written to are still empty
+            if fs::metadata(&path)?.len() == 0 {
+                continue;
+            }
+            let reader = DiskLogReader::open(&path)?;
+            self.current = Some((path, reader));
+        }
+    }
+}
+
+impl Iterator for WrapLogReader {
+    type Item = Result<ErlTerm, DiskLogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        return self.next_term().transpose();
+    }
+}
+
+// The number of the file being written to, from the index file. Its
+// layout changed over the OTP releases, they all start with the number
+fn current_file(index: &Path) -> Option<u32> {
+    let index = fs::read(index).ok()?;
+    let number = match index.as_slice() {
+        [0, 0, 0, 0, 0, _version, n @ ..] if n.len() >= 4 => u32::from_be_bytes([n[0], n[1], n[2], n[3]]),
+        [0, n @ ..] if n.len() >= 4 => u32::from_be_bytes([n[0], n[1], n[2], n[3]]),
+        [n, ..] => *n as u32,
+        [] => return None,
+    };
+    return Some(number).filter(|n| *n > 0);
+}
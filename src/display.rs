@@ -0,0 +1,260 @@
+// Renders terms in Erlang syntax, similarly to io:format("~w", [Term]).
+//
+// Pids, ports, references and local funs have no literal syntax,
+// they are rendered the way the Erlang shell prints them but with
+// the node name instead of a node index: #Pid<nonode@nohost.87.0>
+//
+// The alternate form ({:#}) breaks tuples, lists and maps that do not fit
+// into LINE_WIDTH columns over multiple lines, like io:format("~p").
+
+use core::fmt;
+
+use crate::*;
+
+const RESERVED_WORDS: &[&str] = &[
+    "after", "and", "andalso", "band", "begin", "bnot", "bor", "bsl", "bsr", "bxor", "case",
+    "catch", "cond", "div", "else", "end", "fun", "if", "let", "maybe", "not", "of", "or",
+    "orelse", "receive", "rem", "try", "when", "xor",
+];
+
+const LINE_WIDTH: usize = 80;
+
+impl fmt::Display for ErlTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return write_pretty(f, self, 0);
+        }
+        match self {
+            ErlTerm::Atom(name) => write_atom(f, name),
+            ErlTerm::SmallInteger(i) => write!(f, "{}", i),
+            ErlTerm::Integer(i) => write!(f, "{}", i),
+            ErlTerm::BigInteger(i) => write!(f, "{}", i),
+            ErlTerm::Float(x) => write_float(f, x.0),
+            ErlTerm::Binary(bytes) => write_binary(f, bytes),
+            ErlTerm::BitBinary(bytes, bits) => {
+                write!(f, "<<")?;
+                for (i, b) in bytes.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", b)?;
+                }
+                if !bytes.is_empty() && *bits != 8 {
+                    write!(f, ":{}", bits)?;
+                }
+                write!(f, ">>")
+            }
+            ErlTerm::Pid(pid) => write!(f, "{}", pid),
+            ErlTerm::V3Port(port) => write!(f, "#Port<{}.{}>", port.node.name, port.id),
+            ErlTerm::V4Port(port) => write!(f, "#Port<{}.{}>", port.node.name, port.id),
+            ErlTerm::Tuple(t) => {
+                write!(f, "{{")?;
+                write_elements(f, &t.elements)?;
+                write!(f, "}}")
+            }
+            ErlTerm::List(l) => {
+                write!(f, "[")?;
+                write_elements(f, &l.elements)?;
+                write!(f, "]")
+            }
+            ErlTerm::ImproperList(l) => {
+                write!(f, "[")?;
+                write_elements(f, &l.elements)?;
+                write!(f, "|{}]", l.tail)
+            }
+            ErlTerm::Ref(r) => write!(f, "{}", r),
+            ErlTerm::ExternalFun(fun) => {
+                write!(f, "fun ")?;
+                write_atom(f, &fun.module.name)?;
+                write!(f, ":")?;
+                write_atom(f, &fun.function_name.name)?;
+                write!(f, "/{}", fun.arity)
+            }
+            ErlTerm::InternalFun(fun) => {
+                write!(f, "#Fun<{}.{}.{}>", fun.module.name, fun.old_index, fun.old_uniq_hash)
+            }
+            ErlTerm::Map(m) => {
+                write!(f, "#{{")?;
+                for (i, (key, value)) in m.entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}=>{}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_atom(f, &self.name)
+    }
+}
+
+impl fmt::Display for ErlPid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#Pid<{}.{}.{}>", self.node.name, self.id, self.serial)
+    }
+}
+
+impl fmt::Display for Ref {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#Ref<{}", self.node.name)?;
+        for id in &self.id {
+            write!(f, ".{}", id)?;
+        }
+        write!(f, ">")
+    }
+}
+
+// Elements of a broken up tuple or list are aligned with the first one,
+// map associations with the first key
+fn write_pretty(f: &mut fmt::Formatter<'_>, term: &ErlTerm, column: usize) -> fmt::Result {
+    let (open, elements, tail, close) = match term {
+        ErlTerm::Tuple(t) => ("{", &t.elements, None, "}"),
+        ErlTerm::List(l) => ("[", &l.elements, None, "]"),
+        ErlTerm::ImproperList(l) => ("[", &l.elements, Some(&l.tail), "]"),
+        ErlTerm::Map(m) if !m.entries.is_empty() && !fits(term, column) => return write_pretty_map(f, m, column),
+        _ => return write!(f, "{}", term),
+    };
+    if elements.is_empty() || fits(term, column) {
+        return write!(f, "{}", term);
+    }
+
+    let column = column + 1;
+    write!(f, "{}", open)?;
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",\n{:column$}", "")?;
+        }
+        write_pretty(f, element, column)?;
+    }
+    if let Some(tail) = tail {
+        write!(f, "|")?;
+        write_pretty(f, tail, column)?;
+    }
+    write!(f, "{}", close)
+}
+
+fn write_pretty_map(f: &mut fmt::Formatter<'_>, map: &Map, column: usize) -> fmt::Result {
+    let column = column + 2;
+    write!(f, "#{{")?;
+    for (i, (key, value)) in map.entries.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",\n{:column$}", "")?;
+        }
+        write!(f, "{} => ", key)?;
+        write_pretty(f, value, column + width(key) + 4)?;
+    }
+    write!(f, "}}")
+}
+
+// Counts characters up to a limit, so that a term too wide for a line
+// is not rendered in full at every level of nesting
+struct Width {
+    used: usize,
+    limit: usize,
+}
+
+impl fmt::Write for Width {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.used += s.chars().count();
+        if self.used > self.limit {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+fn fits(term: &ErlTerm, column: usize) -> bool {
+    let mut width = Width { used: 0, limit: LINE_WIDTH.saturating_sub(column) };
+    return fmt::Write::write_fmt(&mut width, format_args!("{}", term)).is_ok();
+}
+
+fn width(term: &ErlTerm) -> usize {
+    let mut width = Width { used: 0, limit: usize::MAX };
+    let _ = fmt::Write::write_fmt(&mut width, format_args!("{}", term));
+    return width.used;
+}
+
+fn write_elements(f: &mut fmt::Formatter<'_>, elements: &[ErlTerm]) -> fmt::Result {
+    for (i, term) in elements.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+        write!(f, "{}", term)?;
+    }
+    Ok(())
+}
+
+fn write_atom(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    if needs_quoting(name) {
+        write!(f, "'")?;
+        for c in name.chars() {
+            match c {
+                '\'' => write!(f, "\\'")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "'")
+    } else {
+        write!(f, "{}", name)
+    }
+}
+
+fn needs_quoting(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_lowercase() => {}
+        _ => return true,
+    }
+    if !chars.all(|c| c.is_alphanumeric() || c == '_' || c == '@') {
+        return true;
+    }
+    return RESERVED_WORDS.contains(&name);
+}
+
+fn write_float(f: &mut fmt::Formatter<'_>, x: f64) -> fmt::Result {
+    let s = format!("{:?}", x);
+    // Erlang requires a fractional part before the exponent: 1.0e20, not 1e20
+    match s.find('e') {
+        Some(i) if !s[..i].contains('.') => write!(f, "{}.0{}", &s[..i], &s[i..]),
+        _ => write!(f, "{}", s),
+    }
+}
+
+fn write_binary(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    match core::str::from_utf8(bytes) {
+        Ok(s) if !s.is_empty() && s.chars().all(|c| !c.is_control() || c == '\n' || c == '\t') => {
+            write!(f, "<<\"")?;
+            for c in s.chars() {
+                match c {
+                    '"' => write!(f, "\\\"")?,
+                    '\\' => write!(f, "\\\\")?,
+                    '\n' => write!(f, "\\n")?,
+                    '\t' => write!(f, "\\t")?,
+                    c => write!(f, "{}", c)?,
+                }
+            }
+            if s.is_ascii() {
+                write!(f, "\">>")
+            } else {
+                write!(f, "\"/utf8>>")
+            }
+        }
+        _ => {
+            write!(f, "<<")?;
+            for (i, b) in bytes.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ",")?;
+                }
+                write!(f, "{}", b)?;
+            }
+            write!(f, ">>")
+        }
+    }
+}
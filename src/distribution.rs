@@ -0,0 +1,575 @@
+// Erlang distribution connections (requires the distribution feature).
+//
+// Connection::connect looks the node up in EPMD and performs the
+// handshake as a hidden node, like erl_call does, Connection::accept
+// performs the other side of it. Both sides then exchange distribution
+// messages: a control message, such as {2, '', ToPid} for a send (see
+// ControlMessage), and an optional payload. See
+// https://www.erlang.org/doc/apps/erts/erl_dist_protocol.html
+//
+// The handshake speaks version 6 (OTP 23 and later), and falls back to
+// version 5 with a complement for peers that EPMD reports as older.
+//
+// Messages are sent without a distribution header. Received messages may
+// have one when DFLAG_FRAGMENTS is negotiated, fragments are reassembled.
+// The atom cache is never negotiated.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+use crate::*;
+use crate::constants;
+
+pub const DFLAG_PUBLISHED: u64 = 0x1;
+pub const DFLAG_ATOM_CACHE: u64 = 0x2;
+pub const DFLAG_EXTENDED_REFERENCES: u64 = 0x4;
+pub const DFLAG_DIST_MONITOR: u64 = 0x8;
+pub const DFLAG_FUN_TAGS: u64 = 0x10;
+pub const DFLAG_DIST_MONITOR_NAME: u64 = 0x20;
+pub const DFLAG_HIDDEN_ATOM_CACHE: u64 = 0x40;
+pub const DFLAG_NEW_FUN_TAGS: u64 = 0x80;
+pub const DFLAG_EXTENDED_PIDS_PORTS: u64 = 0x100;
+pub const DFLAG_EXPORT_PTR_TAG: u64 = 0x200;
+pub const DFLAG_BIT_BINARIES: u64 = 0x400;
+pub const DFLAG_NEW_FLOATS: u64 = 0x800;
+pub const DFLAG_UNICODE_IO: u64 = 0x1000;
+pub const DFLAG_DIST_HDR_ATOM_CACHE: u64 = 0x2000;
+pub const DFLAG_SMALL_ATOM_TAGS: u64 = 0x4000;
+pub const DFLAG_UTF8_ATOMS: u64 = 0x10000;
+pub const DFLAG_MAP_TAG: u64 = 0x20000;
+pub const DFLAG_BIG_CREATION: u64 = 0x40000;
+pub const DFLAG_SEND_SENDER: u64 = 0x80000;
+pub const DFLAG_BIG_SEQTRACE_LABELS: u64 = 0x100000;
+pub const DFLAG_EXIT_PAYLOAD: u64 = 0x400000;
+pub const DFLAG_FRAGMENTS: u64 = 0x800000;
+pub const DFLAG_HANDSHAKE_23: u64 = 0x1000000;
+pub const DFLAG_UNLINK_ID: u64 = 0x2000000;
+pub const DFLAG_MANDATORY_25_DIGEST: u64 = 0x4000000;
+pub const DFLAG_SPAWN: u64 = 1 << 32;
+pub const DFLAG_NAME_ME: u64 = 1 << 33;
+pub const DFLAG_V4_NC: u64 = 1 << 34;
+pub const DFLAG_ALIAS: u64 = 1 << 35;
+
+// What the encoder produces needs these: NEW_PID_EXT and NEWER_REFERENCE_EXT,
+// UTF-8 atoms, NEW_FLOAT_EXT, bit binaries and the newer fun tags
+pub const DFLAG_REQUIRED: u64 = DFLAG_EXTENDED_REFERENCES
+    | DFLAG_EXTENDED_PIDS_PORTS
+    | DFLAG_FUN_TAGS
+    | DFLAG_NEW_FUN_TAGS
+    | DFLAG_EXPORT_PTR_TAG
+    | DFLAG_BIT_BINARIES
+    | DFLAG_NEW_FLOATS
+    | DFLAG_UTF8_ATOMS
+    | DFLAG_BIG_CREATION;
+
+// Advertised by default. OTP 25 and later refuse peers without
+// DFLAG_MAP_TAG and DFLAG_HANDSHAKE_23, OTP 26 and later without
+// DFLAG_V4_NC and DFLAG_UNLINK_ID
+pub const DFLAG_DEFAULT: u64 = DFLAG_REQUIRED
+    | DFLAG_DIST_MONITOR
+    | DFLAG_DIST_MONITOR_NAME
+    | DFLAG_SMALL_ATOM_TAGS
+    | DFLAG_MAP_TAG
+    | DFLAG_SEND_SENDER
+    | DFLAG_BIG_SEQTRACE_LABELS
+    | DFLAG_EXIT_PAYLOAD
+    | DFLAG_FRAGMENTS
+    | DFLAG_HANDSHAKE_23
+    | DFLAG_UNLINK_ID
+    | DFLAG_MANDATORY_25_DIGEST
+    | DFLAG_V4_NC;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(7);
+
+// Handshake messages
+const SEND_NAME_V5: u8 = b'n';
+const SEND_NAME_V6: u8 = b'N';
+const STATUS: u8 = b's';
+const COMPLEMENT: u8 = b'c';
+const CHALLENGE_REPLY: u8 = b'r';
+const CHALLENGE_ACK: u8 = b'a';
+
+// Distribution message headers
+const PASS_THROUGH: u8 = 112;
+const NORMAL_HEADER: u8 = 68;
+const FRAGMENT_HEADER: u8 = 69;
+const FRAGMENT_CONTINUATION: u8 = 70;
+
+#[derive(Error, Debug)]
+pub enum DistributionError {
+    #[error("failed to talk to the other node")]
+    Io(#[from] io::Error),
+    #[error("failed to look up the node in EPMD")]
+    Epmd(#[from] EpmdError),
+    #[error("node name must look like name@host")]
+    InvalidNodeName { name: String },
+    #[error("node is not registered in EPMD")]
+    NodeNotFound { name: String },
+    #[error("the other node refused the connection")]
+    Refused { status: String },
+    #[error("the other node uses a different cookie")]
+    AuthenticationFailed,
+    #[error("the other node lacks required distribution flags")]
+    MissingFlags { missing: u64 },
+    #[error("unexpected handshake message")]
+    UnexpectedMessage { tag: u8 },
+    #[error("malformed distribution message")]
+    MalformedMessage,
+    #[error("unsupported or malformed control message")]
+    UnsupportedControlMessage { control: ErlTerm },
+    #[error("failed to decode a distribution message")]
+    DecodingFailure(#[from] DecodingError),
+    #[error("failed to encode a distribution message")]
+    EncodingFailure(#[from] EncodingError),
+    #[error("name is already registered")]
+    NameTaken { name: String },
+    #[error("received an exit signal")]
+    Exit { from: ErlPid, reason: Box<ErlTerm> },
+    #[error("no reply in time")]
+    Timeout,
+    #[error("lost the connection to the node")]
+    NodeDown { name: String },
+    #[error("the called process exited")]
+    CallFailed { reason: Box<ErlTerm> },
+    #[error("rpc call failed")]
+    BadRpc { reason: Box<ErlTerm> },
+}
+
+// Who we are during the handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeConfig {
+    // name@host
+    pub name: String,
+    pub cookie: String,
+    // goes into the pids and references created by the local node
+    pub creation: u32,
+    // add DFLAG_PUBLISHED to connect as a visible node
+    pub flags: u64,
+    pub timeout: Duration,
+}
+
+impl HandshakeConfig {
+    // A hidden node with a random creation, as it is not registered in EPMD
+    pub fn new(name: &str, cookie: &str) -> Result<Self, DistributionError> {
+        split_node_name(name)?;
+        return Ok(HandshakeConfig {
+            name: name.to_string(),
+            cookie: cookie.to_string(),
+            creation: random_creation()?,
+            flags: DFLAG_DEFAULT,
+            timeout: HANDSHAKE_TIMEOUT,
+        });
+    }
+}
+
+pub struct Connection {
+    stream: TcpStream,
+    // shared with clones, so that their packets do not interleave
+    write_lock: Arc<Mutex<()>>,
+    peer_name: String,
+    peer_creation: u32,
+    flags: u64,
+    // partially received fragmented messages, by sequence id
+    fragments: HashMap<u64, Vec<u8>>,
+}
+
+impl Connection {
+    // Looks the node up in the EPMD of its host and connects to it
+    pub fn connect(config: &HandshakeConfig, node: &str) -> Result<Self, DistributionError> {
+        let (alive, host) = split_node_name(node)?;
+        let epmd = EpmdClient::on_host(host)?;
+        let info = epmd
+            .port_please(alive)?
+            .ok_or_else(|| DistributionError::NodeNotFound { name: node.to_string() })?;
+        let addr = SocketAddr::new(epmd.addr().ip(), info.port);
+        return Connection::connect_to(config, addr, info.highest_version);
+    }
+
+    // Connects to a node at a known address. version is the highest
+    // distribution version the node supports, as reported by EPMD
+    pub fn connect_to(config: &HandshakeConfig, addr: SocketAddr, version: u16) -> Result<Self, DistributionError> {
+        let stream = TcpStream::connect_timeout(&addr, config.timeout)?;
+        return Connection::initiate(stream, config, version);
+    }
+
+    // The initiating side of the handshake, over an established connection
+    pub fn initiate(mut stream: TcpStream, config: &HandshakeConfig, version: u16) -> Result<Self, DistributionError> {
+        with_timeout(&stream, config.timeout)?;
+        let name = config.name.as_bytes();
+        let mut send_name = Vec::new();
+        let sent_v5 = version < 6;
+        if sent_v5 {
+            send_name.push(SEND_NAME_V5);
+            send_name.write_u16::<BigEndian>(5)?;
+            send_name.write_u32::<BigEndian>(config.flags as u32)?;
+            send_name.extend_from_slice(name);
+        } else {
+            send_name.push(SEND_NAME_V6);
+            send_name.write_u64::<BigEndian>(config.flags)?;
+            send_name.write_u32::<BigEndian>(config.creation)?;
+            send_name.write_u16::<BigEndian>(name.len() as u16)?;
+            send_name.extend_from_slice(name);
+        }
+        write_handshake(&mut stream, &send_name)?;
+
+        let status = read_handshake(&mut stream)?;
+        match status.split_first() {
+            Some((&STATUS, b"ok" | b"ok_simultaneous")) => {}
+            // the other node still has a connection from a node with our name,
+            // replacing it is what a restarted node would want
+            Some((&STATUS, b"alive")) => write_handshake(&mut stream, b"strue")?,
+            Some((&STATUS, status)) => {
+                return Err(DistributionError::Refused { status: String::from_utf8_lossy(status).into_owned() })
+            }
+            Some((&tag, _)) => return Err(DistributionError::UnexpectedMessage { tag }),
+            None => return Err(DistributionError::MalformedMessage),
+        }
+
+        let challenge = read_handshake(&mut stream)?;
+        let peer = parse_name_message(&challenge, true)?;
+        check_flags(peer.flags)?;
+        if sent_v5 && peer.version == 6 {
+            let mut complement = vec![COMPLEMENT];
+            complement.write_u32::<BigEndian>((config.flags >> 32) as u32)?;
+            complement.write_u32::<BigEndian>(config.creation)?;
+            write_handshake(&mut stream, &complement)?;
+        }
+
+        let own_challenge = random_u32()?;
+        let mut reply = vec![CHALLENGE_REPLY];
+        reply.write_u32::<BigEndian>(own_challenge)?;
+        reply.extend_from_slice(&digest(&config.cookie, peer.challenge));
+        write_handshake(&mut stream, &reply)?;
+
+        let ack = read_handshake(&mut stream)?;
+        match ack.split_first() {
+            Some((&CHALLENGE_ACK, peer_digest)) if peer_digest == digest(&config.cookie, own_challenge) => {}
+            Some((&CHALLENGE_ACK, _)) => return Err(DistributionError::AuthenticationFailed),
+            Some((&tag, _)) => return Err(DistributionError::UnexpectedMessage { tag }),
+            None => return Err(DistributionError::MalformedMessage),
+        }
+        return Connection::established(stream, config, peer);
+    }
+
+    // The accepting side of the handshake, over a connection accepted from
+    // the node's listen socket
+    pub fn accept(mut stream: TcpStream, config: &HandshakeConfig) -> Result<Self, DistributionError> {
+        with_timeout(&stream, config.timeout)?;
+        let send_name = read_handshake(&mut stream)?;
+        let mut peer = parse_name_message(&send_name, false)?;
+        if let Err(e) = check_flags(peer.flags) {
+            write_handshake(&mut stream, b"snot_allowed")?;
+            return Err(e);
+        }
+        write_handshake(&mut stream, b"sok")?;
+
+        // peers that sent the old send_name but know the new handshake
+        // get the new challenge, and complete their flags afterwards
+        let new_challenge = peer.version == 6 || peer.flags & DFLAG_HANDSHAKE_23 != 0;
+        let own_challenge = random_u32()?;
+        let name = config.name.as_bytes();
+        let mut challenge = Vec::new();
+        if new_challenge {
+            challenge.push(SEND_NAME_V6);
+            challenge.write_u64::<BigEndian>(config.flags)?;
+            challenge.write_u32::<BigEndian>(own_challenge)?;
+            challenge.write_u32::<BigEndian>(config.creation)?;
+            challenge.write_u16::<BigEndian>(name.len() as u16)?;
+        } else {
+            challenge.push(SEND_NAME_V5);
+            challenge.write_u16::<BigEndian>(5)?;
+            challenge.write_u32::<BigEndian>(config.flags as u32)?;
+            challenge.write_u32::<BigEndian>(own_challenge)?;
+        }
+        challenge.extend_from_slice(name);
+        write_handshake(&mut stream, &challenge)?;
+
+        let mut reply = read_handshake(&mut stream)?;
+        if peer.version == 5 && new_challenge {
+            let mut complement = &reply[..];
+            match complement.read_u8()? {
+                COMPLEMENT => {}
+                tag => return Err(DistributionError::UnexpectedMessage { tag }),
+            }
+            peer.flags |= (complement.read_u32::<BigEndian>()? as u64) << 32;
+            peer.creation = complement.read_u32::<BigEndian>()?;
+            reply = read_handshake(&mut stream)?;
+        }
+
+        let mut fields = &reply[..];
+        match fields.read_u8()? {
+            CHALLENGE_REPLY => {}
+            tag => return Err(DistributionError::UnexpectedMessage { tag }),
+        }
+        let peer_challenge = fields.read_u32::<BigEndian>()?;
+        if fields != digest(&config.cookie, own_challenge) {
+            return Err(DistributionError::AuthenticationFailed);
+        }
+        let mut ack = vec![CHALLENGE_ACK];
+        ack.extend_from_slice(&digest(&config.cookie, peer_challenge));
+        write_handshake(&mut stream, &ack)?;
+        return Connection::established(stream, config, peer);
+    }
+
+    fn established(stream: TcpStream, config: &HandshakeConfig, peer: PeerName) -> Result<Self, DistributionError> {
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        return Ok(Connection {
+            stream,
+            write_lock: Arc::new(Mutex::new(())),
+            peer_name: peer.name,
+            peer_creation: peer.creation,
+            flags: config.flags & peer.flags,
+            fragments: HashMap::new(),
+        });
+    }
+
+    pub fn peer_name(&self) -> &str {
+        return &self.peer_name;
+    }
+
+    pub fn peer_creation(&self) -> u32 {
+        return self.peer_creation;
+    }
+
+    // The flags both nodes advertised
+    pub fn flags(&self) -> u64 {
+        return self.flags;
+    }
+
+    // Sends a control message with an optional payload
+    pub fn send(&mut self, control: &ErlTerm, message: Option<&ErlTerm>) -> Result<(), DistributionError> {
+        let mut packet = vec![0, 0, 0, 0, PASS_THROUGH];
+        control.encode(Box::new(&mut packet))?;
+        if let Some(message) = message {
+            message.encode(Box::new(&mut packet))?;
+        }
+        let len = u32::try_from(packet.len() - 4).map_err(|_| EncodingError::ValueOutOfRange())?;
+        packet[..4].copy_from_slice(&len.to_be_bytes());
+        return self.write(&packet);
+    }
+
+    // Waits for the next control message and its payload. Ticks are
+    // answered along the way, so the other node only considers us gone
+    // when nobody receives for longer than its net_ticktime
+    pub fn receive(&mut self) -> Result<(ErlTerm, Option<ErlTerm>), DistributionError> {
+        loop {
+            let len = self.stream.read_u32::<BigEndian>()? as usize;
+            if len == 0 {
+                self.tick()?;
+                continue;
+            }
+            let mut packet = Vec::new();
+            (&mut self.stream).take(len as u64).read_to_end(&mut packet)?;
+            if packet.len() < len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            if let Some(message) = self.reassemble(packet)? {
+                return decode_message(&message);
+            }
+        }
+    }
+
+    pub fn send_control(&mut self, control: &ControlMessage, payload: Option<&ErlTerm>) -> Result<(), DistributionError> {
+        return self.send(&ErlTerm::from(control), payload);
+    }
+
+    pub fn receive_control(&mut self) -> Result<(ControlMessage, Option<ErlTerm>), DistributionError> {
+        let (control, payload) = self.receive()?;
+        return Ok((ControlMessage::try_from(control)?, payload));
+    }
+
+    pub fn tick(&mut self) -> Result<(), DistributionError> {
+        return self.write(&[0, 0, 0, 0]);
+    }
+
+    // Another handle to the same connection, e.g. to receive on one thread
+    // and send on others. Fragmented messages are reassembled per handle
+    pub fn try_clone(&self) -> Result<Self, DistributionError> {
+        return Ok(Connection {
+            stream: self.stream.try_clone()?,
+            write_lock: self.write_lock.clone(),
+            peer_name: self.peer_name.clone(),
+            peer_creation: self.peer_creation,
+            flags: self.flags,
+            fragments: HashMap::new(),
+        });
+    }
+
+    // Closes the connection for all handles, pending receives fail
+    pub fn shutdown(&self) -> Result<(), DistributionError> {
+        self.stream.shutdown(Shutdown::Both)?;
+        return Ok(());
+    }
+
+    fn write(&mut self, packet: &[u8]) -> Result<(), DistributionError> {
+        let _guard = self.write_lock.lock().unwrap();
+        self.stream.write_all(packet)?;
+        return Ok(());
+    }
+
+    // Returns the control message and payload bytes once a message is
+    // complete, starting at the first term
+    fn reassemble(&mut self, packet: Vec<u8>) -> Result<Option<Vec<u8>>, DistributionError> {
+        let mut fields = &packet[..];
+        match fields.read_u8()? {
+            PASS_THROUGH => return Ok(Some(packet[1..].to_vec())),
+            constants::TERM_FORMAT_VERSION => {}
+            _ => return Err(DistributionError::MalformedMessage),
+        }
+        let header = fields.read_u8()?;
+        if header == NORMAL_HEADER {
+            skip_atom_cache_refs(&mut fields)?;
+            return Ok(Some(fields.to_vec()));
+        }
+
+        let sequence_id = fields.read_u64::<BigEndian>()?;
+        let fragment_id = fields.read_u64::<BigEndian>()?;
+        match header {
+            FRAGMENT_HEADER => {
+                skip_atom_cache_refs(&mut fields)?;
+                self.fragments.insert(sequence_id, fields.to_vec());
+            }
+            FRAGMENT_CONTINUATION => match self.fragments.get_mut(&sequence_id) {
+                Some(message) => message.extend_from_slice(fields),
+                None => return Err(DistributionError::MalformedMessage),
+            },
+            _ => return Err(DistributionError::MalformedMessage),
+        }
+        // fragment ids count down to 1
+        if fragment_id == 1 {
+            return Ok(self.fragments.remove(&sequence_id));
+        }
+        return Ok(None);
+    }
+}
+
+struct PeerName {
+    version: u16,
+    flags: u64,
+    creation: u32,
+    // only set in challenges
+    challenge: u32,
+    name: String,
+}
+
+// send_name and challenge messages, version 5 and 6
+fn parse_name_message(message: &[u8], is_challenge: bool) -> Result<PeerName, DistributionError> {
+    let mut fields = message;
+    let mut peer = PeerName { version: 6, flags: 0, creation: 0, challenge: 0, name: String::new() };
+    match fields.read_u8()? {
+        SEND_NAME_V5 => {
+            peer.version = fields.read_u16::<BigEndian>()?;
+            if peer.version != 5 {
+                return Err(DistributionError::MalformedMessage);
+            }
+            peer.flags = fields.read_u32::<BigEndian>()? as u64;
+            if is_challenge {
+                peer.challenge = fields.read_u32::<BigEndian>()?;
+            }
+        }
+        SEND_NAME_V6 => {
+            peer.flags = fields.read_u64::<BigEndian>()?;
+            if is_challenge {
+                peer.challenge = fields.read_u32::<BigEndian>()?;
+            }
+            peer.creation = fields.read_u32::<BigEndian>()?;
+            let len = fields.read_u16::<BigEndian>()? as usize;
+            if fields.len() < len {
+                return Err(DistributionError::MalformedMessage);
+            }
+            fields = &fields[..len];
+        }
+        tag => return Err(DistributionError::UnexpectedMessage { tag }),
+    }
+    peer.name = String::from_utf8(fields.to_vec()).map_err(|_| DistributionError::MalformedMessage)?;
+    return Ok(peer);
+}
+
+fn check_flags(flags: u64) -> Result<(), DistributionError> {
+    let missing = DFLAG_REQUIRED & !flags;
+    if missing != 0 {
+        return Err(DistributionError::MissingFlags { missing });
+    }
+    return Ok(());
+}
+
+// The atom cache is not negotiated, so headers cannot refer to it
+fn skip_atom_cache_refs(fields: &mut &[u8]) -> Result<(), DistributionError> {
+    if fields.read_u8()? != 0 {
+        return Err(DistributionError::MalformedMessage);
+    }
+    return Ok(());
+}
+
+// Terms after a distribution header may come without a version byte
+fn decode_message(bytes: &[u8]) -> Result<(ErlTerm, Option<ErlTerm>), DistributionError> {
+    let mut decoder = SliceDecoder::new(bytes);
+    let next_term = |decoder: &mut SliceDecoder| match bytes.get(decoder.position()) {
+        Some(&constants::TERM_FORMAT_VERSION) => decoder.decode(),
+        _ => decoder.decode_without_version(),
+    };
+    let control = next_term(&mut decoder)?;
+    if decoder.is_at_end() {
+        return Ok((control, None));
+    }
+    let message = next_term(&mut decoder)?;
+    return Ok((control, Some(message)));
+}
+
+fn write_handshake(stream: &mut TcpStream, message: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(message.len() + 2);
+    packet.write_u16::<BigEndian>(message.len() as u16)?;
+    packet.extend_from_slice(message);
+    return stream.write_all(&packet);
+}
+
+fn read_handshake(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let len = stream.read_u16::<BigEndian>()? as usize;
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message)?;
+    return Ok(message);
+}
+
+fn with_timeout(stream: &TcpStream, timeout: Duration) -> io::Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    return stream.set_write_timeout(Some(timeout));
+}
+
+// MD5 of the cookie followed by the challenge in decimal
+fn digest(cookie: &str, challenge: u32) -> [u8; 16] {
+    let mut md5 = Md5::new();
+    md5.update(cookie.as_bytes());
+    md5.update(challenge.to_string().as_bytes());
+    return md5.finalize().into();
+}
+
+fn random_u32() -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    getrandom::getrandom(&mut bytes).map_err(io::Error::from)?;
+    return Ok(u32::from_be_bytes(bytes));
+}
+
+// 0 means "any incarnation" in older nodes, and is never handed out
+fn random_creation() -> io::Result<u32> {
+    loop {
+        let creation = random_u32()?;
+        if creation != 0 {
+            return Ok(creation);
+        }
+    }
+}
+
+pub(crate) fn split_node_name(name: &str) -> Result<(&str, &str), DistributionError> {
+    match name.split_once('@') {
+        Some((alive, host)) if !alive.is_empty() && !host.is_empty() => Ok((alive, host)),
+        _ => Err(DistributionError::InvalidNodeName { name: name.to_string() }),
+    }
+}
@@ -0,0 +1,274 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use num::bigint::Sign;
+use std::io;
+
+use crate::*;
+use crate::constants;
+
+pub struct Encoder<'a> {
+    writer: Box<dyn io::Write + 'a>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(writer: Box<dyn io::Write + 'a>) -> Self {
+        Encoder { writer, depth: 0, max_depth: DEFAULT_MAX_NESTING_DEPTH }
+    }
+
+    // Rejects terms nested more than max_depth levels deep, encoding is
+    // recursive like decoding
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        return self;
+    }
+
+    pub fn encode(&mut self, term: &ErlTerm) -> EncodingResult {
+        self.writer.write_u8(constants::TERM_FORMAT_VERSION)?;
+        return self.encode_term(term);
+    }
+
+    // Encodes a term without the version byte
+    pub(crate) fn encode_term(&mut self, term: &ErlTerm) -> EncodingResult {
+        if self.depth >= self.max_depth {
+            return Err(EncodingError::NestingTooDeep { limit: self.max_depth });
+        }
+        self.depth += 1;
+        let result = self.encode_tagged(term);
+        self.depth -= 1;
+        return result;
+    }
+
+    fn encode_tagged(&mut self, term: &ErlTerm) -> EncodingResult {
+        match term {
+            ErlTerm::Atom(name) => self.encode_atom(name),
+            ErlTerm::SmallInteger(i) => {
+                self.writer.write_u8(constants::SMALL_INTEGER_EXT)?;
+                self.writer.write_u8(*i)?;
+                Ok(())
+            }
+            ErlTerm::Integer(i) => {
+                self.writer.write_u8(constants::INTEGER_EXT)?;
+                self.writer.write_i32::<BigEndian>(*i)?;
+                Ok(())
+            }
+            ErlTerm::BigInteger(i) => self.encode_big_integer(i),
+            ErlTerm::Float(f) => {
+                self.writer.write_u8(constants::NEW_FLOAT_EXT)?;
+                self.writer.write_f64::<BigEndian>(f.0)?;
+                Ok(())
+            }
+            ErlTerm::Binary(bytes) => {
+                self.writer.write_u8(constants::BINARY_EXT)?;
+                self.write_length_u32(bytes.len())?;
+                self.writer.write_all(bytes)?;
+                Ok(())
+            }
+            ErlTerm::BitBinary(bytes, tail_len) => self.encode_bit_binary(bytes, *tail_len),
+            ErlTerm::Pid(pid) => self.encode_pid(pid),
+            ErlTerm::V3Port(port) => {
+                self.writer.write_u8(constants::NEW_PORT_EXT)?;
+                self.encode_atom(&port.node.name)?;
+                self.writer.write_u32::<BigEndian>(port.id)?;
+                self.writer.write_u32::<BigEndian>(port.creation)?;
+                Ok(())
+            }
+            ErlTerm::V4Port(port) => {
+                self.writer.write_u8(constants::V4_PORT_EXT)?;
+                self.encode_atom(&port.node.name)?;
+                self.writer.write_u64::<BigEndian>(port.id)?;
+                self.writer.write_u32::<BigEndian>(port.creation)?;
+                Ok(())
+            }
+            ErlTerm::Tuple(t) => self.encode_tuple(&t.elements),
+            ErlTerm::List(l) => {
+                if l.is_nil() {
+                    self.writer.write_u8(constants::NIL_EXT)?;
+                    return Ok(());
+                }
+                if let Some(bytes) = string_bytes(&l.elements) {
+                    self.writer.write_u8(constants::STRING_EXT)?;
+                    self.writer.write_u16::<BigEndian>(bytes.len() as u16)?;
+                    self.writer.write_all(&bytes)?;
+                    return Ok(());
+                }
+                self.encode_list_elements(&l.elements)?;
+                self.writer.write_u8(constants::NIL_EXT)?;
+                Ok(())
+            }
+            ErlTerm::ImproperList(l) => {
+                self.encode_list_elements(&l.elements)?;
+                self.encode_term(&l.tail)
+            }
+            ErlTerm::Ref(r) => {
+                if r.id.len() > u16::MAX as usize {
+                    return Err(EncodingError::ValueOutOfRange());
+                }
+                self.writer.write_u8(constants::NEWER_REFERENCE_EXT)?;
+                self.writer.write_u16::<BigEndian>(r.id.len() as u16)?;
+                self.encode_atom(&r.node.name)?;
+                self.writer.write_u32::<BigEndian>(r.creation)?;
+                for id in &r.id {
+                    self.writer.write_u32::<BigEndian>(*id)?;
+                }
+                Ok(())
+            }
+            ErlTerm::ExternalFun(fun) => {
+                self.writer.write_u8(constants::FUN_EXPORT_EXT)?;
+                self.encode_atom(&fun.module.name)?;
+                self.encode_atom(&fun.function_name.name)?;
+                self.writer.write_u8(constants::SMALL_INTEGER_EXT)?;
+                self.writer.write_u8(fun.arity)?;
+                Ok(())
+            }
+            ErlTerm::InternalFun(fun) => self.encode_internal_fun(fun),
+            ErlTerm::Map(m) => {
+                self.writer.write_u8(constants::MAP_EXT)?;
+                self.write_length_u32(m.entries.len())?;
+                for (key, value) in &m.entries {
+                    self.encode_term(key)?;
+                    self.encode_term(value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn write_length_u32(&mut self, n: usize) -> EncodingResult {
+        if n > u32::MAX as usize {
+            return Err(EncodingError::ValueOutOfRange());
+        }
+        self.writer.write_u32::<BigEndian>(n as u32)?;
+        return Ok(());
+    }
+
+    fn encode_atom(&mut self, name: &str) -> EncodingResult {
+        let bytes = name.as_bytes();
+        if bytes.len() <= u8::MAX as usize {
+            self.writer.write_u8(constants::SMALL_ATOM_UTF8_EXT)?;
+            self.writer.write_u8(bytes.len() as u8)?;
+        } else if bytes.len() <= u16::MAX as usize {
+            self.writer.write_u8(constants::ATOM_UTF8_EXT)?;
+            self.writer.write_u16::<BigEndian>(bytes.len() as u16)?;
+        } else {
+            return Err(EncodingError::ValueOutOfRange());
+        }
+        self.writer.write_all(bytes)?;
+        return Ok(());
+    }
+
+    fn encode_big_integer(&mut self, i: &BigInt) -> EncodingResult {
+        // section 12.18:
+        // The digits are stored with the least significant byte stored first.
+        let (sign, digits) = i.to_bytes_le();
+        let sign_byte = if sign == Sign::Minus { 1 } else { 0 };
+        if digits.len() <= u8::MAX as usize {
+            self.writer.write_u8(constants::SMALL_BIG_EXT)?;
+            self.writer.write_u8(digits.len() as u8)?;
+        } else {
+            self.writer.write_u8(constants::LARGE_BIG_EXT)?;
+            self.write_length_u32(digits.len())?;
+        }
+        self.writer.write_u8(sign_byte)?;
+        self.writer.write_all(&digits)?;
+        return Ok(());
+    }
+
+    fn encode_bit_binary(&mut self, bytes: &[u8], tail_len: u8) -> EncodingResult {
+        if tail_len > 8 || (bytes.is_empty() != (tail_len == 0)) {
+            return Err(EncodingError::ValueOutOfRange());
+        }
+        self.writer.write_u8(constants::BIT_BINARY_EXT)?;
+        self.write_length_u32(bytes.len())?;
+        self.writer.write_u8(tail_len)?;
+        if let Some((last, init)) = bytes.split_last() {
+            self.writer.write_all(init)?;
+            // the decoder keeps the trailing bits right-aligned,
+            // the external format keeps them left-aligned
+            self.writer.write_u8(((*last as u16) << (8 - tail_len)) as u8)?;
+        }
+        return Ok(());
+    }
+
+    fn encode_pid(&mut self, pid: &ErlPid) -> EncodingResult {
+        self.writer.write_u8(constants::NEW_PID_EXT)?;
+        self.encode_atom(&pid.node.name)?;
+        self.writer.write_u32::<BigEndian>(pid.id)?;
+        self.writer.write_u32::<BigEndian>(pid.serial)?;
+        self.writer.write_u32::<BigEndian>(pid.creation)?;
+        return Ok(());
+    }
+
+    fn encode_tuple(&mut self, elements: &[ErlTerm]) -> EncodingResult {
+        if elements.len() <= u8::MAX as usize {
+            self.writer.write_u8(constants::SMALL_TUPLE_EXT)?;
+            self.writer.write_u8(elements.len() as u8)?;
+        } else {
+            self.writer.write_u8(constants::LARGE_TUPLE_EXT)?;
+            self.write_length_u32(elements.len())?;
+        }
+        for element in elements {
+            self.encode_term(element)?;
+        }
+        return Ok(());
+    }
+
+    // Everything but the tail
+    fn encode_list_elements(&mut self, elements: &[ErlTerm]) -> EncodingResult {
+        self.writer.write_u8(constants::LIST_EXT)?;
+        self.write_length_u32(elements.len())?;
+        for element in elements {
+            self.encode_term(element)?;
+        }
+        return Ok(());
+    }
+
+    fn encode_internal_fun(&mut self, fun: &InternalFun) -> EncodingResult {
+        // the size field covers the entire term after the tag, including itself,
+        // so the rest of the term has to be encoded first
+        let mut body = Vec::new();
+        {
+            let mut inner = Encoder::new(Box::new(&mut body)).with_max_depth(self.max_depth);
+            inner.depth = self.depth;
+            inner.writer.write_u8(fun.arity)?;
+            inner.writer.write_all(&fun.uniq_beam_md5)?;
+            inner.writer.write_u32::<BigEndian>(fun.index)?;
+            inner.writer.write_u32::<BigEndian>(fun.free_vars.len() as u32)?;
+            inner.encode_atom(&fun.module.name)?;
+            inner.encode_term(&integer_term(fun.old_index))?;
+            inner.encode_term(&integer_term(fun.old_uniq_hash))?;
+            inner.encode_pid(&fun.creator_pid)?;
+            for var in &fun.free_vars {
+                inner.encode_term(var)?;
+            }
+        }
+
+        self.writer.write_u8(constants::NEW_FUN_EXT)?;
+        self.write_length_u32(4 + body.len())?;
+        self.writer.write_all(&body)?;
+        return Ok(());
+    }
+}
+
+// Erlang uses STRING_EXT for proper lists of up to 65535 small integers
+fn string_bytes(elements: &[ErlTerm]) -> Option<Vec<u8>> {
+    if elements.len() > u16::MAX as usize {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(elements.len());
+    for element in elements {
+        match element {
+            ErlTerm::SmallInteger(b) => bytes.push(*b),
+            _ => return None,
+        }
+    }
+    return Some(bytes);
+}
+
+// Erlang uses SMALL_INTEGER_EXT whenever the value fits
+fn integer_term(i: i32) -> ErlTerm {
+    match u8::try_from(i) {
+        Ok(small) => ErlTerm::SmallInteger(small),
+        Err(_) => ErlTerm::Integer(i),
+    }
+}
@@ -0,0 +1,385 @@
+// The Erlang Port Mapper Daemon protocol (requires the epmd feature).
+//
+// Nodes register their name and distribution port with the EPMD of their
+// host, and look up the port of other nodes before connecting to them.
+// EpmdClient implements the requests used for that, EpmdServer is a
+// minimal stand-in for the real daemon, for tests that cannot rely on
+// an Erlang installation.
+//
+// Every request is a connection of its own: a 2 byte length, the request
+// tag and its fields. The daemon answers and closes the connection, except
+// for registrations, which last for as long as the connection stays open.
+// See https://www.erlang.org/doc/apps/erts/erl_dist_protocol.html
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use thiserror::Error;
+
+pub const EPMD_DEFAULT_PORT: u16 = 4369;
+
+const ALIVE2_REQ: u8 = 120;
+const ALIVE2_RESP: u8 = 121;
+const ALIVE2_X_RESP: u8 = 118;
+const PORT_PLEASE2_REQ: u8 = 122;
+const PORT2_RESP: u8 = 119;
+const NAMES_REQ: u8 = 110;
+
+// Distribution protocol versions of OTP 23 and later
+const HIGHEST_VERSION: u16 = 6;
+const LOWEST_VERSION: u16 = 5;
+
+#[derive(Error, Debug)]
+pub enum EpmdError {
+    #[error("failed to talk to EPMD")]
+    Io(#[from] io::Error),
+    #[error("unexpected EPMD response")]
+    UnexpectedResponse { tag: u8 },
+    #[error("malformed EPMD response")]
+    MalformedResponse,
+    #[error("EPMD refused to register the node")]
+    RegistrationRefused { result: u8 },
+    #[error("node name or extra data is longer than 65535 bytes")]
+    TooLong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeType {
+    Normal,
+    Hidden,
+    Other(u8),
+}
+
+impl From<u8> for NodeType {
+    fn from(value: u8) -> Self {
+        match value {
+            77 => NodeType::Normal,
+            72 => NodeType::Hidden,
+            other => NodeType::Other(other),
+        }
+    }
+}
+
+impl From<NodeType> for u8 {
+    fn from(node_type: NodeType) -> Self {
+        match node_type {
+            NodeType::Normal => 77,
+            NodeType::Hidden => 72,
+            NodeType::Other(other) => other,
+        }
+    }
+}
+
+// What EPMD knows about a node. The name is the part before the @
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub name: String,
+    pub port: u16,
+    pub node_type: NodeType,
+    // 0 is TCP/IPv4, the only protocol EPMD defines
+    pub protocol: u8,
+    pub highest_version: u16,
+    pub lowest_version: u16,
+    pub extra: Vec<u8>,
+}
+
+impl NodeInfo {
+    // A normal node that speaks the distribution protocol of OTP 23 and later
+    pub fn new(name: &str, port: u16) -> Self {
+        NodeInfo {
+            name: name.to_string(),
+            port,
+            node_type: NodeType::Normal,
+            protocol: 0,
+            highest_version: HIGHEST_VERSION,
+            lowest_version: LOWEST_VERSION,
+            extra: Vec::new(),
+        }
+    }
+}
+
+//
+// Client
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpmdClient {
+    addr: SocketAddr,
+}
+
+impl EpmdClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        EpmdClient { addr }
+    }
+
+    // The local EPMD, at ERL_EPMD_PORT when that is set, like erl does
+    pub fn localhost() -> Self {
+        return EpmdClient::new(SocketAddr::from((Ipv4Addr::LOCALHOST, epmd_port())));
+    }
+
+    // The EPMD of another host, at ERL_EPMD_PORT when that is set
+    pub fn on_host(host: &str) -> io::Result<Self> {
+        let addr = (host, epmd_port())
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("cannot resolve {}", host)))?;
+        return Ok(EpmdClient::new(addr));
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    // PORT_PLEASE2_REQ, None when no node with this name is registered
+    pub fn port_please(&self, name: &str) -> Result<Option<NodeInfo>, EpmdError> {
+        let mut stream = self.request(PORT_PLEASE2_REQ, name.as_bytes())?;
+        let tag = stream.read_u8()?;
+        if tag != PORT2_RESP {
+            return Err(EpmdError::UnexpectedResponse { tag });
+        }
+        if stream.read_u8()? != 0 {
+            return Ok(None);
+        }
+        return Ok(Some(read_node_info(&mut stream)?));
+    }
+
+    // NAMES_REQ, the names and ports of all registered nodes
+    pub fn names(&self) -> Result<Vec<(String, u16)>, EpmdError> {
+        let mut stream = self.request(NAMES_REQ, &[])?;
+        let _epmd_port = stream.read_u32::<BigEndian>()?;
+        let mut text = String::new();
+        stream.read_to_string(&mut text)?;
+        return text.lines().map(parse_names_line).collect();
+    }
+
+    // ALIVE2_REQ. The node stays registered for as long as the returned
+    // Registration is kept around
+    pub fn register(&self, node: &NodeInfo) -> Result<Registration, EpmdError> {
+        let mut body = Vec::new();
+        write_node_info(&mut body, node)?;
+        let mut stream = self.request(ALIVE2_REQ, &body)?;
+        let tag = stream.read_u8()?;
+        let result = stream.read_u8()?;
+        // EPMD before OTP 23 answers with a 16 bit creation
+        let creation = match tag {
+            ALIVE2_X_RESP => stream.read_u32::<BigEndian>()?,
+            ALIVE2_RESP => stream.read_u16::<BigEndian>()? as u32,
+            _ => return Err(EpmdError::UnexpectedResponse { tag }),
+        };
+        if result != 0 {
+            return Err(EpmdError::RegistrationRefused { result });
+        }
+        return Ok(Registration { _stream: stream, creation });
+    }
+
+    fn request(&self, tag: u8, body: &[u8]) -> Result<TcpStream, EpmdError> {
+        let len = u16::try_from(body.len() + 1).map_err(|_| EpmdError::TooLong)?;
+        let mut request = Vec::with_capacity(body.len() + 3);
+        request.write_u16::<BigEndian>(len)?;
+        request.push(tag);
+        request.extend_from_slice(body);
+
+        let mut stream = TcpStream::connect(self.addr)?;
+        stream.write_all(&request)?;
+        return Ok(stream);
+    }
+}
+
+// A node registration, dropping it closes the connection to EPMD
+// and so unregisters the node
+#[derive(Debug)]
+pub struct Registration {
+    _stream: TcpStream,
+    creation: u32,
+}
+
+impl Registration {
+    // Tells incarnations of a node with the same name apart, goes into
+    // the pids, ports and references the node creates
+    pub fn creation(&self) -> u32 {
+        return self.creation;
+    }
+}
+
+fn epmd_port() -> u16 {
+    return env::var("ERL_EPMD_PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(EPMD_DEFAULT_PORT);
+}
+
+// name foo at port 4370
+fn parse_names_line(line: &str) -> Result<(String, u16), EpmdError> {
+    let rest = line.strip_prefix("name ").ok_or(EpmdError::MalformedResponse)?;
+    let (name, port) = rest.rsplit_once(" at port ").ok_or(EpmdError::MalformedResponse)?;
+    let port = port.trim().parse().map_err(|_| EpmdError::MalformedResponse)?;
+    return Ok((name.to_string(), port));
+}
+
+// The fields ALIVE2_REQ and PORT2_RESP have in common
+fn write_node_info(out: &mut Vec<u8>, node: &NodeInfo) -> Result<(), EpmdError> {
+    let name_len = u16::try_from(node.name.len()).map_err(|_| EpmdError::TooLong)?;
+    let extra_len = u16::try_from(node.extra.len()).map_err(|_| EpmdError::TooLong)?;
+    out.write_u16::<BigEndian>(node.port)?;
+    out.push(node.node_type.into());
+    out.push(node.protocol);
+    out.write_u16::<BigEndian>(node.highest_version)?;
+    out.write_u16::<BigEndian>(node.lowest_version)?;
+    out.write_u16::<BigEndian>(name_len)?;
+    out.extend_from_slice(node.name.as_bytes());
+    out.write_u16::<BigEndian>(extra_len)?;
+    out.extend_from_slice(&node.extra);
+    return Ok(());
+}
+
+fn read_node_info(reader: &mut impl Read) -> Result<NodeInfo, EpmdError> {
+    let port = reader.read_u16::<BigEndian>()?;
+    let node_type = NodeType::from(reader.read_u8()?);
+    let protocol = reader.read_u8()?;
+    let highest_version = reader.read_u16::<BigEndian>()?;
+    let lowest_version = reader.read_u16::<BigEndian>()?;
+    let name = read_u16_prefixed(reader)?;
+    let name = String::from_utf8(name).map_err(|_| EpmdError::MalformedResponse)?;
+    let extra = read_u16_prefixed(reader)?;
+    return Ok(NodeInfo { name, port, node_type, protocol, highest_version, lowest_version, extra });
+}
+
+fn read_u16_prefixed(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = reader.read_u16::<BigEndian>()? as usize;
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    return Ok(bytes);
+}
+
+//
+// Server
+//
+
+// An in-process EPMD on a thread of its own, stopped when dropped
+pub struct EpmdServer {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Registry {
+    nodes: Mutex<HashMap<String, NodeInfo>>,
+    last_creation: AtomicU32,
+}
+
+impl EpmdServer {
+    // Listens on an ephemeral port of the loopback interface
+    pub fn start() -> io::Result<Self> {
+        return EpmdServer::bind((Ipv4Addr::LOCALHOST, 0));
+    }
+
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let registry = Arc::new(Registry::default());
+
+        let accepting = stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    // a client that goes away mid-request only ends its own connection
+                    let _ = serve(stream, &registry, addr.port());
+                });
+            }
+        });
+        return Ok(EpmdServer { addr, stopped });
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    pub fn client(&self) -> EpmdClient {
+        return EpmdClient::new(self.addr);
+    }
+}
+
+impl Drop for EpmdServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wakes up the accepting thread so that it notices
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(mut stream: TcpStream, registry: &Registry, epmd_port: u16) -> Result<(), EpmdError> {
+    let len = stream.read_u16::<BigEndian>()? as usize;
+    let mut request = vec![0; len];
+    stream.read_exact(&mut request)?;
+    let Some((&tag, mut body)) = request.split_first() else { return Ok(()) };
+
+    match tag {
+        ALIVE2_REQ => {
+            let node = read_node_info(&mut body)?;
+            let name = node.name.clone();
+            let registered = {
+                let mut nodes = registry.nodes.lock().unwrap();
+                if nodes.contains_key(&name) {
+                    false
+                } else {
+                    nodes.insert(name.clone(), node);
+                    true
+                }
+            };
+            if !registered {
+                stream.write_all(&[ALIVE2_X_RESP, 1, 0, 0, 0, 0])?;
+                return Ok(());
+            }
+            let creation = registry.last_creation.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut response = vec![ALIVE2_X_RESP, 0];
+            response.write_u32::<BigEndian>(creation)?;
+            let written = stream.write_all(&response);
+            // registered until the node closes the connection
+            if written.is_ok() {
+                let _ = io::copy(&mut stream, &mut io::sink());
+            }
+            registry.nodes.lock().unwrap().remove(&name);
+            written?;
+        }
+        PORT_PLEASE2_REQ => {
+            let name = String::from_utf8_lossy(body);
+            let node = registry.nodes.lock().unwrap().get(name.as_ref()).cloned();
+            let mut response = vec![PORT2_RESP];
+            match node {
+                Some(node) => {
+                    response.push(0);
+                    write_node_info(&mut response, &node)?;
+                }
+                None => response.push(1),
+            }
+            stream.write_all(&response)?;
+        }
+        NAMES_REQ => {
+            let mut response = Vec::new();
+            response.write_u32::<BigEndian>(epmd_port as u32)?;
+            let nodes = registry.nodes.lock().unwrap();
+            let mut names: Vec<&NodeInfo> = nodes.values().collect();
+            names.sort_by(|a, b| a.name.cmp(&b.name));
+            for node in names {
+                response.extend_from_slice(format!("name {} at port {}\n", node.name, node.port).as_bytes());
+            }
+            drop(nodes);
+            stream.write_all(&response)?;
+        }
+        // other requests are closed without an answer, like EPMD does
+        _ => {}
+    }
+    return Ok(());
+}
@@ -0,0 +1,243 @@
+// Reading ets:tab2file dumps (requires the ets feature).
+//
+// tab2file writes a disk_log (see disk_log.rs) whose first term is a
+// header, a tuple of {Key, Value} pairs with what ets:info/1 says about the
+// table:
+//
+//     {{name, users}, {type, set}, {protection, protected}, {named_table, true},
+//      {keypos, 1}, {size, 2}, ..., {major_version, 1}, {minor_version, 0},
+//      {extended_info, [md5sum, object_count]}}
+//
+// followed by one term per object. With extended_info the last term is
+//
+//     ['$end_of_table', [{count, Objects}, {md5, MD5}]]
+//
+// where MD5 is over the term_to_binary of the header and every object, in
+// the order they were logged. TabFileReader reads the header, streams the
+// objects and checks the count and the MD5 once it reaches the end, as
+// ets:file2tab(File, [{verify, true}]) does.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+use crate::*;
+
+#[derive(Error, Debug)]
+pub enum TabFileError {
+    #[error("failed to read the table file")]
+    DiskLog(#[from] DiskLogError),
+    #[error("the table file has no valid header")]
+    InvalidHeader,
+    #[error("table object is not a tuple with the key at keypos")]
+    InvalidObject { object: Box<ErlTerm> },
+    #[error("the number of objects does not match the table file's count")]
+    CountMismatch { expected: u64, actual: u64 },
+    #[error("the table file's MD5 does not match its contents")]
+    ChecksumMismatch,
+    #[error("the table file ends without its end of table marker")]
+    MissingEndOfTable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableType {
+    Set,
+    OrderedSet,
+    Bag,
+    DuplicateBag,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableHeader {
+    pub name: Atom,
+    pub table_type: TableType,
+    pub protection: Atom,
+    pub named_table: bool,
+    pub keypos: usize,
+    // the number of objects when the table was dumped
+    pub size: u64,
+    // absent in files written before OTP R14B
+    pub version: Option<(u32, u32)>,
+    pub md5sum: bool,
+    pub object_count: bool,
+    // every {Key, Value} of the header, including the ones above
+    pub info: Vec<(Atom, ErlTerm)>,
+}
+
+impl TryFrom<&ErlTerm> for TableHeader {
+    type Error = TabFileError;
+
+    fn try_from(header: &ErlTerm) -> Result<Self, Self::Error> {
+        let ErlTerm::Tuple(Tuple { elements }) = header else { return Err(TabFileError::InvalidHeader) };
+        let mut info = Vec::with_capacity(elements.len());
+        for element in elements {
+            match element {
+                ErlTerm::Tuple(Tuple { elements: pair }) => match pair.as_slice() {
+                    [ErlTerm::Atom(key), value] => info.push((Atom { name: *key }, value.clone())),
+                    _ => return Err(TabFileError::InvalidHeader),
+                },
+                _ => return Err(TabFileError::InvalidHeader),
+            }
+        }
+        let get = |key: &str| info.iter().find(|(k, _)| k.name.as_str() == key).map(|(_, value)| value);
+        let atom = |key: &str| match get(key) {
+            Some(ErlTerm::Atom(name)) => Ok(Atom { name: *name }),
+            _ => Err(TabFileError::InvalidHeader),
+        };
+        let integer = |key: &str| match get(key) {
+            Some(ErlTerm::SmallInteger(i)) => Ok(*i as u64),
+            Some(ErlTerm::Integer(i)) if *i >= 0 => Ok(*i as u64),
+            Some(ErlTerm::BigInteger(i)) => u64::try_from(i).map_err(|_| TabFileError::InvalidHeader),
+            _ => Err(TabFileError::InvalidHeader),
+        };
+
+        let table_type = match atom("type")?.name.as_str() {
+            "set" => TableType::Set,
+            "ordered_set" => TableType::OrderedSet,
+            "bag" => TableType::Bag,
+            "duplicate_bag" => TableType::DuplicateBag,
+            _ => return Err(TabFileError::InvalidHeader),
+        };
+        let named_table = match atom("named_table")?.name.as_str() {
+            "true" => true,
+            "false" => false,
+            _ => return Err(TabFileError::InvalidHeader),
+        };
+        let version = match (integer("major_version"), integer("minor_version")) {
+            (Ok(major), Ok(minor)) => Some((major as u32, minor as u32)),
+            _ => None,
+        };
+        let extended_info = match get("extended_info") {
+            Some(ErlTerm::List(List { elements })) => elements.clone(),
+            Some(_) => return Err(TabFileError::InvalidHeader),
+            None => Vec::new(),
+        };
+        let has = |flag: &str| extended_info.iter().any(|e| matches!(e, ErlTerm::Atom(a) if a.as_str() == flag));
+        let keypos = integer("keypos")? as usize;
+        if keypos == 0 {
+            return Err(TabFileError::InvalidHeader);
+        }
+
+        return Ok(TableHeader {
+            name: atom("name")?,
+            table_type,
+            protection: atom("protection")?,
+            named_table,
+            keypos,
+            size: integer("size")?,
+            version,
+            md5sum: has("md5sum"),
+            object_count: has("object_count"),
+            info,
+        });
+    }
+}
+
+pub struct TabFileReader<R: Read> {
+    log: DiskLogReader<R>,
+    header: TableHeader,
+    md5: Option<Md5>,
+    count: u64,
+    done: bool,
+}
+
+impl TabFileReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, TabFileError> {
+        return TabFileReader::new(BufReader::new(File::open(path).map_err(DiskLogError::from)?));
+    }
+}
+
+impl<R: Read> TabFileReader<R> {
+    // Reads the header
+    pub fn new(reader: R) -> Result<Self, TabFileError> {
+        let mut log = DiskLogReader::new(reader)?;
+        let (term, bytes) = log.next_chunk()?.ok_or(TabFileError::InvalidHeader)?;
+        let header = TableHeader::try_from(&term)?;
+        let md5 = header.md5sum.then(|| Md5::new().chain_update(&bytes));
+        return Ok(TabFileReader { log, header, md5, count: 0, done: false });
+    }
+
+    pub fn header(&self) -> &TableHeader {
+        return &self.header;
+    }
+
+    // Corrupt chunks of the underlying log skipped so far. A file with
+    // extended info fails verification after any of them
+    pub fn bad_chunks(&self) -> &[BadChunk] {
+        return self.log.bad_chunks();
+    }
+
+    fn next_object(&mut self) -> Result<Option<ErlTerm>, TabFileError> {
+        let Some((term, bytes)) = self.log.next_chunk()? else {
+            if self.header.md5sum || self.header.object_count {
+                return Err(TabFileError::MissingEndOfTable);
+            }
+            return Ok(None);
+        };
+        if let Some(end) = end_of_table(&term) {
+            self.verify(end)?;
+            return Ok(None);
+        }
+        match &term {
+            ErlTerm::Tuple(Tuple { elements }) if elements.len() >= self.header.keypos => {}
+            _ => return Err(TabFileError::InvalidObject { object: Box::new(term) }),
+        }
+        if let Some(md5) = &mut self.md5 {
+            md5.update(&bytes);
+        }
+        self.count += 1;
+        return Ok(Some(term));
+    }
+
+    fn verify(&mut self, end: &[ErlTerm]) -> Result<(), TabFileError> {
+        for info in end {
+            let ErlTerm::Tuple(Tuple { elements }) = info else { continue };
+            match elements.as_slice() {
+                [ErlTerm::Atom(key), count] if key.as_str() == "count" => {
+                    let expected = match count {
+                        ErlTerm::SmallInteger(i) => *i as u64,
+                        ErlTerm::Integer(i) => *i as u64,
+                        ErlTerm::BigInteger(i) => u64::try_from(i).unwrap_or(u64::MAX),
+                        _ => u64::MAX,
+                    };
+                    if expected != self.count {
+                        return Err(TabFileError::CountMismatch { expected, actual: self.count });
+                    }
+                }
+                [ErlTerm::Atom(key), ErlTerm::Binary(md5)] if key.as_str() == "md5" => {
+                    let actual = self.md5.take().map(|md5| md5.finalize().to_vec());
+                    if actual.as_ref() != Some(md5) {
+                        return Err(TabFileError::ChecksumMismatch);
+                    }
+                }
+                _ => {}
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl<R: Read> Iterator for TabFileReader<R> {
+    type Item = Result<ErlTerm, TabFileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let object = self.next_object();
+        self.done = !matches!(object, Ok(Some(_)));
+        return object.transpose();
+    }
+}
+
+// The info of ['$end_of_table', Info]
+fn end_of_table(term: &ErlTerm) -> Option<&[ErlTerm]> {
+    let ErlTerm::List(List { elements }) = term else { return None };
+    match elements.as_slice() {
+        [ErlTerm::Atom(marker), ErlTerm::List(info)] if marker.as_str() == "$end_of_table" => Some(&info.elements),
+        _ => None,
+    }
+}
@@ -0,0 +1,359 @@
+// proptest strategies that generate well-formed terms, together with
+// their external format.
+//
+// The bytes are produced here rather than by Encoder, and pick between
+// the equivalent encodings a term can have (ATOM_EXT vs ATOM_UTF8_EXT,
+// SMALL_BIG_EXT vs LARGE_BIG_EXT and so on), so they can be used to test
+// the decoders independently of the encoder.
+
+use byteorder::{BigEndian, WriteBytesExt};
+use num::bigint::{BigInt, Sign};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::*;
+use crate::constants;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermParams {
+    // how deep tuples, lists, maps and funs can nest
+    pub depth: u32,
+    // maximum number of elements in a tuple, list, map or fun environment
+    pub size: usize,
+    // maximum length of binaries and of big integer digits
+    pub max_binary_len: usize,
+}
+
+impl Default for TermParams {
+    fn default() -> Self {
+        TermParams { depth: 3, size: 8, max_binary_len: 64 }
+    }
+}
+
+impl Arbitrary for ErlTerm {
+    type Parameters = TermParams;
+    type Strategy = BoxedStrategy<ErlTerm>;
+
+    fn arbitrary_with(params: TermParams) -> Self::Strategy {
+        return term_with_etf_bytes(params).prop_map(|(term, _)| term).boxed();
+    }
+}
+
+impl Arbitrary for InternedAtom {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<InternedAtom>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        return atom_name().prop_filter_map("atom table is full", |name| InternedAtom::new(&name).ok()).boxed();
+    }
+}
+
+// The external format of a term, starting with the version byte
+pub fn etf_bytes(params: TermParams) -> BoxedStrategy<Vec<u8>> {
+    return term_with_etf_bytes(params).prop_map(|(_, bytes)| bytes).boxed();
+}
+
+// A term and one of its external format encodings, starting with the
+// version byte. Decoding the bytes produces the term.
+pub fn term_with_etf_bytes(params: TermParams) -> BoxedStrategy<(ErlTerm, Vec<u8>)> {
+    return body(params)
+        .prop_map(|(term, body)| {
+            let mut bytes = Vec::with_capacity(body.len() + 1);
+            bytes.push(constants::TERM_FORMAT_VERSION);
+            bytes.extend_from_slice(&body);
+            (term, bytes)
+        })
+        .boxed();
+}
+
+type Encoded = (ErlTerm, Vec<u8>);
+
+fn body(params: TermParams) -> BoxedStrategy<Encoded> {
+    let size = params.size;
+    let leaf = leaf(params);
+    let desired_size = (size as u32).saturating_mul(params.depth.max(1)).max(1);
+    return leaf
+        .prop_recursive(params.depth, desired_size, size.max(1) as u32, move |inner| {
+            let non_list = inner.clone().prop_filter("improper list tails are not lists", |(term, _)| {
+                !matches!(term, ErlTerm::List(_) | ErlTerm::ImproperList(_))
+            });
+            prop_oneof![
+                (vec(inner.clone(), 0..=size), any::<bool>()).prop_map(|(elements, large)| tuple(elements, large)),
+                vec(inner.clone(), 0..=size).prop_map(list),
+                vec((inner.clone(), inner.clone()), 0..=size).prop_map(map),
+                (vec(inner.clone(), 1..=size.max(1)), non_list).prop_map(|(elements, tail)| improper_list(elements, tail)),
+                internal_fun(vec(inner, 0..=size)),
+            ]
+        })
+        .boxed();
+}
+
+fn leaf(params: TermParams) -> BoxedStrategy<Encoded> {
+    let max_binary_len = params.max_binary_len;
+    return prop_oneof![
+        encoded_atom().prop_map(|(name, bytes)| (ErlTerm::Atom(name), bytes)),
+        any::<u8>().prop_map(|i| {
+            (ErlTerm::SmallInteger(i), vec![constants::SMALL_INTEGER_EXT, i])
+        }),
+        any::<i32>().prop_map(|i| {
+            let mut bytes = vec![constants::INTEGER_EXT];
+            bytes.extend_from_slice(&i.to_be_bytes());
+            (ErlTerm::Integer(i), bytes)
+        }),
+        big_integer(max_binary_len),
+        any::<f64>().prop_filter("Erlang floats are finite", |f| f.is_finite()).prop_map(|f| {
+            let mut bytes = vec![constants::NEW_FLOAT_EXT];
+            bytes.extend_from_slice(&f.to_be_bytes());
+            (ErlTerm::Float(OrderedFloat(f)), bytes)
+        }),
+        vec(any::<u8>(), 0..=max_binary_len).prop_map(|data| {
+            let mut bytes = vec![constants::BINARY_EXT];
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&data);
+            (ErlTerm::Binary(data), bytes)
+        }),
+        bit_binary(max_binary_len),
+        vec(any::<u8>(), 1..=max_binary_len.max(1)).prop_map(|data| {
+            let mut bytes = vec![constants::STRING_EXT];
+            bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&data);
+            let elements = data.into_iter().map(ErlTerm::SmallInteger).collect();
+            (ErlTerm::List(List { elements }), bytes)
+        }),
+        pid().prop_map(|(pid, bytes)| (ErlTerm::Pid(pid), bytes)),
+        (encoded_atom(), any::<u32>(), any::<u32>()).prop_map(|((node, mut bytes), id, creation)| {
+            bytes.insert(0, constants::NEW_PORT_EXT);
+            bytes.write_u32::<BigEndian>(id).unwrap();
+            bytes.write_u32::<BigEndian>(creation).unwrap();
+            (ErlTerm::V3Port(ErlV3Port { node: Atom { name: node }, id, creation }), bytes)
+        }),
+        (encoded_atom(), any::<u64>(), any::<u32>()).prop_map(|((node, mut bytes), id, creation)| {
+            bytes.insert(0, constants::V4_PORT_EXT);
+            bytes.write_u64::<BigEndian>(id).unwrap();
+            bytes.write_u32::<BigEndian>(creation).unwrap();
+            (ErlTerm::V4Port(ErlV4Port { node: Atom { name: node }, id, creation }), bytes)
+        }),
+        reference(),
+        (encoded_atom(), encoded_atom(), any::<u8>()).prop_map(|((module, m), (function_name, f), arity)| {
+            let mut bytes = vec![constants::FUN_EXPORT_EXT];
+            bytes.extend_from_slice(&m);
+            bytes.extend_from_slice(&f);
+            bytes.extend_from_slice(&[constants::SMALL_INTEGER_EXT, arity]);
+            let fun = ExternalFun { module: Atom { name: module }, function_name: Atom { name: function_name }, arity };
+            (ErlTerm::ExternalFun(fun), bytes)
+        }),
+    ]
+    .boxed();
+}
+
+// Up to 255 characters. Most names come from a small set so that
+// generated terms share atoms, like real ones do.
+fn atom_name() -> BoxedStrategy<String> {
+    return prop_oneof![
+        3 => prop::sample::select(vec!["ok", "error", "undefined", "true", "false", "rabbit@localhost", "nonode@nohost"])
+            .prop_map(String::from),
+        1 => vec(any::<char>(), 0..=255).prop_map(|chars| chars.into_iter().collect()),
+    ]
+    .boxed();
+}
+
+fn encoded_atom() -> BoxedStrategy<(InternedAtom, Vec<u8>)> {
+    return (atom_name(), 0..3u8)
+        .prop_filter_map("atom table is full", |(name, encoding)| {
+            let utf8 = name.as_bytes();
+            let mut bytes = Vec::with_capacity(utf8.len() + 3);
+            match encoding {
+                0 if utf8.len() <= u8::MAX as usize => {
+                    bytes.push(constants::SMALL_ATOM_UTF8_EXT);
+                    bytes.push(utf8.len() as u8);
+                    bytes.extend_from_slice(utf8);
+                }
+                // Latin1 agrees with the Windows-1252 decoding the decoders
+                // use everywhere but 0x80..0xa0
+                2 if name.chars().all(|c| (c as u32) < 0x80 || (0xa0..=0xff).contains(&(c as u32))) => {
+                    bytes.push(constants::ATOM_EXT);
+                    bytes.extend_from_slice(&(name.chars().count() as u16).to_be_bytes());
+                    bytes.extend(name.chars().map(|c| c as u8));
+                }
+                _ => {
+                    bytes.push(constants::ATOM_UTF8_EXT);
+                    bytes.extend_from_slice(&(utf8.len() as u16).to_be_bytes());
+                    bytes.extend_from_slice(utf8);
+                }
+            }
+            Some((InternedAtom::new(&name).ok()?, bytes))
+        })
+        .boxed();
+}
+
+// Always outside of the i32 range, Erlang uses INTEGER_EXT for anything smaller
+fn big_integer(max_binary_len: usize) -> BoxedStrategy<Encoded> {
+    return (vec(any::<u8>(), 4..=max_binary_len.max(4)), 1..=u8::MAX, any::<bool>(), any::<bool>())
+        .prop_map(|(mut digits, most_significant, negative, large)| {
+            digits.push(most_significant);
+            let sign = if negative { Sign::Minus } else { Sign::Plus };
+            let mut bytes = Vec::with_capacity(digits.len() + 6);
+            if large {
+                bytes.push(constants::LARGE_BIG_EXT);
+                bytes.extend_from_slice(&(digits.len() as u32).to_be_bytes());
+            } else {
+                bytes.push(constants::SMALL_BIG_EXT);
+                bytes.push(digits.len() as u8);
+            }
+            bytes.push(negative as u8);
+            bytes.extend_from_slice(&digits);
+            (ErlTerm::BigInteger(BigInt::from_bytes_le(sign, &digits)), bytes)
+        })
+        .boxed();
+}
+
+fn bit_binary(max_binary_len: usize) -> BoxedStrategy<Encoded> {
+    return (vec(any::<u8>(), 1..=max_binary_len.max(1)), 1..=8u8)
+        .prop_map(|(mut data, tail_len)| {
+            // the term keeps the trailing bits right-aligned,
+            // the external format keeps them left-aligned
+            let last = data.last_mut().unwrap();
+            *last = ((*last as u16) & ((1 << tail_len) - 1)) as u8;
+            let mut bytes = vec![constants::BIT_BINARY_EXT];
+            bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            bytes.push(tail_len);
+            bytes.extend_from_slice(&data[..data.len() - 1]);
+            bytes.push(((*data.last().unwrap() as u16) << (8 - tail_len)) as u8);
+            (ErlTerm::BitBinary(data, tail_len), bytes)
+        })
+        .boxed();
+}
+
+fn pid() -> BoxedStrategy<(ErlPid, Vec<u8>)> {
+    return (encoded_atom(), any::<u32>(), any::<u32>(), any::<u32>())
+        .prop_map(|((node, mut bytes), id, serial, creation)| {
+            bytes.insert(0, constants::NEW_PID_EXT);
+            bytes.write_u32::<BigEndian>(id).unwrap();
+            bytes.write_u32::<BigEndian>(serial).unwrap();
+            bytes.write_u32::<BigEndian>(creation).unwrap();
+            (ErlPid { node: Atom { name: node }, id, serial, creation }, bytes)
+        })
+        .boxed();
+}
+
+// Erlang references have up to 5 ID words
+fn reference() -> BoxedStrategy<Encoded> {
+    return (encoded_atom(), any::<u32>(), vec(any::<u32>(), 1..=5))
+        .prop_map(|((node, node_bytes), creation, id)| {
+            let mut bytes = vec![constants::NEWER_REFERENCE_EXT];
+            bytes.extend_from_slice(&(id.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&node_bytes);
+            bytes.write_u32::<BigEndian>(creation).unwrap();
+            for word in &id {
+                bytes.write_u32::<BigEndian>(*word).unwrap();
+            }
+            (ErlTerm::Ref(Ref { node: Atom { name: node }, creation, id }), bytes)
+        })
+        .boxed();
+}
+
+fn tuple(elements: Vec<Encoded>, large: bool) -> Encoded {
+    let mut bytes = Vec::new();
+    if large || elements.len() > u8::MAX as usize {
+        bytes.push(constants::LARGE_TUPLE_EXT);
+        bytes.extend_from_slice(&(elements.len() as u32).to_be_bytes());
+    } else {
+        bytes.push(constants::SMALL_TUPLE_EXT);
+        bytes.push(elements.len() as u8);
+    }
+    let elements = concat(elements, &mut bytes);
+    return (ErlTerm::Tuple(Tuple { elements }), bytes);
+}
+
+fn list(elements: Vec<Encoded>) -> Encoded {
+    if elements.is_empty() {
+        return (ErlTerm::List(List::nil()), vec![constants::NIL_EXT]);
+    }
+    let mut bytes = vec![constants::LIST_EXT];
+    bytes.extend_from_slice(&(elements.len() as u32).to_be_bytes());
+    let elements = concat(elements, &mut bytes);
+    bytes.push(constants::NIL_EXT);
+    return (ErlTerm::List(List { elements }), bytes);
+}
+
+// Keys are unique, like in maps that Erlang encodes
+fn map(mut pairs: Vec<(Encoded, Encoded)>) -> Encoded {
+    let mut seen = Vec::with_capacity(pairs.len());
+    pairs.retain(|((key, _), _)| {
+        let unique = !seen.contains(key);
+        seen.push(key.clone());
+        unique
+    });
+    let mut bytes = vec![constants::MAP_EXT];
+    bytes.extend_from_slice(&(pairs.len() as u32).to_be_bytes());
+    let mut entries = Vec::with_capacity(pairs.len());
+    for ((key, key_bytes), (value, value_bytes)) in pairs {
+        bytes.extend_from_slice(&key_bytes);
+        bytes.extend_from_slice(&value_bytes);
+        entries.push((key, value));
+    }
+    return (ErlTerm::Map(Map { entries }), bytes);
+}
+
+fn improper_list(elements: Vec<Encoded>, (tail, tail_bytes): Encoded) -> Encoded {
+    let mut bytes = vec![constants::LIST_EXT];
+    bytes.extend_from_slice(&(elements.len() as u32).to_be_bytes());
+    let elements = concat(elements, &mut bytes);
+    bytes.extend_from_slice(&tail_bytes);
+    return (ErlTerm::ImproperList(ImproperList { elements, tail: Box::new(tail) }), bytes);
+}
+
+fn internal_fun(free_vars: impl Strategy<Value = Vec<Encoded>> + 'static) -> BoxedStrategy<Encoded> {
+    let header = (any::<u8>(), any::<[u8; 16]>(), any::<u32>(), encoded_atom(), any::<i32>(), any::<i32>());
+    return (header, pid(), free_vars)
+        .prop_map(|(header, (creator_pid, pid_bytes), free_vars)| {
+            let (arity, uniq_beam_md5, index, (module, module_bytes), old_index, old_uniq_hash) = header;
+            let mut body = vec![arity];
+            body.extend_from_slice(&uniq_beam_md5);
+            body.write_u32::<BigEndian>(index).unwrap();
+            body.write_u32::<BigEndian>(free_vars.len() as u32).unwrap();
+            body.extend_from_slice(&module_bytes);
+            body.extend_from_slice(&integer_bytes(old_index));
+            body.extend_from_slice(&integer_bytes(old_uniq_hash));
+            body.extend_from_slice(&pid_bytes);
+            let free_vars = concat(free_vars, &mut body);
+
+            // the size covers everything after the tag, including itself
+            let mut bytes = vec![constants::NEW_FUN_EXT];
+            bytes.write_u32::<BigEndian>(4 + body.len() as u32).unwrap();
+            bytes.extend_from_slice(&body);
+            let fun = InternalFun {
+                arity,
+                uniq_beam_md5,
+                index,
+                free_variable_count: free_vars.len() as u32,
+                module: Atom { name: module },
+                old_index,
+                old_uniq_hash,
+                creator_pid,
+                free_vars,
+            };
+            (ErlTerm::InternalFun(fun), bytes)
+        })
+        .boxed();
+}
+
+fn integer_bytes(i: i32) -> Vec<u8> {
+    match u8::try_from(i) {
+        Ok(small) => vec![constants::SMALL_INTEGER_EXT, small],
+        Err(_) => {
+            let mut bytes = vec![constants::INTEGER_EXT];
+            bytes.extend_from_slice(&i.to_be_bytes());
+            bytes
+        }
+    }
+}
+
+fn concat(encoded: Vec<Encoded>, bytes: &mut Vec<u8>) -> Vec<ErlTerm> {
+    let mut terms = Vec::with_capacity(encoded.len());
+    for (term, term_bytes) in encoded {
+        bytes.extend_from_slice(&term_bytes);
+        terms.push(term);
+    }
+    return terms;
+}
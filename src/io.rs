@@ -0,0 +1,40 @@
+// The parts of std::io::Error the decoders use, for builds without std.
+// DecodingError::DecodingFailure wraps this instead of std::io::Error,
+// so matching on the error kind works the same either way.
+
+use alloc::string::String;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    InvalidData,
+    UnexpectedEof,
+    Other,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl Error {
+    pub fn new<M: Into<String>>(kind: ErrorKind, message: M) -> Self {
+        Error { kind, message: message.into() }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        return self.kind;
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.write_str(&self.message);
+    }
+}
+
+impl core::error::Error for Error {}
@@ -0,0 +1,487 @@
+// Conversion between terms and JSON (requires the json feature).
+//
+// The mapping is controlled by JsonOptions. The default options produce
+// human-friendly, lossy JSON, JsonOptions::lossless() produces JSON that
+// converts back into an equal term. Integers do not record their width and
+// come back in the smallest representation that fits, like decoded ETF.
+//
+// Term                  | JSON
+// ----------------------+---------------------------------------------------------------
+// atom                  | "name" (AtomMapping::String) or {"atom": "name"} (AtomMapping::Tagged);
+//                       | with AtomMapping::String, true, false and null become JSON literals
+// integer               | number, or {"bigint": "digits"} when outside of the 64-bit range
+// float                 | number
+// binary                | "text" (BinaryMapping::Utf8, non-UTF-8 binaries are tagged),
+//                       | "base64" (BinaryMapping::Base64) or {"binary": "base64"} (BinaryMapping::Tagged)
+// bit binary            | {"bits": "base64", "trailing_bits": n}
+// tuple                 | [...] (TupleMapping::Array) or {"tuple": [...]} (TupleMapping::Tagged)
+// proper list           | [...], or {"key": value, ...} for proplists when proplists_as_objects is set
+// improper list         | {"improper_list": [...], "tail": tail}
+// map                   | {"key": value, ...} (MapMapping::Object, when all keys are atoms or UTF-8 binaries)
+//                       | or {"map": [[key, value], ...]} (MapMapping::Tagged)
+// pid                   | {"pid": {"node": "name", "id": n, "serial": n, "creation": n}}
+// port                  | {"port": {"node": "name", "id": n, "creation": n}}, {"v4_port": {...}} for V4 ports
+// reference             | {"ref": {"node": "name", "creation": n, "id": [n, ...]}}
+// external fun          | {"fun": {"module": "name", "function": "name", "arity": n}}
+// internal fun          | {"internal_fun": {"module": "name", "arity": n, "uniq": "hex", "index": n,
+//                       |   "old_index": n, "old_uniq_hash": n, "creator_pid": {"pid": ...}, "free_vars": [...]}}
+//
+// Converting JSON back into terms:
+//
+//  * true, false and null become atoms
+//  * strings become atoms when atoms are strings and binaries are tagged, otherwise binaries
+//    (decoded from base64 with BinaryMapping::Base64)
+//  * arrays become lists
+//  * {"map": [[key, value], ...]} becomes a map
+//  * objects with a single known tag key (or "improper_list" and "tail") become the tagged term,
+//    other objects become proplists with atom keys
+//  * integers use the smallest fitting representation (SmallInteger, Integer, BigInteger)
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use num::bigint::BigInt;
+use num::ToPrimitive;
+use serde_json::{json, Map, Number, Value};
+use thiserror::Error;
+
+use crate::*;
+
+#[derive(Error, Debug)]
+pub enum JsonError {
+    #[error("tagged JSON object is missing a field or has a field of a wrong type")]
+    InvalidField { tag: String, field: String },
+    #[error("invalid base64 data")]
+    InvalidBase64(#[from] base64::DecodeError),
+    #[error("too many atoms")]
+    AtomTableFull(#[from] AtomTableFull),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AtomMapping {
+    String,
+    Tagged,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BinaryMapping {
+    Utf8,
+    Base64,
+    Tagged,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TupleMapping {
+    Array,
+    Tagged,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MapMapping {
+    Object,
+    Tagged,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JsonOptions {
+    pub atoms: AtomMapping,
+    pub binaries: BinaryMapping,
+    pub tuples: TupleMapping,
+    pub maps: MapMapping,
+    pub proplists_as_objects: bool,
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        JsonOptions {
+            atoms: AtomMapping::String,
+            binaries: BinaryMapping::Utf8,
+            tuples: TupleMapping::Array,
+            maps: MapMapping::Object,
+            proplists_as_objects: true,
+        }
+    }
+}
+
+impl JsonOptions {
+    // Options that make term => JSON => term conversions round trip
+    pub fn lossless() -> Self {
+        JsonOptions {
+            atoms: AtomMapping::Tagged,
+            binaries: BinaryMapping::Tagged,
+            tuples: TupleMapping::Tagged,
+            maps: MapMapping::Tagged,
+            proplists_as_objects: false,
+        }
+    }
+}
+
+impl ErlTerm {
+    pub fn to_json(&self, options: &JsonOptions) -> Value {
+        return to_json(self, options);
+    }
+
+    pub fn from_json(value: &Value, options: &JsonOptions) -> Result<ErlTerm, JsonError> {
+        return from_json(value, options);
+    }
+}
+
+//
+// ErlTerm => JSON
+//
+
+fn to_json(term: &ErlTerm, options: &JsonOptions) -> Value {
+    match term {
+        ErlTerm::Atom(name) => atom_to_json(name, options),
+        ErlTerm::SmallInteger(i) => json!(i),
+        ErlTerm::Integer(i) => json!(i),
+        ErlTerm::BigInteger(i) => bigint_to_json(i),
+        ErlTerm::Float(f) => match Number::from_f64(f.0) {
+            Some(n) => Value::Number(n),
+            None => Value::Null,
+        },
+        ErlTerm::Binary(bytes) => binary_to_json(bytes, options),
+        ErlTerm::BitBinary(bytes, bits) => {
+            json!({"bits": BASE64.encode(bytes), "trailing_bits": bits})
+        }
+        ErlTerm::Pid(pid) => json!({"pid": pid_fields(pid)}),
+        ErlTerm::V3Port(port) => {
+            json!({"port": {"node": port.node.name.as_str(), "id": port.id, "creation": port.creation}})
+        }
+        ErlTerm::V4Port(port) => {
+            json!({"v4_port": {"node": port.node.name.as_str(), "id": port.id, "creation": port.creation}})
+        }
+        ErlTerm::Tuple(t) => {
+            let elements = elements_to_json(&t.elements, options);
+            match options.tuples {
+                TupleMapping::Array => elements,
+                TupleMapping::Tagged => json!({ "tuple": elements }),
+            }
+        }
+        ErlTerm::List(l) => {
+            if options.proplists_as_objects {
+                if let Some(object) = proplist_to_json(&l.elements, options) {
+                    return object;
+                }
+            }
+            elements_to_json(&l.elements, options)
+        }
+        ErlTerm::ImproperList(l) => json!({
+            "improper_list": elements_to_json(&l.elements, options),
+            "tail": to_json(&l.tail, options)
+        }),
+        ErlTerm::Ref(r) => {
+            json!({"ref": {"node": r.node.name.as_str(), "creation": r.creation, "id": r.id}})
+        }
+        ErlTerm::ExternalFun(fun) => json!({"fun": {
+            "module": fun.module.name.as_str(),
+            "function": fun.function_name.name.as_str(),
+            "arity": fun.arity
+        }}),
+        ErlTerm::InternalFun(fun) => {
+            let uniq: String = fun.uniq_beam_md5.iter().map(|b| format!("{:02x}", b)).collect();
+            json!({"internal_fun": {
+                "module": fun.module.name.as_str(),
+                "arity": fun.arity,
+                "uniq": uniq,
+                "index": fun.index,
+                "old_index": fun.old_index,
+                "old_uniq_hash": fun.old_uniq_hash,
+                "creator_pid": {"pid": pid_fields(&fun.creator_pid)},
+                "free_vars": elements_to_json(&fun.free_vars, options)
+            }})
+        }
+        ErlTerm::Map(m) => {
+            if options.maps == MapMapping::Object {
+                let pairs = m.entries.iter().map(|(k, v)| (k, v));
+                if let Some(object) = pairs_to_json(pairs, m.entries.len(), options) {
+                    return object;
+                }
+            }
+            let entries = m.entries.iter().map(|(k, v)| json!([to_json(k, options), to_json(v, options)]));
+            json!({ "map": entries.collect::<Vec<_>>() })
+        }
+    }
+}
+
+fn atom_to_json(name: &str, options: &JsonOptions) -> Value {
+    match options.atoms {
+        AtomMapping::String => match name {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "null" => Value::Null,
+            _ => Value::String(name.to_string()),
+        },
+        AtomMapping::Tagged => json!({ "atom": name }),
+    }
+}
+
+fn bigint_to_json(i: &BigInt) -> Value {
+    if let Some(n) = i.to_i64() {
+        return json!(n);
+    }
+    if let Some(n) = i.to_u64() {
+        return json!(n);
+    }
+    return json!({ "bigint": i.to_string() });
+}
+
+fn binary_to_json(bytes: &[u8], options: &JsonOptions) -> Value {
+    match options.binaries {
+        BinaryMapping::Utf8 => match std::str::from_utf8(bytes) {
+            Ok(s) => Value::String(s.to_string()),
+            Err(_) => json!({ "binary": BASE64.encode(bytes) }),
+        },
+        BinaryMapping::Base64 => Value::String(BASE64.encode(bytes)),
+        BinaryMapping::Tagged => json!({ "binary": BASE64.encode(bytes) }),
+    }
+}
+
+fn pid_fields(pid: &ErlPid) -> Value {
+    return json!({"node": pid.node.name.as_str(), "id": pid.id, "serial": pid.serial, "creation": pid.creation});
+}
+
+fn elements_to_json(elements: &[ErlTerm], options: &JsonOptions) -> Value {
+    return Value::Array(elements.iter().map(|e| to_json(e, options)).collect());
+}
+
+// Lists of {Key, Value} pairs with atom or binary keys
+fn proplist_to_json(elements: &[ErlTerm], options: &JsonOptions) -> Option<Value> {
+    if elements.is_empty() {
+        return None;
+    }
+    let mut pairs = Vec::with_capacity(elements.len());
+    for element in elements {
+        match element {
+            ErlTerm::Tuple(t) if t.elements.len() == 2 => pairs.push((&t.elements[0], &t.elements[1])),
+            _ => return None,
+        }
+    }
+    return pairs_to_json(pairs.into_iter(), elements.len(), options);
+}
+
+// An object, if every key is an atom or a UTF-8 binary
+fn pairs_to_json<'t>(
+    pairs: impl Iterator<Item = (&'t ErlTerm, &'t ErlTerm)>,
+    len: usize,
+    options: &JsonOptions,
+) -> Option<Value> {
+    let mut object = Map::with_capacity(len);
+    for (key, value) in pairs {
+        let key = match key {
+            ErlTerm::Atom(name) => name.to_string(),
+            ErlTerm::Binary(bytes) => String::from_utf8(bytes.clone()).ok()?,
+            _ => return None,
+        };
+        object.insert(key, to_json(value, options));
+    }
+    return Some(Value::Object(object));
+}
+
+//
+// JSON => ErlTerm
+//
+
+fn from_json(value: &Value, options: &JsonOptions) -> Result<ErlTerm, JsonError> {
+    match value {
+        Value::Null => Ok(ErlTerm::Atom(InternedAtom::new("null")?)),
+        Value::Bool(b) => Ok(ErlTerm::Atom(InternedAtom::new(&b.to_string())?)),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Ok(integer_term(BigInt::from(i)))
+            } else if let Some(i) = n.as_u64() {
+                Ok(integer_term(BigInt::from(i)))
+            } else {
+                Ok(ErlTerm::Float(OrderedFloat(n.as_f64().unwrap_or(f64::NAN))))
+            }
+        }
+        Value::String(s) => {
+            if options.atoms == AtomMapping::String && options.binaries == BinaryMapping::Tagged {
+                Ok(ErlTerm::Atom(InternedAtom::new(s)?))
+            } else if options.binaries == BinaryMapping::Base64 {
+                Ok(ErlTerm::Binary(BASE64.decode(s)?))
+            } else {
+                Ok(ErlTerm::Binary(s.as_bytes().to_vec()))
+            }
+        }
+        Value::Array(values) => Ok(ErlTerm::List(List { elements: values_to_terms(values, options)? })),
+        Value::Object(object) => object_to_term(object, options),
+    }
+}
+
+fn integer_term(i: BigInt) -> ErlTerm {
+    if let Some(n) = i.to_u8() {
+        return ErlTerm::SmallInteger(n);
+    }
+    if let Some(n) = i.to_i32() {
+        return ErlTerm::Integer(n);
+    }
+    return ErlTerm::BigInteger(i);
+}
+
+fn values_to_terms(values: &[Value], options: &JsonOptions) -> Result<Vec<ErlTerm>, JsonError> {
+    return values.iter().map(|v| from_json(v, options)).collect();
+}
+
+fn object_to_term(object: &Map<String, Value>, options: &JsonOptions) -> Result<ErlTerm, JsonError> {
+    if object.len() == 2 && object.contains_key("improper_list") && object.contains_key("tail") {
+        let fields = Fields { tag: "improper_list", object };
+        return Ok(ErlTerm::ImproperList(ImproperList {
+            elements: values_to_terms(fields.array("improper_list")?, options)?,
+            tail: Box::new(from_json(&object["tail"], options)?),
+        }));
+    }
+    if object.len() == 2 && object.contains_key("bits") && object.contains_key("trailing_bits") {
+        let fields = Fields { tag: "bits", object };
+        let bytes = BASE64.decode(fields.str("bits")?)?;
+        let bits: u8 = fields.uint("trailing_bits")?;
+        // the trailing bits the decoder accepts
+        if bits > 8 || bytes.is_empty() != (bits == 0) {
+            return Err(fields.invalid("trailing_bits"));
+        }
+        return Ok(ErlTerm::BitBinary(bytes, bits));
+    }
+    if object.len() == 1 {
+        let (tag, value) = object.iter().next().unwrap();
+        if let Some(term) = tagged_to_term(tag, value, options)? {
+            return Ok(term);
+        }
+    }
+
+    // a proplist
+    let mut elements = Vec::with_capacity(object.len());
+    for (key, value) in object {
+        elements.push(ErlTerm::Tuple(Tuple {
+            elements: vec![ErlTerm::Atom(InternedAtom::new(key)?), from_json(value, options)?],
+        }));
+    }
+    return Ok(ErlTerm::List(List { elements }));
+}
+
+fn tagged_to_term(tag: &str, value: &Value, options: &JsonOptions) -> Result<Option<ErlTerm>, JsonError> {
+    let invalid = || JsonError::InvalidField { tag: tag.to_string(), field: tag.to_string() };
+    let term = match (tag, value) {
+        ("atom", Value::String(s)) => ErlTerm::Atom(InternedAtom::new(s)?),
+        ("binary", Value::String(s)) => ErlTerm::Binary(BASE64.decode(s)?),
+        ("bigint", Value::String(s)) => match s.parse::<BigInt>() {
+            Ok(i) => integer_term(i),
+            Err(_) => return Err(invalid()),
+        },
+        ("tuple", Value::Array(values)) => ErlTerm::Tuple(Tuple { elements: values_to_terms(values, options)? }),
+        ("map", Value::Array(values)) => {
+            let mut entries = Vec::with_capacity(values.len());
+            for value in values {
+                match value.as_array().map(|pair| pair.as_slice()) {
+                    Some([key, value]) => entries.push((from_json(key, options)?, from_json(value, options)?)),
+                    _ => return Err(invalid()),
+                }
+            }
+            ErlTerm::Map(crate::Map { entries })
+        }
+        ("pid", Value::Object(object)) => ErlTerm::Pid(pid_from_fields(&Fields { tag, object })?),
+        ("port", Value::Object(object)) => {
+            let fields = Fields { tag, object };
+            ErlTerm::V3Port(ErlV3Port {
+                node: fields.atom("node")?,
+                id: fields.uint("id")?,
+                creation: fields.uint("creation")?,
+            })
+        }
+        ("v4_port", Value::Object(object)) => {
+            let fields = Fields { tag, object };
+            ErlTerm::V4Port(ErlV4Port {
+                node: fields.atom("node")?,
+                id: fields.uint("id")?,
+                creation: fields.uint("creation")?,
+            })
+        }
+        ("ref", Value::Object(object)) => {
+            let fields = Fields { tag, object };
+            let mut id = Vec::new();
+            for v in fields.array("id")? {
+                id.push(v.as_u64().and_then(|n| u32::try_from(n).ok()).ok_or_else(|| fields.invalid("id"))?);
+            }
+            ErlTerm::Ref(Ref { node: fields.atom("node")?, creation: fields.uint("creation")?, id })
+        }
+        ("fun", Value::Object(object)) => {
+            let fields = Fields { tag, object };
+            ErlTerm::ExternalFun(ExternalFun {
+                module: fields.atom("module")?,
+                function_name: fields.atom("function")?,
+                arity: fields.uint("arity")?,
+            })
+        }
+        ("internal_fun", Value::Object(object)) => {
+            let fields = Fields { tag, object };
+            let uniq = fields.str("uniq")?;
+            let mut uniq_beam_md5 = [0; 16];
+            if uniq.len() != 32 || !uniq.is_ascii() {
+                return Err(fields.invalid("uniq"));
+            }
+            for (i, byte) in uniq_beam_md5.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&uniq[2 * i..2 * i + 2], 16).map_err(|_| fields.invalid("uniq"))?;
+            }
+            let creator_pid = match object.get("creator_pid").and_then(|v| v.get("pid")) {
+                Some(Value::Object(pid)) => pid_from_fields(&Fields { tag: "pid", object: pid })?,
+                _ => return Err(fields.invalid("creator_pid")),
+            };
+            let free_vars = values_to_terms(fields.array("free_vars")?, options)?;
+            ErlTerm::InternalFun(InternalFun {
+                arity: fields.uint("arity")?,
+                uniq_beam_md5,
+                index: fields.uint("index")?,
+                free_variable_count: free_vars.len() as u32,
+                module: fields.atom("module")?,
+                old_index: fields.int("old_index")?,
+                old_uniq_hash: fields.int("old_uniq_hash")?,
+                creator_pid,
+                free_vars,
+            })
+        }
+        _ => return Ok(None),
+    };
+    return Ok(Some(term));
+}
+
+fn pid_from_fields(fields: &Fields) -> Result<ErlPid, JsonError> {
+    return Ok(ErlPid {
+        node: fields.atom("node")?,
+        id: fields.uint("id")?,
+        serial: fields.uint("serial")?,
+        creation: fields.uint("creation")?,
+    });
+}
+
+// Typed access to the fields of a tagged object
+struct Fields<'a> {
+    tag: &'a str,
+    object: &'a Map<String, Value>,
+}
+
+impl<'a> Fields<'a> {
+    fn invalid(&self, field: &str) -> JsonError {
+        return JsonError::InvalidField { tag: self.tag.to_string(), field: field.to_string() };
+    }
+
+    fn str(&self, field: &str) -> Result<&'a str, JsonError> {
+        return self.object.get(field).and_then(|v| v.as_str()).ok_or_else(|| self.invalid(field));
+    }
+
+    fn atom(&self, field: &str) -> Result<Atom, JsonError> {
+        return Ok(Atom { name: InternedAtom::new(self.str(field)?)? });
+    }
+
+    // Fails on numbers that do not fit into T as well
+    fn uint<T: TryFrom<u64>>(&self, field: &str) -> Result<T, JsonError> {
+        let n = self.object.get(field).and_then(|v| v.as_u64());
+        return n.and_then(|n| T::try_from(n).ok()).ok_or_else(|| self.invalid(field));
+    }
+
+    fn int<T: TryFrom<i64>>(&self, field: &str) -> Result<T, JsonError> {
+        let n = self.object.get(field).and_then(|v| v.as_i64());
+        return n.and_then(|n| T::try_from(n).ok()).ok_or_else(|| self.invalid(field));
+    }
+
+    fn array(&self, field: &str) -> Result<&'a Vec<Value>, JsonError> {
+        return self.object.get(field).and_then(|v| v.as_array()).ok_or_else(|| self.invalid(field));
+    }
+}
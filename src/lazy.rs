@@ -0,0 +1,215 @@
+use std::ops::Range;
+
+use crate::*;
+use crate::constants;
+
+// A term backed by a byte buffer. Only the top level structure
+// (tuple elements, list items, the tail of an improper list and the
+// keys and values of a map) is indexed, everything else is decoded when
+// accessed. Indexing walks the term once, which also validates it, and
+// an element is indexed again when it is accessed.
+#[derive(Debug, Clone)]
+pub struct LazyTerm<'a> {
+    // the term bytes starting with its tag, without the version byte
+    bytes: &'a [u8],
+    // keys and values take turns for maps, STRING_EXT has none
+    elements: Vec<Range<usize>>,
+    // only set for improper lists
+    tail: Option<Range<usize>>,
+}
+
+// The items of a STRING_EXT list, as terms of their own
+static SMALL_INTEGERS: [[u8; 2]; 256] = small_integers();
+
+const fn small_integers() -> [[u8; 2]; 256] {
+    let mut terms = [[constants::SMALL_INTEGER_EXT, 0]; 256];
+    let mut i = 0;
+    while i < 256 {
+        terms[i][1] = i as u8;
+        i += 1;
+    }
+    return terms;
+}
+
+impl<'a> LazyTerm<'a> {
+    // Indexes a term in the external term format, trailing bytes
+    // after the term are ignored
+    pub fn new(bytes: &'a [u8]) -> Result<Self, DecodingError> {
+        match bytes.first() {
+            Some(&constants::TERM_FORMAT_VERSION) => {}
+            Some(&version) => return Err(DecodingError::UnsupportedVersion { version }),
+            None => return Err(unexpected_eof()),
+        }
+
+        return Self::index(&bytes[1..]);
+    }
+
+    // Indexes the term at the start of bytes
+    fn index(bytes: &'a [u8]) -> Result<Self, DecodingError> {
+        let tag = *bytes.first().ok_or_else(unexpected_eof)?;
+        let (header, count) = match tag {
+            constants::SMALL_TUPLE_EXT => (2, *bytes.get(1).ok_or_else(unexpected_eof)? as usize),
+            constants::LARGE_TUPLE_EXT | constants::LIST_EXT | constants::MAP_EXT => {
+                let size = bytes.get(1..5).ok_or_else(unexpected_eof)?;
+                let size = u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize;
+                if tag == constants::MAP_EXT {
+                    (5, size.saturating_mul(2))
+                } else {
+                    (5, size)
+                }
+            }
+            _ => {
+                let n = Decoder::new(Box::new(bytes)).skip_next_term()?;
+                return Ok(LazyTerm { bytes: &bytes[..n], elements: Vec::new(), tail: None });
+            }
+        };
+
+        let mut decoder = Decoder::new(Box::new(&bytes[header..]));
+        // every element takes at least a byte
+        let mut elements = Vec::with_capacity(count.min(bytes.len() - header));
+        let mut offset = header;
+        for _i in 0..count {
+            let n = decoder.skip_next_term()?;
+            elements.push(offset..offset + n);
+            offset += n;
+        }
+
+        let mut tail = None;
+        if tag == constants::LIST_EXT {
+            let n = decoder.skip_next_term()?;
+            if bytes[offset] != constants::NIL_EXT {
+                tail = Some(offset..offset + n);
+            }
+            offset += n;
+        }
+
+        return Ok(LazyTerm { bytes: &bytes[..offset], elements, tail });
+    }
+
+    // External term format tag of this term
+    pub fn tag(&self) -> u8 {
+        return self.bytes[0];
+    }
+
+    pub fn is_tuple(&self) -> bool {
+        return matches!(self.tag(), constants::SMALL_TUPLE_EXT | constants::LARGE_TUPLE_EXT);
+    }
+
+    pub fn is_list(&self) -> bool {
+        return matches!(self.tag(), constants::LIST_EXT | constants::NIL_EXT | constants::STRING_EXT);
+    }
+
+    pub fn is_map(&self) -> bool {
+        return self.tag() == constants::MAP_EXT;
+    }
+
+    pub fn is_improper_list(&self) -> bool {
+        return self.tail.is_some();
+    }
+
+    // Number of tuple elements, list items or map entries, zero for other terms
+    pub fn len(&self) -> usize {
+        match self.tag() {
+            // the tag and a 16 bit length
+            constants::STRING_EXT => self.bytes.len() - 3,
+            constants::MAP_EXT => self.elements.len() / 2,
+            _ => self.elements.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // A lazy view of a tuple element or list item
+    pub fn element(&self, index: usize) -> Result<LazyTerm<'a>, DecodingError> {
+        if self.is_map() {
+            return Err(DecodingError::CompoundTypeDecodingFailure());
+        }
+        if index >= self.len() {
+            return Err(DecodingError::IndexOutOfBounds { index, size: self.len() });
+        }
+        if self.tag() == constants::STRING_EXT {
+            let bytes: &'static [u8] = &SMALL_INTEGERS[self.bytes[3 + index] as usize];
+            return Ok(LazyTerm { bytes, elements: Vec::new(), tail: None });
+        }
+        return Self::index(&self.bytes[self.elements[index].clone()]);
+    }
+
+    pub fn tuple_element(&self, index: usize) -> DecodingResult {
+        if !self.is_tuple() {
+            return Err(DecodingError::CompoundTypeDecodingFailure());
+        }
+        return self.element(index)?.decode();
+    }
+
+    pub fn list_item(&self, index: usize) -> DecodingResult {
+        if !self.is_list() {
+            return Err(DecodingError::CompoundTypeDecodingFailure());
+        }
+        return self.element(index)?.decode();
+    }
+
+    // The tail of an improper list
+    pub fn list_tail(&self) -> Result<Option<LazyTerm<'a>>, DecodingError> {
+        match &self.tail {
+            Some(range) => Ok(Some(Self::index(&self.bytes[range.clone()])?)),
+            None => Ok(None),
+        }
+    }
+
+    // Tuple elements or list items, nothing for maps
+    pub fn elements(&self) -> impl Iterator<Item = LazyTerm<'a>> + '_ {
+        let len = if self.is_map() { 0 } else { self.len() };
+        // every element has been validated when this term was indexed
+        return (0..len).map(move |index| self.element(index).unwrap());
+    }
+
+    // Lazy views of the keys and values of a map, in the order they were encoded
+    pub fn entries(&self) -> impl Iterator<Item = (LazyTerm<'a>, LazyTerm<'a>)> + '_ {
+        let pairs = if self.is_map() { self.elements.chunks(2) } else { [].chunks(2) };
+        return pairs.map(move |pair| {
+            let key = Self::index(&self.bytes[pair[0].clone()]).unwrap();
+            let value = Self::index(&self.bytes[pair[1].clone()]).unwrap();
+            (key, value)
+        });
+    }
+
+    // A lazy view of the value under a key of a map, keys are decoded
+    // to compare them but values are not
+    pub fn map_value(&self, key: &ErlTerm) -> Result<Option<LazyTerm<'a>>, DecodingError> {
+        if !self.is_map() {
+            return Err(DecodingError::CompoundTypeDecodingFailure());
+        }
+        for (k, value) in self.entries() {
+            if k.decode()? == *key {
+                return Ok(Some(value));
+            }
+        }
+        return Ok(None);
+    }
+
+    // Fully decodes this term
+    pub fn decode(&self) -> DecodingResult {
+        return Decoder::new(Box::new(self.bytes)).read_next_term();
+    }
+
+    // The term bytes without the version byte
+    pub fn as_bytes(&self) -> &'a [u8] {
+        return self.bytes;
+    }
+
+    // A standalone copy of this term in the external term format,
+    // suitable for forwarding as is
+    pub fn to_etf(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(1 + self.bytes.len());
+        out.push(constants::TERM_FORMAT_VERSION);
+        out.extend_from_slice(self.bytes);
+        return out;
+    }
+}
+
+fn unexpected_eof() -> DecodingError {
+    let e = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+    return DecodingError::DecodingFailure(e);
+}
@@ -1,24 +1,153 @@
 // Encodes and decodes Erlang external form format.
 
+#![allow(clippy::needless_return)]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+mod annotate;
+#[cfg(feature = "arena")]
+mod arena;
+mod atoms;
+#[cfg(feature = "beam")]
+mod beam;
+#[cfg(feature = "bert")]
+mod bert;
+#[cfg(feature = "cbor")]
+mod cbor;
 mod constants;
+#[cfg(feature = "distribution")]
+mod control;
+mod cursor;
+#[cfg(feature = "std")]
 mod decoding;
+mod diff;
+#[cfg(feature = "disk_log")]
+mod disk_log;
+mod display;
+#[cfg(feature = "distribution")]
+mod distribution;
+#[cfg(feature = "std")]
+mod encoding;
 mod conversions;
+#[cfg(feature = "epmd")]
+mod epmd;
+#[cfg(feature = "ets")]
+mod ets;
+#[cfg(feature = "proptest")]
+mod generators;
+#[cfg(feature = "json")]
+mod json;
+#[cfg(not(feature = "std"))]
+pub mod io;
+#[cfg(feature = "std")]
+mod lazy;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+#[cfg(feature = "distribution")]
+mod node;
 mod numerical;
+#[cfg(feature = "port")]
+mod port;
+#[cfg(feature = "std")]
+mod pattern;
+mod query;
+mod slice;
+
+// io::Error is what DecodingError::DecodingFailure wraps, a minimal
+// stand-in for std::io's without std
+#[cfg(feature = "std")]
+pub use std::io;
 
-use std::io;
+// what the std prelude would otherwise bring into every module
+#[cfg(not(feature = "std"))]
+#[allow(unused_imports)]
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 
 use num::bigint::BigInt;
 use ordered_float::OrderedFloat;
-use std::convert::TryInto;
+use core::convert::TryInto;
 use thiserror::Error;
 
-use decoding::Decoder;
+pub use annotate::{annotate, AnnotatedLine, Annotation, AnnotationFailure};
+#[cfg(feature = "arena")]
+pub use arena::{decode_in, decode_in_with_max_depth, ArenaInternalFun, ArenaTerm};
+#[cfg(feature = "arena")]
+pub use bumpalo::Bump;
+pub use atoms::{atom_count, set_atom_limit, AtomTableFull, InternedAtom, ATOM_TABLE_DEFAULT_LIMIT};
+#[cfg(feature = "beam")]
+pub use beam::{BeamError, BeamFile};
+#[cfg(feature = "bert")]
+pub use bert::{BertClient, BertError, BertErrorType, BertRpcError, BertServer, BertTerm};
+#[cfg(feature = "cbor")]
+pub use cbor::{
+    CborError, CBOR_TAG_ATOM, CBOR_TAG_BIT_BINARY, CBOR_TAG_EXTERNAL_FUN, CBOR_TAG_IMPROPER_LIST,
+    CBOR_TAG_INTERNAL_FUN, CBOR_TAG_MAP, CBOR_TAG_PID, CBOR_TAG_REF, CBOR_TAG_TUPLE, CBOR_TAG_V3_PORT, CBOR_TAG_V4_PORT,
+};
+#[cfg(feature = "std")]
+pub use decoding::Decoder;
+pub use diff::{diff, render_diff, Change};
+#[cfg(feature = "disk_log")]
+pub use disk_log::{BadChunk, BadChunkReason, DiskLogError, DiskLogReader, WrapLogReader};
+#[cfg(feature = "distribution")]
+pub use control::{ControlMessage, Process};
+#[cfg(feature = "distribution")]
+pub use distribution::{
+    Connection, DistributionError, HandshakeConfig, DFLAG_PUBLISHED, DFLAG_ATOM_CACHE,
+    DFLAG_EXTENDED_REFERENCES, DFLAG_DIST_MONITOR, DFLAG_FUN_TAGS, DFLAG_DIST_MONITOR_NAME,
+    DFLAG_HIDDEN_ATOM_CACHE, DFLAG_NEW_FUN_TAGS, DFLAG_EXTENDED_PIDS_PORTS, DFLAG_EXPORT_PTR_TAG,
+    DFLAG_BIT_BINARIES, DFLAG_NEW_FLOATS, DFLAG_UNICODE_IO, DFLAG_DIST_HDR_ATOM_CACHE, DFLAG_SMALL_ATOM_TAGS,
+    DFLAG_UTF8_ATOMS, DFLAG_MAP_TAG, DFLAG_BIG_CREATION, DFLAG_SEND_SENDER, DFLAG_BIG_SEQTRACE_LABELS,
+    DFLAG_EXIT_PAYLOAD, DFLAG_FRAGMENTS, DFLAG_HANDSHAKE_23, DFLAG_UNLINK_ID, DFLAG_MANDATORY_25_DIGEST,
+    DFLAG_SPAWN, DFLAG_NAME_ME, DFLAG_V4_NC, DFLAG_ALIAS, DFLAG_REQUIRED, DFLAG_DEFAULT,
+};
+#[cfg(feature = "std")]
+pub use encoding::Encoder;
+#[cfg(feature = "epmd")]
+pub use epmd::{EpmdClient, EpmdError, EpmdServer, NodeInfo, NodeType, Registration, EPMD_DEFAULT_PORT};
+#[cfg(feature = "distribution")]
+pub use node::{BadMessage, Mailbox, Node};
+#[cfg(feature = "port")]
+pub use port::{read_packet, serve_port, write_packet, Packet, Port, PortError};
+#[cfg(feature = "ets")]
+pub use ets::{TabFileError, TabFileReader, TableHeader, TableType};
+#[cfg(feature = "proptest")]
+pub use generators::{etf_bytes, term_with_etf_bytes, TermParams};
+#[cfg(feature = "json")]
+pub use json::{AtomMapping, BinaryMapping, JsonError, JsonOptions, MapMapping, TupleMapping};
+#[cfg(feature = "std")]
+pub use lazy::LazyTerm;
+#[cfg(feature = "msgpack")]
+pub use msgpack::{
+    MsgPackError, MSGPACK_EXT_ATOM, MSGPACK_EXT_BIG_INTEGER, MSGPACK_EXT_BIT_BINARY, MSGPACK_EXT_EXTERNAL_FUN,
+    MSGPACK_EXT_IMPROPER_LIST, MSGPACK_EXT_INTERNAL_FUN, MSGPACK_EXT_MAP, MSGPACK_EXT_PID, MSGPACK_EXT_REF, MSGPACK_EXT_TUPLE,
+    MSGPACK_EXT_V3_PORT, MSGPACK_EXT_V4_PORT,
+};
+#[cfg(feature = "std")]
+pub use pattern::{Bindings, Pattern, PatternError};
+pub use query::{Path, QueryError, Selector};
+pub use slice::SliceDecoder;
 
 //
 // Types
 //
 
+// Deepest nesting of compound terms the decoders and the encoder accept
+// unless configured otherwise with `with_max_depth`. Both are recursive, this
+// keeps hostile input from overflowing the stack, even that of a 2 MiB thread
+// in a debug build. Every level takes up to about 1 KiB of stack in release
+// builds and 10 KiB in debug builds, deeper limits need a larger stack.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 128;
+
 pub type DecodingResult = Result<ErlTerm, DecodingError>;
+pub type EncodingResult = Result<(), EncodingError>;
 
 #[derive(Error, Debug)]
 pub enum DecodingError {
@@ -32,13 +161,29 @@ pub enum DecodingError {
     CompoundTypeDecodingFailure(),
     #[error("format version is unsupported")]
     UnsupportedVersion { version: u8 },
+    #[error("element index is out of bounds")]
+    IndexOutOfBounds { index: usize, size: usize },
+    #[error("too many atoms")]
+    AtomTableFull(#[from] AtomTableFull),
+    #[error("term is nested too deeply")]
+    NestingTooDeep { limit: usize },
     #[error("other types of errors")]
     Other,
 }
 
+#[derive(Error, Debug)]
+pub enum EncodingError {
+    #[error("failed to write encoded term")]
+    EncodingFailure(#[from] io::Error),
+    #[error("value does not fit into the external term format")]
+    ValueOutOfRange(),
+    #[error("term is nested too deeply")]
+    NestingTooDeep { limit: usize },
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum ErlTerm {
-    Atom(String),
+    Atom(InternedAtom),
     SmallInteger(u8),
     Integer(i32),
     BigInteger(BigInt),
@@ -53,12 +198,13 @@ pub enum ErlTerm {
     ImproperList(ImproperList),
     Ref(Ref),
     ExternalFun(ExternalFun),
-    InternalFun(InternalFun)
+    InternalFun(InternalFun),
+    Map(Map)
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Atom {
-    pub name: String,
+    pub name: InternedAtom,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
@@ -160,13 +306,81 @@ pub struct InternalFun {
     pub free_vars: Vec<ErlTerm>
 }
 
+// Entries are kept in the order they were decoded (or added) in,
+// but two maps are equal when they have the same entries in any order
+#[derive(Debug, Eq, Clone)]
+pub struct Map {
+    pub entries: Vec<(ErlTerm, ErlTerm)>,
+}
+impl Map {
+    pub fn empty() -> Self {
+        Map {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: &ErlTerm) -> Option<&ErlTerm> {
+        return self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        if self.entries.len() != other.entries.len() {
+            return false;
+        }
+        if self.entries == other.entries {
+            return true;
+        }
+        return self.entries.iter().all(|(k, v)| other.get(k) == Some(v))
+            && other.entries.iter().all(|(k, v)| self.get(k) == Some(v));
+    }
+}
+
+// Only the size, so that equal maps hash the same whatever their order
+impl core::hash::Hash for Map {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.entries.len().hash(state);
+    }
+}
+
 //
 // Decoding
 //
 
 impl ErlTerm {
+    #[cfg(feature = "std")]
     pub fn decode(reader: Box<dyn io::Read>) -> DecodingResult {
         return Decoder::new(reader).decode();
     }
+
+    // Faster than decode for terms that are already in memory
+    pub fn from_bytes(bytes: &[u8]) -> DecodingResult {
+        return SliceDecoder::new(bytes).decode();
+    }
+}
+
+//
+// Encoding
+//
+
+#[cfg(feature = "std")]
+impl ErlTerm {
+    pub fn encode(&self, writer: Box<dyn io::Write + '_>) -> EncodingResult {
+        return Encoder::new(writer).encode(self);
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut out = Vec::new();
+        self.encode(Box::new(&mut out))?;
+        return Ok(out);
+    }
+}
+
+// Checks that the input starts with a well-formed term without materialising it,
+// returns the number of bytes the term occupies
+#[cfg(feature = "std")]
+pub fn validate(bytes: &[u8]) -> Result<usize, DecodingError> {
+    return Decoder::new(Box::new(bytes)).skip_term();
 }
 
@@ -0,0 +1,446 @@
+// Conversion between terms and MessagePack (requires the msgpack feature).
+//
+// Types that MessagePack has no native representation for use extension
+// types from the MSGPACK_EXT_* range below, so that any term round trips.
+// Extension payloads of compound types are MessagePack-encoded arrays:
+//
+// Term            | MessagePack
+// ----------------+------------------------------------------------------------
+// atom            | ext ATOM(UTF-8 bytes), true and false are booleans
+// integer         | integer, ext BIG_INTEGER(two's complement, big endian) outside of the 64-bit range
+// float           | float 64
+// binary          | bin
+// bit binary      | ext BIT_BINARY([bin, trailing bits])
+// tuple           | ext TUPLE([elements])
+// proper list     | array
+// improper list   | ext IMPROPER_LIST([[elements], tail])
+// pid             | ext PID([node, id, serial, creation])
+// port            | ext V3_PORT([node, id, creation]), ext V4_PORT([node, id, creation])
+// reference       | ext REF([node, creation, [id, ...]])
+// external fun    | ext EXTERNAL_FUN([module, function, arity])
+// internal fun    | ext INTERNAL_FUN([module, arity, uniq, index, old_index, old_uniq_hash, pid, [free vars]])
+// map             | ext MAP([[key, value], ...])
+//
+// Values produced by other MessagePack encoders are converted as follows: strings
+// become binaries, nil becomes the atom null, maps become proplists.
+
+use num::bigint::BigInt;
+use num::ToPrimitive;
+use rmpv::Value;
+use thiserror::Error;
+
+use crate::*;
+
+pub const MSGPACK_EXT_ATOM: i8 = 1;
+pub const MSGPACK_EXT_PID: i8 = 2;
+pub const MSGPACK_EXT_V3_PORT: i8 = 3;
+pub const MSGPACK_EXT_V4_PORT: i8 = 4;
+pub const MSGPACK_EXT_REF: i8 = 5;
+pub const MSGPACK_EXT_BIT_BINARY: i8 = 6;
+pub const MSGPACK_EXT_IMPROPER_LIST: i8 = 7;
+pub const MSGPACK_EXT_EXTERNAL_FUN: i8 = 8;
+pub const MSGPACK_EXT_INTERNAL_FUN: i8 = 9;
+pub const MSGPACK_EXT_TUPLE: i8 = 10;
+pub const MSGPACK_EXT_BIG_INTEGER: i8 = 11;
+pub const MSGPACK_EXT_MAP: i8 = 12;
+
+#[derive(Error, Debug)]
+pub enum MsgPackError {
+    #[error("failed to read MessagePack data")]
+    DecodingFailure(#[from] rmpv::decode::Error),
+    #[error("failed to write MessagePack data")]
+    EncodingFailure(#[from] rmpv::encode::Error),
+    #[error("MessagePack value cannot be converted into a term")]
+    UnsupportedValue { value: Value },
+    #[error("too many atoms")]
+    AtomTableFull(#[from] AtomTableFull),
+    #[error("MessagePack value is nested too deeply")]
+    NestingTooDeep { limit: usize },
+}
+
+impl ErlTerm {
+    pub fn to_msgpack_value(&self) -> Value {
+        return to_value(self);
+    }
+
+    pub fn from_msgpack_value(value: &Value) -> Result<ErlTerm, MsgPackError> {
+        return from_value(value, 0);
+    }
+
+    pub fn to_msgpack(&self) -> Result<Vec<u8>, MsgPackError> {
+        return Ok(TermWriter::write_twice(|w| w.term(self))?);
+    }
+
+    pub fn from_msgpack(bytes: &[u8]) -> Result<ErlTerm, MsgPackError> {
+        let value = rmpv::decode::read_value(&mut &bytes[..])?;
+        return from_value(&value, 0);
+    }
+}
+
+//
+// ErlTerm => MessagePack
+//
+
+// Writing to a Vec cannot fail
+fn ext(ext_type: i8, fields: Vec<Value>) -> Value {
+    let mut payload = Vec::new();
+    rmpv::encode::write_value(&mut payload, &Value::Array(fields)).unwrap();
+    return Value::Ext(ext_type, payload);
+}
+
+fn text(s: &str) -> Value {
+    return Value::from(s);
+}
+
+fn array(elements: &[ErlTerm]) -> Value {
+    return Value::Array(elements.iter().map(to_value).collect());
+}
+
+fn pid_value(pid: &ErlPid) -> Value {
+    return ext(MSGPACK_EXT_PID, vec![
+        text(&pid.node.name),
+        pid.id.into(),
+        pid.serial.into(),
+        pid.creation.into(),
+    ]);
+}
+
+fn to_value(term: &ErlTerm) -> Value {
+    match term {
+        ErlTerm::Atom(name) => match name.as_str() {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            _ => Value::Ext(MSGPACK_EXT_ATOM, name.as_bytes().to_vec()),
+        },
+        ErlTerm::SmallInteger(i) => (*i).into(),
+        ErlTerm::Integer(i) => (*i).into(),
+        ErlTerm::BigInteger(i) => {
+            if let Some(n) = i.to_i64() {
+                return n.into();
+            }
+            if let Some(n) = i.to_u64() {
+                return n.into();
+            }
+            Value::Ext(MSGPACK_EXT_BIG_INTEGER, i.to_signed_bytes_be())
+        }
+        ErlTerm::Float(f) => Value::F64(f.0),
+        ErlTerm::Binary(bytes) => Value::Binary(bytes.clone()),
+        ErlTerm::BitBinary(bytes, bits) => {
+            ext(MSGPACK_EXT_BIT_BINARY, vec![Value::Binary(bytes.clone()), (*bits).into()])
+        }
+        ErlTerm::Pid(pid) => pid_value(pid),
+        ErlTerm::V3Port(port) => ext(MSGPACK_EXT_V3_PORT, vec![
+            text(&port.node.name),
+            port.id.into(),
+            port.creation.into(),
+        ]),
+        ErlTerm::V4Port(port) => ext(MSGPACK_EXT_V4_PORT, vec![
+            text(&port.node.name),
+            port.id.into(),
+            port.creation.into(),
+        ]),
+        ErlTerm::List(l) => array(&l.elements),
+        ErlTerm::Ref(r) => ext(MSGPACK_EXT_REF, vec![
+            text(&r.node.name),
+            r.creation.into(),
+            Value::Array(r.id.iter().map(|&i| i.into()).collect()),
+        ]),
+        ErlTerm::ExternalFun(fun) => ext(MSGPACK_EXT_EXTERNAL_FUN, vec![
+            text(&fun.module.name),
+            text(&fun.function_name.name),
+            fun.arity.into(),
+        ]),
+        ErlTerm::Tuple(t) => compound_ext(MSGPACK_EXT_TUPLE, |w| w.array(&t.elements)),
+        ErlTerm::ImproperList(l) => compound_ext(MSGPACK_EXT_IMPROPER_LIST, |w| w.improper_list(l)),
+        ErlTerm::InternalFun(fun) => compound_ext(MSGPACK_EXT_INTERNAL_FUN, |w| w.internal_fun(fun)),
+        ErlTerm::Map(m) => compound_ext(MSGPACK_EXT_MAP, |w| w.map(m)),
+    }
+}
+
+// Writing to a Vec cannot fail
+fn compound_ext(ext_type: i8, fields: impl Fn(&mut TermWriter) -> Result<(), rmpv::encode::Error>) -> Value {
+    return Value::Ext(ext_type, TermWriter::write_twice(fields).unwrap());
+}
+
+// Writes terms as MessagePack without building a Value for every nested
+// term. An extension's length comes before its payload, so the term is
+// written twice: the first pass only measures the payloads, the second
+// writes each one after its length. Serializing every payload on its own
+// would copy the whole subtree again at each level.
+struct TermWriter {
+    // None while measuring
+    out: Option<Vec<u8>>,
+    written: usize,
+    // Payload lengths in the order their extensions are written
+    lengths: Vec<u32>,
+    next: usize,
+}
+
+impl io::Write for TermWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written += buf.len();
+        if let Some(out) = &mut self.out {
+            out.extend_from_slice(buf);
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl TermWriter {
+    fn write_twice(write: impl Fn(&mut TermWriter) -> Result<(), rmpv::encode::Error>) -> Result<Vec<u8>, rmpv::encode::Error> {
+        let mut measure = TermWriter { out: None, written: 0, lengths: Vec::new(), next: 0 };
+        write(&mut measure)?;
+        let out = Some(Vec::with_capacity(measure.written));
+        let mut writer = TermWriter { out, written: 0, lengths: measure.lengths, next: 0 };
+        write(&mut writer)?;
+        return Ok(writer.out.unwrap_or_default());
+    }
+
+    fn term(&mut self, term: &ErlTerm) -> Result<(), rmpv::encode::Error> {
+        match term {
+            ErlTerm::List(l) => self.array(&l.elements),
+            ErlTerm::Tuple(t) => self.ext(MSGPACK_EXT_TUPLE, |w| w.array(&t.elements)),
+            ErlTerm::ImproperList(l) => self.ext(MSGPACK_EXT_IMPROPER_LIST, |w| w.improper_list(l)),
+            ErlTerm::InternalFun(fun) => self.ext(MSGPACK_EXT_INTERNAL_FUN, |w| w.internal_fun(fun)),
+            ErlTerm::Map(m) => self.ext(MSGPACK_EXT_MAP, |w| w.map(m)),
+            _ => rmpv::encode::write_value(self, &to_value(term)),
+        }
+    }
+
+    fn ext(
+        &mut self,
+        ext_type: i8,
+        fields: impl FnOnce(&mut Self) -> Result<(), rmpv::encode::Error>,
+    ) -> Result<(), rmpv::encode::Error> {
+        if self.out.is_some() {
+            let len = self.lengths[self.next];
+            self.next += 1;
+            rmp::encode::write_ext_meta(self, len, ext_type)?;
+            return fields(self);
+        }
+        // Take a slot before the nested payloads take theirs
+        let slot = self.lengths.len();
+        self.lengths.push(0);
+        let start = self.written;
+        fields(self)?;
+        let len = (self.written - start) as u32;
+        self.lengths[slot] = len;
+        rmp::encode::write_ext_meta(self, len, ext_type)?;
+        return Ok(());
+    }
+
+    fn array(&mut self, elements: &[ErlTerm]) -> Result<(), rmpv::encode::Error> {
+        rmp::encode::write_array_len(self, elements.len() as u32)?;
+        for element in elements {
+            self.term(element)?;
+        }
+        return Ok(());
+    }
+
+    fn improper_list(&mut self, l: &ImproperList) -> Result<(), rmpv::encode::Error> {
+        rmp::encode::write_array_len(self, 2)?;
+        self.array(&l.elements)?;
+        return self.term(&l.tail);
+    }
+
+    fn internal_fun(&mut self, fun: &InternalFun) -> Result<(), rmpv::encode::Error> {
+        rmp::encode::write_array_len(self, 8)?;
+        for value in [
+            text(&fun.module.name),
+            fun.arity.into(),
+            Value::Binary(fun.uniq_beam_md5.to_vec()),
+            fun.index.into(),
+            fun.old_index.into(),
+            fun.old_uniq_hash.into(),
+            pid_value(&fun.creator_pid),
+        ] {
+            rmpv::encode::write_value(self, &value)?;
+        }
+        return self.array(&fun.free_vars);
+    }
+
+    fn map(&mut self, m: &Map) -> Result<(), rmpv::encode::Error> {
+        rmp::encode::write_array_len(self, m.entries.len() as u32)?;
+        for (k, v) in &m.entries {
+            rmp::encode::write_array_len(self, 2)?;
+            self.term(k)?;
+            self.term(v)?;
+        }
+        return Ok(());
+    }
+}
+
+//
+// MessagePack => ErlTerm
+//
+
+// Extension payloads are decoded separately, so rmpv's depth limit starts over
+// in each of them. `depth` counts the levels across payloads.
+fn from_value(value: &Value, depth: usize) -> Result<ErlTerm, MsgPackError> {
+    if depth >= DEFAULT_MAX_NESTING_DEPTH {
+        return Err(MsgPackError::NestingTooDeep { limit: DEFAULT_MAX_NESTING_DEPTH });
+    }
+    let unsupported = || MsgPackError::UnsupportedValue { value: value.clone() };
+    match value {
+        Value::Nil => Ok(ErlTerm::Atom(InternedAtom::new("null")?)),
+        Value::Boolean(b) => Ok(ErlTerm::Atom(InternedAtom::new(&b.to_string())?)),
+        Value::Integer(i) => {
+            if let Some(n) = i.as_i64() {
+                Ok(integer_term(BigInt::from(n)))
+            } else if let Some(n) = i.as_u64() {
+                Ok(integer_term(BigInt::from(n)))
+            } else {
+                Err(unsupported())
+            }
+        }
+        Value::F32(f) => Ok(ErlTerm::Float(OrderedFloat(*f as f64))),
+        Value::F64(f) => Ok(ErlTerm::Float(OrderedFloat(*f))),
+        Value::String(s) => Ok(ErlTerm::Binary(s.as_bytes().to_vec())),
+        Value::Binary(bytes) => Ok(ErlTerm::Binary(bytes.clone())),
+        Value::Array(values) => Ok(ErlTerm::List(List { elements: values_to_terms(values, depth)? })),
+        Value::Map(pairs) => {
+            let mut elements = Vec::with_capacity(pairs.len());
+            for (k, v) in pairs {
+                elements.push(ErlTerm::Tuple(Tuple { elements: vec![from_value(k, depth + 2)?, from_value(v, depth + 2)?] }));
+            }
+            Ok(ErlTerm::List(List { elements }))
+        }
+        Value::Ext(ext_type, payload) => ext_to_term(*ext_type, payload, depth)?.ok_or_else(unsupported),
+    }
+}
+
+// Elements of a term at `depth`
+fn values_to_terms(values: &[Value], depth: usize) -> Result<Vec<ErlTerm>, MsgPackError> {
+    return values.iter().map(|value| from_value(value, depth + 1)).collect();
+}
+
+fn integer_term(i: BigInt) -> ErlTerm {
+    if let Some(n) = i.to_u8() {
+        return ErlTerm::SmallInteger(n);
+    }
+    if let Some(n) = i.to_i32() {
+        return ErlTerm::Integer(n);
+    }
+    return ErlTerm::BigInteger(i);
+}
+
+fn ext_to_term(ext_type: i8, payload: &[u8], depth: usize) -> Result<Option<ErlTerm>, MsgPackError> {
+    match ext_type {
+        MSGPACK_EXT_ATOM => {
+            return match std::str::from_utf8(payload) {
+                Ok(name) => Ok(Some(ErlTerm::Atom(InternedAtom::new(name)?))),
+                Err(_) => Ok(None),
+            };
+        }
+        MSGPACK_EXT_BIG_INTEGER => {
+            return Ok(Some(integer_term(BigInt::from_signed_bytes_be(payload))));
+        }
+        _ => {}
+    }
+
+    let value = rmpv::decode::read_value(&mut &payload[..])?;
+    let fields: &[Value] = match &value {
+        Value::Array(values) => values,
+        _ => return Ok(None),
+    };
+    let term = match (ext_type, fields) {
+        (MSGPACK_EXT_TUPLE, _) => ErlTerm::Tuple(Tuple { elements: values_to_terms(fields, depth)? }),
+        (MSGPACK_EXT_MAP, _) => {
+            let mut entries = Vec::with_capacity(fields.len());
+            for field in fields {
+                match field.as_array().map(|pair| pair.as_slice()) {
+                    Some([k, v]) => entries.push((from_value(k, depth + 1)?, from_value(v, depth + 1)?)),
+                    _ => return Ok(None),
+                }
+            }
+            ErlTerm::Map(Map { entries })
+        }
+        (MSGPACK_EXT_BIT_BINARY, [Value::Binary(bytes), bits]) => match number(bits) {
+            Some(bits) => ErlTerm::BitBinary(bytes.clone(), bits),
+            None => return Ok(None),
+        },
+        (MSGPACK_EXT_IMPROPER_LIST, [Value::Array(elements), tail]) => ErlTerm::ImproperList(ImproperList {
+            elements: values_to_terms(elements, depth)?,
+            tail: Box::new(from_value(tail, depth + 1)?),
+        }),
+        (
+            MSGPACK_EXT_INTERNAL_FUN,
+            [module, arity, Value::Binary(uniq), index, old_index, old_uniq_hash, Value::Ext(MSGPACK_EXT_PID, pid), Value::Array(free_vars)],
+        ) => {
+            let free_vars = values_to_terms(free_vars, depth)?;
+            let creator_pid = match ext_to_term(MSGPACK_EXT_PID, pid, depth + 1)? {
+                Some(ErlTerm::Pid(pid)) => pid,
+                _ => return Ok(None),
+            };
+            let fun = || -> Option<ErlTerm> {
+                Some(ErlTerm::InternalFun(InternalFun {
+                    arity: number(arity)?,
+                    uniq_beam_md5: uniq.as_slice().try_into().ok()?,
+                    index: number(index)?,
+                    free_variable_count: free_vars.len() as u32,
+                    module: atom(module)?,
+                    old_index: number(old_index)?,
+                    old_uniq_hash: number(old_uniq_hash)?,
+                    creator_pid,
+                    free_vars,
+                }))
+            };
+            return Ok(fun());
+        }
+        _ => return Ok(scalar_fields_to_term(ext_type, fields)),
+    };
+    return Ok(Some(term));
+}
+
+// Extension types whose fields are atoms and integers only
+fn scalar_fields_to_term(ext_type: i8, fields: &[Value]) -> Option<ErlTerm> {
+    let term = match (ext_type, fields) {
+        (MSGPACK_EXT_PID, [node, id, serial, creation]) => ErlTerm::Pid(ErlPid {
+            node: atom(node)?,
+            id: number(id)?,
+            serial: number(serial)?,
+            creation: number(creation)?,
+        }),
+        (MSGPACK_EXT_V3_PORT, [node, id, creation]) => ErlTerm::V3Port(ErlV3Port {
+            node: atom(node)?,
+            id: number(id)?,
+            creation: number(creation)?,
+        }),
+        (MSGPACK_EXT_V4_PORT, [node, id, creation]) => ErlTerm::V4Port(ErlV4Port {
+            node: atom(node)?,
+            id: number(id)?,
+            creation: number(creation)?,
+        }),
+        (MSGPACK_EXT_REF, [node, creation, Value::Array(ids)]) => ErlTerm::Ref(Ref {
+            node: atom(node)?,
+            creation: number(creation)?,
+            id: ids.iter().map(number).collect::<Option<Vec<u32>>>()?,
+        }),
+        (MSGPACK_EXT_EXTERNAL_FUN, [module, function_name, arity]) => ErlTerm::ExternalFun(ExternalFun {
+            module: atom(module)?,
+            function_name: atom(function_name)?,
+            arity: number(arity)?,
+        }),
+        _ => return None,
+    };
+    return Some(term);
+}
+
+fn atom(value: &Value) -> Option<Atom> {
+    let name = InternedAtom::new(value.as_str()?).ok()?;
+    return Some(Atom { name });
+}
+
+fn number<T: TryFrom<i64> + TryFrom<u64>>(value: &Value) -> Option<T> {
+    match value {
+        Value::Integer(i) => match i.as_i64() {
+            Some(n) => T::try_from(n).ok(),
+            None => T::try_from(i.as_u64()?).ok(),
+        },
+        _ => None,
+    }
+}
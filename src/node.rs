@@ -0,0 +1,780 @@
+// A local Erlang node (requires the distribution feature).
+//
+// Node is the equivalent of JInterface's OtpNode: it accepts and opens
+// distribution connections, allocates pids and references with its own
+// name and creation, and routes incoming messages to Mailboxes, which
+// play the part of processes (OtpMbox). Mailboxes can be registered
+// under a name, linked to and monitored, on this node or another one.
+//
+// Everything a mailbox does goes through the same control messages
+// whether the other process is local or remote, a local one is merely
+// handled without a connection. Signals follow the Erlang semantics:
+//
+//  * an exit signal from a linked process (or exit/2) makes the next
+//    receive fail with DistributionError::Exit, unless its reason is
+//    normal: mailboxes do not trap exits
+//  * monitors deliver {'DOWN', Ref, process, Pid, Reason} messages
+//  * losing a connection counts as an exit with reason noconnection
+//    for everything linked to or monitored on the other node
+//
+// A dropped mailbox exits with reason normal. Mailboxes have no group
+// leaders and no aliases, so group_leader/2 signals are ignored and
+// messages sent to an alias are dropped, as they are for inactive aliases.
+// Spawn requests are refused with notsup.
+//
+// Messages that fail to decode do not take the connection down, they
+// are kept for take_bad_messages.
+//
+// On top of that, Mailbox::call and Mailbox::rpc do what gen_server:call
+// and rpc:call do, which covers most of what one wants from a node.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::*;
+
+// Pids have 15 bit ids and 13 bit serials, like in JInterface
+const MAX_PID_ID: u32 = 0x7fff;
+const MAX_PID_SERIAL: u32 = 0x1fff;
+
+// Only the most recent bad messages are kept
+const MAX_BAD_MESSAGES: usize = 64;
+
+#[derive(Clone)]
+pub struct Node {
+    inner: Arc<NodeInner>,
+}
+
+struct NodeInner {
+    config: HandshakeConfig,
+    name: InternedAtom,
+    // the configured one until EPMD hands out another
+    creation: AtomicU32,
+    addr: SocketAddr,
+    state: Mutex<NodeState>,
+}
+
+#[derive(Default)]
+struct NodeState {
+    closed: bool,
+    next_pid: (u32, u32),
+    next_ref: u64,
+    mailboxes: HashMap<(u32, u32), Arc<Queue>>,
+    names: HashMap<InternedAtom, ErlPid>,
+    connections: HashMap<String, Arc<Mutex<Connection>>>,
+    // (local pid, linked pid), both directions for local links
+    links: HashSet<(ErlPid, ErlPid)>,
+    // monitors set up by local mailboxes
+    monitoring: HashMap<Ref, Monitor>,
+    // monitors on local mailboxes: (watched, watcher, how the watcher referred to it)
+    monitored: HashMap<Ref, (ErlPid, ErlPid, Process)>,
+    registration: Option<Registration>,
+    bad_messages: VecDeque<BadMessage>,
+}
+
+// A message from another node that could not be decoded or handled
+#[derive(Debug)]
+pub struct BadMessage {
+    pub node: String,
+    pub error: DistributionError,
+}
+
+struct Monitor {
+    watcher: ErlPid,
+    watched: Process,
+    node: InternedAtom,
+}
+
+enum Delivery {
+    Message(ErlTerm),
+    Exit { from: ErlPid, reason: ErlTerm },
+}
+
+#[derive(Default)]
+struct Queue {
+    deliveries: Mutex<VecDeque<Delivery>>,
+    available: Condvar,
+}
+
+impl Queue {
+    fn push(&self, delivery: Delivery) {
+        self.deliveries.lock().unwrap().push_back(delivery);
+        self.available.notify_one();
+    }
+}
+
+impl Node {
+    // Starts listening for connections from other nodes on all interfaces
+    pub fn start(config: HandshakeConfig) -> Result<Self, DistributionError> {
+        let name = InternedAtom::new(&config.name).map_err(DecodingError::from)?;
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, listener.local_addr()?.port()));
+        let node = Node {
+            inner: Arc::new(NodeInner {
+                creation: AtomicU32::new(config.creation),
+                config,
+                name,
+                addr,
+                state: Mutex::new(NodeState::default()),
+            }),
+        };
+
+        let accepting = node.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.inner.state.lock().unwrap().closed {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let node = accepting.clone();
+                // a slow handshake should not hold up the others
+                thread::spawn(move || {
+                    if let Ok(connection) = Connection::accept(stream, &node.config()) {
+                        node.add_connection(connection);
+                    }
+                });
+            }
+        });
+        return Ok(node);
+    }
+
+    pub fn name(&self) -> &str {
+        return self.inner.name.as_str();
+    }
+
+    pub fn creation(&self) -> u32 {
+        return self.inner.creation.load(Ordering::SeqCst);
+    }
+
+    // The configuration with the current creation, for handshakes
+    fn config(&self) -> HandshakeConfig {
+        return HandshakeConfig { creation: self.creation(), ..self.inner.config.clone() };
+    }
+
+    // Where other nodes on this host can connect to
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.inner.addr;
+    }
+
+    // Registers the node in EPMD, so that other nodes can connect to it by name.
+    // From then on the node uses the creation EPMD hands out, which tells it
+    // apart from earlier incarnations, so mailboxes are best created afterwards
+    pub fn register_with(&self, epmd: &EpmdClient) -> Result<(), DistributionError> {
+        let (alive, _) = distribution::split_node_name(self.name())?;
+        let mut info = NodeInfo::new(alive, self.inner.addr.port());
+        if self.inner.config.flags & DFLAG_PUBLISHED == 0 {
+            info.node_type = NodeType::Hidden;
+        }
+        let registration = epmd.register(&info)?;
+        self.inner.creation.store(registration.creation(), Ordering::SeqCst);
+        self.inner.state.lock().unwrap().registration = Some(registration);
+        return Ok(());
+    }
+
+    // Connects to a node registered in the EPMD of its host, unless
+    // already connected
+    pub fn connect(&self, node: &str) -> Result<(), DistributionError> {
+        self.connection(node)?;
+        return Ok(());
+    }
+
+    // Connects to a node at a known address, returns its name
+    pub fn connect_to(&self, addr: SocketAddr) -> Result<String, DistributionError> {
+        let connection = Connection::connect_to(&self.config(), addr, 6)?;
+        let name = connection.peer_name().to_string();
+        self.add_connection(connection);
+        return Ok(name);
+    }
+
+    // Messages from other nodes that could not be decoded or handled since
+    // the last call, oldest first
+    pub fn take_bad_messages(&self) -> Vec<BadMessage> {
+        return self.inner.state.lock().unwrap().bad_messages.drain(..).collect();
+    }
+
+    pub fn connected_nodes(&self) -> Vec<String> {
+        let mut nodes: Vec<String> = self.inner.state.lock().unwrap().connections.keys().cloned().collect();
+        nodes.sort();
+        return nodes;
+    }
+
+    // Closes all connections and stops accepting new ones
+    pub fn close(&self) {
+        let connections: Vec<Arc<Mutex<Connection>>> = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.closed = true;
+            state.registration = None;
+            state.connections.values().cloned().collect()
+        };
+        for connection in connections {
+            let _ = connection.lock().unwrap().shutdown();
+        }
+        // wakes up the accepting thread so that it notices
+        let _ = TcpStream::connect(self.inner.addr);
+    }
+
+    pub fn create_mailbox(&self) -> Mailbox {
+        let mut state = self.inner.state.lock().unwrap();
+        let (id, serial) = state.next_pid;
+        state.next_pid = if id < MAX_PID_ID { (id + 1, serial) } else { (0, (serial + 1) & MAX_PID_SERIAL) };
+        let pid = ErlPid {
+            node: Atom { name: self.inner.name },
+            id,
+            serial,
+            creation: self.creation(),
+        };
+        let queue = Arc::new(Queue::default());
+        state.mailboxes.insert((id, serial), queue.clone());
+        return Mailbox { node: self.clone(), pid, queue };
+    }
+
+    pub fn create_named_mailbox(&self, name: &str) -> Result<Mailbox, DistributionError> {
+        let mailbox = self.create_mailbox();
+        mailbox.register(name)?;
+        return Ok(mailbox);
+    }
+
+    pub fn whereis(&self, name: &str) -> Option<ErlPid> {
+        let name = InternedAtom::new(name).ok()?;
+        return self.inner.state.lock().unwrap().names.get(&name).cloned();
+    }
+
+    // A reference that is unique for this node's incarnation
+    pub fn make_ref(&self) -> Ref {
+        let mut state = self.inner.state.lock().unwrap();
+        state.next_ref += 1;
+        let n = state.next_ref;
+        return Ref {
+            node: Atom { name: self.inner.name },
+            creation: self.creation(),
+            id: vec![(n & 0x3ffff) as u32, (n >> 18) as u32, (n >> 50) as u32],
+        };
+    }
+
+    fn connection(&self, node: &str) -> Result<Arc<Mutex<Connection>>, DistributionError> {
+        if let Some(connection) = self.inner.state.lock().unwrap().connections.get(node) {
+            return Ok(connection.clone());
+        }
+        let connection = Connection::connect(&self.config(), node)?;
+        return Ok(self.add_connection(connection));
+    }
+
+    fn add_connection(&self, connection: Connection) -> Arc<Mutex<Connection>> {
+        let peer = connection.peer_name().to_string();
+        let mut reader = connection;
+        let writer = match reader.try_clone() {
+            Ok(writer) => Arc::new(Mutex::new(writer)),
+            Err(_) => return Arc::new(Mutex::new(reader)),
+        };
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.closed {
+                let _ = reader.shutdown();
+                return writer;
+            }
+            // the newer connection wins when both nodes connected at once
+            state.connections.insert(peer.clone(), writer.clone());
+        }
+
+        let node = self.clone();
+        let own_writer = writer.clone();
+        thread::spawn(move || {
+            loop {
+                match reader.receive_control() {
+                    Ok((control, payload)) => node.handle(control, payload),
+                    // the message was read in full, so the next one can still be
+                    // read. Everything else is an I/O or framing error
+                    Err(error @ (DistributionError::DecodingFailure(_) | DistributionError::UnsupportedControlMessage { .. })) => {
+                        node.bad_message(&peer, error);
+                    }
+                    Err(_) => break,
+                }
+            }
+            node.connection_lost(&peer, &own_writer);
+        });
+        return writer;
+    }
+
+    fn bad_message(&self, peer: &str, error: DistributionError) {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.bad_messages.len() == MAX_BAD_MESSAGES {
+            state.bad_messages.pop_front();
+        }
+        state.bad_messages.push_back(BadMessage { node: peer.to_string(), error });
+    }
+
+    // Sends a control message to the node the pid belongs to, which may be this one
+    fn route(&self, node: &str, control: ControlMessage, payload: Option<ErlTerm>) -> Result<(), DistributionError> {
+        if node == self.name() {
+            self.handle(control, payload);
+            return Ok(());
+        }
+        let connection = self.connection(node)?;
+        let mut connection = connection.lock().unwrap();
+        return connection.send_control(&control, payload.as_ref());
+    }
+
+    // Signals that could not be sent are as good as lost, as in Erlang
+    fn route_all(&self, signals: Vec<(String, ControlMessage)>) {
+        for (node, control) in signals {
+            let _ = self.route(&node, control, None);
+        }
+    }
+
+    fn handle(&self, control: ControlMessage, payload: Option<ErlTerm>) {
+        let mut replies = Vec::new();
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            match control {
+                ControlMessage::Send { to, .. } | ControlMessage::SendSender { to, .. } => {
+                    if let Some(message) = payload {
+                        state.deliver(&to, Delivery::Message(message));
+                    }
+                }
+                ControlMessage::RegSend { to_name, .. } => {
+                    if let (Some(to), Some(message)) = (state.names.get(&to_name.name).cloned(), payload) {
+                        state.deliver(&to, Delivery::Message(message));
+                    }
+                }
+                ControlMessage::Link { from, to } => {
+                    if state.is_alive(&to) {
+                        state.links.insert((to, from));
+                    } else {
+                        let reply = ControlMessage::Exit { from: to, to: from.clone(), reason: atom("noproc"), token: None };
+                        replies.push((node_of(&from), reply));
+                    }
+                }
+                ControlMessage::Unlink { from, to } => {
+                    state.links.remove(&(to, from));
+                }
+                ControlMessage::UnlinkId { id, from, to } => {
+                    state.links.remove(&(to.clone(), from.clone()));
+                    replies.push((node_of(&from), ControlMessage::UnlinkIdAck { id, from: to, to: from }));
+                }
+                ControlMessage::Exit { from, to, reason, .. } => {
+                    // only linked processes get exit signals
+                    let linked = state.links.remove(&(to.clone(), from.clone()));
+                    if linked {
+                        state.exit(&to, from, reason);
+                    }
+                }
+                ControlMessage::PayloadExit { from, to, .. } => {
+                    let linked = state.links.remove(&(to.clone(), from.clone()));
+                    if linked {
+                        state.exit(&to, from, payload.unwrap_or_else(|| atom("normal")));
+                    }
+                }
+                ControlMessage::Exit2 { from, to, reason, .. } => {
+                    state.exit(&to, from, reason);
+                }
+                ControlMessage::PayloadExit2 { from, to, .. } => {
+                    state.exit(&to, from, payload.unwrap_or_else(|| atom("normal")));
+                }
+                ControlMessage::MonitorP { from, to, reference } => {
+                    let watched = match &to {
+                        Process::Pid(pid) => Some(pid.clone()),
+                        Process::Name(name) => state.names.get(&name.name).cloned(),
+                    };
+                    match watched {
+                        Some(watched) if state.is_alive(&watched) => {
+                            state.monitored.insert(reference, (watched, from, to));
+                        }
+                        _ => replies.push((
+                            node_of(&from),
+                            ControlMessage::MonitorPExit { from: to, to: from, reference, reason: atom("noproc") },
+                        )),
+                    }
+                }
+                ControlMessage::DemonitorP { reference, .. } => {
+                    state.monitored.remove(&reference);
+                }
+                ControlMessage::MonitorPExit { reference, reason, .. } => {
+                    state.down(&reference, reason);
+                }
+                ControlMessage::PayloadMonitorPExit { reference, .. } => {
+                    state.down(&reference, payload.unwrap_or_else(|| atom("normal")));
+                }
+                ControlMessage::SpawnRequest { request_id, from, .. } => {
+                    let result = atom("notsup");
+                    let reply = ControlMessage::SpawnReply { request_id, to: from.clone(), flags: 0, result, token: None };
+                    replies.push((node_of(&from), reply));
+                }
+                // mailboxes have neither aliases nor group leaders
+                ControlMessage::AliasSend { .. } | ControlMessage::GroupLeader { .. } => {}
+                // links to the node as a whole, and replies to requests we never make
+                ControlMessage::NodeLink | ControlMessage::SpawnReply { .. } | ControlMessage::UnlinkIdAck { .. } => {}
+            }
+        }
+        self.route_all(replies);
+    }
+
+    fn connection_lost(&self, peer: &str, connection: &Arc<Mutex<Connection>>) {
+        let mut state = self.inner.state.lock().unwrap();
+        match state.connections.get(peer) {
+            Some(current) if Arc::ptr_eq(current, connection) => {
+                state.connections.remove(peer);
+            }
+            // replaced by a newer connection to the same node
+            _ => return,
+        }
+
+        let on_peer = |pid: &ErlPid| pid.node.name.as_str() == peer;
+        let links: Vec<(ErlPid, ErlPid)> = state.links.iter().filter(|(_, other)| on_peer(other)).cloned().collect();
+        for (local, other) in links {
+            state.links.remove(&(local.clone(), other.clone()));
+            state.deliver(&local, Delivery::Exit { from: other, reason: atom("noconnection") });
+        }
+        let monitors: Vec<Ref> = state
+            .monitoring
+            .iter()
+            .filter(|(_, monitor)| monitor.node == peer)
+            .map(|(reference, _)| reference.clone())
+            .collect();
+        for reference in monitors {
+            state.down(&reference, atom("noconnection"));
+        }
+        state.monitored.retain(|_, (_, watcher, _)| !on_peer(watcher));
+    }
+}
+
+impl NodeState {
+    fn is_alive(&self, pid: &ErlPid) -> bool {
+        return self.mailboxes.contains_key(&(pid.id, pid.serial));
+    }
+
+    // Exit signals with reason normal only end the process that sent
+    // them to itself, other processes ignore them unless they trap exits
+    fn exit(&self, to: &ErlPid, from: ErlPid, reason: ErlTerm) {
+        if reason == atom("normal") && from != *to {
+            return;
+        }
+        self.deliver(to, Delivery::Exit { from, reason });
+    }
+
+    // Messages to pids that are gone are dropped, as in Erlang
+    fn deliver(&self, to: &ErlPid, delivery: Delivery) {
+        if let Some(queue) = self.mailboxes.get(&(to.id, to.serial)) {
+            queue.push(delivery);
+        }
+    }
+
+    // Delivers {'DOWN', Ref, process, Pid | {Name, Node}, Reason} for a monitor
+    fn down(&mut self, reference: &Ref, reason: ErlTerm) {
+        let Some(monitor) = self.monitoring.remove(reference) else { return };
+        let object = match monitor.watched {
+            Process::Pid(pid) => ErlTerm::Pid(pid),
+            Process::Name(name) => ErlTerm::Tuple(Tuple {
+                elements: vec![name.into(), ErlTerm::Atom(monitor.node)],
+            }),
+        };
+        let down = ErlTerm::Tuple(Tuple {
+            elements: vec![atom("DOWN"), ErlTerm::Ref(reference.clone()), atom("process"), object, reason],
+        });
+        self.deliver(&monitor.watcher, Delivery::Message(down));
+    }
+}
+
+fn node_of(pid: &ErlPid) -> String {
+    return pid.node.name.to_string();
+}
+
+fn atom(name: &'static str) -> ErlTerm {
+    return ErlTerm::Atom(InternedAtom::fixed(name));
+}
+
+pub struct Mailbox {
+    node: Node,
+    pid: ErlPid,
+    queue: Arc<Queue>,
+}
+
+impl Mailbox {
+    pub fn pid(&self) -> &ErlPid {
+        return &self.pid;
+    }
+
+    pub fn node(&self) -> &Node {
+        return &self.node;
+    }
+
+    // Registers the mailbox under a name, which other nodes can send to
+    pub fn register(&self, name: &str) -> Result<(), DistributionError> {
+        let name = InternedAtom::new(name).map_err(DecodingError::from)?;
+        let mut state = self.node.inner.state.lock().unwrap();
+        if state.names.contains_key(&name) {
+            return Err(DistributionError::NameTaken { name: name.to_string() });
+        }
+        state.names.insert(name, self.pid.clone());
+        return Ok(());
+    }
+
+    pub fn send(&self, to: &ErlPid, message: ErlTerm) -> Result<(), DistributionError> {
+        let control = ControlMessage::Send { to: to.clone(), token: None };
+        return self.node.route(to.node.name.as_str(), control, Some(message));
+    }
+
+    // Sends to a process registered under a name on the given node
+    pub fn send_named(&self, name: &str, node: &str, message: ErlTerm) -> Result<(), DistributionError> {
+        let to_name = Atom { name: InternedAtom::new(name).map_err(DecodingError::from)? };
+        let control = ControlMessage::RegSend { from: self.pid.clone(), to_name, token: None };
+        return self.node.route(node, control, Some(message));
+    }
+
+    // Waits for the next message, fails when an exit signal arrives first
+    pub fn receive(&self) -> Result<ErlTerm, DistributionError> {
+        let mut deliveries = self.queue.deliveries.lock().unwrap();
+        loop {
+            if let Some(delivery) = deliveries.pop_front() {
+                return into_message(delivery);
+            }
+            deliveries = self.queue.available.wait(deliveries).unwrap();
+        }
+    }
+
+    // As receive, None when nothing arrives in time
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<Option<ErlTerm>, DistributionError> {
+        let deadline = Instant::now() + timeout;
+        let mut deliveries = self.queue.deliveries.lock().unwrap();
+        loop {
+            if let Some(delivery) = deliveries.pop_front() {
+                return into_message(delivery).map(Some);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            deliveries = self.queue.available.wait_timeout(deliveries, deadline - now).unwrap().0;
+        }
+    }
+
+    // Takes the first message the filter accepts, leaving the others in the
+    // mailbox for later. Exit signals are never skipped
+    pub fn receive_matching<T>(
+        &self,
+        timeout: Duration,
+        mut filter: impl FnMut(&ErlTerm) -> Option<T>,
+    ) -> Result<Option<T>, DistributionError> {
+        let deadline = Instant::now() + timeout;
+        let mut deliveries = self.queue.deliveries.lock().unwrap();
+        let mut seen = 0;
+        loop {
+            while seen < deliveries.len() {
+                let matched = match &deliveries[seen] {
+                    Delivery::Message(message) => filter(message),
+                    Delivery::Exit { .. } => return into_message(deliveries.remove(seen).unwrap()).map(|_| None),
+                };
+                if matched.is_some() {
+                    deliveries.remove(seen);
+                    return Ok(matched);
+                }
+                seen += 1;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            deliveries = self.queue.available.wait_timeout(deliveries, deadline - now).unwrap().0;
+        }
+    }
+
+    // gen_server:call(Server, Request, Timeout)
+    pub fn call(&self, server: &ErlPid, request: ErlTerm, timeout: Duration) -> Result<ErlTerm, DistributionError> {
+        return self.gen_call(Process::Pid(server.clone()), &node_of(server), request, timeout);
+    }
+
+    // gen_server:call({Name, Node}, Request, Timeout)
+    pub fn call_named(&self, name: &str, node: &str, request: ErlTerm, timeout: Duration) -> Result<ErlTerm, DistributionError> {
+        let name = Atom { name: InternedAtom::new(name).map_err(DecodingError::from)? };
+        return self.gen_call(Process::Name(name), node, request, timeout);
+    }
+
+    // rpc:call(Node, Module, Function, Args, Timeout), through the rex server
+    // of the other node
+    pub fn rpc(
+        &self,
+        node: &str,
+        module: &str,
+        function: &str,
+        args: Vec<ErlTerm>,
+        timeout: Duration,
+    ) -> Result<ErlTerm, DistributionError> {
+        let request = ErlTerm::Tuple(Tuple {
+            elements: vec![
+                atom("call"),
+                ErlTerm::Atom(InternedAtom::new(module).map_err(DecodingError::from)?),
+                ErlTerm::Atom(InternedAtom::new(function).map_err(DecodingError::from)?),
+                ErlTerm::List(List { elements: args }),
+                atom("user"),
+            ],
+        });
+        match self.call_named("rex", node, request, timeout)? {
+            ErlTerm::Tuple(Tuple { mut elements }) if elements.len() == 2 && elements[0] == atom("badrpc") => {
+                return Err(DistributionError::BadRpc { reason: Box::new(elements.pop().unwrap()) });
+            }
+            reply => return Ok(reply),
+        }
+    }
+
+    // Monitors the server while waiting, like gen:call, so that a server
+    // or node that goes away fails the call instead of running into the timeout
+    fn gen_call(&self, server: Process, node: &str, request: ErlTerm, timeout: Duration) -> Result<ErlTerm, DistributionError> {
+        let reference = self.monitor_process(server.clone(), InternedAtom::new(node).map_err(DecodingError::from)?);
+        let from = ErlTerm::Tuple(Tuple { elements: vec![ErlTerm::Pid(self.pid.clone()), ErlTerm::Ref(reference.clone())] });
+        let message = ErlTerm::Tuple(Tuple { elements: vec![atom("$gen_call"), from, request] });
+        let control = match server {
+            Process::Pid(to) => ControlMessage::Send { to, token: None },
+            Process::Name(to_name) => ControlMessage::RegSend { from: self.pid.clone(), to_name, token: None },
+        };
+        // a message that cannot be sent shows up as a DOWN for the monitor
+        let _ = self.node.route(node, control, Some(message));
+
+        let tag = ErlTerm::Ref(reference.clone());
+        let result = self.receive_matching(timeout, |message| {
+            let ErlTerm::Tuple(Tuple { elements }) = message else { return None };
+            match elements.as_slice() {
+                [r, reply] if *r == tag => Some(Ok(reply.clone())),
+                [down, r, _, _, reason] if *down == atom("DOWN") && *r == tag => Some(Err(reason.clone())),
+                _ => None,
+            }
+        });
+        let reply = match result {
+            Ok(Some(Ok(reply))) => reply,
+            Ok(Some(Err(reason))) if reason == atom("noconnection") => {
+                return Err(DistributionError::NodeDown { name: node.to_string() });
+            }
+            Ok(Some(Err(reason))) => return Err(DistributionError::CallFailed { reason: Box::new(reason) }),
+            Ok(None) => {
+                self.demonitor(&reference)?;
+                return Err(DistributionError::Timeout);
+            }
+            Err(e) => {
+                let _ = self.demonitor(&reference);
+                return Err(e);
+            }
+        };
+        self.demonitor(&reference)?;
+        // the server may have gone down right after replying
+        self.receive_matching(Duration::ZERO, |message| match message {
+            ErlTerm::Tuple(Tuple { elements }) if elements.len() == 5 && elements[1] == tag => Some(()),
+            _ => None,
+        })?;
+        return Ok(reply);
+    }
+
+    // Links are bidirectional: whichever side exits first sends the other an exit signal
+    pub fn link(&self, to: &ErlPid) -> Result<(), DistributionError> {
+        self.node.inner.state.lock().unwrap().links.insert((self.pid.clone(), to.clone()));
+        let control = ControlMessage::Link { from: self.pid.clone(), to: to.clone() };
+        if self.node.route(to.node.name.as_str(), control, None).is_err() {
+            let mut state = self.node.inner.state.lock().unwrap();
+            state.links.remove(&(self.pid.clone(), to.clone()));
+            state.deliver(&self.pid, Delivery::Exit { from: to.clone(), reason: atom("noconnection") });
+        }
+        return Ok(());
+    }
+
+    pub fn unlink(&self, to: &ErlPid) -> Result<(), DistributionError> {
+        let id = {
+            let mut state = self.node.inner.state.lock().unwrap();
+            state.links.remove(&(self.pid.clone(), to.clone()));
+            state.next_ref += 1;
+            state.next_ref
+        };
+        let control = ControlMessage::UnlinkId { id, from: self.pid.clone(), to: to.clone() };
+        return self.node.route(to.node.name.as_str(), control, None);
+    }
+
+    // The returned reference is in the DOWN message, and stops the monitor
+    // when passed to demonitor
+    pub fn monitor(&self, pid: &ErlPid) -> Result<Ref, DistributionError> {
+        return Ok(self.monitor_process(Process::Pid(pid.clone()), pid.node.name));
+    }
+
+    // Monitors whichever process is registered under the name on the node
+    pub fn monitor_named(&self, name: &str, node: &str) -> Result<Ref, DistributionError> {
+        let name = Atom { name: InternedAtom::new(name).map_err(DecodingError::from)? };
+        let node = InternedAtom::new(node).map_err(DecodingError::from)?;
+        return Ok(self.monitor_process(Process::Name(name), node));
+    }
+
+    fn monitor_process(&self, watched: Process, node: InternedAtom) -> Ref {
+        let reference = self.node.make_ref();
+        let monitor = Monitor { watcher: self.pid.clone(), watched: watched.clone(), node };
+        self.node.inner.state.lock().unwrap().monitoring.insert(reference.clone(), monitor);
+        let control = ControlMessage::MonitorP { from: self.pid.clone(), to: watched, reference: reference.clone() };
+        if self.node.route(node.as_str(), control, None).is_err() {
+            self.node.inner.state.lock().unwrap().down(&reference, atom("noconnection"));
+        }
+        return reference;
+    }
+
+    pub fn demonitor(&self, reference: &Ref) -> Result<(), DistributionError> {
+        let Some(monitor) = self.node.inner.state.lock().unwrap().monitoring.remove(reference) else {
+            return Ok(());
+        };
+        let control = ControlMessage::DemonitorP { from: self.pid.clone(), to: monitor.watched, reference: reference.clone() };
+        return self.node.route(monitor.node.as_str(), control, None);
+    }
+
+    // Closes the mailbox, linked processes and monitors get the reason
+    pub fn exit(self, reason: ErlTerm) {
+        self.close(reason);
+    }
+
+    fn close(&self, reason: ErlTerm) {
+        let mut signals = Vec::new();
+        {
+            let mut state = self.node.inner.state.lock().unwrap();
+            if state.mailboxes.remove(&(self.pid.id, self.pid.serial)).is_none() {
+                return;
+            }
+            state.names.retain(|_, pid| *pid != self.pid);
+
+            let links: Vec<(ErlPid, ErlPid)> = state.links.iter().filter(|(local, _)| *local == self.pid).cloned().collect();
+            for link in links {
+                state.links.remove(&link);
+                let node = node_of(&link.1);
+                signals.push((node, ControlMessage::Exit { from: link.0, to: link.1, reason: reason.clone(), token: None }));
+            }
+            let monitored: Vec<Ref> = state
+                .monitored
+                .iter()
+                .filter(|(_, (watched, _, _))| *watched == self.pid)
+                .map(|(reference, _)| reference.clone())
+                .collect();
+            for reference in monitored {
+                let (_, watcher, process) = state.monitored.remove(&reference).unwrap();
+                let node = node_of(&watcher);
+                signals.push((node, ControlMessage::MonitorPExit { from: process, to: watcher, reference, reason: reason.clone() }));
+            }
+            let monitoring: Vec<Ref> = state
+                .monitoring
+                .iter()
+                .filter(|(_, monitor)| monitor.watcher == self.pid)
+                .map(|(reference, _)| reference.clone())
+                .collect();
+            for reference in monitoring {
+                let monitor = state.monitoring.remove(&reference).unwrap();
+                signals.push((monitor.node.to_string(), ControlMessage::DemonitorP { from: monitor.watcher, to: monitor.watched, reference }));
+            }
+        }
+        self.node.route_all(signals);
+    }
+}
+
+impl Drop for Mailbox {
+    fn drop(&mut self) {
+        self.close(atom("normal"));
+    }
+}
+
+fn into_message(delivery: Delivery) -> Result<ErlTerm, DistributionError> {
+    match delivery {
+        Delivery::Message(message) => Ok(message),
+        Delivery::Exit { from, reason } => Err(DistributionError::Exit { from, reason: Box::new(reason) }),
+    }
+}
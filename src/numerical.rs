@@ -1,5 +1,8 @@
+use alloc::format;
+
 use num::bigint::Sign;
-use std::io;
+
+use crate::io;
 
 pub(crate) fn to_sign(i: u8) -> io::Result<Sign> {
     match i {
@@ -0,0 +1,467 @@
+// Erlang-style pattern matching against terms.
+//
+// Patterns use Erlang syntax:
+//
+//  * atoms: ok, 'EXIT'
+//  * variables: S, Rest, _Ignored (bound variables must match equal terms when repeated)
+//  * the wildcard: _
+//  * integers, floats, strings ("abc" is a list of integers), binaries (<<"abc">>, <<1,2,3:5>>)
+//  * tuples: {ok, Value}
+//  * lists: [], [H | T], [A, B, C]
+//  * maps: #{status := S}, which matches maps that have at least the given
+//    keys. As in Erlang, keys cannot contain variables or wildcards
+//
+// Pids, ports, references and funs have no literal syntax, use Pattern::Literal
+// to match any term exactly.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use num::bigint::BigInt;
+use num::ToPrimitive;
+use thiserror::Error;
+
+use crate::*;
+
+pub type Bindings = HashMap<String, ErlTerm>;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum PatternError {
+    #[error("unexpected character in pattern")]
+    UnexpectedCharacter { position: usize, character: char },
+    #[error("unexpected token in pattern")]
+    UnexpectedToken { position: usize },
+    #[error("pattern ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("invalid number literal")]
+    InvalidNumber { position: usize },
+    #[error("failed to intern an atom")]
+    AtomTableFull(#[from] AtomTableFull),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Variable(String),
+    // matches any integer representation with the same value
+    Integer(BigInt),
+    // matches an equal term
+    Literal(ErlTerm),
+    Tuple(Vec<Pattern>),
+    List { elements: Vec<Pattern>, tail: Box<Pattern> },
+    // key patterns have no variables, so they bind nothing
+    Map(Vec<(Pattern, Pattern)>),
+}
+
+impl Pattern {
+    pub fn parse(input: &str) -> Result<Self, PatternError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let pattern = parser.parse_pattern()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(pattern),
+            Some((position, _)) => Err(PatternError::UnexpectedToken { position: *position }),
+        }
+    }
+
+    pub fn literal(term: ErlTerm) -> Self {
+        return Pattern::Literal(term);
+    }
+
+    // Matches a term, returns variable bindings on success
+    pub fn match_term(&self, term: &ErlTerm) -> Option<Bindings> {
+        let mut bindings = Bindings::new();
+        if self.match_with(term, &mut bindings) {
+            return Some(bindings);
+        }
+        return None;
+    }
+
+    pub fn is_match(&self, term: &ErlTerm) -> bool {
+        return self.match_term(term).is_some();
+    }
+
+    fn match_with(&self, term: &ErlTerm, bindings: &mut Bindings) -> bool {
+        match self {
+            Pattern::Wildcard => true,
+            Pattern::Variable(name) => match bindings.get(name) {
+                Some(bound) => bound == term,
+                None => {
+                    bindings.insert(name.clone(), term.clone());
+                    true
+                }
+            },
+            Pattern::Integer(expected) => match integer_value(term) {
+                Some(actual) => actual == *expected,
+                None => false,
+            },
+            Pattern::Literal(expected) => expected == term,
+            Pattern::Tuple(patterns) => match term {
+                ErlTerm::Tuple(t) => {
+                    t.elements.len() == patterns.len()
+                        && patterns.iter().zip(&t.elements).all(|(p, e)| p.match_with(e, bindings))
+                }
+                _ => false,
+            },
+            Pattern::List { elements: patterns, tail } => {
+                let (elements, rest): (&[ErlTerm], Option<&ErlTerm>) = match term {
+                    ErlTerm::List(l) => (&l.elements, None),
+                    ErlTerm::ImproperList(l) => (&l.elements, Some(&l.tail)),
+                    _ => return false,
+                };
+                if elements.len() < patterns.len() {
+                    return false;
+                }
+                let n = patterns.len();
+                if !patterns.iter().zip(&elements[..n]).all(|(p, e)| p.match_with(e, bindings)) {
+                    return false;
+                }
+                let remainder = match rest {
+                    None => ErlTerm::List(List { elements: elements[n..].to_vec() }),
+                    Some(t) if elements.len() == n => (*t).clone(),
+                    Some(t) => ErlTerm::ImproperList(ImproperList {
+                        elements: elements[n..].to_vec(),
+                        tail: Box::new(t.clone()),
+                    }),
+                };
+                tail.match_with(&remainder, bindings)
+            }
+            Pattern::Map(patterns) => match term {
+                ErlTerm::Map(m) => patterns.iter().all(|(key, value)| {
+                    match m.entries.iter().find(|(k, _)| key.match_with(k, &mut Bindings::new())) {
+                        Some((_, v)) => value.match_with(v, bindings),
+                        None => false,
+                    }
+                }),
+                _ => false,
+            },
+        }
+    }
+
+    // Whether the pattern has no variables or wildcards
+    fn is_ground(&self) -> bool {
+        match self {
+            Pattern::Wildcard | Pattern::Variable(_) => false,
+            Pattern::Integer(_) | Pattern::Literal(_) => true,
+            Pattern::Tuple(elements) => elements.iter().all(Pattern::is_ground),
+            Pattern::List { elements, tail } => elements.iter().all(Pattern::is_ground) && tail.is_ground(),
+            Pattern::Map(pairs) => pairs.iter().all(|(key, value)| key.is_ground() && value.is_ground()),
+        }
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Pattern::parse(s);
+    }
+}
+
+fn integer_value(term: &ErlTerm) -> Option<BigInt> {
+    match term {
+        ErlTerm::SmallInteger(i) => Some(BigInt::from(*i)),
+        ErlTerm::Integer(i) => Some(BigInt::from(*i)),
+        ErlTerm::BigInteger(i) => Some(i.clone()),
+        _ => None,
+    }
+}
+
+//
+// Tokenizer
+//
+
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Atom(String),
+    Variable(String),
+    Integer(BigInt),
+    Float(f64),
+    Str(String),
+    Punct(&'static str),
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, PatternError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = match c {
+            '#' if chars.get(i + 1) == Some(&'{') => {
+                i += 2;
+                Token::Punct("#{")
+            }
+            ':' if chars.get(i + 1) == Some(&'=') => {
+                i += 2;
+                Token::Punct(":=")
+            }
+            '{' | '}' | '[' | ']' | '|' | ',' | ':' => {
+                i += 1;
+                Token::Punct(match c {
+                    '{' => "{",
+                    '}' => "}",
+                    '[' => "[",
+                    ']' => "]",
+                    '|' => "|",
+                    ',' => ",",
+                    _ => ":",
+                })
+            }
+            '<' if chars.get(i + 1) == Some(&'<') => {
+                i += 2;
+                Token::Punct("<<")
+            }
+            '>' if chars.get(i + 1) == Some(&'>') => {
+                i += 2;
+                Token::Punct(">>")
+            }
+            '\'' | '"' => {
+                let (s, next) = read_quoted(&chars, i)?;
+                i = next;
+                if c == '\'' { Token::Atom(s) } else { Token::Str(s) }
+            }
+            '-' | '0'..='9' => {
+                let (token, next) = read_number(&chars, i)?;
+                i = next;
+                token
+            }
+            c if c.is_lowercase() => {
+                let (s, next) = read_name(&chars, i);
+                i = next;
+                Token::Atom(s)
+            }
+            c if c.is_uppercase() || c == '_' => {
+                let (s, next) = read_name(&chars, i);
+                i = next;
+                Token::Variable(s)
+            }
+            c => return Err(PatternError::UnexpectedCharacter { position: i, character: c }),
+        };
+        tokens.push((start, token));
+    }
+
+    return Ok(tokens);
+}
+
+fn read_name(chars: &[char], start: usize) -> (String, usize) {
+    let mut end = start;
+    while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_' || chars[end] == '@') {
+        end += 1;
+    }
+    return (chars[start..end].iter().collect(), end);
+}
+
+fn read_quoted(chars: &[char], start: usize) -> Result<(String, usize), PatternError> {
+    let quote = chars[start];
+    let mut s = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                s.push(match chars[i + 1] {
+                    'n' => '\n',
+                    't' => '\t',
+                    c => c,
+                });
+                i += 2;
+            }
+            c if c == quote => return Ok((s, i + 1)),
+            c => {
+                s.push(c);
+                i += 1;
+            }
+        }
+    }
+    return Err(PatternError::UnexpectedEnd);
+}
+
+fn read_number(chars: &[char], start: usize) -> Result<(Token, usize), PatternError> {
+    let mut end = start;
+    if chars[end] == '-' {
+        end += 1;
+    }
+    let digits_start = end;
+    while end < chars.len() && chars[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end == digits_start {
+        return Err(PatternError::InvalidNumber { position: start });
+    }
+
+    let is_float = end + 1 < chars.len() && chars[end] == '.' && chars[end + 1].is_ascii_digit();
+    if is_float {
+        end += 1;
+        while end < chars.len() && (chars[end].is_ascii_digit() || "eE".contains(chars[end])
+            || ("+-".contains(chars[end]) && "eE".contains(chars[end - 1]))) {
+            end += 1;
+        }
+    }
+
+    let s: String = chars[start..end].iter().collect();
+    let token = if is_float {
+        match s.parse::<f64>() {
+            Ok(f) => Token::Float(f),
+            Err(_) => return Err(PatternError::InvalidNumber { position: start }),
+        }
+    } else {
+        match BigInt::from_str(&s) {
+            Ok(i) => Token::Integer(i),
+            Err(_) => return Err(PatternError::InvalidNumber { position: start }),
+        }
+    };
+    return Ok((token, end));
+}
+
+//
+// Parser
+//
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<(usize, Token), PatternError> {
+        match self.tokens.get(self.pos) {
+            Some(t) => {
+                self.pos += 1;
+                Ok(t.clone())
+            }
+            None => Err(PatternError::UnexpectedEnd),
+        }
+    }
+
+    fn peek_is(&self, punct: &str) -> bool {
+        return matches!(self.tokens.get(self.pos), Some((_, Token::Punct(p))) if *p == punct);
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), PatternError> {
+        match self.next()? {
+            (_, Token::Punct(p)) if p == punct => Ok(()),
+            (position, _) => Err(PatternError::UnexpectedToken { position }),
+        }
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, PatternError> {
+        let (position, token) = self.next()?;
+        match token {
+            Token::Atom(s) => Ok(Pattern::Literal(ErlTerm::Atom(InternedAtom::new(&s)?))),
+            Token::Variable(s) if s == "_" => Ok(Pattern::Wildcard),
+            Token::Variable(s) => Ok(Pattern::Variable(s)),
+            Token::Integer(i) => Ok(Pattern::Integer(i)),
+            Token::Float(f) => Ok(Pattern::Literal(ErlTerm::Float(OrderedFloat(f)))),
+            Token::Str(s) => {
+                let elements = s.chars().map(|c| Pattern::Integer(BigInt::from(c as u32))).collect();
+                Ok(Pattern::List { elements, tail: Box::new(nil_pattern()) })
+            }
+            Token::Punct("{") => {
+                let elements = self.parse_sequence("}")?;
+                self.expect("}")?;
+                Ok(Pattern::Tuple(elements))
+            }
+            Token::Punct("[") => {
+                let elements = self.parse_sequence("]")?;
+                let tail = if self.peek_is("|") && !elements.is_empty() {
+                    self.pos += 1;
+                    self.parse_pattern()?
+                } else {
+                    nil_pattern()
+                };
+                self.expect("]")?;
+                Ok(Pattern::List { elements, tail: Box::new(tail) })
+            }
+            Token::Punct("<<") => self.parse_binary(),
+            Token::Punct("#{") => self.parse_map(),
+            _ => Err(PatternError::UnexpectedToken { position }),
+        }
+    }
+
+    fn parse_map(&mut self) -> Result<Pattern, PatternError> {
+        let mut pairs = Vec::new();
+        if !self.peek_is("}") {
+            loop {
+                let position = self.tokens.get(self.pos).map_or(0, |(position, _)| *position);
+                let key = self.parse_pattern()?;
+                if !key.is_ground() {
+                    return Err(PatternError::UnexpectedToken { position });
+                }
+                self.expect(":=")?;
+                pairs.push((key, self.parse_pattern()?));
+                if self.peek_is(",") {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect("}")?;
+        return Ok(Pattern::Map(pairs));
+    }
+
+    // Comma-separated patterns up to (but not including) a closing token or a "|"
+    fn parse_sequence(&mut self, close: &str) -> Result<Vec<Pattern>, PatternError> {
+        let mut elements = Vec::new();
+        if self.peek_is(close) {
+            return Ok(elements);
+        }
+        loop {
+            elements.push(self.parse_pattern()?);
+            if self.peek_is(",") {
+                self.pos += 1;
+            } else {
+                return Ok(elements);
+            }
+        }
+    }
+
+    fn parse_binary(&mut self) -> Result<Pattern, PatternError> {
+        let mut bytes = Vec::new();
+        let mut trailing_bits = None;
+        if !self.peek_is(">>") {
+            loop {
+                let (position, token) = self.next()?;
+                match token {
+                    Token::Str(s) if trailing_bits.is_none() => bytes.extend_from_slice(s.as_bytes()),
+                    Token::Integer(i) if trailing_bits.is_none() => {
+                        let byte = i.to_u8().ok_or(PatternError::InvalidNumber { position })?;
+                        bytes.push(byte);
+                        if self.peek_is(":") {
+                            self.pos += 1;
+                            let (position, size) = self.next()?;
+                            match size {
+                                Token::Integer(n) if n >= BigInt::from(1) && n <= BigInt::from(8) => {
+                                    trailing_bits = n.to_u8();
+                                }
+                                _ => return Err(PatternError::InvalidNumber { position }),
+                            }
+                        }
+                    }
+                    _ => return Err(PatternError::UnexpectedToken { position }),
+                }
+                if self.peek_is(",") {
+                    self.pos += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(">>")?;
+        match trailing_bits {
+            // a byte-sized segment
+            Some(8) | None => Ok(Pattern::Literal(ErlTerm::Binary(bytes))),
+            Some(n) => Ok(Pattern::Literal(ErlTerm::BitBinary(bytes, n))),
+        }
+    }
+}
+
+fn nil_pattern() -> Pattern {
+    return Pattern::Literal(ErlTerm::List(List::nil()));
+}
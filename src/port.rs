@@ -0,0 +1,161 @@
+// The port protocol (requires the port feature).
+//
+// Erlang talks to port programs started with
+//
+//     open_port({spawn, "program"}, [{packet, N}, binary])
+//
+// over their stdin and stdout, in packets that start with an N byte big
+// endian length. Port reads and writes terms in such packets, and
+// serve_port runs the usual loop of a port program that answers every
+// term the Erlang side sends with a term of its own (term_to_binary on
+// one side, binary_to_term on the other).
+//
+// The port closes stdin when the Erlang side closes the port, which ends
+// the loop.
+
+use std::io::{self, Read, Write};
+
+use thiserror::Error;
+
+use crate::*;
+
+#[derive(Error, Debug)]
+pub enum PortError {
+    #[error("failed to talk to the Erlang side")]
+    Io(#[from] io::Error),
+    #[error("packet headers are 1, 2 or 4 bytes long")]
+    InvalidPacketSize { size: u8 },
+    #[error("term does not fit in a packet")]
+    TooLong { len: usize, max: usize },
+    #[error("failed to decode a term")]
+    DecodingFailure(#[from] DecodingError),
+    #[error("failed to encode a term")]
+    EncodingFailure(#[from] EncodingError),
+}
+
+// The N in {packet, N}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet {
+    One,
+    Two,
+    Four,
+}
+
+impl Packet {
+    pub fn header_len(self) -> usize {
+        match self {
+            Packet::One => 1,
+            Packet::Two => 2,
+            Packet::Four => 4,
+        }
+    }
+
+    pub fn max_len(self) -> usize {
+        match self {
+            Packet::One => u8::MAX as usize,
+            Packet::Two => u16::MAX as usize,
+            Packet::Four => u32::MAX as usize,
+        }
+    }
+}
+
+impl TryFrom<u8> for Packet {
+    type Error = PortError;
+
+    fn try_from(size: u8) -> Result<Self, Self::Error> {
+        match size {
+            1 => Ok(Packet::One),
+            2 => Ok(Packet::Two),
+            4 => Ok(Packet::Four),
+            _ => Err(PortError::InvalidPacketSize { size }),
+        }
+    }
+}
+
+const MAX_PREALLOCATED: usize = 64 * 1024;
+
+// Reads the next packet, None when the input ends before it starts
+pub fn read_packet(reader: &mut impl Read, packet: Packet) -> Result<Option<Vec<u8>>, PortError> {
+    let mut header = [0; 4];
+    let header = &mut header[..packet.header_len()];
+    let mut filled = 0;
+    while filled < header.len() {
+        match reader.read(&mut header[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let len = header.iter().fold(0usize, |len, byte| (len << 8) | *byte as usize);
+    // the header cannot be trusted, the body grows as it is actually read
+    let mut body = Vec::with_capacity(len.min(MAX_PREALLOCATED));
+    reader.take(len as u64).read_to_end(&mut body)?;
+    if body.len() < len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    return Ok(Some(body));
+}
+
+pub fn write_packet(writer: &mut impl Write, packet: Packet, body: &[u8]) -> Result<(), PortError> {
+    if body.len() > packet.max_len() {
+        return Err(PortError::TooLong { len: body.len(), max: packet.max_len() });
+    }
+    let header = (body.len() as u32).to_be_bytes();
+    let mut frame = Vec::with_capacity(packet.header_len() + body.len());
+    frame.extend_from_slice(&header[4 - packet.header_len()..]);
+    frame.extend_from_slice(body);
+    // one write per packet, the Erlang side may be reading byte by byte
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    return Ok(());
+}
+
+pub struct Port<R: Read, W: Write> {
+    reader: R,
+    writer: W,
+    packet: Packet,
+}
+
+impl Port<io::StdinLock<'static>, io::StdoutLock<'static>> {
+    // The port of a program spawned by open_port
+    pub fn stdio(packet: Packet) -> Self {
+        return Port::new(io::stdin().lock(), io::stdout().lock(), packet);
+    }
+}
+
+impl<R: Read, W: Write> Port<R, W> {
+    pub fn new(reader: R, writer: W, packet: Packet) -> Self {
+        return Port { reader, writer, packet };
+    }
+
+    // The next term from the Erlang side, None once it closed the port
+    pub fn receive(&mut self) -> Result<Option<ErlTerm>, PortError> {
+        match read_packet(&mut self.reader, self.packet)? {
+            Some(body) => return Ok(Some(ErlTerm::from_bytes(&body)?)),
+            None => return Ok(None),
+        }
+    }
+
+    pub fn send(&mut self, term: &ErlTerm) -> Result<(), PortError> {
+        return write_packet(&mut self.writer, self.packet, &term.to_bytes()?);
+    }
+
+    // Answers every term with what handle returns, until the port is closed
+    pub fn serve(&mut self, mut handle: impl FnMut(ErlTerm) -> ErlTerm) -> Result<(), PortError> {
+        while let Some(request) = self.receive()? {
+            self.send(&handle(request))?;
+        }
+        return Ok(());
+    }
+
+    pub fn into_inner(self) -> (R, W) {
+        return (self.reader, self.writer);
+    }
+}
+
+// The whole of a port program's main: serves requests on stdin and stdout
+pub fn serve_port(packet: Packet, handle: impl FnMut(ErlTerm) -> ErlTerm) -> Result<(), PortError> {
+    return Port::stdio(packet).serve(handle);
+}
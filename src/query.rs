@@ -0,0 +1,279 @@
+// A small path language for navigating terms.
+//
+// A path is a sequence of selectors applied left to right,
+// each one maps every current match to zero or more new matches:
+//
+//  * [N]      element N (zero-based) of a tuple, list or improper list
+//  * [*]      every element of a tuple, list or improper list
+//  * [|]      the tail of an improper list
+//  * .key     on a tuple: the tuple itself when its first element is the atom key
+//             (a record tag check), on a list: the values of {key, Value} pairs (a proplist lookup)
+//  * .'key'   same as above for atoms that need quoting
+//  * ."key"   same as above for binary keys
+//  * #{key}   the value of a map under key, which is written as for .key
+//  * #{*}     every value of a map
+//  * ..       the current term and all of its descendants, map keys included
+//
+// For example, "[*].resource[1]" selects the second element of every
+// {resource, VHost, queue, Name} tuple in a list, and "[0].#{name}.resource[1]"
+// does the same for the name in a map that comes first in a list.
+
+use core::fmt;
+
+use thiserror::Error;
+
+use crate::*;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum QueryError {
+    #[error("unexpected character in path")]
+    UnexpectedCharacter { position: usize, character: char },
+    #[error("path ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("element index is not a valid number")]
+    InvalidIndex { position: usize },
+    #[error("failed to intern a key")]
+    AtomTableFull(#[from] AtomTableFull),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Selector {
+    Index(usize),
+    AllElements,
+    Tail,
+    Key(ErlTerm),
+    MapKey(ErlTerm),
+    MapValues,
+    Descendants,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Path {
+    pub selectors: Vec<Selector>,
+}
+
+impl Path {
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut selectors = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '[' => {
+                    let (selector, next) = parse_bracket(&chars, i)?;
+                    selectors.push(selector);
+                    i = next;
+                }
+                '#' => {
+                    let (selector, next) = parse_map_key(&chars, i)?;
+                    selectors.push(selector);
+                    i = next;
+                }
+                '.' if chars.get(i + 1) == Some(&'.') => {
+                    selectors.push(Selector::Descendants);
+                    i += 2;
+                    if i < chars.len() && !matches!(chars[i], '[' | '.' | '#') {
+                        let (key, next) = parse_key(&chars, i)?;
+                        selectors.push(Selector::Key(key));
+                        i = next;
+                    }
+                }
+                '.' => match chars.get(i + 1) {
+                    // ".[0]" is the same as "[0]", and ".#{key}" as "#{key}"
+                    Some('[' | '#') => i += 1,
+                    Some(_) => {
+                        let (key, next) = parse_key(&chars, i + 1)?;
+                        selectors.push(Selector::Key(key));
+                        i = next;
+                    }
+                    None => return Err(QueryError::UnexpectedEnd),
+                },
+                c => return Err(QueryError::UnexpectedCharacter { position: i, character: c }),
+            }
+        }
+
+        return Ok(Path { selectors });
+    }
+
+    pub fn select<'a>(&self, term: &'a ErlTerm) -> Vec<&'a ErlTerm> {
+        let mut matches = vec![term];
+        for selector in &self.selectors {
+            let mut next = Vec::new();
+            for m in matches {
+                apply(selector, m, &mut next);
+            }
+            matches = next;
+        }
+        return matches;
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for selector in &self.selectors {
+            match selector {
+                Selector::Index(n) => write!(f, "[{}]", n)?,
+                Selector::AllElements => write!(f, "[*]")?,
+                Selector::Tail => write!(f, "[|]")?,
+                Selector::Key(ErlTerm::Binary(key)) => {
+                    write!(f, ".\"{}\"", String::from_utf8_lossy(key))?
+                }
+                Selector::Key(key) => write!(f, ".{}", key)?,
+                Selector::MapKey(ErlTerm::Binary(key)) => {
+                    write!(f, "#{{\"{}\"}}", String::from_utf8_lossy(key))?
+                }
+                Selector::MapKey(key) => write!(f, "#{{{}}}", key)?,
+                Selector::MapValues => write!(f, "#{{*}}")?,
+                Selector::Descendants => write!(f, "..")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ErlTerm {
+    // Returns all subterms matching a path, see the query module for the syntax
+    pub fn query(&self, path: &str) -> Result<Vec<&ErlTerm>, QueryError> {
+        return Ok(Path::parse(path)?.select(self));
+    }
+
+    // Returns the first subterm matching a path
+    pub fn query_first(&self, path: &str) -> Result<Option<&ErlTerm>, QueryError> {
+        return Ok(self.query(path)?.into_iter().next());
+    }
+}
+
+fn parse_bracket(chars: &[char], start: usize) -> Result<(Selector, usize), QueryError> {
+    let close = match chars[start..].iter().position(|&c| c == ']') {
+        Some(n) => start + n,
+        None => return Err(QueryError::UnexpectedEnd),
+    };
+    let inner: String = chars[start + 1..close].iter().collect();
+    let selector = match inner.trim() {
+        "*" => Selector::AllElements,
+        "|" => Selector::Tail,
+        s => match s.parse::<usize>() {
+            Ok(n) => Selector::Index(n),
+            Err(_) => return Err(QueryError::InvalidIndex { position: start + 1 }),
+        },
+    };
+    return Ok((selector, close + 1));
+}
+
+fn parse_map_key(chars: &[char], start: usize) -> Result<(Selector, usize), QueryError> {
+    match chars.get(start + 1) {
+        Some('{') => {}
+        Some(&c) => return Err(QueryError::UnexpectedCharacter { position: start + 1, character: c }),
+        None => return Err(QueryError::UnexpectedEnd),
+    }
+    let selector = match chars.get(start + 2) {
+        Some('*') => Selector::MapValues,
+        Some(_) => {
+            let (key, next) = parse_key(chars, start + 2)?;
+            return match chars.get(next) {
+                Some('}') => Ok((Selector::MapKey(key), next + 1)),
+                Some(&c) => Err(QueryError::UnexpectedCharacter { position: next, character: c }),
+                None => Err(QueryError::UnexpectedEnd),
+            };
+        }
+        None => return Err(QueryError::UnexpectedEnd),
+    };
+    return match chars.get(start + 3) {
+        Some('}') => Ok((selector, start + 4)),
+        Some(&c) => Err(QueryError::UnexpectedCharacter { position: start + 3, character: c }),
+        None => Err(QueryError::UnexpectedEnd),
+    };
+}
+
+fn parse_key(chars: &[char], start: usize) -> Result<(ErlTerm, usize), QueryError> {
+    match chars[start] {
+        quote @ ('\'' | '"') => {
+            let close = match chars[start + 1..].iter().position(|&c| c == quote) {
+                Some(n) => start + 1 + n,
+                None => return Err(QueryError::UnexpectedEnd),
+            };
+            let name: String = chars[start + 1..close].iter().collect();
+            let key = if quote == '"' {
+                ErlTerm::Binary(name.into_bytes())
+            } else {
+                ErlTerm::Atom(InternedAtom::new(&name)?)
+            };
+            Ok((key, close + 1))
+        }
+        _ => {
+            let mut end = start;
+            while end < chars.len() && is_atom_char(chars[end]) {
+                end += 1;
+            }
+            if end == start {
+                return Err(QueryError::UnexpectedCharacter { position: start, character: chars[start] });
+            }
+            let name: String = chars[start..end].iter().collect();
+            Ok((ErlTerm::Atom(InternedAtom::new(&name)?), end))
+        }
+    }
+}
+
+fn is_atom_char(c: char) -> bool {
+    return c.is_alphanumeric() || c == '_' || c == '@';
+}
+
+fn elements_of(term: &ErlTerm) -> &[ErlTerm] {
+    match term {
+        ErlTerm::Tuple(t) => &t.elements,
+        ErlTerm::List(l) => &l.elements,
+        ErlTerm::ImproperList(l) => &l.elements,
+        _ => &[],
+    }
+}
+
+fn apply<'a>(selector: &Selector, term: &'a ErlTerm, out: &mut Vec<&'a ErlTerm>) {
+    match selector {
+        Selector::Index(n) => out.extend(elements_of(term).get(*n)),
+        Selector::AllElements => out.extend(elements_of(term)),
+        Selector::Tail => {
+            if let ErlTerm::ImproperList(l) = term {
+                out.push(&l.tail);
+            }
+        }
+        Selector::Key(key) => match term {
+            ErlTerm::Tuple(t) if t.elements.first() == Some(key) => out.push(term),
+            ErlTerm::List(_) | ErlTerm::ImproperList(_) => {
+                for element in elements_of(term) {
+                    if let ErlTerm::Tuple(t) = element {
+                        if t.elements.len() == 2 && &t.elements[0] == key {
+                            out.push(&t.elements[1]);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        },
+        Selector::MapKey(key) => {
+            if let ErlTerm::Map(map) = term {
+                out.extend(map.get(key));
+            }
+        }
+        Selector::MapValues => {
+            if let ErlTerm::Map(map) = term {
+                out.extend(map.entries.iter().map(|(_, value)| value));
+            }
+        }
+        Selector::Descendants => {
+            out.push(term);
+            for element in elements_of(term) {
+                apply(selector, element, out);
+            }
+            if let ErlTerm::ImproperList(l) = term {
+                apply(selector, &l.tail, out);
+            }
+            if let ErlTerm::Map(map) = term {
+                for (key, value) in &map.entries {
+                    apply(selector, key, out);
+                    apply(selector, value, out);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+// A decoder for terms that are already in memory.
+//
+// Produces the same terms as Decoder, but reads from a slice with
+// bounds-checked cursor reads instead of a virtual io::Read call per
+// field, and creates atoms straight from the input bytes instead of
+// copying them into a buffer first. See benches/decoding.rs.
+
+use num::bigint::BigInt;
+
+use crate::*;
+use crate::constants;
+use crate::cursor::{latin1_atom, utf8_atom, Cursor};
+use crate::numerical::to_sign;
+
+pub struct SliceDecoder<'b> {
+    cursor: Cursor<'b>,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'b> SliceDecoder<'b> {
+    pub fn new(bytes: &'b [u8]) -> Self {
+        SliceDecoder { cursor: Cursor::new(bytes), depth: 0, max_depth: DEFAULT_MAX_NESTING_DEPTH }
+    }
+
+    // Rejects terms nested more than max_depth levels deep
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        return self;
+    }
+
+    // Decodes the next term, starting with the version byte. Can be called
+    // repeatedly to decode concatenated terms
+    pub fn decode(&mut self) -> DecodingResult {
+        let version = self.cursor.read_u8()?;
+        if version != constants::TERM_FORMAT_VERSION {
+            return Err(DecodingError::UnsupportedVersion { version });
+        }
+        return self.read_next_term();
+    }
+
+    // Decodes the next term when it is not preceded by a version byte,
+    // as in distribution messages that have a distribution header
+    pub fn decode_without_version(&mut self) -> DecodingResult {
+        return self.read_next_term();
+    }
+
+    // Number of bytes consumed so far
+    pub fn position(&self) -> usize {
+        return self.cursor.position();
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        return self.cursor.remaining() == 0;
+    }
+
+    fn read_next_term(&mut self) -> DecodingResult {
+        if self.depth >= self.max_depth {
+            return Err(DecodingError::NestingTooDeep { limit: self.max_depth });
+        }
+        self.depth += 1;
+        let term = self.read_term();
+        self.depth -= 1;
+        return term;
+    }
+
+    fn read_term(&mut self) -> DecodingResult {
+        let tag = self.cursor.read_u8()?;
+        match tag {
+            constants::ATOM_EXT => {
+                let n = self.cursor.read_u16()? as usize;
+                Ok(ErlTerm::Atom(latin1_atom(self.cursor.take(n)?)?))
+            }
+            constants::ATOM_UTF8_EXT => {
+                let n = self.cursor.read_u16()? as usize;
+                Ok(ErlTerm::Atom(utf8_atom(self.cursor.take(n)?)?))
+            }
+            constants::SMALL_ATOM_UTF8_EXT => {
+                let n = self.cursor.read_u8()? as usize;
+                Ok(ErlTerm::Atom(utf8_atom(self.cursor.take(n)?)?))
+            }
+            constants::SMALL_INTEGER_EXT => Ok(ErlTerm::SmallInteger(self.cursor.read_u8()?)),
+            constants::INTEGER_EXT => Ok(ErlTerm::Integer(self.cursor.read_i32()?)),
+            constants::SMALL_BIG_EXT => {
+                let n = self.cursor.read_u8()? as usize;
+                self.big_integer(n)
+            }
+            constants::LARGE_BIG_EXT => {
+                let n = self.cursor.read_u32()? as usize;
+                self.big_integer(n)
+            }
+            constants::NEW_FLOAT_EXT => Ok(ErlTerm::Float(OrderedFloat(self.cursor.read_f64()?))),
+            constants::BINARY_EXT => {
+                let n = self.cursor.read_u32()? as usize;
+                Ok(ErlTerm::Binary(self.cursor.take(n)?.to_vec()))
+            }
+            constants::BIT_BINARY_EXT => self.bit_binary(),
+            constants::NEW_PID_EXT => Ok(ErlTerm::Pid(self.pid()?)),
+            constants::NEW_PORT_EXT => self.v3_port(),
+            constants::V4_PORT_EXT => self.v4_port(),
+            constants::SMALL_TUPLE_EXT => {
+                let n = self.cursor.read_u8()? as usize;
+                self.tuple(n)
+            }
+            constants::LARGE_TUPLE_EXT => {
+                let n = self.cursor.read_u32()? as usize;
+                self.tuple(n)
+            }
+            constants::NIL_EXT => Ok(ErlTerm::List(List::nil())),
+            constants::STRING_EXT => self.string(),
+            constants::LIST_EXT => self.list(),
+            constants::MAP_EXT => self.map(),
+            constants::NEWER_REFERENCE_EXT => self.reference(),
+            constants::FUN_EXPORT_EXT => self.external_fun(),
+            constants::NEW_FUN_EXT => self.internal_fun(),
+            _ => Err(DecodingError::UnrecognizedTag { tag }),
+        }
+    }
+
+    // Anything beyond the simplest terms is decoded outside of read_term,
+    // to keep its stack frame (which every level of nesting pays for) small
+
+    fn bit_binary(&mut self) -> DecodingResult {
+        let n = self.cursor.read_u32()? as usize;
+        let tail_len = self.cursor.read_u8()?;
+        if tail_len > 8 || (n == 0) != (tail_len == 0) {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid bit binary tail length: {}", tail_len),
+            );
+            return Err(DecodingError::DecodingFailure(e));
+        }
+        let mut bytes = self.cursor.take(n)?.to_vec();
+        if let Some(last) = bytes.last_mut() {
+            *last >>= 8 - tail_len;
+        }
+        return Ok(ErlTerm::BitBinary(bytes, tail_len));
+    }
+
+    fn v3_port(&mut self) -> DecodingResult {
+        let node = self.node()?;
+        let id = self.cursor.read_u32()?;
+        let creation = self.cursor.read_u32()?;
+        return Ok(ErlTerm::V3Port(ErlV3Port { node, id, creation }));
+    }
+
+    fn v4_port(&mut self) -> DecodingResult {
+        let node = self.node()?;
+        let id = self.cursor.read_u64()?;
+        let creation = self.cursor.read_u32()?;
+        return Ok(ErlTerm::V4Port(ErlV4Port { node, id, creation }));
+    }
+
+    fn tuple(&mut self, n: usize) -> DecodingResult {
+        return Ok(ErlTerm::Tuple(Tuple { elements: self.terms(n)? }));
+    }
+
+    fn list(&mut self) -> DecodingResult {
+        let n = self.cursor.read_u32()? as usize;
+        let elements = self.terms(n)?;
+        match self.read_next_term()? {
+            ErlTerm::List(tail) if tail.is_nil() => Ok(ErlTerm::List(List { elements })),
+            tail => Ok(ErlTerm::ImproperList(ImproperList { elements, tail: Box::new(tail) })),
+        }
+    }
+
+    // A list of small integers, what term_to_binary produces for strings
+    fn string(&mut self) -> DecodingResult {
+        let n = self.cursor.read_u16()? as usize;
+        let elements = self.cursor.take(n)?.iter().map(|b| ErlTerm::SmallInteger(*b)).collect();
+        return Ok(ErlTerm::List(List { elements }));
+    }
+
+    fn map(&mut self) -> DecodingResult {
+        let n = self.cursor.read_u32()? as usize;
+        // every entry takes at least two bytes
+        let mut entries = Vec::with_capacity(n.min(self.cursor.remaining() / 2));
+        for _ in 0..n {
+            let key = self.read_next_term()?;
+            entries.push((key, self.read_next_term()?));
+        }
+        return Ok(ErlTerm::Map(Map { entries }));
+    }
+
+    fn reference(&mut self) -> DecodingResult {
+        let n = self.cursor.read_u16()? as usize;
+        let node = self.node()?;
+        let creation = self.cursor.read_u32()?;
+        let mut id = Vec::with_capacity(n);
+        for _ in 0..n {
+            id.push(self.cursor.read_u32()?);
+        }
+        return Ok(ErlTerm::Ref(Ref { node, creation, id }));
+    }
+
+    fn external_fun(&mut self) -> DecodingResult {
+        let module = self.node()?;
+        let function_name = self.node()?;
+        let arity = match self.read_next_term()? {
+            ErlTerm::SmallInteger(arity) => arity,
+            _ => return Err(DecodingError::CompoundTypeDecodingFailure()),
+        };
+        return Ok(ErlTerm::ExternalFun(ExternalFun { module, function_name, arity }));
+    }
+
+    fn internal_fun(&mut self) -> DecodingResult {
+        let _size = self.cursor.read_u32()?;
+        let arity = self.cursor.read_u8()?;
+        let mut uniq_beam_md5 = [0; 16];
+        uniq_beam_md5.copy_from_slice(self.cursor.take(16)?);
+        let index = self.cursor.read_u32()?;
+        let free_variable_count = self.cursor.read_u32()?;
+        let module = self.node()?;
+        let old_index = self.small_integer()?;
+        let old_uniq_hash = self.small_integer()?;
+        if self.cursor.read_u8()? != constants::NEW_PID_EXT {
+            return Err(DecodingError::CompoundTypeDecodingFailure());
+        }
+        let creator_pid = self.pid()?;
+        let free_vars = self.terms(free_variable_count as usize)?;
+        return Ok(ErlTerm::InternalFun(InternalFun {
+            arity,
+            uniq_beam_md5,
+            index,
+            free_variable_count,
+            module,
+            old_index,
+            old_uniq_hash,
+            creator_pid,
+            free_vars,
+        }));
+    }
+
+    fn terms(&mut self, n: usize) -> Result<Vec<ErlTerm>, DecodingError> {
+        // every term takes at least one byte, a bogus length cannot
+        // make us allocate more than the input size
+        let mut elements = Vec::with_capacity(n.min(self.cursor.remaining()));
+        for _ in 0..n {
+            elements.push(self.read_next_term()?);
+        }
+        return Ok(elements);
+    }
+
+    fn big_integer(&mut self, n: usize) -> DecodingResult {
+        let sign = to_sign(self.cursor.read_u8()?)?;
+        // section 12.18:
+        // The digits are stored with the least significant byte stored first.
+        let digits = self.cursor.take(n)?;
+        return Ok(ErlTerm::BigInteger(BigInt::from_bytes_le(sign, digits)));
+    }
+
+    fn pid(&mut self) -> Result<ErlPid, DecodingError> {
+        let node = self.node()?;
+        let id = self.cursor.read_u32()?;
+        let serial = self.cursor.read_u32()?;
+        let creation = self.cursor.read_u32()?;
+        return Ok(ErlPid { node, id, serial, creation });
+    }
+
+    fn node(&mut self) -> Result<Atom, DecodingError> {
+        match self.read_next_term()? {
+            ErlTerm::Atom(name) => Ok(Atom { name }),
+            _ => Err(DecodingError::CompoundTypeDecodingFailure()),
+        }
+    }
+
+    fn small_integer(&mut self) -> Result<i32, DecodingError> {
+        match self.read_next_term()? {
+            ErlTerm::SmallInteger(i) => Ok(i as i32),
+            ErlTerm::Integer(i) => Ok(i),
+            _ => Err(DecodingError::CompoundTypeDecodingFailure()),
+        }
+    }
+}
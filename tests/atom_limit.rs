@@ -0,0 +1,55 @@
+#![cfg(feature = "std")]
+#![allow(clippy::needless_return)]
+
+extern crate erl_etf;
+
+use erl_etf::*;
+
+// The atom table is global, so this test needs a process of its own
+#[test]
+fn decoding_fails_when_the_atom_table_is_full() {
+    // term_to_binary(hello).
+    let hello: &[u8] = &[131, 100, 0, 5, 104, 101, 108, 108, 111];
+    ErlTerm::decode(Box::new(hello)).unwrap();
+
+    set_atom_limit(atom_count());
+    // existing atoms can still be decoded
+    assert_eq!(ErlTerm::try_from("hello").unwrap(), ErlTerm::decode(Box::new(hello)).unwrap());
+    // new ones cannot, term_to_binary(world).
+    let world: &[u8] = &[131, 100, 0, 5, 119, 111, 114, 108, 100];
+    assert!(matches!(
+        ErlTerm::decode(Box::new(world)),
+        Err(DecodingError::AtomTableFull(AtomTableFull { .. }))
+    ));
+    // also inside of tuples, lists and maps, term_to_binary({[#{world => 1}]}).
+    let nested: &[u8] = &[
+        131, 104, 1, 108, 0, 0, 0, 1, 116, 0, 0, 0, 1, 100, 0, 5, 119, 111, 114, 108, 100, 97, 1, 106,
+    ];
+    assert!(matches!(ErlTerm::decode(Box::new(nested)), Err(DecodingError::AtomTableFull(_))));
+    assert!(matches!(ErlTerm::from_bytes(nested), Err(DecodingError::AtomTableFull(_))));
+    assert!(InternedAtom::new("world").is_err());
+    assert!(matches!(ErlTerm::try_from("world"), Err(AtomTableFull { .. })));
+    assert!(matches!(InternedAtom::try_from(String::from("world")), Err(AtomTableFull { .. })));
+
+    // clients fail instead of panicking on names they cannot intern
+    #[cfg(feature = "bert")]
+    {
+        let server = BertServer::start(|_, _, args| Ok(ErlTerm::List(List { elements: args }))).unwrap();
+        assert!(matches!(
+            server.client().unwrap().call("world", "hello", vec![]),
+            Err(BertError::DecodingFailure(DecodingError::AtomTableFull(_)))
+        ));
+    }
+
+    // nor are they hidden inside compound terms
+    #[cfg(feature = "cbor")]
+    {
+        use ciborium::value::Value;
+        let atom = Value::Tag(CBOR_TAG_ATOM, Box::new(Value::Text("world".to_string())));
+        let tuple = Value::Tag(CBOR_TAG_TUPLE, Box::new(Value::Array(vec![atom])));
+        assert!(matches!(ErlTerm::from_cbor_value(&tuple), Err(CborError::AtomTableFull(_))));
+    }
+
+    set_atom_limit(ATOM_TABLE_DEFAULT_LIMIT);
+    assert!(InternedAtom::new("world").is_ok());
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "beam")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use common::*;
+use erl_etf::*;
+
+// Assembles a BEAM file the way the compiler lays it out
+fn beam_file(chunks: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut body = b"BEAM".to_vec();
+    for (id, data) in chunks {
+        body.extend_from_slice(id.as_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        body.extend_from_slice(data);
+        body.resize(body.len().div_ceil(4) * 4, 0);
+    }
+    let mut file = b"FOR1".to_vec();
+    file.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    file.extend_from_slice(&body);
+    return file;
+}
+
+fn atom_chunk(atoms: &[&str]) -> Vec<u8> {
+    let mut data = (atoms.len() as u32).to_be_bytes().to_vec();
+    for atom in atoms {
+        data.push(atom.len() as u8);
+        data.extend_from_slice(atom.as_bytes());
+    }
+    return data;
+}
+
+fn table_chunk(rows: &[[u32; 3]]) -> Vec<u8> {
+    let mut data = (rows.len() as u32).to_be_bytes().to_vec();
+    for value in rows.iter().flatten() {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    return data;
+}
+
+// greeter.erl compiled with erlc +deterministic, see tests/fixtures/README.md
+//
+//     -module(greeter).
+//     -export([hello/1]).
+//     -vsn("1.2.0").
+//     -author(joe).
+//     hello(Name) -> io:format("~s~n", [Name]), {greeted, [Name]}.
+fn greeter() -> Vec<u8> {
+    return std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/greeter.beam")).unwrap();
+}
+
+#[test]
+fn splits_files_into_chunks_without_padding() {
+    let beam = BeamFile::parse(&greeter()).unwrap();
+    let ids = beam.chunk_ids();
+    assert_eq!(ids[0], "AtU8");
+    for id in ["Code", "ImpT", "ExpT", "LitT", "Attr", "CInf"] {
+        assert!(ids.contains(&id.to_string()), "no {} chunk", id);
+    }
+    // the padding is not part of the chunk, the term ends with the [] of the list
+    assert_eq!(beam.chunk("Attr").unwrap().last(), Some(&106));
+    assert_eq!(beam.chunk("Dbgi"), None);
+}
+#[test]
+fn reads_the_module_name_exports_and_imports() {
+    let beam = BeamFile::parse(&greeter()).unwrap();
+    assert_eq!(beam.module().unwrap(), "greeter");
+    let exports = beam.exports().unwrap();
+    assert!(exports.contains(&("hello".to_string(), 1)));
+    assert!(exports.contains(&("module_info".to_string(), 0)));
+    assert_eq!(exports.len(), 3);
+    assert!(beam.imports().unwrap().contains(&("io".to_string(), "format".to_string(), 2)));
+    assert!(beam.locals().unwrap().is_empty());
+}
+
+#[test]
+fn decodes_attributes_and_compile_info() {
+    let beam = BeamFile::parse(&greeter()).unwrap();
+    assert_eq!(beam.vsn().unwrap(), Some(list(vec![string("1.2.0")])));
+    assert_eq!(beam.attribute("author").unwrap(), Some(list(vec![atom("joe")])));
+    assert_eq!(beam.attribute("behaviour").unwrap(), None);
+    // erlc writes strings as STRING_EXT
+    assert!(beam.chunk("Attr").unwrap().windows(3).any(|w| w == [107, 0, 5]));
+
+    let ErlTerm::List(info) = beam.compile_info().unwrap() else { panic!("compile info is not a list") };
+    let Some(ErlTerm::Tuple(version)) = info.elements.first() else { panic!("no compiler version") };
+    assert_eq!(version.elements[0], atom("version"));
+    assert!(matches!(&version.elements[1], ErlTerm::List(_)));
+}
+
+#[test]
+fn reads_compressed_and_uncompressed_literal_tables() {
+    let beam = BeamFile::parse(&greeter()).unwrap();
+    assert_eq!(beam.literals().unwrap(), vec![string("~s~n")]);
+
+    // OTP 28 can store the table uncompressed, with a size of 0
+    let mut table = 1u32.to_be_bytes().to_vec();
+    let literal = atom("ok").to_bytes().unwrap();
+    table.extend_from_slice(&(literal.len() as u32).to_be_bytes());
+    table.extend_from_slice(&literal);
+    let mut data = 0u32.to_be_bytes().to_vec();
+    data.extend_from_slice(&table);
+    let beam = BeamFile::parse(&beam_file(&[("LitT", data)])).unwrap();
+    assert_eq!(beam.literals().unwrap(), vec![atom("ok")]);
+}
+
+#[test]
+fn reads_atoms_with_compact_lengths() {
+    // a negative count and lengths in the compact term encoding
+    let name = "a".repeat(300);
+    let mut data = (-2i32).to_be_bytes().to_vec();
+    data.push(0x70 | 0x02);
+    data.extend_from_slice(b"compact");
+    data.extend_from_slice(&[0x08 | (1 << 5), 0x2c]);
+    data.extend_from_slice(name.as_bytes());
+    let beam = BeamFile::parse(&beam_file(&[("AtU8", data)])).unwrap();
+    assert_eq!(beam.atoms().unwrap(), vec!["compact".to_string(), name]);
+}
+
+#[test]
+fn rejects_malformed_files_and_chunks() {
+    assert!(matches!(BeamFile::parse(b"FOR1\x00\x00\x00\x04ELF!"), Err(BeamError::NotABeamFile)));
+    assert!(matches!(BeamFile::parse(b"\x7fELF"), Err(BeamError::NotABeamFile)));
+
+    let mut truncated = greeter();
+    truncated.truncate(40);
+    let size = (truncated.len() - 8) as u32;
+    truncated[4..8].copy_from_slice(&size.to_be_bytes());
+    assert!(matches!(BeamFile::parse(&truncated), Err(BeamError::MalformedChunk { .. })));
+
+    let beam = BeamFile::parse(&beam_file(&[("ExpT", table_chunk(&[[9, 0, 0]]))])).unwrap();
+    assert!(matches!(beam.exports(), Err(BeamError::MissingChunk { id }) if id == "AtU8"));
+    let beam = BeamFile::parse(&beam_file(&[("AtU8", atom_chunk(&["m"])), ("ExpT", table_chunk(&[[9, 0, 0]]))])).unwrap();
+    assert!(matches!(beam.exports(), Err(BeamError::MalformedChunk { id }) if id == "ExpT"));
+}
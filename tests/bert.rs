@@ -0,0 +1,176 @@
+#![cfg(feature = "bert")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use common::*;
+use erl_etf::*;
+
+//
+// BERT terms
+//
+
+#[test]
+fn complex_types_encode_as_bert_tuples() {
+    let cases = [
+        (BertTerm::Nil, tuple(vec![atom("bert"), atom("nil")])),
+        (BertTerm::Bool(true), tuple(vec![atom("bert"), atom("true")])),
+        (BertTerm::Bool(false), tuple(vec![atom("bert"), atom("false")])),
+        (
+            BertTerm::Dict(vec![(BertTerm::Term(atom("name")), BertTerm::Term(binary("bert")))]),
+            tuple(vec![atom("bert"), atom("dict"), list(vec![tuple(vec![atom("name"), binary("bert")])])]),
+        ),
+        // the example from the BERT specification
+        (
+            BertTerm::Time(UNIX_EPOCH + Duration::from_micros(1_255_295_581_446_228)),
+            tuple(vec![
+                atom("bert"),
+                atom("time"),
+                ErlTerm::Integer(1255),
+                ErlTerm::Integer(295581),
+                ErlTerm::Integer(446228),
+            ]),
+        ),
+        (
+            BertTerm::Regex { source: b"^c(a*)t$".to_vec(), options: vec![atom("caseless")] },
+            tuple(vec![atom("bert"), atom("regex"), binary("^c(a*)t$"), list(vec![atom("caseless")])]),
+        ),
+    ];
+    for (bert, term) in cases {
+        assert_eq!(ErlTerm::from(&bert), term);
+        assert_eq!(BertTerm::try_from(term).unwrap(), bert);
+    }
+}
+
+#[test]
+fn complex_types_nest() {
+    let term = list(vec![tuple(vec![atom("ok"), tuple(vec![atom("bert"), atom("nil")])]), ErlTerm::SmallInteger(1)]);
+    let bert = BertTerm::List(vec![
+        BertTerm::Tuple(vec![BertTerm::Term(atom("ok")), BertTerm::Nil]),
+        BertTerm::Term(ErlTerm::SmallInteger(1)),
+    ]);
+    assert_eq!(BertTerm::try_from(term.clone()).unwrap(), bert);
+    assert_eq!(BertTerm::from_bytes(&term.to_bytes().unwrap()).unwrap(), bert);
+    assert_eq!(bert.to_bytes().unwrap(), term.to_bytes().unwrap());
+}
+
+#[test]
+fn times_before_1970_round_trip() {
+    let time = UNIX_EPOCH - Duration::from_micros(1_500_000);
+    let term = ErlTerm::from(&BertTerm::Time(time));
+    let expected = tuple(vec![
+        atom("bert"),
+        atom("time"),
+        ErlTerm::Integer(-1),
+        ErlTerm::Integer(999_998),
+        ErlTerm::Integer(500_000),
+    ]);
+    assert_eq!(term, expected);
+    assert_eq!(BertTerm::try_from(term).unwrap(), BertTerm::Time(time));
+}
+
+#[test]
+fn invalid_bert_tuples_fail_to_convert() {
+    for term in [
+        tuple(vec![atom("bert")]),
+        tuple(vec![atom("bert"), atom("maybe")]),
+        tuple(vec![atom("bert"), atom("dict"), list(vec![atom("not_a_pair")])]),
+        tuple(vec![atom("bert"), atom("time"), atom("now"), ErlTerm::SmallInteger(0), ErlTerm::SmallInteger(0)]),
+    ] {
+        assert!(matches!(BertTerm::try_from(term), Err(BertError::InvalidComplexType { .. })));
+    }
+}
+
+//
+// BERT-RPC
+//
+
+fn calculator() -> BertServer {
+    return BertServer::start(|module, function, args| match (module, function, args.as_slice()) {
+        ("calc", "add", [ErlTerm::SmallInteger(a), ErlTerm::SmallInteger(b)]) => {
+            Ok(ErlTerm::Integer(*a as i32 + *b as i32))
+        }
+        ("calc", "fail", _) => Err(BertRpcError::user("RuntimeError", "failed on purpose")),
+        ("calc", _, _) => Err(BertRpcError::no_such_function(module, function)),
+        _ => Err(BertRpcError::no_such_module(module)),
+    })
+    .unwrap();
+}
+
+#[test]
+fn call_returns_the_reply() {
+    let server = calculator();
+    let mut client = server.client().unwrap();
+    let args = vec![ErlTerm::SmallInteger(1), ErlTerm::SmallInteger(2)];
+    assert_eq!(client.call("calc", "add", args.clone()).unwrap(), ErlTerm::Integer(3));
+    // the connection stays open for more calls
+    assert_eq!(client.call("calc", "add", args).unwrap(), ErlTerm::Integer(3));
+}
+
+#[test]
+fn call_errors_carry_the_error_type() {
+    let server = calculator();
+    let mut client = server.client().unwrap();
+    match client.call("calc", "fail", vec![]) {
+        Err(BertError::Remote(error)) => {
+            assert_eq!(*error, BertRpcError::user("RuntimeError", "failed on purpose"));
+        }
+        other => panic!("expected a user error, got {:?}", other),
+    }
+    match client.call("calc", "mul", vec![]) {
+        Err(BertError::Remote(error)) => assert_eq!((error.error_type, error.code), (BertErrorType::Server, 2)),
+        other => panic!("expected a server error, got {:?}", other),
+    }
+    match client.call("nope", "add", vec![]) {
+        Err(BertError::Remote(error)) => assert_eq!((error.error_type, error.code), (BertErrorType::Server, 1)),
+        other => panic!("expected a server error, got {:?}", other),
+    }
+}
+
+#[test]
+fn cast_gets_noreply() {
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    let server = BertServer::start(move |_, _, args| {
+        sender.lock().unwrap().send(args).unwrap();
+        return Ok(atom("ignored"));
+    })
+    .unwrap();
+    let mut client = server.client().unwrap();
+    client.cast("log", "info", vec![binary("hello")]).unwrap();
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), vec![binary("hello")]);
+}
+
+#[test]
+fn requests_and_replies_are_four_byte_packets() {
+    let server = calculator();
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    let request = tuple(vec![atom("call"), atom("calc"), atom("add"), list(vec![ErlTerm::SmallInteger(2); 2])]);
+    let request = request.to_bytes().unwrap();
+    stream.write_all(&(request.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(&request).unwrap();
+
+    let mut len = [0; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut reply = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(ErlTerm::from_bytes(&reply).unwrap(), tuple(vec![atom("reply"), ErlTerm::Integer(4)]));
+}
+
+#[test]
+fn non_bert_requests_get_a_protocol_error() {
+    let server = calculator();
+    let stream = TcpStream::connect(server.local_addr()).unwrap();
+    let mut port = Port::new(stream.try_clone().unwrap(), stream, Packet::Four);
+    port.send(&atom("hello")).unwrap();
+    let reply = port.receive().unwrap().unwrap();
+    let ErlTerm::Tuple(Tuple { elements }) = reply else { panic!("not an error") };
+    assert_eq!(elements[0], atom("error"));
+    let ErlTerm::Tuple(Tuple { elements: error }) = &elements[1] else { panic!("not an error") };
+    assert_eq!(error[0], atom("protocol"));
+}
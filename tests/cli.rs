@@ -0,0 +1,98 @@
+#![cfg(feature = "cli")]
+#![allow(clippy::needless_return)]
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+// term_to_binary({1, 2, 3, 4}).
+const TUPLE: &[u8] = &[131, 104, 4, 97, 1, 97, 2, 97, 3, 97, 4];
+// term_to_binary(<<"erlang">>).
+const BINARY: &[u8] = &[131, 109, 0, 0, 0, 6, 101, 114, 108, 97, 110, 103];
+
+#[test]
+fn decode_concatenated_and_compressed_terms() {
+    let mut input = TUPLE.to_vec();
+    input.extend(compress(BINARY));
+    let output = etf(&["decode"], &input);
+    assert!(output.status.success());
+    assert_eq!("{1,2,3,4}.\n<<\"erlang\">>.\n", String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn json_round_trip() {
+    let json = etf(&["to-json", "--lossless", "--compact"], TUPLE);
+    assert_eq!("{\"tuple\":[1,2,3,4]}\n", String::from_utf8(json.stdout.clone()).unwrap());
+
+    let back = etf(&["from-json", "--lossless"], &json.stdout);
+    assert_eq!(TUPLE, back.stdout);
+}
+
+#[test]
+fn validate_reports_the_failing_term() {
+    let mut input = TUPLE.to_vec();
+    input.extend(&BINARY[..8]);
+    let output = etf(&["validate"], &input);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("etf: term 1 at offset 11:"), "{}", stderr);
+}
+
+#[test]
+fn stats() {
+    let mut input = TUPLE.to_vec();
+    input.extend(BINARY);
+    let output = etf(&["stats"], &input);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("terms: 2\n"), "{}", stdout);
+    assert!(stdout.contains("max depth: 2\n"), "{}", stdout);
+    assert!(stdout.contains("  integer        4\n"), "{}", stdout);
+    assert!(stdout.contains("6 bytes  term 1 \n"), "{}", stdout);
+}
+
+#[test]
+fn stats_descend_into_maps() {
+    // #{data => <<"abc">>}
+    let input = [131, 116, 0, 0, 0, 1, 119, 4, 100, 97, 116, 97, 109, 0, 0, 0, 3, 97, 98, 99];
+    let output = etf(&["stats"], &input);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("max depth: 2\n"), "{}", stdout);
+    assert!(stdout.contains("3 bytes  term 0 #{data}\n"), "{}", stdout);
+}
+
+#[test]
+fn dump() {
+    let output = etf(&["dump"], &BINARY[..8]);
+    assert!(!output.status.success());
+    assert_eq!(
+        "0000 83  version 131\n\
+         0001 6d 00000006  BINARY_EXT len=6\n\
+         0006   65 72  |er|\n\
+         0008   [..]  !! unexpected end of input: BINARY_EXT needs 6 bytes, 2 left\n",
+        String::from_utf8(output.stdout).unwrap()
+    );
+}
+
+fn etf(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_etf"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    return child.wait_with_output().unwrap();
+}
+
+// term_to_binary(Term, [compressed])
+fn compress(term: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&term[1..]).unwrap();
+    let mut out = vec![131, 80];
+    out.extend(((term.len() - 1) as u32).to_be_bytes());
+    out.extend(encoder.finish().unwrap());
+    return out;
+}
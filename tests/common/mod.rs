@@ -0,0 +1,29 @@
+// Helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use erl_etf::*;
+
+pub fn interned(name: &str) -> InternedAtom {
+    return InternedAtom::new(name).unwrap();
+}
+
+pub fn atom(name: &str) -> ErlTerm {
+    return ErlTerm::Atom(interned(name));
+}
+
+pub fn tuple(elements: Vec<ErlTerm>) -> ErlTerm {
+    return ErlTerm::Tuple(Tuple { elements });
+}
+
+pub fn list(elements: Vec<ErlTerm>) -> ErlTerm {
+    return ErlTerm::List(List { elements });
+}
+
+// A charlist, how Erlang stores strings in attributes and literals
+pub fn string(text: &str) -> ErlTerm {
+    return list(text.bytes().map(ErlTerm::SmallInteger).collect());
+}
+
+pub fn binary(text: &str) -> ErlTerm {
+    return ErlTerm::Binary(text.as_bytes().to_vec());
+}
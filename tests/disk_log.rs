@@ -0,0 +1,187 @@
+#![cfg(feature = "disk_log")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process;
+
+use common::*;
+use erl_etf::*;
+use md5::{Digest, Md5};
+
+const CLOSED: &[u8] = &[1, 2, 3, 4, 99, 88, 77, 11];
+const OPENED: &[u8] = &[1, 2, 3, 4, 6, 7, 8, 9];
+
+// A chunk the way disk_log:log/2 writes it
+fn chunk(term: &ErlTerm) -> Vec<u8> {
+    let bytes = term.to_bytes().unwrap();
+    let size = (bytes.len() as u32).to_be_bytes();
+    let mut chunk = size.to_vec();
+    chunk.extend_from_slice(&[98, 87, 76, 65]);
+    if bytes.len() >= 65528 {
+        chunk.extend_from_slice(&Md5::digest(size));
+    }
+    chunk.extend_from_slice(&bytes);
+    return chunk;
+}
+
+fn log(header: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+    return [header.to_vec(), chunks.concat()].concat();
+}
+
+fn read(bytes: Vec<u8>) -> (Vec<ErlTerm>, Vec<BadChunk>) {
+    let mut reader = DiskLogReader::new(Cursor::new(bytes)).unwrap();
+    let terms = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    return (terms, reader.bad_chunks().to_vec());
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("erl-etf-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+//
+// Halt logs
+//
+
+#[test]
+fn reads_logged_terms() {
+    let terms = vec![atom("first"), ErlTerm::SmallInteger(2), ErlTerm::Binary(b"third".to_vec())];
+    let bytes = log(CLOSED, &terms.iter().map(chunk).collect::<Vec<_>>());
+    let reader = DiskLogReader::new(Cursor::new(bytes.clone())).unwrap();
+    assert!(reader.was_closed());
+    assert_eq!(read(bytes), (terms, vec![]));
+}
+
+#[test]
+fn reads_a_log_written_by_disk_log() {
+    // see tests/fixtures/README.md, strings and maps are STRING_EXT and MAP_EXT
+    let mut reader = DiskLogReader::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/events.LOG")).unwrap();
+    assert!(reader.was_closed());
+    let terms = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    let user = ErlTerm::Map(Map { entries: vec![(atom("name"), string("joe")), (atom("roles"), list(vec![atom("admin")]))] });
+    let expected = vec![
+        tuple(vec![atom("user_created"), user]),
+        string("plain text"),
+        tuple(vec![atom("user_deleted"), binary("joe"), ErlTerm::SmallInteger(7)]),
+    ];
+    assert_eq!(terms, expected);
+    assert!(reader.bad_chunks().is_empty());
+}
+
+#[test]
+fn reads_big_terms_with_md5_checked_sizes() {
+    let big = ErlTerm::Binary(vec![7; 70000]);
+    let (terms, bad_chunks) = read(log(CLOSED, &[chunk(&big), chunk(&atom("after"))]));
+    assert_eq!(terms, vec![big.clone(), atom("after")]);
+    assert!(bad_chunks.is_empty());
+
+    // a size that does not match its MD5
+    let mut corrupt = chunk(&big);
+    corrupt[2] ^= 0x40;
+    let (terms, bad_chunks) = read(log(CLOSED, &[corrupt, chunk(&atom("after"))]));
+    assert_eq!(terms, vec![atom("after")]);
+    assert_eq!(bad_chunks[0].reason, BadChunkReason::ChecksumMismatch);
+    assert_eq!(bad_chunks[0].offset, 8);
+}
+
+#[test]
+fn skips_garbage_between_chunks() {
+    let first = chunk(&atom("first"));
+    let garbage = b"not a chunk at all".to_vec();
+    let (terms, bad_chunks) = read(log(CLOSED, &[first.clone(), garbage.clone(), chunk(&atom("second"))]));
+    assert_eq!(terms, vec![atom("first"), atom("second")]);
+    let expected = BadChunk { offset: 8 + first.len() as u64, len: garbage.len() as u64, reason: BadChunkReason::BadBytes };
+    assert_eq!(bad_chunks, vec![expected]);
+}
+
+#[test]
+fn reports_a_truncated_last_chunk() {
+    let first = chunk(&atom("first"));
+    let mut second = chunk(&ErlTerm::Binary(b"cut off".to_vec()));
+    second.truncate(10);
+    let bytes = log(OPENED, &[first.clone(), second]);
+    assert!(!DiskLogReader::new(Cursor::new(bytes.clone())).unwrap().was_closed());
+
+    let (terms, bad_chunks) = read(bytes);
+    assert_eq!(terms, vec![atom("first")]);
+    assert_eq!(bad_chunks, vec![BadChunk { offset: 8 + first.len() as u64, len: 10, reason: BadChunkReason::Truncated }]);
+}
+
+#[test]
+fn reports_undecodable_terms_and_carries_on() {
+    let mut bad = 2u32.to_be_bytes().to_vec();
+    bad.extend_from_slice(&[98, 87, 76, 65, 131, 255]);
+    let (terms, bad_chunks) = read(log(CLOSED, &[bad, chunk(&atom("ok"))]));
+    assert_eq!(terms, vec![atom("ok")]);
+    assert_eq!(bad_chunks, vec![BadChunk { offset: 8, len: 10, reason: BadChunkReason::Undecodable }]);
+}
+
+#[test]
+fn reads_chunks_with_the_old_magic() {
+    let bytes = atom("old").to_bytes().unwrap();
+    let mut old = (bytes.len() as u32).to_be_bytes().to_vec();
+    old.extend_from_slice(&[12, 33, 44, 55]);
+    old.extend_from_slice(&bytes);
+    assert_eq!(read(log(CLOSED, &[old])).0, vec![atom("old")]);
+}
+
+#[test]
+fn rejects_files_without_a_disk_log_header() {
+    assert!(matches!(DiskLogReader::new(Cursor::new(b"\x83d\x00\x02ok".to_vec())), Err(DiskLogError::NotADiskLog)));
+    assert!(matches!(DiskLogReader::new(Cursor::new(vec![1, 2, 3])), Err(DiskLogError::NotADiskLog)));
+}
+
+#[test]
+fn opens_halt_logs_from_disk() {
+    let dir = temp_dir("halt");
+    let path = dir.join("test.LOG");
+    fs::write(&path, log(CLOSED, &[chunk(&atom("on_disk"))])).unwrap();
+    let terms = DiskLogReader::open(&path).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(terms, vec![atom("on_disk")]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+//
+// Wrap logs
+//
+
+#[test]
+fn reads_wrap_log_files_oldest_first() {
+    let dir = temp_dir("wrap");
+    let base = dir.join("events");
+    let file = |n: u32| PathBuf::from(format!("{}.{}", base.display(), n));
+    // 3 is the oldest, 2 the one being written to
+    fs::write(file(1), log(CLOSED, &[chunk(&ErlTerm::SmallInteger(3)), chunk(&ErlTerm::SmallInteger(4))])).unwrap();
+    fs::write(file(2), log(OPENED, &[chunk(&ErlTerm::SmallInteger(5)), b"garbage".to_vec()])).unwrap();
+    fs::write(file(3), log(CLOSED, &[chunk(&ErlTerm::SmallInteger(1)), chunk(&ErlTerm::SmallInteger(2))])).unwrap();
+    let mut index = vec![0, 0, 0, 0, 0, 2];
+    index.extend_from_slice(&2u32.to_be_bytes());
+    fs::write(format!("{}.idx", base.display()), index).unwrap();
+
+    let mut reader = WrapLogReader::open(&base).unwrap();
+    assert_eq!(reader.files(), &[file(3), file(1), file(2)]);
+    let terms = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(terms, (1..=5).map(ErlTerm::SmallInteger).collect::<Vec<_>>());
+    let bad_chunks = reader.bad_chunks();
+    assert_eq!(bad_chunks.len(), 1);
+    assert_eq!(bad_chunks[0].0, file(2));
+    assert_eq!(bad_chunks[0].1.reason, BadChunkReason::Truncated);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reads_wrap_logs_without_an_index_file() {
+    let dir = temp_dir("wrap-no-index");
+    let base = dir.join("events");
+    fs::write(format!("{}.1", base.display()), log(CLOSED, &[chunk(&atom("only"))])).unwrap();
+    let terms = WrapLogReader::open(&base).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(terms, vec![atom("only")]);
+    assert!(matches!(WrapLogReader::open(dir.join("missing")), Err(DiskLogError::NoWrapLogFiles { .. })));
+    fs::remove_dir_all(dir).unwrap();
+}
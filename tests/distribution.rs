@@ -0,0 +1,392 @@
+#![cfg(feature = "distribution")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use common::*;
+use erl_etf::*;
+use md5::{Digest, Md5};
+
+fn config(name: &str, cookie: &str) -> HandshakeConfig {
+    return HandshakeConfig::new(name, cookie).unwrap();
+}
+
+fn listen() -> (TcpListener, SocketAddr) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    return (listener, addr);
+}
+
+// Connects a node with the given send_name version to an accepting node
+fn connect_pair(
+    initiating: HandshakeConfig,
+    accepting: HandshakeConfig,
+    version: u16,
+) -> (Result<Connection, DistributionError>, Result<Connection, DistributionError>) {
+    let (listener, addr) = listen();
+    let acceptor = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        return Connection::accept(stream, &accepting);
+    });
+    let initiator = Connection::connect_to(&initiating, addr, version);
+    return (initiator, acceptor.join().unwrap());
+}
+
+fn read_handshake(stream: &mut TcpStream) -> Vec<u8> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len).unwrap();
+    let mut message = vec![0; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message).unwrap();
+    return message;
+}
+
+fn write_handshake(stream: &mut TcpStream, message: &[u8]) {
+    stream.write_all(&(message.len() as u16).to_be_bytes()).unwrap();
+    stream.write_all(message).unwrap();
+}
+
+fn write_packet(stream: &mut TcpStream, packet: &[u8]) {
+    stream.write_all(&(packet.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(packet).unwrap();
+}
+
+fn md5(cookie: &str, challenge: u32) -> Vec<u8> {
+    return Md5::new().chain_update(cookie).chain_update(challenge.to_string()).finalize().to_vec();
+}
+
+// Accepts a connection from the node under test with the challenge 1234567 and
+// cookie "secret", checks its messages, and then hands over the connection
+fn scripted_peer(script: impl FnOnce(TcpStream) + Send + 'static) -> (SocketAddr, thread::JoinHandle<()>) {
+    let (listener, addr) = listen();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let send_name = read_handshake(&mut stream);
+        assert_eq!(b'N', send_name[0]);
+        assert_eq!(DFLAG_DEFAULT.to_be_bytes(), send_name[1..9]);
+        assert_eq!(b"\x00\x0erust@localhost", &send_name[13..]);
+        write_handshake(&mut stream, b"sok");
+
+        let mut challenge = vec![b'N'];
+        challenge.extend_from_slice(&DFLAG_DEFAULT.to_be_bytes());
+        challenge.extend_from_slice(&1234567u32.to_be_bytes());
+        challenge.extend_from_slice(&[0, 0, 0, 1, 0, 13]);
+        challenge.extend_from_slice(b"rabbit@erlang");
+        write_handshake(&mut stream, &challenge);
+
+        let reply = read_handshake(&mut stream);
+        assert_eq!(b'r', reply[0]);
+        assert_eq!(
+            vec![16, 116, 236, 157, 131, 30, 71, 170, 131, 228, 115, 148, 202, 41, 242, 222],
+            reply[5..]
+        );
+        let their_challenge = u32::from_be_bytes(reply[1..5].try_into().unwrap());
+        write_handshake(&mut stream, &[&b"a"[..], &md5("secret", their_challenge)].concat());
+        script(stream);
+    });
+    return (addr, handle);
+}
+
+//
+// Handshake
+//
+
+#[test]
+fn handshake_as_initiating_node() {
+    let (addr, peer) = scripted_peer(|_| {});
+    let connection = Connection::connect_to(&config("rust@localhost", "secret"), addr, 6).unwrap();
+    peer.join().unwrap();
+    assert_eq!("rabbit@erlang", connection.peer_name());
+    assert_eq!(1, connection.peer_creation());
+    assert_eq!(DFLAG_DEFAULT, connection.flags());
+}
+
+#[test]
+fn handshake_between_two_nodes() {
+    for version in [5, 6] {
+        let published = HandshakeConfig { flags: DFLAG_DEFAULT | DFLAG_PUBLISHED, ..config("b@localhost", "secret") };
+        let creation = published.creation;
+        let (a, b) = connect_pair(config("a@localhost", "secret"), published, version);
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!("b@localhost", a.peer_name());
+        assert_eq!("a@localhost", b.peer_name());
+        // a is hidden
+        assert_eq!(DFLAG_DEFAULT, a.flags());
+        assert_eq!(DFLAG_DEFAULT, b.flags());
+        // version 5 peers send the high flags and creation in the complement
+        assert_eq!(creation, a.peer_creation());
+    }
+}
+
+#[test]
+fn handshake_fails_with_different_cookies() {
+    let (a, b) = connect_pair(config("a@localhost", "secret"), config("b@localhost", "other"), 6);
+    assert!(a.is_err());
+    assert!(matches!(b, Err(DistributionError::AuthenticationFailed)));
+}
+
+#[test]
+fn handshake_refuses_nodes_without_required_flags() {
+    let old = HandshakeConfig { flags: DFLAG_DEFAULT & !DFLAG_UTF8_ATOMS, ..config("a@localhost", "secret") };
+    let (a, b) = connect_pair(old, config("b@localhost", "secret"), 6);
+    assert!(matches!(a, Err(DistributionError::Refused { status }) if status == "not_allowed"));
+    assert!(matches!(b, Err(DistributionError::MissingFlags { missing: DFLAG_UTF8_ATOMS })));
+}
+
+#[test]
+fn node_names_need_a_host() {
+    assert!(matches!(
+        HandshakeConfig::new("rust", "secret"),
+        Err(DistributionError::InvalidNodeName { .. })
+    ));
+}
+
+//
+// Messages
+//
+
+#[test]
+fn messages_between_two_nodes() {
+    let (a, b) = connect_pair(config("a@localhost", "secret"), config("b@localhost", "secret"), 6);
+    let (mut a, mut b) = (a.unwrap(), b.unwrap());
+
+    let control = tuple(vec![ErlTerm::SmallInteger(6), atom("a"), atom(""), atom("rex")]);
+    let message = ErlTerm::Binary(b"hello".to_vec());
+    a.send(&control, Some(&message)).unwrap();
+    a.send(&control, None).unwrap();
+    assert_eq!((control.clone(), Some(message)), b.receive().unwrap());
+    assert_eq!((control, None), b.receive().unwrap());
+}
+
+#[test]
+fn receiving_answers_ticks_and_reassembles_fragments() {
+    let control = tuple(vec![ErlTerm::SmallInteger(2), atom(""), atom("rust")]);
+    let message = ErlTerm::Binary(vec![7; 1000]);
+
+    let (addr, peer) = scripted_peer({
+        let (control, message) = (control.clone(), message.clone());
+        move |mut stream| {
+            write_packet(&mut stream, &[]);
+            let mut tick = [1; 4];
+            stream.read_exact(&mut tick).unwrap();
+            assert_eq!([0; 4], tick);
+
+            // a normal header, terms without version bytes
+            let mut packet = vec![131, 68, 0];
+            packet.extend_from_slice(&control.to_bytes().unwrap()[1..]);
+            write_packet(&mut stream, &packet);
+
+            let bytes = [control.to_bytes().unwrap(), message.to_bytes().unwrap()].concat();
+            for (i, chunk) in bytes.chunks(400).enumerate() {
+                let fragment_id = 3 - i as u64;
+                let mut packet = if i == 0 { vec![131, 69] } else { vec![131, 70] };
+                packet.extend_from_slice(&42u64.to_be_bytes());
+                packet.extend_from_slice(&fragment_id.to_be_bytes());
+                if i == 0 {
+                    packet.push(0);
+                }
+                packet.extend_from_slice(chunk);
+                write_packet(&mut stream, &packet);
+            }
+        }
+    });
+    let mut connection = Connection::connect_to(&config("rust@localhost", "secret"), addr, 6).unwrap();
+    assert_eq!((control.clone(), None), connection.receive().unwrap());
+    assert_eq!((control, Some(message)), connection.receive().unwrap());
+    peer.join().unwrap();
+}
+
+#[test]
+fn connect_looks_nodes_up_in_epmd() {
+    // ERL_EPMD_PORT is not set in tests, there is no node named after this test
+    let config = config("rust@localhost", "secret");
+    match Connection::connect(&config, "no_such_node_for_erl_etf@localhost") {
+        Err(DistributionError::NodeNotFound { .. }) | Err(DistributionError::Epmd(_)) => {}
+        other => panic!("unexpected result: {:?}", other.map(|c| c.peer_name().to_string())),
+    }
+}
+
+//
+// Control messages
+//
+
+fn pid(id: u32) -> ErlPid {
+    return ErlPid { node: Atom { name: interned("a@localhost") }, id, serial: 0, creation: 1 };
+}
+
+fn reference() -> Ref {
+    return Ref { node: Atom { name: interned("a@localhost") }, creation: 1, id: vec![1, 2, 3] };
+}
+
+fn every_control_message() -> Vec<ControlMessage> {
+    let token = Some(tuple(vec![ErlTerm::SmallInteger(1), atom("label")]));
+    let reason = atom("normal");
+    let name = Atom { name: interned("rex") };
+    let mut messages = vec![
+        ControlMessage::Link { from: pid(1), to: pid(2) },
+        ControlMessage::Unlink { from: pid(1), to: pid(2) },
+        ControlMessage::NodeLink,
+        ControlMessage::GroupLeader { from: pid(1), to: pid(2) },
+        ControlMessage::MonitorP { from: pid(1), to: Process::Name(name.clone()), reference: reference() },
+        ControlMessage::DemonitorP { from: pid(1), to: Process::Pid(pid(2)), reference: reference() },
+        ControlMessage::MonitorPExit {
+            from: Process::Pid(pid(2)),
+            to: pid(1),
+            reference: reference(),
+            reason: reason.clone(),
+        },
+        ControlMessage::PayloadMonitorPExit { from: Process::Name(name.clone()), to: pid(1), reference: reference() },
+        ControlMessage::UnlinkId { id: u64::MAX, from: pid(1), to: pid(2) },
+        ControlMessage::UnlinkIdAck { id: 7, from: pid(1), to: pid(2) },
+    ];
+    for token in [None, token] {
+        messages.extend([
+            ControlMessage::Send { to: pid(2), token: token.clone() },
+            ControlMessage::Exit { from: pid(1), to: pid(2), reason: reason.clone(), token: token.clone() },
+            ControlMessage::RegSend { from: pid(1), to_name: name.clone(), token: token.clone() },
+            ControlMessage::Exit2 { from: pid(1), to: pid(2), reason: reason.clone(), token: token.clone() },
+            ControlMessage::SendSender { from: pid(1), to: pid(2), token: token.clone() },
+            ControlMessage::PayloadExit { from: pid(1), to: pid(2), token: token.clone() },
+            ControlMessage::PayloadExit2 { from: pid(1), to: pid(2), token: token.clone() },
+            ControlMessage::SpawnRequest {
+                request_id: reference(),
+                from: pid(1),
+                group_leader: pid(2),
+                module: Atom { name: interned("erlang") },
+                function: Atom { name: interned("apply") },
+                arity: 2,
+                options: vec![atom("link")],
+                token: token.clone(),
+            },
+            ControlMessage::SpawnReply {
+                request_id: reference(),
+                to: pid(1),
+                flags: 1,
+                result: ErlTerm::Pid(pid(3)),
+                token: token.clone(),
+            },
+            ControlMessage::AliasSend { from: pid(1), alias: reference(), token: token.clone() },
+        ]);
+    }
+    return messages;
+}
+
+#[test]
+fn control_messages_round_trip() {
+    for message in every_control_message() {
+        let bytes = ErlTerm::from(&message).to_bytes().unwrap();
+        let term = ErlTerm::from_bytes(&bytes).unwrap();
+        assert_eq!(message, ControlMessage::try_from(term).unwrap());
+    }
+}
+
+#[test]
+fn control_messages_as_tuples() {
+    // {2, '', ToPid}
+    assert_eq!(
+        tuple(vec![ErlTerm::SmallInteger(2), atom(""), ErlTerm::Pid(pid(2))]),
+        ErlTerm::from(ControlMessage::Send { to: pid(2), token: None })
+    );
+    // {6, FromPid, '', ToName}
+    assert_eq!(
+        tuple(vec![ErlTerm::SmallInteger(6), ErlTerm::Pid(pid(1)), atom(""), atom("rex")]),
+        ErlTerm::from(ControlMessage::RegSend { from: pid(1), to_name: Atom { name: interned("rex") }, token: None })
+    );
+    // {13, FromPid, ToPid, TraceToken, Reason}
+    assert_eq!(
+        tuple(vec![ErlTerm::SmallInteger(13), ErlTerm::Pid(pid(1)), ErlTerm::Pid(pid(2)), atom("token"), atom("kill")]),
+        ErlTerm::from(ControlMessage::Exit { from: pid(1), to: pid(2), reason: atom("kill"), token: Some(atom("token")) })
+    );
+    // {35, Id, FromPid, ToPid}, ids beyond 32 bits are big integers
+    assert_eq!(
+        tuple(vec![ErlTerm::SmallInteger(35), ErlTerm::BigInteger(u64::MAX.into()), ErlTerm::Pid(pid(1)), ErlTerm::Pid(pid(2))]),
+        ErlTerm::from(ControlMessage::UnlinkId { id: u64::MAX, from: pid(1), to: pid(2) })
+    );
+}
+
+#[test]
+fn unknown_control_messages_fail_to_convert() {
+    for control in [
+        atom("send"),
+        tuple(vec![]),
+        tuple(vec![ErlTerm::SmallInteger(99)]),
+        tuple(vec![ErlTerm::SmallInteger(2), atom("")]),
+        tuple(vec![ErlTerm::SmallInteger(2), atom(""), atom("not_a_pid")]),
+    ] {
+        assert!(matches!(
+            ControlMessage::try_from(control.clone()),
+            Err(DistributionError::UnsupportedControlMessage { control: c }) if c == control
+        ));
+    }
+}
+
+#[test]
+fn control_messages_between_two_nodes() {
+    let (a, b) = connect_pair(config("a@localhost", "secret"), config("b@localhost", "secret"), 6);
+    let (mut a, mut b) = (a.unwrap(), b.unwrap());
+    let control = ControlMessage::RegSend { from: pid(1), to_name: Atom { name: interned("rex") }, token: None };
+    assert!(control.has_payload());
+    a.send_control(&control, Some(&atom("hello"))).unwrap();
+    assert_eq!((control, Some(atom("hello"))), b.receive_control().unwrap());
+}
+
+//
+// Nodes
+//
+
+#[test]
+fn nodes_keep_reading_after_bad_messages() {
+    let node = Node::start(config("rust@localhost", "secret")).unwrap();
+    let mailbox = node.create_named_mailbox("inbox").unwrap();
+    let (addr, peer) = scripted_peer(|mut stream| {
+        let control = tuple(vec![ErlTerm::SmallInteger(6), ErlTerm::Pid(pid(1)), atom(""), atom("inbox")]);
+        for message in [vec![131, 7], atom("ok").to_bytes().unwrap()] {
+            let packet = [&[112][..], &control.to_bytes().unwrap(), &message].concat();
+            write_packet(&mut stream, &packet);
+        }
+        // hold the connection open until the node has read both
+        let mut rest = Vec::new();
+        let _ = stream.read_to_end(&mut rest);
+    });
+    assert_eq!("rabbit@erlang", node.connect_to(addr).unwrap());
+    assert_eq!(atom("ok"), mailbox.receive_timeout(Duration::from_secs(5)).unwrap().unwrap());
+
+    let bad = node.take_bad_messages();
+    assert_eq!(1, bad.len());
+    assert_eq!("rabbit@erlang", bad[0].node);
+    assert!(matches!(bad[0].error, DistributionError::DecodingFailure(_)));
+    assert!(node.take_bad_messages().is_empty());
+    node.close();
+    peer.join().unwrap();
+}
+
+#[test]
+fn nodes_refuse_spawn_requests() {
+    let node = Node::start(config("a@localhost", "secret")).unwrap();
+    let (listener, addr) = listen();
+    let from = ErlPid { node: Atom { name: interned("b@localhost") }, id: 1, serial: 0, creation: 1 };
+    let request = ControlMessage::SpawnRequest {
+        request_id: reference(),
+        from: from.clone(),
+        group_leader: from.clone(),
+        module: Atom { name: interned("erlang") },
+        function: Atom { name: interned("apply") },
+        arity: 2,
+        options: vec![],
+        token: None,
+    };
+    let peer = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut connection = Connection::accept(stream, &config("b@localhost", "secret")).unwrap();
+        connection.send_control(&request, Some(&list(vec![]))).unwrap();
+        return connection.receive_control().unwrap();
+    });
+    node.connect_to(addr).unwrap();
+    let reply = ControlMessage::SpawnReply { request_id: reference(), to: from, flags: 0, result: atom("notsup"), token: None };
+    assert_eq!((reply, None), peer.join().unwrap());
+    node.close();
+}
@@ -0,0 +1,129 @@
+#![cfg(feature = "epmd")]
+#![allow(clippy::needless_return)]
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+use erl_etf::*;
+
+// Accepts a single connection, answers it with the given bytes and returns what the client sent
+fn fake_epmd(response: &'static [u8]) -> (EpmdClient, thread::JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = EpmdClient::new(listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut len = [0; 2];
+        stream.read_exact(&mut len).unwrap();
+        let mut request = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut request).unwrap();
+        stream.write_all(response).unwrap();
+        return [&len[..], &request].concat();
+    });
+    return (client, handle);
+}
+
+//
+// Wire format, as described in erl_dist_protocol
+//
+
+#[test]
+fn register_sends_alive2_req() {
+    let (client, epmd) = fake_epmd(&[118, 0, 0, 0, 0, 42]);
+    let registration = client.register(&NodeInfo::new("foo", 4370)).unwrap();
+    assert_eq!(42, registration.creation());
+    assert_eq!(
+        vec![0, 16, 120, 0x11, 0x12, 77, 0, 0, 6, 0, 5, 0, 3, b'f', b'o', b'o', 0, 0],
+        epmd.join().unwrap()
+    );
+}
+
+#[test]
+fn register_accepts_alive2_resp_from_older_epmd() {
+    let (client, _epmd) = fake_epmd(&[121, 0, 0, 3]);
+    assert_eq!(3, client.register(&NodeInfo::new("foo", 4370)).unwrap().creation());
+
+    let (client, _epmd) = fake_epmd(&[121, 1, 0, 0]);
+    assert!(matches!(
+        client.register(&NodeInfo::new("foo", 4370)),
+        Err(EpmdError::RegistrationRefused { result: 1 })
+    ));
+}
+
+#[test]
+fn port_please_parses_port2_resp() {
+    let (client, epmd) = fake_epmd(&[119, 0, 0x11, 0x12, 72, 0, 0, 6, 0, 5, 0, 3, b'b', b'a', b'r', 0, 1, 9]);
+    let node = client.port_please("bar").unwrap().unwrap();
+    assert_eq!(vec![0, 4, 122, b'b', b'a', b'r'], epmd.join().unwrap());
+    assert_eq!(
+        NodeInfo { node_type: NodeType::Hidden, extra: vec![9], ..NodeInfo::new("bar", 4370) },
+        node
+    );
+
+    let (client, _epmd) = fake_epmd(&[119, 1]);
+    assert_eq!(None, client.port_please("bar").unwrap());
+}
+
+#[test]
+fn names_parses_names_resp() {
+    let (client, epmd) = fake_epmd(b"\x00\x00\x11\x11name foo at port 4370\nname bar baz at port 38123\n");
+    assert_eq!(
+        vec![("foo".to_string(), 4370), ("bar baz".to_string(), 38123)],
+        client.names().unwrap()
+    );
+    assert_eq!(vec![0, 1, 110], epmd.join().unwrap());
+}
+
+//
+// In-process server
+//
+
+#[test]
+fn registered_nodes_can_be_looked_up() {
+    let epmd = EpmdServer::start().unwrap();
+    let client = epmd.client();
+    assert_eq!(None, client.port_please("rabbit").unwrap());
+
+    let _rabbit = client.register(&NodeInfo::new("rabbit", 25672)).unwrap();
+    let _hare = client.register(&NodeInfo { node_type: NodeType::Hidden, ..NodeInfo::new("hare", 25673) }).unwrap();
+    assert_eq!(Some(NodeInfo::new("rabbit", 25672)), client.port_please("rabbit").unwrap());
+    assert_eq!(NodeType::Hidden, client.port_please("hare").unwrap().unwrap().node_type);
+    assert_eq!(
+        vec![("hare".to_string(), 25673), ("rabbit".to_string(), 25672)],
+        client.names().unwrap()
+    );
+}
+
+#[test]
+fn names_are_registered_once_at_a_time() {
+    let epmd = EpmdServer::start().unwrap();
+    let client = epmd.client();
+
+    let first = client.register(&NodeInfo::new("rabbit", 25672)).unwrap();
+    assert!(matches!(
+        client.register(&NodeInfo::new("rabbit", 25673)),
+        Err(EpmdError::RegistrationRefused { .. })
+    ));
+
+    let creation = first.creation();
+    drop(first);
+    // the server notices the closed connection asynchronously
+    let second = (0..100)
+        .find_map(|_| {
+            thread::sleep(Duration::from_millis(10));
+            client.register(&NodeInfo::new("rabbit", 25673)).ok()
+        })
+        .unwrap();
+    assert_ne!(creation, second.creation());
+    assert_eq!(25673, client.port_please("rabbit").unwrap().unwrap().port);
+
+    drop(second);
+    for _ in 0..100 {
+        if client.names().unwrap().is_empty() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("rabbit is still registered");
+}
@@ -0,0 +1,158 @@
+#![cfg(feature = "ets")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use erl_etf::*;
+use md5::{Digest, Md5};
+
+fn pair(key: &str, value: ErlTerm) -> ErlTerm {
+    return tuple(vec![atom(key), value]);
+}
+
+fn header(extended_info: &[&str]) -> ErlTerm {
+    let mut info = vec![
+        pair("name", atom("users")),
+        pair("type", atom("set")),
+        pair("protection", atom("protected")),
+        pair("named_table", atom("true")),
+        pair("keypos", ErlTerm::SmallInteger(1)),
+        pair("size", ErlTerm::SmallInteger(2)),
+    ];
+    if !extended_info.is_empty() {
+        info.push(pair("major_version", ErlTerm::SmallInteger(1)));
+        info.push(pair("minor_version", ErlTerm::SmallInteger(0)));
+        info.push(pair("extended_info", list(extended_info.iter().map(|flag| atom(flag)).collect())));
+    }
+    return tuple(info);
+}
+
+fn objects() -> Vec<ErlTerm> {
+    return vec![
+        tuple(vec![ErlTerm::SmallInteger(1), ErlTerm::Binary(b"alice".to_vec())]),
+        tuple(vec![ErlTerm::SmallInteger(2), ErlTerm::Binary(b"bob".to_vec())]),
+    ];
+}
+
+// What ets:tab2file(users, File, [{extended_info, ExtendedInfo}]) writes
+fn tab_file(header: &ErlTerm, objects: &[ErlTerm], end: Option<Vec<ErlTerm>>) -> Vec<u8> {
+    let mut file = vec![1, 2, 3, 4, 99, 88, 77, 11];
+    let mut log = |term: &ErlTerm| {
+        let bytes = term.to_bytes().unwrap();
+        file.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        file.extend_from_slice(&[98, 87, 76, 65]);
+        file.extend_from_slice(&bytes);
+    };
+    log(header);
+    objects.iter().for_each(&mut log);
+    if let Some(end) = end {
+        log(&list(vec![atom("$end_of_table"), list(end)]));
+    }
+    return file;
+}
+
+fn md5(header: &ErlTerm, objects: &[ErlTerm]) -> ErlTerm {
+    let mut md5 = Md5::new();
+    for term in [header].into_iter().chain(objects) {
+        md5.update(term.to_bytes().unwrap());
+    }
+    return ErlTerm::Binary(md5.finalize().to_vec());
+}
+
+fn read_all(bytes: Vec<u8>) -> Result<Vec<ErlTerm>, TabFileError> {
+    return TabFileReader::new(Cursor::new(bytes)).unwrap().collect();
+}
+
+#[test]
+fn parses_the_header() {
+    let reader = TabFileReader::new(Cursor::new(tab_file(&header(&["md5sum", "object_count"]), &[], None))).unwrap();
+    let header = reader.header();
+    assert_eq!(header.name, Atom { name: interned("users") });
+    assert_eq!(header.table_type, TableType::Set);
+    assert_eq!(header.protection, Atom { name: interned("protected") });
+    assert!(header.named_table);
+    assert_eq!((header.keypos, header.size), (1, 2));
+    assert_eq!(header.version, Some((1, 0)));
+    assert!(header.md5sum && header.object_count);
+    assert_eq!(header.info.len(), 9);
+}
+
+#[test]
+fn reads_a_file_written_by_tab2file() {
+    // see tests/fixtures/README.md, the MD5 is over the STRING_EXT and MAP_EXT bytes as written
+    let reader = TabFileReader::open(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/users.tab")).unwrap();
+    let header = reader.header();
+    assert_eq!(header.name, Atom { name: interned("users") });
+    assert_eq!(header.table_type, TableType::Set);
+    assert_eq!((header.keypos, header.size), (1, 2));
+    assert!(header.md5sum && header.object_count);
+
+    let email = ErlTerm::Map(Map { entries: vec![(atom("email"), binary("alice@example.com"))] });
+    let objects = [
+        tuple(vec![ErlTerm::SmallInteger(1), string("alice"), email]),
+        tuple(vec![ErlTerm::SmallInteger(2), string("bob"), ErlTerm::Map(Map::empty())]),
+    ];
+    // in the order of the table's hash, not of insertion
+    let read = reader.collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(read.len(), 2);
+    assert!(objects.iter().all(|object| read.contains(object)));
+}
+
+#[test]
+fn reads_objects_without_extended_info() {
+    let objects = objects();
+    assert_eq!(read_all(tab_file(&header(&[]), &objects, None)).unwrap(), objects);
+}
+
+#[test]
+fn verifies_the_count_and_md5() {
+    let header = header(&["md5sum", "object_count"]);
+    let objects = objects();
+    let end = vec![pair("count", ErlTerm::SmallInteger(2)), pair("md5", md5(&header, &objects))];
+    assert_eq!(read_all(tab_file(&header, &objects, Some(end))).unwrap(), objects);
+}
+
+#[test]
+fn reports_a_checksum_mismatch() {
+    let header = header(&["md5sum"]);
+    let objects = objects();
+    let end = vec![pair("md5", md5(&header, &objects[..1]))];
+    let result = read_all(tab_file(&header, &objects, Some(end)));
+    assert!(matches!(result, Err(TabFileError::ChecksumMismatch)));
+}
+
+#[test]
+fn reports_a_count_mismatch() {
+    let header = header(&["object_count"]);
+    let end = vec![pair("count", ErlTerm::SmallInteger(3))];
+    let result = read_all(tab_file(&header, &objects(), Some(end)));
+    assert!(matches!(result, Err(TabFileError::CountMismatch { expected: 3, actual: 2 })));
+}
+
+#[test]
+fn reports_a_missing_end_of_table() {
+    // a dump that was cut short
+    let mut reader = TabFileReader::new(Cursor::new(tab_file(&header(&["object_count"]), &objects(), None))).unwrap();
+    assert!(reader.next().unwrap().is_ok());
+    assert!(reader.next().unwrap().is_ok());
+    assert!(matches!(reader.next(), Some(Err(TabFileError::MissingEndOfTable))));
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn rejects_invalid_files() {
+    let not_a_header = tab_file(&atom("users"), &[], None);
+    assert!(matches!(TabFileReader::new(Cursor::new(not_a_header)), Err(TabFileError::InvalidHeader)));
+    let no_header = tab_file(&header(&[]), &[], None)[..8].to_vec();
+    assert!(matches!(TabFileReader::new(Cursor::new(no_header)), Err(TabFileError::InvalidHeader)));
+    assert!(matches!(
+        TabFileReader::new(Cursor::new(b"not a log".to_vec())),
+        Err(TabFileError::DiskLog(DiskLogError::NotADiskLog))
+    ));
+
+    let result = read_all(tab_file(&header(&[]), &[atom("not_a_tuple")], None));
+    assert!(matches!(result, Err(TabFileError::InvalidObject { .. })));
+}
@@ -0,0 +1,8 @@
+-module(greeter).
+-export([hello/1]).
+-vsn("1.2.0").
+-author(joe).
+
+hello(Name) ->
+    io:format("~s~n", [Name]),
+    {greeted, [Name]}.
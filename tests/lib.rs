@@ -1,3 +1,6 @@
+#![cfg(feature = "std")]
+#![allow(clippy::needless_return)]
+
 extern crate erl_etf;
 
 use erl_etf::*;
@@ -469,22 +472,1272 @@ fn decode_internal_fun() {
     ]);
     let res: InternalFun = ErlTerm::decode(input).unwrap().try_into().unwrap();
 
-    assert_eq!(Atom { name: String::from("a") }, res.module);
+    assert_eq!(Atom { name: InternedAtom::new("a").unwrap() }, res.module);
     assert_eq!(0, res.index);
     assert_eq!(vec![115, 60, 203, 97, 151, 228, 98, 75, 71, 169, 49, 166, 34, 126, 65, 11],
                res.uniq_beam_md5);
 }
 
+#[test]
+fn decode_string() {
+    // term_to_binary("abc").
+    // <<131,107,0,3,97,98,99>>
+    let bytes = [131, 107, 0, 3, 97, 98, 99];
+    let expected = list_of_u8(vec![97, 98, 99]);
+    assert_eq!(expected, ErlTerm::decode(binary_data(bytes)).unwrap());
+    assert_eq!(expected, ErlTerm::from_bytes(&bytes).unwrap());
+    assert_eq!(bytes.len(), validate(&bytes).unwrap());
+
+    // term_to_binary({"", "ok"}), the empty string is NIL_EXT
+    let bytes = [131, 104, 2, 106, 107, 0, 2, 111, 107];
+    let expected = ErlTerm::Tuple(Tuple { elements: vec![empty_list(), list_of_u8(b"ok".to_vec())] });
+    assert_eq!(expected, ErlTerm::from_bytes(&bytes).unwrap());
+}
+
+#[test]
+fn decode_map() {
+    // term_to_binary(#{name => "joe", tags => [1, 2]}) on OTP 26+
+    // <<131,116,0,0,0,2,119,4,110,97,109,101,107,0,3,106,111,101,119,4,116,97,103,115,107,0,2,1,2>>
+    let bytes = [
+        131, 116, 0, 0, 0, 2, 119, 4, 110, 97, 109, 101, 107, 0, 3, 106, 111, 101, 119, 4, 116, 97,
+        103, 115, 107, 0, 2, 1, 2,
+    ];
+    let expected = map(vec![
+        (atom("name"), list_of_u8(b"joe".to_vec())),
+        (atom("tags"), list_of_u8(vec![1, 2])),
+    ]);
+    assert_eq!(expected, ErlTerm::decode(binary_data(bytes)).unwrap());
+    assert_eq!(expected, ErlTerm::from_bytes(&bytes).unwrap());
+    assert_eq!(bytes.len(), validate(&bytes).unwrap());
+
+    let decoded: Map = expected.try_into().unwrap();
+    assert_eq!(Some(&list_of_u8(vec![1, 2])), decoded.get(&atom("tags")));
+    assert_eq!(None, decoded.get(&atom("age")));
+
+    // term_to_binary(#{}).
+    assert_eq!(map(vec![]), ErlTerm::from_bytes(&[131, 116, 0, 0, 0, 0]).unwrap());
+    // term_to_binary(#{a => 1}) before OTP 26, with ATOM_EXT keys
+    let bytes = [131, 116, 0, 0, 0, 1, 100, 0, 1, 97, 97, 1];
+    assert_eq!(map(vec![(atom("a"), small_integer(1))]), ErlTerm::decode(binary_data(bytes)).unwrap());
+
+    // a map claiming 4 billion entries
+    assert!(ErlTerm::from_bytes(&[131, 116, 255, 255, 255, 255, 106]).is_err());
+    assert!(ErlTerm::decode(binary_data(&[131, 116, 255, 255, 255, 255, 106])).is_err());
+}
+
+#[test]
+fn maps_are_equal_regardless_of_entry_order() {
+    let a = map(vec![(atom("a"), small_integer(1)), (atom("b"), small_integer(2))]);
+    let b = map(vec![(atom("b"), small_integer(2)), (atom("a"), small_integer(1))]);
+    assert_eq!(a, b);
+    assert_ne!(a, map(vec![(atom("a"), small_integer(1)), (atom("b"), small_integer(3))]));
+    assert_ne!(a, map(vec![(atom("a"), small_integer(1))]));
+
+    let set: std::collections::HashSet<ErlTerm> = [a, b].into_iter().collect();
+    assert_eq!(1, set.len());
+}
+
+#[cfg(feature = "zlib")]
+#[test]
+fn decode_compressed_term() {
+    // term_to_binary({1, 2, 3, 4}).
+    let tuple = [131, 104, 4, 97, 1, 97, 2, 97, 3, 97, 4];
+    let expected = ErlTerm::from_bytes(&tuple).unwrap();
+
+    // term_to_binary({1, 2, 3, 4}, [compressed]) followed by the same term uncompressed,
+    // the first decode must not consume the second term
+    let mut input = compress(&tuple);
+    input.extend(tuple);
+    let mut reader = Cursor::new(input);
+    assert_eq!(expected, Decoder::new(Box::new(&mut reader)).decode().unwrap());
+    assert_eq!(expected, Decoder::new(Box::new(&mut reader)).decode().unwrap());
+
+    // a wrong uncompressed size
+    for size in [9, 11] {
+        let mut input = compress(&tuple);
+        input[2..6].copy_from_slice(&(size as u32).to_be_bytes());
+        assert!(ErlTerm::decode(binary_data(input)).is_err());
+    }
+
+    // the nesting limit applies to the compressed term
+    let mut nested = vec![131];
+    nested.extend([104, 1].repeat(3));
+    nested.push(106);
+    let mut decoder = Decoder::new(binary_data(compress(&nested))).with_max_depth(3);
+    assert!(matches!(decoder.decode(), Err(DecodingError::NestingTooDeep { limit: 3 })));
+    let mut decoder = Decoder::new(binary_data(compress(&nested))).with_max_depth(4);
+    assert!(decoder.decode().is_ok());
+}
+
+// term_to_binary(Term, [compressed])
+#[cfg(feature = "zlib")]
+fn compress(term: &[u8]) -> Vec<u8> {
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&term[1..]).unwrap();
+    let mut out = vec![131, 80];
+    out.extend(((term.len() - 1) as u32).to_be_bytes());
+    out.extend(encoder.finish().unwrap());
+    return out;
+}
+
+//
+// Encoding
+//
+
+#[test]
+fn encode_matches_erlang_output() {
+    let fixtures: Vec<&[u8]> = vec![
+        // term_to_binary({1, 2, 3, 4}).
+        &[131, 104, 4, 97, 1, 97, 2, 97, 3, 97, 4],
+        // term_to_binary([1, 2, 3, 99999999]).
+        &[131, 108, 0, 0, 0, 4, 97, 1, 97, 2, 97, 3, 98, 5, 245, 224, 255, 106],
+        // term_to_binary([1, 2, 3, 99999999 | 5]).
+        &[131, 108, 0, 0, 0, 4, 97, 1, 97, 2, 97, 3, 98, 5, 245, 224, 255, 97, 5],
+        // term_to_binary(<<1, 2, 3:5>>).
+        &[131, 77, 0, 0, 0, 3, 5, 1, 2, 24],
+        // term_to_binary({<<"erlang">>, <<"rust">>}).
+        &[131, 104, 2, 109, 0, 0, 0, 6, 101, 114, 108, 97, 110, 103, 109, 0, 0, 0, 4, 114, 117, 115, 116],
+        // term_to_binary(-5130000000).
+        &[131, 110, 5, 1, 128, 60, 197, 49, 1],
+        // term_to_binary(hello) on OTP 26+
+        &[131, 119, 5, 104, 101, 108, 108, 111],
+        // term_to_binary([]).
+        &[131, 106],
+        // term_to_binary("abc").
+        &[131, 107, 0, 3, 97, 98, 99],
+        // term_to_binary([1, 2, 300]), not a string because of 300
+        &[131, 108, 0, 0, 0, 3, 97, 1, 97, 2, 98, 0, 0, 1, 44, 106],
+        // term_to_binary(#{name => "joe", tags => [1, 2]}) on OTP 26+
+        &[
+            131, 116, 0, 0, 0, 2, 119, 4, 110, 97, 109, 101, 107, 0, 3, 106, 111, 101, 119, 4, 116,
+            97, 103, 115, 107, 0, 2, 1, 2,
+        ],
+    ];
+
+    for bytes in fixtures {
+        let term = ErlTerm::decode(binary_data(bytes)).unwrap();
+        assert_eq!(bytes, term.to_bytes().unwrap(), "{:?}", term);
+    }
+}
+
+#[test]
+fn encode_round_trips() {
+    for term in sample_terms() {
+        let bytes = term.to_bytes().unwrap();
+        assert_eq!(term, ErlTerm::decode(binary_data(bytes)).unwrap());
+    }
+}
+
+#[test]
+fn encoding_limits_nesting_depth() {
+    // [[[...]]] nested depth times
+    let nested = |depth: usize| {
+        let mut term = ErlTerm::List(List { elements: vec![] });
+        for _ in 0..depth {
+            term = ErlTerm::List(List { elements: vec![term] });
+        }
+        term
+    };
+
+    let mut bytes = Vec::new();
+    Encoder::new(Box::new(&mut bytes)).with_max_depth(10).encode(&nested(9)).unwrap();
+    assert_eq!(nested(9), ErlTerm::from_bytes(&bytes).unwrap());
+    assert!(matches!(
+        Encoder::new(Box::new(Vec::new())).with_max_depth(10).encode(&nested(10)),
+        Err(EncodingError::NestingTooDeep { limit: 10 })
+    ));
+
+    assert!(nested(DEFAULT_MAX_NESTING_DEPTH - 1).to_bytes().is_ok());
+    assert!(matches!(
+        nested(DEFAULT_MAX_NESTING_DEPTH).to_bytes(),
+        Err(EncodingError::NestingTooDeep { .. })
+    ));
+}
+
+#[test]
+fn encode_rejects_invalid_bit_binaries() {
+    assert!(matches!(bit_binary(vec![1], 0).to_bytes(), Err(EncodingError::ValueOutOfRange())));
+    assert!(matches!(bit_binary(vec![1], 9).to_bytes(), Err(EncodingError::ValueOutOfRange())));
+}
+
+//
+// Annotated dumps
+//
+
+#[test]
+fn annotate_nested_terms() {
+    // term_to_binary({ok, <<"hello">>}) on OTP 26+
+    let bytes = [131, 104, 2, 119, 2, 111, 107, 109, 0, 0, 0, 5, 104, 101, 108, 108, 111];
+    let annotation = annotate(&bytes);
+    assert_eq!(None, annotation.failure);
+    assert_eq!(
+        "0000 83  version 131\n\
+         0001 68 02  SMALL_TUPLE_EXT arity=2\n\
+         0003   77 02  SMALL_ATOM_UTF8_EXT len=2\n\
+         0005     6f 6b  |ok|\n\
+         0007   6d 00000005  BINARY_EXT len=5\n\
+         000c     68 65 6c 6c 6f  |hello|\n",
+        annotation.to_string()
+    );
+}
+
+#[test]
+fn annotate_highlights_the_failing_byte() {
+    // a truncated binary
+    let annotation = annotate(&[131, 104, 2, 97, 1, 109, 0, 0, 0, 5, 104, 101]);
+    let failure = annotation.failure.clone().unwrap();
+    assert_eq!(12, failure.offset);
+    assert_eq!(None, failure.byte);
+    assert!(annotation.to_string().ends_with(
+        "000a     68 65  |he|\n\
+         000c     [..]  !! unexpected end of input: BINARY_EXT needs 5 bytes, 2 left\n"
+    ));
+
+    // an unknown tag inside of a list
+    let annotation = annotate(&[131, 108, 0, 0, 0, 2, 97, 1, 127, 106]);
+    assert_eq!(
+        "0000 83  version 131\n\
+         0001 6c 00000002  LIST_EXT len=2\n\
+         0006   61 01  SMALL_INTEGER_EXT 1\n\
+         0008   [7f]  !! unrecognized tag 127\n",
+        annotation.to_string()
+    );
+
+    // a map value that is missing
+    // term_to_binary(#{"a" => 1}) without its last two bytes
+    let annotation = annotate(&[131, 116, 0, 0, 0, 1, 107, 0, 1, 97]);
+    assert_eq!(
+        "0000 83  version 131\n\
+         0001 74 00000001  MAP_EXT arity=1\n\
+         0006   6b 0001  STRING_EXT len=1\n\
+         0009     61  |a|\n\
+         000a   [..]  !! unexpected end of input: tag needs 1 bytes, 0 left\n",
+        annotation.to_string()
+    );
+
+    // the sign byte of a big integer
+    let failure = annotate(&[131, 110, 1, 2, 5]).failure.unwrap();
+    assert_eq!((3, Some(2)), (failure.offset, failure.byte));
+
+    // SMALL_ATOM_EXT, which the decoders do not accept either
+    let bytes = [131, 104, 1, 115, 1, 97];
+    assert!(matches!(ErlTerm::from_bytes(&bytes), Err(DecodingError::UnrecognizedTag { tag: 115 })));
+    let failure = annotate(&bytes).failure.unwrap();
+    assert_eq!((3, Some(115)), (failure.offset, failure.byte));
+
+    // invalid UTF-8 in an ATOM_UTF8_EXT
+    let bytes = [131, 118, 0, 2, 208, 40];
+    assert!(ErlTerm::from_bytes(&bytes).is_err());
+    let failure = annotate(&bytes).failure.unwrap();
+    assert_eq!((4, "invalid UTF-8 atom"), (failure.offset, failure.reason.as_str()));
+}
+
+//
+// Atom interning
+//
+
+#[test]
+fn decoded_atoms_are_interned() {
+    // term_to_binary([ok, ok]).
+    let input = binary_data(&[131, 108, 0, 0, 0, 2, 100, 0, 2, 111, 107, 100, 0, 2, 111, 107, 106]);
+    let list: List = ErlTerm::decode(input).unwrap().try_into().unwrap();
+    let (a, b) = match (&list.elements[0], &list.elements[1]) {
+        (ErlTerm::Atom(a), ErlTerm::Atom(b)) => (*a, *b),
+        _ => panic!("expected atoms"),
+    };
+    assert_eq!(a, b);
+    assert!(std::ptr::eq(a.as_str(), b.as_str()));
+    assert_eq!(InternedAtom::new("ok").unwrap(), a);
+    assert_eq!("ok", a.as_str());
+    assert_ne!(InternedAtom::new("error").unwrap(), a);
+
+    let set: std::collections::HashSet<InternedAtom> = [a, b, InternedAtom::new("error").unwrap()].into_iter().collect();
+    assert_eq!(2, set.len());
+}
+
+//
+// Arena decoding
+//
+
+#[cfg(feature = "arena")]
+#[test]
+fn arena_decoding_matches_decoder() {
+    let arena = Bump::new();
+    for term in sample_terms() {
+        let bytes = term.to_bytes().unwrap();
+        assert_eq!(term, decode_in(&arena, &bytes).unwrap().to_term());
+    }
+
+    // [{1, <<"a">>}, {2, <<"a">>}, ...]
+    let list = ErlTerm::List(List {
+        elements: (0..1000)
+            .map(|i| {
+                let n = if i < 256 { small_integer(i as u8) } else { integer(i) };
+                ErlTerm::Tuple(Tuple { elements: vec![n, binary("a")] })
+            })
+            .collect(),
+    });
+    let bytes = list.to_bytes().unwrap();
+    let decoded = decode_in(&arena, &bytes).unwrap();
+    match decoded {
+        ArenaTerm::List(elements) => {
+            assert_eq!(1000, elements.len());
+            assert_eq!(ArenaTerm::Tuple(&[ArenaTerm::SmallInteger(1), ArenaTerm::Binary(b"a")]), elements[1]);
+        }
+        other => panic!("expected a list, got {:?}", other),
+    }
+    assert_eq!(list, ErlTerm::from(&decoded));
+}
+
+#[cfg(feature = "arena")]
+#[test]
+fn arena_decoding_rejects_malformed_input() {
+    let arena = Bump::new();
+    assert!(matches!(decode_in(&arena, &[131, 109, 0, 0, 0, 5, 1]), Err(DecodingError::DecodingFailure(_))));
+    assert!(matches!(decode_in(&arena, &[131, 7]), Err(DecodingError::UnrecognizedTag { tag: 7 })));
+    assert!(matches!(decode_in(&arena, &[130, 106]), Err(DecodingError::UnsupportedVersion { version: 130 })));
+    // a list claiming 4 billion elements
+    assert!(decode_in(&arena, &[131, 108, 255, 255, 255, 255, 106]).is_err());
+    assert!(decode_in(&arena, &[131, 116, 255, 255, 255, 255, 106]).is_err());
+}
+
+//
+// Slice decoding
+//
+
+#[test]
+fn slice_decoding_matches_decoder() {
+    for term in sample_terms() {
+        let bytes = term.to_bytes().unwrap();
+        assert_eq!(term, ErlTerm::from_bytes(&bytes).unwrap());
+    }
+
+    // term_to_binary(a), the legacy ATOM_EXT
+    assert_eq!(atom("a"), ErlTerm::from_bytes(&[131, 100, 0, 1, 97]).unwrap());
+    // term_to_binary(-5130000000).
+    let bytes = [131, 110, 5, 1, 128, 60, 197, 49, 1];
+    assert_eq!(
+        ErlTerm::decode(binary_data(bytes)).unwrap(),
+        ErlTerm::from_bytes(&bytes).unwrap()
+    );
+}
+
+#[test]
+fn slice_decoder_reads_concatenated_terms() {
+    let mut bytes = vec![131, 97, 1];
+    bytes.extend([131, 104, 2, 97, 2, 106]);
+    let mut decoder = SliceDecoder::new(&bytes);
+    assert_eq!(small_integer(1), decoder.decode().unwrap());
+    assert_eq!(3, decoder.position());
+    assert_eq!(
+        ErlTerm::Tuple(Tuple { elements: vec![small_integer(2), empty_list()] }),
+        decoder.decode().unwrap()
+    );
+    assert!(decoder.is_at_end());
+}
+
+#[test]
+fn slice_decoding_rejects_malformed_input() {
+    assert!(matches!(ErlTerm::from_bytes(&[131, 109, 0, 0, 0, 5, 1]), Err(DecodingError::DecodingFailure(_))));
+    assert!(matches!(ErlTerm::from_bytes(&[131, 7]), Err(DecodingError::UnrecognizedTag { tag: 7 })));
+    assert!(matches!(ErlTerm::from_bytes(&[131, 77, 0, 0, 0, 1, 0, 1]), Err(DecodingError::DecodingFailure(_))));
+    assert!(ErlTerm::from_bytes(&[131, 108, 255, 255, 255, 255, 106]).is_err());
+}
+
+//
+// Malformed input
+//
+
+// Inputs found by fuzzing (see fuzz/) that used to panic, abort
+// or run out of memory
+const MALFORMED: &[&[u8]] = &[
+    // LIST_EXT with 4294967295 elements
+    &[131, 108, 255, 255, 255, 255, 100, 108, 0, 0, 0, 2, 100, 0, 2, 111, 107, 106],
+    // LARGE_TUPLE_EXT with 83984444 elements
+    &[131, 105, 5, 1, 128, 60, 197, 49, 1],
+    // NEW_PORT_EXT with a tuple for a node name
+    &[131, 89, 105, 113, 113, 131, 89, 0, 0, 0, 0, 100, 0, 0, 0, 0, 0, 0, 93, 93],
+    // NEWER_REFERENCE_EXT with a port for a node name
+    &[131, 90, 97, 123, 105, 89, 89, 89, 89, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 97, 97, 97, 42],
+    // LARGE_BIG_EXT with 4022321152 digits
+    &[131, 111, 239, 187, 191, 0, 0, 0, 0, 0, 0, 39, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 93, 93],
+    // BINARY_EXT with 4294967295 bytes
+    &[131, 109, 255, 255, 255, 255, 0],
+    // BIT_BINARY_EXT with 0 and 9 trailing bits
+    &[131, 77, 0, 0, 0, 1, 0, 255],
+    &[131, 77, 0, 0, 0, 1, 9, 255],
+    // FUN_EXPORT_EXT with an integer for a module name
+    &[131, 113, 97, 1, 100, 0, 1, 102, 97, 1],
+    // FUN_EXPORT_EXT with an integer arity
+    &[131, 113, 100, 0, 1, 109, 100, 0, 1, 102, 98, 0, 0, 1, 0],
+    // NEW_FUN_EXT with a truncated MD5
+    &[131, 112, 0, 0, 0, 71, 1, 115, 60, 203],
+    // NEW_FUN_EXT with a mangled creator pid
+    &[
+        131, 112, 0, 0, 0, 71, 1, 115, 60, 203, 97, 151, 228, 98, 166, 34, 126, 65, 11, 0, 0, 0, 0, 0, 0, 0, 1,
+        100, 0, 1, 97, 97, 0, 98, 3, 153, 230, 91, 88, 100, 0, 13, 110, 111, 110, 111, 100, 101, 64, 110, 111,
+        104, 75, 71, 169, 49, 166, 34, 126, 65, 11, 0, 0, 0, 0, 0, 0, 0, 1, 100, 0, 1, 97, 97, 0, 98, 3, 153,
+        230, 91, 88, 100, 0, 13, 110, 111, 110, 111, 100, 101, 64, 110, 111, 104, 111, 115, 116, 0, 0, 0, 36,
+        0, 0, 0, 0, 0, 0, 0, 0, 97, 10,
+    ],
+];
+
+#[test]
+fn malformed_input_fails_to_decode() {
+    for bytes in MALFORMED {
+        assert!(Decoder::new(Box::new(*bytes)).decode().is_err(), "{:?}", bytes);
+        assert!(ErlTerm::from_bytes(bytes).is_err(), "{:?}", bytes);
+        assert!(validate(bytes).is_err(), "{:?}", bytes);
+        assert!(annotate(bytes).failure.is_some(), "{:?}", bytes);
+        #[cfg(feature = "arena")]
+        assert!(decode_in(&Bump::new(), bytes).is_err(), "{:?}", bytes);
+    }
+}
+
+#[test]
+fn decoding_limits_nesting_depth() {
+    // [[[...]]] nested depth times, every level takes 6 bytes
+    let nested = |depth: usize| {
+        let mut bytes = vec![131];
+        for _ in 0..depth {
+            bytes.extend_from_slice(&[108, 0, 0, 0, 1]);
+        }
+        bytes.push(106);
+        bytes.extend(std::iter::repeat_n(106, depth));
+        bytes
+    };
+
+    let shallow = nested(9);
+    assert!(Decoder::new(Box::new(&shallow[..])).with_max_depth(10).decode().is_ok());
+    assert!(SliceDecoder::new(&shallow).with_max_depth(10).decode().is_ok());
+    #[cfg(feature = "arena")]
+    assert!(decode_in_with_max_depth(&Bump::new(), &shallow, 10).is_ok());
+
+    let bytes = nested(10);
+    assert!(matches!(
+        Decoder::new(Box::new(&bytes[..])).with_max_depth(10).decode(),
+        Err(DecodingError::NestingTooDeep { limit: 10 })
+    ));
+    assert!(matches!(
+        Decoder::new(Box::new(&bytes[..])).with_max_depth(10).skip_term(),
+        Err(DecodingError::NestingTooDeep { limit: 10 })
+    ));
+    assert!(matches!(
+        SliceDecoder::new(&bytes).with_max_depth(10).decode(),
+        Err(DecodingError::NestingTooDeep { limit: 10 })
+    ));
+    #[cfg(feature = "arena")]
+    assert!(matches!(
+        decode_in_with_max_depth(&Bump::new(), &bytes, 10),
+        Err(DecodingError::NestingTooDeep { limit: 10 })
+    ));
+    assert!(ErlTerm::from_bytes(&bytes).is_ok());
+
+    let deepest = nested(DEFAULT_MAX_NESTING_DEPTH - 1);
+    assert!(Decoder::new(Box::new(&deepest[..])).decode().is_ok());
+    assert!(ErlTerm::from_bytes(&deepest).is_ok());
+    assert_eq!(deepest.len(), validate(&deepest).unwrap());
+    #[cfg(feature = "arena")]
+    assert!(decode_in(&Bump::new(), &deepest).is_ok());
+
+    for bytes in [nested(DEFAULT_MAX_NESTING_DEPTH), nested(1_000_000)] {
+        assert!(matches!(
+            Decoder::new(Box::new(&bytes[..])).decode(),
+            Err(DecodingError::NestingTooDeep { .. })
+        ));
+        assert!(matches!(ErlTerm::from_bytes(&bytes), Err(DecodingError::NestingTooDeep { .. })));
+        assert!(matches!(validate(&bytes), Err(DecodingError::NestingTooDeep { .. })));
+        assert!(annotate(&bytes).failure.unwrap().reason.starts_with("nested more than"));
+        #[cfg(feature = "arena")]
+        assert!(matches!(decode_in(&Bump::new(), &bytes), Err(DecodingError::NestingTooDeep { .. })));
+    }
+}
+
+//
+// Validation
+//
+
+#[test]
+fn validate_scalar_terms() {
+    // term_to_binary(a).
+    assert_eq!(5, validate(&[131, 100, 0, 1, 97]).unwrap());
+    // term_to_binary(-1000).
+    assert_eq!(6, validate(&[131, 98, 255, 255, 252, 24]).unwrap());
+    // term_to_binary(5130000000).
+    assert_eq!(9, validate(&[131, 110, 5, 0, 128, 150, 197, 49, 1]).unwrap());
+    // term_to_binary(121.7)
+    assert_eq!(10, validate(&[131, 70, 64, 94, 108, 204, 204, 204, 204, 205]).unwrap());
+    assert_eq!(10, validate(&[131, 77, 0, 0, 0, 3, 5, 1, 2, 24]).unwrap());
+}
+
+#[test]
+fn validate_compound_terms() {
+    // term_to_binary([1, 2, 3, 99999999 | 5])
+    let input1 = [131,108,0,0,0,4,97,1,97,2,97,3,98,5,245,224,255,97,5];
+    assert_eq!(input1.len(), validate(&input1).unwrap());
+
+    // term_to_binary(make_ref()).
+    let input2 = [
+        131,90,0,3,100,0,13,110,111,110,111,100,101,64,110,111,104,111,115,116,0,0,0,0,0,2,23,123,77,156,0,1,94,82,239,55
+    ];
+    assert_eq!(input2.len(), validate(&input2).unwrap());
+
+    // term_to_binary(fun() -> 1 + 1 end).
+    let input3 = [
+        131, 112, 0, 0, 0, 71, 1, 115, 60, 203, 97, 151, 228, 98, 75, 71, 169, 49, 166, 34, 126,
+        65, 11, 0, 0, 0, 0, 0, 0, 0, 1, 100, 0, 1, 97, 97, 0, 98, 3, 153, 230, 91, 88, 100, 0, 13,
+        110, 111, 110, 111, 100, 101, 64, 110, 111, 104, 111, 115, 116, 0, 0, 0, 36, 0, 0, 0, 0, 0,
+        0, 0, 0, 97, 10,
+    ];
+    assert_eq!(input3.len(), validate(&input3).unwrap());
+}
+
+#[test]
+fn validate_reports_length_of_the_first_term_only() {
+    // term_to_binary({1, 2}) followed by term_to_binary(a)
+    let input = [131, 104, 2, 97, 1, 97, 2, 131, 100, 0, 1, 97];
+    assert_eq!(7, validate(&input).unwrap());
+
+    let mut decoder = Decoder::new(binary_data(input));
+    assert_eq!(7, decoder.skip_term().unwrap());
+    assert_eq!(5, decoder.skip_term().unwrap());
+    assert!(decoder.skip_term().is_err());
+}
+
+#[test]
+fn validate_rejects_malformed_terms() {
+    // truncated binary
+    assert!(validate(&[131, 109, 0, 0, 0, 3, 97, 98]).is_err());
+    // invalid UTF-8 in a SMALL_ATOM_UTF8_EXT
+    assert!(validate(&[131, 119, 2, 208, 40]).is_err());
+    // bigint sign must be 0 or 1
+    assert!(validate(&[131, 110, 1, 2, 1]).is_err());
+    // pid node must be an atom
+    assert!(validate(&[131, 88, 97, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    // tuple element with an unknown tag
+    assert!(matches!(
+        validate(&[131, 104, 1, 1]),
+        Err(DecodingError::UnrecognizedTag { tag: 1 })
+    ));
+    // unsupported version
+    assert!(matches!(
+        validate(&[130, 106]),
+        Err(DecodingError::UnsupportedVersion { version: 130 })
+    ));
+}
+
+#[test]
+fn validate_limits_nesting_depth() {
+    // [[[...]]] nested depth times
+    let nested = |depth: usize| {
+        let mut bytes = vec![131];
+        for _ in 0..depth {
+            bytes.extend_from_slice(&[108, 0, 0, 0, 1]);
+        }
+        bytes.push(106);
+        bytes.extend(std::iter::repeat_n(106, depth));
+        bytes
+    };
+
+    let deepest = nested(DEFAULT_MAX_NESTING_DEPTH - 1);
+    assert_eq!(deepest.len(), validate(&deepest).unwrap());
+    assert!(Decoder::new(Box::new(&deepest[..])).decode().is_ok());
+
+    let too_deep = nested(1_000_000);
+    assert!(matches!(validate(&too_deep), Err(DecodingError::NestingTooDeep { .. })));
+    assert!(matches!(
+        Decoder::new(Box::new(&too_deep[..])).decode(),
+        Err(DecodingError::NestingTooDeep { .. })
+    ));
+}
+
+//
+// Lazy decoding
+//
+
+#[test]
+fn lazy_tuple_element_access() {
+    // term_to_binary({<<"aa">>, <<"bbb">>, <<"c">>, <<"dddd">>}).
+    let input = [
+        131,104,4,109,0,0,0,2,97,97,109,0,0,0,3,98,98,98,109,0,0,0,1,99,109,0,0,0,4,100,100,100,100
+    ];
+    let lazy = LazyTerm::new(&input).unwrap();
+
+    assert!(lazy.is_tuple());
+    assert_eq!(4, lazy.len());
+    assert_eq!(binary("bbb"), lazy.tuple_element(1).unwrap());
+    assert_eq!(binary("dddd"), lazy.tuple_element(3).unwrap());
+    assert!(matches!(
+        lazy.tuple_element(4),
+        Err(DecodingError::IndexOutOfBounds { index: 4, size: 4 })
+    ));
+    assert!(lazy.list_item(0).is_err());
+    assert_eq!(tuple_of_binaries(vec!["aa", "bbb", "c", "dddd"]), lazy.decode().unwrap());
+}
+
+#[test]
+fn lazy_list_item_access() {
+    // term_to_binary([1, 2, 3, 99999999 | 5])
+    let input = [131,108,0,0,0,4,97,1,97,2,97,3,98,5,245,224,255,97,5];
+    let lazy = LazyTerm::new(&input).unwrap();
+
+    assert!(lazy.is_list());
+    assert!(lazy.is_improper_list());
+    assert_eq!(integer(99999999), lazy.list_item(3).unwrap());
+    assert_eq!(small_integer(5), lazy.list_tail().unwrap().unwrap().decode().unwrap());
+
+    // term_to_binary([]).
+    let nil = LazyTerm::new(&[131, 106]).unwrap();
+    assert!(nil.is_list());
+    assert!(nil.is_empty());
+    assert!(nil.list_tail().unwrap().is_none());
+}
+
+#[test]
+fn lazy_nested_subterms_forward_raw_bytes() {
+    // term_to_binary({a, {1, 2}}).
+    let input = [131, 104, 2, 100, 0, 1, 97, 104, 2, 97, 1, 97, 2];
+    let lazy = LazyTerm::new(&input).unwrap();
+
+    let inner = lazy.element(1).unwrap();
+    assert!(inner.is_tuple());
+    assert_eq!(small_integer(2), inner.tuple_element(1).unwrap());
+    assert_eq!(&[104, 2, 97, 1, 97, 2], inner.as_bytes());
+    assert_eq!(vec![131, 104, 2, 97, 1, 97, 2], inner.to_etf());
+    assert_eq!(tuple_of_u8(vec![1, 2]), ErlTerm::decode(binary_data(inner.to_etf())).unwrap());
+
+    let tags: Vec<u8> = lazy.elements().map(|e| e.tag()).collect();
+    assert_eq!(vec![100, 104], tags);
+}
+
+#[test]
+fn lazy_map_and_string_access() {
+    // term_to_binary(#{name => "joe", tags => [1, 2]}) on OTP 26 and later
+    let input = [
+        131,116,0,0,0,2,119,4,110,97,109,101,107,0,3,106,111,101,119,4,116,97,103,115,107,0,2,1,2
+    ];
+    let lazy = LazyTerm::new(&input).unwrap();
+
+    assert!(lazy.is_map());
+    assert_eq!(2, lazy.len());
+    assert!(lazy.element(0).is_err());
+    assert_eq!(0, lazy.elements().count());
+    let name = lazy.map_value(&atom("name")).unwrap().unwrap();
+    assert!(name.is_list());
+    assert_eq!(3, name.len());
+    assert_eq!(small_integer(b'o'), name.list_item(1).unwrap());
+    assert_eq!(&[97, b'e'], name.element(2).unwrap().as_bytes());
+    assert!(matches!(name.list_item(3), Err(DecodingError::IndexOutOfBounds { index: 3, size: 3 })));
+    assert_eq!(list_of_u8(vec![1, 2]), lazy.map_value(&atom("tags")).unwrap().unwrap().decode().unwrap());
+    assert!(lazy.map_value(&atom("age")).unwrap().is_none());
+
+    let keys: Vec<ErlTerm> = lazy.entries().map(|(key, _)| key.decode().unwrap()).collect();
+    assert_eq!(vec![atom("name"), atom("tags")], keys);
+    assert!(LazyTerm::new(&[131, 106]).unwrap().map_value(&atom("name")).is_err());
+}
+
+#[test]
+fn lazy_term_rejects_malformed_input() {
+    assert!(LazyTerm::new(&[]).is_err());
+    assert!(LazyTerm::new(&[130, 106]).is_err());
+    // the last tuple element is truncated
+    assert!(LazyTerm::new(&[131, 104, 2, 97, 1, 98, 0]).is_err());
+    // a map value and the tail of a list are missing
+    assert!(LazyTerm::new(&[131, 116, 0, 0, 0, 1, 97, 1]).is_err());
+    assert!(LazyTerm::new(&[131, 108, 0, 0, 0, 1, 97, 1]).is_err());
+    assert!(LazyTerm::new(&[131, 108, 0, 0]).is_err());
+    // trailing bytes are ignored
+    assert_eq!(&[104, 1, 106], LazyTerm::new(&[131, 104, 1, 106, 0, 0]).unwrap().as_bytes());
+}
+
+//
+// Queries
+//
+
+#[test]
+fn query_tuple_and_list_elements() {
+    let term = ErlTerm::List(List {
+        elements: vec![tuple_of_u8(vec![1, 2]), tuple_of_u8(vec![3, 4])],
+    });
+
+    assert_eq!(vec![&small_integer(2)], term.query("[0][1]").unwrap());
+    assert_eq!(vec![&small_integer(1), &small_integer(3)], term.query("[*][0]").unwrap());
+    assert_eq!(vec![&term], term.query("").unwrap());
+    assert!(term.query("[5]").unwrap().is_empty());
+    assert_eq!(Some(&small_integer(4)), term.query_first(".[1].[1]").unwrap());
+}
+
+#[test]
+fn query_records_and_proplists() {
+    // [{name, {resource, <<"/">>, queue, <<"qq.1">>}}, {durable, true}]
+    let resource = ErlTerm::Tuple(Tuple {
+        elements: vec![atom("resource"), binary("/"), atom("queue"), binary("qq.1")],
+    });
+    let term = ErlTerm::List(List {
+        elements: vec![
+            ErlTerm::Tuple(Tuple { elements: vec![atom("name"), resource] }),
+            ErlTerm::Tuple(Tuple { elements: vec![atom("durable"), atom("true")] }),
+        ],
+    });
+
+    assert_eq!(vec![&binary("/")], term.query(".name.resource[1]").unwrap());
+    assert_eq!(vec![&atom("true")], term.query(".'durable'").unwrap());
+    assert!(term.query(".name.exchange").unwrap().is_empty());
+    assert_eq!(vec![&binary("qq.1")], term.query("..resource[3]").unwrap());
+}
+
+#[test]
+fn query_improper_list_tail() {
+    // [1, 2, 3, 99999999 | 5]
+    let input = binary_data(&[131,108,0,0,0,4,97,1,97,2,97,3,98,5,245,224,255,97,5]);
+    let term = ErlTerm::decode(input).unwrap();
+
+    assert_eq!(vec![&small_integer(5)], term.query("[|]").unwrap());
+    assert_eq!(4, term.query("[*]").unwrap().len());
+    // the list itself, four elements and the tail
+    assert_eq!(6, term.query("..").unwrap().len());
+}
+
+#[test]
+fn query_rejects_malformed_paths() {
+    let term = empty_list();
+    assert_eq!(Err(QueryError::UnexpectedEnd), term.query("[0"));
+    assert_eq!(Err(QueryError::InvalidIndex { position: 1 }), term.query("[a]"));
+    assert_eq!(Err(QueryError::UnexpectedCharacter { position: 1, character: '[' }), term.query("#[name]"));
+    assert_eq!(Err(QueryError::UnexpectedCharacter { position: 6, character: ']' }), term.query("#{name]"));
+    assert_eq!(Err(QueryError::UnexpectedEnd), term.query("#{*"));
+}
+
+#[test]
+fn query_maps() {
+    // [#{name => {resource, <<"/">>, queue, <<"qq.1">>}, <<"type">> => quorum}]
+    let resource = ErlTerm::Tuple(Tuple {
+        elements: vec![atom("resource"), binary("/"), atom("queue"), binary("qq.1")],
+    });
+    let queue = map(vec![(atom("name"), resource), (binary("type"), atom("quorum"))]);
+    let term = ErlTerm::List(List { elements: vec![queue] });
+
+    assert_eq!(vec![&binary("/")], term.query("[0].#{name}.resource[1]").unwrap());
+    assert_eq!(vec![&atom("quorum")], term.query("[0]#{\"type\"}").unwrap());
+    assert_eq!(2, term.query("[0]#{*}").unwrap().len());
+    assert!(term.query("[0]#{durable}").unwrap().is_empty());
+    assert!(term.query("#{name}").unwrap().is_empty());
+    // keys are descendants too
+    assert_eq!(vec![&binary("qq.1")], term.query("..resource[3]").unwrap());
+    assert!(term.query("..").unwrap().contains(&&binary("type")));
+
+    let path = Path::parse("[0]#{name}#{\"type\"}#{*}").unwrap();
+    assert_eq!(Path::parse(&path.to_string()).unwrap(), path);
+}
+
+//
+// Pattern matching
+//
+
+#[test]
+fn pattern_match_binds_variables() {
+    // {ok, <<"running">>, [1, 2, 3]}
+    let term = ErlTerm::Tuple(Tuple {
+        elements: vec![atom("ok"), binary("running"), list_of_u8(vec![1, 2, 3])],
+    });
+
+    let pattern = Pattern::parse("{ok, S, [_ | T]}").unwrap();
+    let bindings = pattern.match_term(&term).unwrap();
+    assert_eq!(2, bindings.len());
+    assert_eq!(binary("running"), bindings["S"]);
+    assert_eq!(list_of_u8(vec![2, 3]), bindings["T"]);
+
+    assert!(Pattern::parse("{ok, <<\"running\">>, [1, 2, 3]}").unwrap().is_match(&term));
+    assert!(!Pattern::parse("{error, _, _}").unwrap().is_match(&term));
+    assert!(!Pattern::parse("{ok, _}").unwrap().is_match(&term));
+    assert!(!Pattern::parse("{ok, _, [_, _]}").unwrap().is_match(&term));
+}
+
+#[test]
+fn pattern_match_repeated_variables_must_be_equal() {
+    let pattern: Pattern = "{X, X}".parse().unwrap();
+    assert!(pattern.is_match(&tuple_of_u8(vec![7, 7])));
+    assert!(!pattern.is_match(&tuple_of_u8(vec![7, 8])));
+}
+
+#[test]
+fn pattern_match_literals() {
+    assert!(Pattern::parse("1000").unwrap().is_match(&integer(1000)));
+    assert!(Pattern::parse("-1").unwrap().is_match(&integer(-1)));
+    assert!(Pattern::parse("5130000000").unwrap().is_match(&big_integer(5130000000)));
+    // integers match regardless of their representation
+    assert!(Pattern::parse("255").unwrap().is_match(&integer(255)));
+    assert!(Pattern::parse("-121.7").unwrap().is_match(&float(-121.7)));
+    assert!(Pattern::parse("'Cádiz'").unwrap().is_match(&atom("Cádiz")));
+    assert!(Pattern::parse("<<1, 2, 3:5>>").unwrap().is_match(&bit_binary(vec![1, 2, 3], 5)));
+    assert!(Pattern::parse("\"ab\"").unwrap().is_match(&list_of_u8(vec![97, 98])));
+    assert!(Pattern::parse("[]").unwrap().is_match(&empty_list()));
+
+    let pid = erl_pid(atom("nonode@nohost"), 87, 0, 0);
+    let pattern = Pattern::Tuple(vec![Pattern::literal(pid.clone()), Pattern::Wildcard]);
+    assert!(pattern.is_match(&ErlTerm::Tuple(Tuple { elements: vec![pid, atom("x")] })));
+}
+
+#[test]
+fn pattern_match_improper_list_tails() {
+    // [1, 2, 3, 99999999 | 5]
+    let input = binary_data(&[131,108,0,0,0,4,97,1,97,2,97,3,98,5,245,224,255,97,5]);
+    let term = ErlTerm::decode(input).unwrap();
+
+    let bindings = Pattern::parse("[1, 2, 3, Big | Tail]").unwrap().match_term(&term).unwrap();
+    assert_eq!(integer(99999999), bindings["Big"]);
+    assert_eq!(small_integer(5), bindings["Tail"]);
+
+    let bindings = Pattern::parse("[_ | T]").unwrap().match_term(&term).unwrap();
+    assert!(matches!(bindings["T"], ErlTerm::ImproperList(_)));
+    assert!(!Pattern::parse("[_, _, _, _]").unwrap().is_match(&term));
+}
+
+#[test]
+fn pattern_match_maps() {
+    let pattern = Pattern::parse("{ok, #{status := S, <<\"node\">> := _}, [_ | T]}").unwrap();
+    let status = map(vec![(atom("status"), atom("running")), (binary("node"), atom("rabbit@host")), (atom("pid"), integer(-1))]);
+    let term = ErlTerm::Tuple(Tuple {
+        elements: vec![atom("ok"), status, list_of_u8(vec![1, 2, 3])],
+    });
+    let bindings = pattern.match_term(&term).unwrap();
+    assert_eq!(atom("running"), bindings["S"]);
+    assert_eq!(list_of_u8(vec![2, 3]), bindings["T"]);
+
+    // keys match any integer representation
+    assert!(Pattern::parse("#{1 := one}").unwrap().is_match(&map(vec![(integer(1), atom("one"))])));
+    assert!(Pattern::parse("#{}").unwrap().is_match(&map(vec![(atom("a"), atom("b"))])));
+    assert!(!Pattern::parse("#{}").unwrap().is_match(&empty_list()));
+    assert!(!Pattern::parse("#{status := S}").unwrap().is_match(&map(vec![])));
+}
+
+#[test]
+fn pattern_parse_errors() {
+    assert_eq!(Err(PatternError::UnexpectedEnd), Pattern::parse("{ok, S"));
+    assert_eq!(Err(PatternError::UnexpectedToken { position: 7 }), Pattern::parse("{ok, #{S := running}}"));
+    assert_eq!(Err(PatternError::UnexpectedCharacter { position: 9, character: '=' }), Pattern::parse("#{status => S}"));
+    assert_eq!(Err(PatternError::UnexpectedToken { position: 3 }), Pattern::parse("ok ok"));
+    assert_eq!(Err(PatternError::UnexpectedCharacter { position: 0, character: '$' }), Pattern::parse("$"));
+}
+
+//
+// Formatting
+//
+
+#[test]
+fn display_in_erlang_syntax() {
+    assert_eq!("ok", atom("ok").to_string());
+    assert_eq!("'Cádiz'", atom("Cádiz").to_string());
+    assert_eq!("'end'", atom("end").to_string());
+    assert_eq!("nonode@nohost", atom("nonode@nohost").to_string());
+    assert_eq!("-1000", integer(-1000).to_string());
+    assert_eq!("5130000000", big_integer(5130000000).to_string());
+    assert_eq!("-121.7", float(-121.7).to_string());
+    assert_eq!("1.0e20", float(1e20).to_string());
+    assert_eq!("<<\"abc\">>", binary("abc").to_string());
+    assert_eq!("<<\"кириллица\"/utf8>>", binary("кириллица").to_string());
+    assert_eq!("<<0,255>>", ErlTerm::Binary(vec![0, 255]).to_string());
+    assert_eq!("<<1,2,3:5>>", bit_binary(vec![1, 2, 3], 5).to_string());
+    assert_eq!("{1,2,3}", tuple_of_u8(vec![1, 2, 3]).to_string());
+    assert_eq!("[]", empty_list().to_string());
+    assert_eq!("#Pid<nonode@nohost.87.0>", erl_pid(atom("nonode@nohost"), 87, 0, 0).to_string());
+    assert_eq!("fun erlang:'+'/2", erl_external_fun(atom("erlang"), atom("+"), 2).to_string());
+
+    // [1, 2, 3, 99999999 | 5]
+    let input = binary_data(&[131,108,0,0,0,4,97,1,97,2,97,3,98,5,245,224,255,97,5]);
+    assert_eq!("[1,2,3,99999999|5]", ErlTerm::decode(input).unwrap().to_string());
+}
+
+#[test]
+fn display_alternate_form_breaks_long_terms() {
+    let short = tuple_of_u8(vec![1, 2, 3]);
+    assert_eq!("{1,2,3}", format!("{:#}", short));
+
+    let queue = ErlTerm::Tuple(Tuple {
+        elements: vec![
+            atom("amqqueue"),
+            ErlTerm::List(List {
+                elements: vec![
+                    ErlTerm::Tuple(Tuple { elements: vec![atom("name"), binary("a-long-queue-name")] }),
+                    ErlTerm::Tuple(Tuple { elements: vec![atom("durable"), atom("true")] }),
+                    ErlTerm::Tuple(Tuple { elements: vec![atom("type"), atom("rabbit_quorum_queue")] }),
+                ],
+            }),
+            atom("ok"),
+        ],
+    });
+    assert_eq!(
+        "{amqqueue,\n [{name,<<\"a-long-queue-name\">>},{durable,true},{type,rabbit_quorum_queue}],\n ok}",
+        format!("{:#}", queue)
+    );
+
+    let arguments = map(vec![(binary("x-queue-type"), binary("quorum")), (binary("x-max-length"), integer(100000))]);
+    let queue = map(vec![(atom("name"), binary("a-long-queue-name")), (atom("arguments"), arguments)]);
+    assert_eq!(
+        "#{name => <<\"a-long-queue-name\">>,\n  arguments => #{<<\"x-queue-type\">>=><<\"quorum\">>,<<\"x-max-length\">>=>100000}}",
+        format!("{:#}", queue)
+    );
+
+    // only line breaks and indentation are added, however deep the term
+    let mut deep = atom("leaf");
+    for _ in 0..200 {
+        deep = ErlTerm::List(List { elements: vec![deep, atom("sibling")] });
+    }
+    assert_eq!(deep.to_string(), format!("{:#}", deep).replace(['\n', ' '], ""));
+}
+
+//
+// Diffing
+//
+
+#[test]
+fn diff_of_equal_terms_is_empty() {
+    let term = tuple_of_binaries(vec!["erlang", "rust"]);
+    assert!(diff(&term, &term.clone()).is_empty());
+}
+
+#[test]
+fn diff_reports_changed_tuple_slots() {
+    let old = ErlTerm::Tuple(Tuple { elements: vec![atom("queue"), tuple_of_u8(vec![1, 2])] });
+    let new = ErlTerm::Tuple(Tuple { elements: vec![atom("queue"), tuple_of_u8(vec![1, 3])] });
+
+    let changes = diff(&old, &new);
+    assert_eq!(
+        vec![Change::Changed {
+            path: Path::parse("[1][1]").unwrap(),
+            old: small_integer(2),
+            new: small_integer(3)
+        }],
+        changes
+    );
+    assert_eq!("[1][1]: changed 2 to 3", render_diff(&changes));
+}
+
+#[test]
+fn diff_reports_added_and_removed_list_elements() {
+    let old = list_of_u8(vec![1, 2, 3, 4]);
+    let new = list_of_u8(vec![1, 3, 4, 5]);
+
+    let changes = diff(&old, &new);
+    assert_eq!(
+        "[1]: removed 2\n[3]: added 5",
+        render_diff(&changes)
+    );
+}
+
+#[test]
+fn diff_reports_type_changes() {
+    let old = ErlTerm::List(List { elements: vec![atom("a"), binary("b")] });
+    let new = ErlTerm::List(List { elements: vec![atom("a"), atom("b")] });
+    assert_eq!(
+        "[1]: changed binary <<\"b\">> to atom b",
+        render_diff(&diff(&old, &new))
+    );
+
+    // [1, 2 | 3] vs [1, 2]
+    let improper = ErlTerm::ImproperList(ImproperList {
+        elements: vec![small_integer(1), small_integer(2)],
+        tail: Box::new(small_integer(3)),
+    });
+    assert_eq!(
+        "[|]: changed integer 3 to list []",
+        render_diff(&diff(&improper, &list_of_u8(vec![1, 2])))
+    );
+    assert_eq!(
+        "(root): changed atom a to integer 1",
+        render_diff(&diff(&atom("a"), &small_integer(1)))
+    );
+}
+
+#[test]
+fn diff_reports_changed_map_values() {
+    let old = map(vec![(atom("name"), binary("qq.1")), (atom("durable"), atom("true")), (atom("node"), atom("a"))]);
+    let new = map(vec![(atom("node"), atom("b")), (atom("name"), binary("qq.1")), (binary("type"), atom("quorum"))]);
+    assert_eq!(
+        "#{durable}: removed true\n#{node}: changed a to b\n#{\"type\"}: added quorum",
+        render_diff(&diff(&old, &new))
+    );
+    assert_eq!("(root): changed map #{} to list []", render_diff(&diff(&map(vec![]), &empty_list())));
+}
+
+//
+// JSON
+//
+
+#[cfg(feature = "json")]
+#[test]
+fn json_default_mapping_is_human_friendly() {
+    use serde_json::json;
+
+    // [{name, <<"qq.1">>}, {durable, true}, {args, {1, 2}}]
+    let term = ErlTerm::List(List {
+        elements: vec![
+            ErlTerm::Tuple(Tuple { elements: vec![atom("name"), binary("qq.1")] }),
+            ErlTerm::Tuple(Tuple { elements: vec![atom("durable"), atom("true")] }),
+            ErlTerm::Tuple(Tuple { elements: vec![atom("args"), tuple_of_u8(vec![1, 2])] }),
+        ],
+    });
+    let options = JsonOptions::default();
+    assert_eq!(
+        json!({"name": "qq.1", "durable": true, "args": [1, 2]}),
+        term.to_json(&options)
+    );
+    let big = ErlTerm::BigInteger(-num::BigInt::from(u64::MAX) - 1);
+    assert_eq!(json!({"bigint": "-18446744073709551616"}), big.to_json(&options));
+    assert_eq!(json!({"binary": "AP8="}), ErlTerm::Binary(vec![0, 255]).to_json(&options));
+
+    // back into a proplist with atom keys and binary values
+    let back = ErlTerm::from_json(&json!({"name": "qq.1", "size": 1000}), &options).unwrap();
+    assert_eq!(
+        ErlTerm::List(List {
+            elements: vec![
+                ErlTerm::Tuple(Tuple { elements: vec![atom("name"), binary("qq.1")] }),
+                ErlTerm::Tuple(Tuple { elements: vec![atom("size"), integer(1000)] }),
+            ],
+        }),
+        back
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_lossless_mapping_round_trips() {
+    let options = JsonOptions::lossless();
+    for term in sample_terms() {
+        let json = term.to_json(&options);
+        let text = serde_json::to_string(&json).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(term, ErlTerm::from_json(&parsed, &options).unwrap(), "{}", text);
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_rejects_malformed_tagged_objects() {
+    use serde_json::json;
+
+    let options = JsonOptions::lossless();
+    assert!(matches!(
+        ErlTerm::from_json(&json!({"pid": {"node": "a@b", "id": 1}}), &options),
+        Err(JsonError::InvalidField { .. })
+    ));
+    assert!(matches!(
+        ErlTerm::from_json(&json!({"binary": "not base64!"}), &options),
+        Err(JsonError::InvalidBase64(_))
+    ));
+
+    // numbers that do not fit the field are not truncated
+    for (json, invalid) in [
+        (json!({"pid": {"node": "a@b", "id": 4294967296u64, "serial": 0, "creation": 0}}), "id"),
+        (json!({"fun": {"module": "m", "function": "f", "arity": 256}}), "arity"),
+        (json!({"ref": {"node": "a@b", "creation": 0, "id": [4294967296u64]}}), "id"),
+        (json!({"bits": "AQ==", "trailing_bits": 256}), "trailing_bits"),
+        (json!({"bits": "AQ==", "trailing_bits": 9}), "trailing_bits"),
+        (json!({"bits": "AQ==", "trailing_bits": 0}), "trailing_bits"),
+    ] {
+        match ErlTerm::from_json(&json, &options) {
+            Err(JsonError::InvalidField { field, .. }) => assert_eq!(invalid, field),
+            other => panic!("{}: {:?}", json, other),
+        }
+    }
+}
+
+//
+// CBOR
+//
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_round_trips_through_etf() {
+    for term in sample_terms() {
+        let etf = term.to_bytes().unwrap();
+        let cbor = ErlTerm::decode(binary_data(etf.clone())).unwrap().to_cbor().unwrap();
+        let back = ErlTerm::from_cbor(&cbor).unwrap();
+        assert_eq!(term, back);
+        assert_eq!(etf, back.to_bytes().unwrap());
+    }
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_native_values_convert_to_terms() {
+    use ciborium::value::Value;
+
+    let value = Value::Map(vec![
+        (Value::Text("enabled".to_string()), Value::Bool(true)),
+        (Value::Text("limit".to_string()), Value::Null),
+    ]);
+    assert_eq!(
+        ErlTerm::List(List {
+            elements: vec![
+                ErlTerm::Tuple(Tuple { elements: vec![binary("enabled"), atom("true")] }),
+                ErlTerm::Tuple(Tuple { elements: vec![binary("limit"), atom("null")] }),
+            ],
+        }),
+        ErlTerm::from_cbor_value(&value).unwrap()
+    );
+    assert!(matches!(
+        ErlTerm::from_cbor_value(&Value::Tag(CBOR_TAG_PID, Box::new(Value::Array(vec![])))),
+        Err(CborError::UnsupportedValue { .. })
+    ));
+    for (bytes, bits) in [(vec![1], 0), (vec![1], 9), (vec![], 1)] {
+        let value = Value::Tag(CBOR_TAG_BIT_BINARY, Box::new(Value::Array(vec![Value::Bytes(bytes), bits.into()])));
+        assert!(matches!(ErlTerm::from_cbor_value(&value), Err(CborError::UnsupportedValue { .. })));
+    }
+}
+
+//
+// MessagePack
+//
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_round_trips_through_etf() {
+    for term in sample_terms() {
+        let etf = term.to_bytes().unwrap();
+        let msgpack = ErlTerm::decode(binary_data(etf.clone())).unwrap().to_msgpack().unwrap();
+        let back = ErlTerm::from_msgpack(&msgpack).unwrap();
+        assert_eq!(term, back);
+        assert_eq!(etf, back.to_bytes().unwrap());
+
+        let mut from_value = Vec::new();
+        rmpv::encode::write_value(&mut from_value, &term.to_msgpack_value()).unwrap();
+        assert_eq!(msgpack, from_value);
+    }
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_native_values_convert_to_terms() {
+    use rmpv::Value;
+
+    let value = Value::Map(vec![
+        (Value::from("enabled"), Value::Boolean(true)),
+        (Value::from("limit"), Value::Nil),
+    ]);
+    assert_eq!(
+        ErlTerm::List(List {
+            elements: vec![
+                ErlTerm::Tuple(Tuple { elements: vec![binary("enabled"), atom("true")] }),
+                ErlTerm::Tuple(Tuple { elements: vec![binary("limit"), atom("null")] }),
+            ],
+        }),
+        ErlTerm::from_msgpack_value(&value).unwrap()
+    );
+    assert!(matches!(
+        ErlTerm::from_msgpack_value(&Value::Ext(MSGPACK_EXT_PID, vec![0x90])),
+        Err(MsgPackError::UnsupportedValue { .. })
+    ));
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_limits_nesting_depth() {
+    // {{{...}}} nested depth times
+    let nested = |depth: usize| {
+        let mut term = ErlTerm::Tuple(Tuple { elements: vec![] });
+        for _ in 1..depth {
+            term = ErlTerm::Tuple(Tuple { elements: vec![term] });
+        }
+        term
+    };
+    let deepest = nested(DEFAULT_MAX_NESTING_DEPTH);
+    assert_eq!(deepest, ErlTerm::from_msgpack(&deepest.to_msgpack().unwrap()).unwrap());
+    assert!(matches!(
+        ErlTerm::from_msgpack(&nested(DEFAULT_MAX_NESTING_DEPTH + 1).to_msgpack().unwrap()),
+        Err(MsgPackError::NestingTooDeep { .. })
+    ));
+
+    // Every tuple is an ext 32 TUPLE around a one element array, rmpv
+    // decodes each of them on its own
+    let depth = 100_000;
+    let mut bytes = Vec::with_capacity(7 * depth);
+    for level in (1..=depth).rev() {
+        bytes.push(0xc9);
+        bytes.extend_from_slice(&(7 * level as u32 - 6).to_be_bytes());
+        bytes.push(MSGPACK_EXT_TUPLE as u8);
+        bytes.push(if level > 1 { 0x91 } else { 0x90 });
+    }
+    assert!(matches!(ErlTerm::from_msgpack(&bytes), Err(MsgPackError::NestingTooDeep { .. })));
+}
+
+//
+// Generated terms
+//
+
+#[cfg(feature = "proptest")]
+proptest::proptest! {
+    #[test]
+    fn generated_bytes_decode_to_generated_terms((term, bytes) in term_with_etf_bytes(TermParams::default())) {
+        proptest::prop_assert_eq!(&term, &ErlTerm::decode(binary_data(bytes.clone())).unwrap());
+        proptest::prop_assert_eq!(&term, &ErlTerm::from_bytes(&bytes).unwrap());
+        proptest::prop_assert_eq!(bytes.len(), validate(&bytes).unwrap());
+    }
+
+    #[test]
+    fn generated_terms_round_trip_through_the_encoder(
+        term in proptest::prelude::any_with::<ErlTerm>(TermParams { depth: 5, size: 4, max_binary_len: 300 })
+    ) {
+        proptest::prop_assert_eq!(&term, &ErlTerm::from_bytes(&term.to_bytes().unwrap()).unwrap());
+    }
+}
+
 //
 // Helpers
 //
 
+// One term of every kind, as the decoder produces them
+fn sample_terms() -> Vec<ErlTerm> {
+    // term_to_binary(fun() -> 1 + 1 end).
+    let fun = ErlTerm::decode(binary_data(&[
+        131, 112, 0, 0, 0, 71, 1, 115, 60, 203, 97, 151, 228, 98, 75, 71, 169, 49, 166, 34, 126,
+        65, 11, 0, 0, 0, 0, 0, 0, 0, 1, 100, 0, 1, 97, 97, 0, 98, 3, 153, 230, 91, 88, 100, 0, 13,
+        110, 111, 110, 111, 100, 101, 64, 110, 111, 104, 111, 115, 116, 0, 0, 0, 36, 0, 0, 0, 0, 0,
+        0, 0, 0, 97, 10,
+    ])).unwrap();
+    let improper = ErlTerm::ImproperList(ImproperList {
+        elements: vec![small_integer(1), atom("true")],
+        tail: Box::new(binary("tail")),
+    });
+    return vec![
+        atom("ok"),
+        atom("null"),
+        integer(-1000),
+        big_integer(5130000000),
+        ErlTerm::BigInteger(num::BigInt::from(u64::MAX) * 4),
+        ErlTerm::BigInteger(-num::BigInt::from(u128::MAX) * u128::MAX),
+        float(121.7),
+        float(1.0),
+        binary("abc кириллица"),
+        bit_binary(vec![1, 2, 3], 5),
+        erl_pid(atom("nonode@nohost"), 87, 0, 0),
+        erl_v3_port(atom("nonode@nohost"), 4, 0),
+        erl_v4_port(atom("nonode@nohost"), 4, 0),
+        erl_ref(atom("nonode@nohost"), 0, vec![137083, 1302069249, 1582493495]),
+        erl_external_fun(atom("erlang"), atom("+"), 2),
+        fun,
+        tuple_of_binaries(vec!["erlang", "rust"]),
+        list_of_u8(vec![1, 2, 3]),
+        empty_list(),
+        improper,
+        list_of_u8(b"a string".to_vec()),
+        map(vec![
+            (atom("name"), list_of_u8(b"joe".to_vec())),
+            (binary("tags"), list_of_u8(vec![1, 2])),
+            (tuple_of_u8(vec![1, 2]), map(vec![])),
+        ]),
+    ];
+}
+
 fn binary_data<T>(bytes: T) -> Box<Cursor<T>> {
     Box::new(Cursor::new(bytes))
 }
 
 fn atom(s: &str) -> ErlTerm {
-    ErlTerm::Atom(s.to_string())
+    ErlTerm::Atom(InternedAtom::new(s).unwrap())
 }
 
 fn small_integer(i: u8) -> ErlTerm {
@@ -500,7 +1753,7 @@ fn big_integer(i: i64) -> ErlTerm {
 }
 
 fn float(i: f64) -> ErlTerm {
-    ErlTerm::Float(OrderedFloat::<f64>(i as f64))
+    ErlTerm::Float(OrderedFloat::<f64>(i))
 }
 
 fn binary(s: &str) -> ErlTerm {
@@ -552,7 +1805,6 @@ fn tuple_of_u8(vec: Vec<u8>) -> ErlTerm {
     ErlTerm::Tuple(Tuple { elements: xs })
 }
 
-#[allow(dead_code)]
 fn list_of_u8(vec: Vec<u8>) -> ErlTerm {
     let xs = vec
         .iter()
@@ -569,6 +1821,10 @@ fn tuple_of_binaries(vec: Vec<&str>) -> ErlTerm {
     ErlTerm::Tuple(Tuple { elements: xs })
 }
 
+fn map(entries: Vec<(ErlTerm, ErlTerm)>) -> ErlTerm {
+    ErlTerm::Map(Map { entries })
+}
+
 fn empty_list() -> ErlTerm {
     return ErlTerm::List(List::nil());
 }
@@ -577,6 +1833,6 @@ fn erl_external_fun(mod_name: ErlTerm, fun_name: ErlTerm, arity: u8) -> ErlTerm
     return ErlTerm::ExternalFun(ExternalFun {
         module: TryInto::<Atom>::try_into(mod_name).unwrap(),
         function_name: TryInto::<Atom>::try_into(fun_name).unwrap(),
-        arity: arity
+        arity
     })
 }
\ No newline at end of file
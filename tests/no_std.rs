@@ -0,0 +1,30 @@
+#![allow(clippy::needless_return)]
+
+// Only uses what is available without the std feature:
+//
+//   cargo test --no-default-features --test no_std
+
+extern crate erl_etf;
+
+use erl_etf::*;
+
+#[test]
+fn slice_decoding_without_std() {
+    // term_to_binary({ok, [1, <<"a">>]}).
+    let bytes = [131, 104, 2, 119, 2, 111, 107, 108, 0, 0, 0, 2, 97, 1, 109, 0, 0, 0, 1, 97, 106];
+    let term = ErlTerm::from_bytes(&bytes).unwrap();
+    assert_eq!("{ok,[1,<<\"a\">>]}", term.to_string());
+
+    let mut decoder = SliceDecoder::new(&bytes);
+    decoder.decode().unwrap();
+    assert!(decoder.is_at_end());
+}
+
+#[test]
+fn decoding_errors_without_std() {
+    // truncated term_to_binary(<<"erlang">>)
+    match ErlTerm::from_bytes(&[131, 109, 0, 0, 0, 6, 101, 114]) {
+        Err(DecodingError::DecodingFailure(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,392 @@
+#![cfg(feature = "distribution")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::*;
+use erl_etf::*;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn start(name: &str) -> Node {
+    return Node::start(HandshakeConfig::new(name, "secret").unwrap()).unwrap();
+}
+
+// Two nodes connected over loopback, with names unique to the test
+fn pair() -> (Node, Node) {
+    static PAIRS: AtomicUsize = AtomicUsize::new(0);
+    let n = PAIRS.fetch_add(1, Ordering::Relaxed);
+    let a = start(&format!("a{}@localhost", n));
+    let b = start(&format!("b{}@localhost", n));
+    assert_eq!(a.connect_to(b.local_addr()).unwrap(), b.name());
+    // the accepting side finishes the handshake on its own thread
+    let deadline = Instant::now() + TIMEOUT;
+    while b.connected_nodes().is_empty() {
+        assert!(Instant::now() < deadline, "b never saw the connection");
+        thread::sleep(Duration::from_millis(1));
+    }
+    return (a, b);
+}
+
+fn receive(mailbox: &Mailbox) -> ErlTerm {
+    return mailbox.receive_timeout(TIMEOUT).unwrap().expect("no message");
+}
+
+fn receive_exit(mailbox: &Mailbox) -> (ErlPid, ErlTerm) {
+    match mailbox.receive_timeout(TIMEOUT) {
+        Err(DistributionError::Exit { from, reason }) => return (from, *reason),
+        other => panic!("expected an exit signal, got {:?}", other),
+    }
+}
+
+//
+// Local node
+//
+
+#[test]
+fn pids_and_refs_are_unique() {
+    let node = start("pids@localhost");
+    let first = node.create_mailbox();
+    let second = node.create_mailbox();
+    assert_ne!(first.pid(), second.pid());
+    assert_eq!(first.pid().node.name.as_str(), "pids@localhost");
+    assert_eq!(first.pid().creation, node.creation());
+
+    let r1 = node.make_ref();
+    let r2 = node.make_ref();
+    assert_ne!(r1, r2);
+    assert_eq!(r1.id.len(), 3);
+    assert_eq!(r1.creation, node.creation());
+}
+
+#[test]
+fn sends_locally_and_by_registered_name() {
+    let node = start("local@localhost");
+    let server = node.create_named_mailbox("server").unwrap();
+    let client = node.create_mailbox();
+    assert_eq!(node.whereis("server").as_ref(), Some(server.pid()));
+    assert!(matches!(
+        node.create_named_mailbox("server"),
+        Err(DistributionError::NameTaken { name }) if name == "server"
+    ));
+
+    client.send_named("server", "local@localhost", atom("ping")).unwrap();
+    assert_eq!(receive(&server), atom("ping"));
+    server.send(client.pid(), atom("pong")).unwrap();
+    assert_eq!(receive(&client), atom("pong"));
+    assert_eq!(client.receive_timeout(Duration::from_millis(10)).unwrap(), None);
+
+    drop(server);
+    assert_eq!(node.whereis("server"), None);
+}
+
+#[test]
+fn links_and_exits_locally() {
+    let node = start("links@localhost");
+    let a = node.create_mailbox();
+    let b = node.create_mailbox();
+    a.link(b.pid()).unwrap();
+    let b_pid = b.pid().clone();
+    b.exit(atom("crashed"));
+    assert_eq!(receive_exit(&a), (b_pid, atom("crashed")));
+}
+
+//
+// Two nodes
+//
+
+#[test]
+fn sends_between_nodes() {
+    let (a, b) = pair();
+    assert_eq!(a.connected_nodes(), vec![b.name().to_string()]);
+    assert_eq!(b.connected_nodes(), vec![a.name().to_string()]);
+
+    let server = b.create_named_mailbox("echo").unwrap();
+    let client = a.create_mailbox();
+    let request = tuple(vec![ErlTerm::Pid(client.pid().clone()), atom("hello")]);
+    client.send_named("echo", b.name(), request).unwrap();
+
+    let (from, message) = match receive(&server) {
+        ErlTerm::Tuple(Tuple { elements }) => match elements.as_slice() {
+            [ErlTerm::Pid(from), message] => (from.clone(), message.clone()),
+            _ => panic!("unexpected request"),
+        },
+        _ => panic!("unexpected request"),
+    };
+    assert_eq!(&from, client.pid());
+    server.send(&from, message).unwrap();
+    assert_eq!(receive(&client), atom("hello"));
+}
+
+#[test]
+fn links_between_nodes() {
+    let (a, b) = pair();
+    let watcher = a.create_mailbox();
+    let worker = b.create_mailbox();
+    watcher.link(worker.pid()).unwrap();
+    watcher.send(worker.pid(), atom("linked")).unwrap();
+    assert_eq!(receive(&worker), atom("linked"));
+
+    // the link goes both ways
+    let watcher_pid = watcher.pid().clone();
+    watcher.exit(atom("crashed"));
+    assert_eq!(receive_exit(&worker), (watcher_pid, atom("crashed")));
+}
+
+#[test]
+fn normal_exits_are_ignored() {
+    let (a, b) = pair();
+    let watcher = a.create_mailbox();
+    let worker = b.create_mailbox();
+    watcher.link(worker.pid()).unwrap();
+    watcher.send(worker.pid(), atom("linked")).unwrap();
+    assert_eq!(receive(&worker), atom("linked"));
+
+    drop(watcher);
+    assert_eq!(worker.receive_timeout(Duration::from_millis(100)).unwrap(), None);
+    // the worker carries on
+    let other = a.create_mailbox();
+    other.send(worker.pid(), atom("after")).unwrap();
+    assert_eq!(receive(&worker), atom("after"));
+}
+
+#[test]
+fn register_with_uses_the_epmd_creation() {
+    let epmd = EpmdServer::start().unwrap();
+    let node = start("registered@localhost");
+    node.register_with(&epmd.client()).unwrap();
+    let creation = node.creation();
+    assert_eq!(1, creation);
+    assert_eq!(creation, node.create_mailbox().pid().creation);
+    node.close();
+}
+
+#[test]
+fn unlinks_between_nodes() {
+    let (a, b) = pair();
+    let watcher = a.create_mailbox();
+    let worker = b.create_mailbox();
+    watcher.link(worker.pid()).unwrap();
+    watcher.unlink(worker.pid()).unwrap();
+    // the ack has arrived when a message sent afterwards has
+    worker.send(watcher.pid(), atom("done")).unwrap();
+    assert_eq!(receive(&watcher), atom("done"));
+
+    worker.exit(atom("crashed"));
+    assert_eq!(watcher.receive_timeout(Duration::from_millis(100)).unwrap(), None);
+}
+
+#[test]
+fn monitors_between_nodes() {
+    let (a, b) = pair();
+    let watcher = a.create_mailbox();
+    let worker = b.create_mailbox();
+    let reference = watcher.monitor(worker.pid()).unwrap();
+    // signals between two processes arrive in order, so the monitor is in place
+    // once this message is
+    watcher.send(worker.pid(), atom("monitored")).unwrap();
+    assert_eq!(receive(&worker), atom("monitored"));
+    let worker_pid = worker.pid().clone();
+    worker.exit(atom("shutdown"));
+
+    let down = tuple(vec![
+        atom("DOWN"),
+        ErlTerm::Ref(reference),
+        atom("process"),
+        ErlTerm::Pid(worker_pid),
+        atom("shutdown"),
+    ]);
+    assert_eq!(receive(&watcher), down);
+}
+
+#[test]
+fn monitoring_a_missing_process_fires_at_once() {
+    let (a, b) = pair();
+    let watcher = a.create_mailbox();
+    let gone = b.create_mailbox().pid().clone();
+    let reference = watcher.monitor(&gone).unwrap();
+
+    let down = tuple(vec![atom("DOWN"), ErlTerm::Ref(reference), atom("process"), ErlTerm::Pid(gone), atom("noproc")]);
+    assert_eq!(receive(&watcher), down);
+}
+
+#[test]
+fn demonitored_processes_send_no_down() {
+    let (a, b) = pair();
+    let watcher = a.create_mailbox();
+    let worker = b.create_mailbox();
+    let reference = watcher.monitor(worker.pid()).unwrap();
+    watcher.demonitor(&reference).unwrap();
+    worker.send(watcher.pid(), atom("done")).unwrap();
+    assert_eq!(receive(&watcher), atom("done"));
+
+    drop(worker);
+    assert_eq!(watcher.receive_timeout(Duration::from_millis(100)).unwrap(), None);
+}
+
+#[test]
+fn connection_loss_exits_links_and_fires_monitors() {
+    let (a, b) = pair();
+    let watcher = a.create_mailbox();
+    let linked = a.create_mailbox();
+    let worker = b.create_mailbox();
+    let reference = watcher.monitor(worker.pid()).unwrap();
+    linked.link(worker.pid()).unwrap();
+    // makes sure b has seen the link before going away
+    worker.send(linked.pid(), atom("linked")).unwrap();
+    assert_eq!(receive(&linked), atom("linked"));
+
+    b.close();
+    assert_eq!(receive_exit(&linked), (worker.pid().clone(), atom("noconnection")));
+    let down = tuple(vec![
+        atom("DOWN"),
+        ErlTerm::Ref(reference),
+        atom("process"),
+        ErlTerm::Pid(worker.pid().clone()),
+        atom("noconnection"),
+    ]);
+    assert_eq!(receive(&watcher), down);
+    assert!(a.connected_nodes().is_empty());
+}
+
+//
+// Calls
+//
+
+// Answers gen_server calls like a server would, with the reply function
+fn serve(mailbox: Mailbox, reply: impl Fn(ErlTerm) -> Option<ErlTerm> + Send + 'static) -> thread::JoinHandle<()> {
+    return thread::spawn(move || {
+        while let Ok(Some(message)) = mailbox.receive_timeout(TIMEOUT) {
+            let ErlTerm::Tuple(Tuple { elements }) = message else { panic!("not a call") };
+            let [call, ErlTerm::Tuple(Tuple { elements: from }), request] = elements.as_slice() else {
+                panic!("not a call")
+            };
+            assert_eq!(*call, atom("$gen_call"));
+            let [ErlTerm::Pid(pid), tag @ ErlTerm::Ref(_)] = from.as_slice() else { panic!("not a call") };
+            if let Some(reply) = reply(request.clone()) {
+                mailbox.send(pid, tuple(vec![tag.clone(), reply])).unwrap();
+            }
+        }
+    });
+}
+
+#[test]
+fn calls_wait_for_the_matching_reply() {
+    let (a, b) = pair();
+    let server = b.create_mailbox();
+    let server_pid = server.pid().clone();
+    serve(server, |request| Some(tuple(vec![atom("ok"), request])));
+
+    let client = a.create_mailbox();
+    // unrelated messages stay in the mailbox
+    client.send(client.pid(), atom("unrelated")).unwrap();
+    let reply = client.call(&server_pid, atom("ping"), TIMEOUT).unwrap();
+    assert_eq!(reply, tuple(vec![atom("ok"), atom("ping")]));
+    assert_eq!(receive(&client), atom("unrelated"));
+}
+
+#[test]
+fn calls_registered_servers_on_other_nodes() {
+    let (a, b) = pair();
+    let server = b.create_named_mailbox("counter").unwrap();
+    serve(server, |_| Some(ErlTerm::SmallInteger(1)));
+
+    let client = a.create_mailbox();
+    assert_eq!(client.call_named("counter", b.name(), atom("next"), TIMEOUT).unwrap(), ErlTerm::SmallInteger(1));
+    assert!(matches!(
+        client.call_named("nobody", b.name(), atom("next"), TIMEOUT),
+        Err(DistributionError::CallFailed { reason }) if *reason == atom("noproc")
+    ));
+}
+
+#[test]
+fn calls_time_out() {
+    let (a, b) = pair();
+    let server = b.create_mailbox();
+    let server_pid = server.pid().clone();
+    serve(server, |_| None);
+
+    let client = a.create_mailbox();
+    let result = client.call(&server_pid, atom("ping"), Duration::from_millis(50));
+    assert!(matches!(result, Err(DistributionError::Timeout)));
+}
+
+#[test]
+fn calls_to_unreachable_nodes_fail() {
+    let node = start("lonely@localhost");
+    let client = node.create_mailbox();
+    let result = client.call_named("server", "nobody@localhost", atom("ping"), TIMEOUT);
+    assert!(matches!(result, Err(DistributionError::NodeDown { name }) if name == "nobody@localhost"));
+}
+
+#[test]
+fn rpc_calls_rex() {
+    let (a, b) = pair();
+    let rex = b.create_named_mailbox("rex").unwrap();
+    serve(rex, |request| {
+        let ErlTerm::Tuple(Tuple { elements }) = request else { panic!("not an rpc call") };
+        match elements.as_slice() {
+            [call, module, function, ErlTerm::List(args), group_leader] => {
+                assert_eq!((call, group_leader), (&atom("call"), &atom("user")));
+                if *module == atom("erlang") && *function == atom("length") {
+                    return Some(ErlTerm::SmallInteger(args.elements.len() as u8));
+                }
+                let undef = tuple(vec![atom("EXIT"), atom("undef")]);
+                return Some(tuple(vec![atom("badrpc"), undef]));
+            }
+            _ => panic!("not an rpc call"),
+        }
+    });
+
+    let client = a.create_mailbox();
+    let args = vec![atom("x"), atom("y")];
+    assert_eq!(client.rpc(b.name(), "erlang", "length", args, TIMEOUT).unwrap(), ErlTerm::SmallInteger(2));
+    match client.rpc(b.name(), "rabbit_amqqueue", "list", vec![], TIMEOUT) {
+        Err(DistributionError::BadRpc { reason }) => {
+            assert_eq!(*reason, tuple(vec![atom("EXIT"), atom("undef")]))
+        }
+        other => panic!("expected badrpc, got {:?}", other),
+    }
+}
+
+#[test]
+fn rpc_replies_with_maps_and_strings() {
+    let (a, b) = pair();
+    // replies like this one travel as STRING_EXT and MAP_EXT
+    let status = ErlTerm::Map(Map {
+        entries: vec![
+            (atom("node"), string("rabbit@host")),
+            (atom("listeners"), list(vec![string("amqp"), string("")])),
+            (atom("alarms"), ErlTerm::Map(Map::empty())),
+        ],
+    });
+
+    let rex = b.create_named_mailbox("rex").unwrap();
+    serve(rex, {
+        let status = status.clone();
+        move |_| Some(status.clone())
+    });
+    let client = a.create_mailbox();
+    assert_eq!(client.rpc(b.name(), "rabbit", "status", vec![], TIMEOUT).unwrap(), status);
+}
+
+#[test]
+fn calls_fail_when_the_node_goes_down() {
+    let (a, b) = pair();
+    let b_name = b.name().to_string();
+    let server = b.create_named_mailbox("stuck").unwrap();
+    // closes the node once the call has arrived
+    let closing = thread::spawn(move || {
+        server.receive_timeout(TIMEOUT).unwrap().unwrap();
+        b.close();
+    });
+
+    let client = a.create_mailbox();
+    let result = client.call_named("stuck", &b_name, atom("ping"), TIMEOUT);
+    assert!(matches!(result, Err(DistributionError::NodeDown { name }) if name == b_name));
+    closing.join().unwrap();
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "port")]
+#![allow(clippy::needless_return)]
+
+mod common;
+
+use std::io::{self, Cursor};
+
+use common::*;
+use erl_etf::*;
+
+// The packets an Erlang port would send for the terms
+fn packets(packet: Packet, terms: &[ErlTerm]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for term in terms {
+        write_packet(&mut out, packet, &term.to_bytes().unwrap()).unwrap();
+    }
+    return out;
+}
+
+#[test]
+fn packets_have_length_headers() {
+    let mut out = Vec::new();
+    write_packet(&mut out, Packet::One, b"abc").unwrap();
+    write_packet(&mut out, Packet::Two, b"abc").unwrap();
+    write_packet(&mut out, Packet::Four, b"abc").unwrap();
+    assert_eq!(out, b"\x03abc\x00\x03abc\x00\x00\x00\x03abc");
+
+    let mut input = Cursor::new(out);
+    assert_eq!(read_packet(&mut input, Packet::One).unwrap(), Some(b"abc".to_vec()));
+    assert_eq!(read_packet(&mut input, Packet::Two).unwrap(), Some(b"abc".to_vec()));
+    assert_eq!(read_packet(&mut input, Packet::Four).unwrap(), Some(b"abc".to_vec()));
+    assert_eq!(read_packet(&mut input, Packet::Four).unwrap(), None);
+}
+
+#[test]
+fn packet_sizes_are_checked() {
+    assert_eq!(Packet::try_from(2).unwrap(), Packet::Two);
+    assert!(matches!(Packet::try_from(3), Err(PortError::InvalidPacketSize { size: 3 })));
+
+    let mut out = Vec::new();
+    let result = write_packet(&mut out, Packet::One, &[0; 256]);
+    assert!(matches!(result, Err(PortError::TooLong { len: 256, max: 255 })));
+    assert!(out.is_empty());
+}
+
+#[test]
+fn truncated_packets_are_io_errors() {
+    let mut input = Cursor::new(b"\x00\x00\x00\x05ab".to_vec());
+    match read_packet(&mut input, Packet::Four) {
+        Err(PortError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("expected an io error, got {:?}", other),
+    }
+    let mut input = Cursor::new(b"\x00".to_vec());
+    assert!(matches!(read_packet(&mut input, Packet::Two), Err(PortError::Io(_))));
+    // a header claiming 4 GiB is not taken at its word
+    let mut input = Cursor::new(b"\xff\xff\xff\xffab".to_vec());
+    match read_packet(&mut input, Packet::Four) {
+        Err(PortError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        other => panic!("expected an io error, got {:?}", other),
+    }
+}
+
+#[test]
+fn sends_and_receives_terms() {
+    let term = tuple(vec![atom("ok"), ErlTerm::Binary(b"data".to_vec())]);
+    let mut port = Port::new(Cursor::new(packets(Packet::Two, std::slice::from_ref(&term))), Vec::new(), Packet::Two);
+    assert_eq!(port.receive().unwrap(), Some(term.clone()));
+    assert_eq!(port.receive().unwrap(), None);
+
+    port.send(&term).unwrap();
+    let (_, written) = port.into_inner();
+    assert_eq!(written, packets(Packet::Two, &[term]));
+}
+
+#[test]
+fn serve_replies_to_each_request() {
+    let requests = [atom("ping"), ErlTerm::SmallInteger(1)];
+    let mut port = Port::new(Cursor::new(packets(Packet::Four, &requests)), Vec::new(), Packet::Four);
+    port.serve(|request| tuple(vec![atom("reply"), request])).unwrap();
+
+    let (_, written) = port.into_inner();
+    let replies = [
+        tuple(vec![atom("reply"), atom("ping")]),
+        tuple(vec![atom("reply"), ErlTerm::SmallInteger(1)]),
+    ];
+    assert_eq!(written, packets(Packet::Four, &replies));
+}
+
+#[test]
+fn serve_stops_on_malformed_terms() {
+    let mut input = Vec::new();
+    write_packet(&mut input, Packet::Four, b"\x83\xff").unwrap();
+    let mut port = Port::new(Cursor::new(input), Vec::new(), Packet::Four);
+    assert!(matches!(port.serve(|request| request), Err(PortError::DecodingFailure(_))));
+}